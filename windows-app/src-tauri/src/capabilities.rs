@@ -0,0 +1,354 @@
+//! Which WS functions and features a site offers.
+//!
+//! Built from `core_webservice_get_site_info`: the `functions` list says
+//! what the token may call, `version` tells the Moodle release. Features
+//! that moved to new functions over the years are listed in [`FEATURES`]
+//! with the preferred function first, so the UI asks "which function do I
+//! use for forum posts" once instead of trying endpoints until one works.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+
+use crate::model::SiteInfo;
+
+/// Version numbers of Moodle releases referenced below.
+const RELEASES: &[(u64, &str)] = &[
+    (2011120500, "2.2"),
+    (2013051400, "2.5"),
+    (2014051200, "2.7"),
+    (2014111000, "2.8"),
+    (2015051100, "2.9"),
+    (2016120500, "3.2"),
+    (2017051500, "3.3"),
+    (2017111300, "3.4"),
+    (2018120300, "3.6"),
+    (2019052000, "3.7"),
+    (2020061500, "3.9"),
+    (2022041900, "4.0"),
+];
+
+/// A feature and the functions that implement it, preferred first.
+pub struct FeatureSpec {
+    pub key: &'static str,
+    /// `(function, minimum Moodle version)`.
+    pub functions: &'static [(&'static str, u64)],
+    /// Site setting from `advancedfeatures` that must be on, if any.
+    pub site_setting: Option<&'static str>,
+}
+
+/// Features the app toggles on. Legacy functions come after their
+/// replacements and are only used when the replacement is missing.
+pub const FEATURES: &[FeatureSpec] = &[
+    FeatureSpec {
+        key: "batchCalls",
+        functions: &[("tool_mobile_call_external_functions", 2019052000)],
+        site_setting: None,
+    },
+    FeatureSpec {
+        key: "qrLogin",
+        functions: &[("tool_mobile_get_tokens_for_qr_login", 2020061500)],
+        site_setting: None,
+    },
+    FeatureSpec {
+        key: "courseContents",
+        functions: &[("core_course_get_contents", 2011120500)],
+        site_setting: None,
+    },
+    FeatureSpec {
+        key: "courseUpdates",
+        functions: &[("core_course_check_updates", 2016120500)],
+        site_setting: None,
+    },
+    FeatureSpec {
+        key: "courseTimeline",
+        functions: &[
+            ("core_course_get_enrolled_courses_by_timeline_classification", 2018120300),
+            ("core_enrol_get_users_courses", 2011120500),
+        ],
+        site_setting: None,
+    },
+    FeatureSpec {
+        key: "forumDiscussions",
+        functions: &[
+            ("mod_forum_get_forum_discussions", 2019052000),
+            ("mod_forum_get_forum_discussions_paginated", 2014111000),
+        ],
+        site_setting: None,
+    },
+    FeatureSpec {
+        key: "forumDiscussionPosts",
+        functions: &[
+            ("mod_forum_get_discussion_posts", 2019052000),
+            ("mod_forum_get_forum_discussion_posts", 2014051200),
+        ],
+        site_setting: None,
+    },
+    FeatureSpec {
+        key: "upcomingEvents",
+        functions: &[
+            ("core_calendar_get_action_events_by_timesort", 2017051500),
+            ("core_calendar_get_calendar_events", 2013051400),
+        ],
+        site_setting: None,
+    },
+    FeatureSpec {
+        key: "monthlyCalendar",
+        functions: &[("core_calendar_get_calendar_monthly_view", 2017111300)],
+        site_setting: None,
+    },
+    FeatureSpec {
+        key: "gradeItems",
+        functions: &[
+            ("gradereport_user_get_grade_items", 2016120500),
+            ("gradereport_user_get_grades_table", 2015051100),
+        ],
+        site_setting: None,
+    },
+    FeatureSpec {
+        key: "courseGrades",
+        functions: &[("gradereport_overview_get_course_grades", 2016120500)],
+        site_setting: None,
+    },
+    FeatureSpec {
+        key: "conversations",
+        functions: &[
+            ("core_message_get_conversations", 2018120300),
+            ("core_message_data_for_messagearea_conversations", 2016120500),
+        ],
+        site_setting: Some("messaging"),
+    },
+    FeatureSpec {
+        key: "sendMessage",
+        functions: &[
+            ("core_message_send_messages_to_conversation", 2018120300),
+            ("core_message_send_instant_messages", 2011120500),
+        ],
+        site_setting: Some("messaging"),
+    },
+    FeatureSpec {
+        key: "notifications",
+        functions: &[
+            ("message_popup_get_popup_notifications", 2016120500),
+            ("core_message_get_messages", 2014111000),
+        ],
+        site_setting: None,
+    },
+    FeatureSpec {
+        key: "privateFiles",
+        functions: &[("core_files_get_files", 2011120500)],
+        site_setting: None,
+    },
+];
+
+/// Human-readable release for a version number, e.g. `2019052000` → `3.7`.
+pub fn release_name(version: u64) -> String {
+    RELEASES
+        .iter()
+        .find(|(v, _)| *v == version)
+        .map(|(_, name)| name.to_string())
+        .unwrap_or_else(|| version.to_string())
+}
+
+/// Minimum Moodle version of a function the app knows about.
+pub fn min_version(function: &str) -> Option<u64> {
+    FEATURES
+        .iter()
+        .flat_map(|feature| feature.functions.iter())
+        .find(|(name, _)| *name == function)
+        .map(|(_, version)| *version)
+}
+
+/// Parses site info `version` (`2023100902.05`) into its integer part.
+pub fn parse_version(version: &str) -> Option<u64> {
+    version.split('.').next()?.trim().parse().ok()
+}
+
+/// One function that could implement a feature.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionSupport {
+    pub function: String,
+    pub available: bool,
+    pub min_version: u64,
+    pub min_release: String,
+}
+
+/// How a feature is supported on the site.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeatureSupport {
+    pub available: bool,
+    /// The function to call, `None` if the feature is unavailable.
+    pub function: Option<String>,
+    /// Whether `function` is a legacy fallback rather than the preferred one.
+    pub fallback: bool,
+    /// Turned off by the site administrator (e.g. messaging disabled).
+    pub disabled_by_site: bool,
+    pub candidates: Vec<FunctionSupport>,
+}
+
+/// Everything the UI needs to decide which features to show.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CapabilityMatrix {
+    pub version: Option<u64>,
+    pub release: Option<String>,
+    pub functions: BTreeSet<String>,
+    pub features: BTreeMap<String, FeatureSupport>,
+}
+
+impl CapabilityMatrix {
+    /// Builds the matrix for a site.
+    pub fn from_site_info(info: &SiteInfo) -> Self {
+        let mut matrix = Self {
+            version: info.version.as_deref().and_then(parse_version),
+            release: info.release.clone(),
+            functions: info.functions.iter().map(|f| f.name.clone()).collect(),
+            features: BTreeMap::new(),
+        };
+
+        for spec in FEATURES {
+            let disabled_by_site = spec.site_setting.is_some_and(|setting| {
+                info.advancedfeatures
+                    .iter()
+                    .any(|feature| feature.name == setting && feature.value == 0)
+            });
+            let candidates: Vec<FunctionSupport> = spec
+                .functions
+                .iter()
+                .map(|(function, min_version)| FunctionSupport {
+                    function: function.to_string(),
+                    available: matrix.is_available(function),
+                    min_version: *min_version,
+                    min_release: release_name(*min_version),
+                })
+                .collect();
+            let chosen = (!disabled_by_site)
+                .then(|| candidates.iter().position(|c| c.available))
+                .flatten();
+            matrix.features.insert(
+                spec.key.to_string(),
+                FeatureSupport {
+                    available: chosen.is_some(),
+                    function: chosen.map(|i| candidates[i].function.clone()),
+                    fallback: chosen.is_some_and(|i| i > 0),
+                    disabled_by_site,
+                    candidates,
+                },
+            );
+        }
+        matrix
+    }
+
+    /// Whether the token may call `function`. Sites that do not list their
+    /// functions are judged by version for the functions in [`FEATURES`].
+    pub fn is_available(&self, function: &str) -> bool {
+        if !self.functions.is_empty() {
+            return self.functions.contains(function);
+        }
+        matches!((self.version, min_version(function)), (Some(site), Some(min)) if site >= min)
+    }
+
+    /// The function to use for a feature key from [`FEATURES`].
+    pub fn function_for(&self, feature: &str) -> Option<&str> {
+        self.features.get(feature)?.function.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::SiteFunction;
+
+    /// Site info recorded from a Moodle 4.3 site.
+    fn site_info() -> SiteInfo {
+        serde_json::from_str(include_str!("../tests/fixtures/core_webservice_get_site_info.json"))
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_versions() {
+        assert_eq!(parse_version("2023100902.05"), Some(2023100902));
+        assert_eq!(parse_version(" 2023100904"), Some(2023100904));
+        assert_eq!(parse_version("abc"), None);
+        assert_eq!(release_name(2019052000), "3.7");
+        assert_eq!(release_name(2023100904), "2023100904");
+        assert_eq!(min_version("mod_forum_get_discussion_posts"), Some(2019052000));
+        assert_eq!(min_version("core_webservice_get_site_info"), None);
+    }
+
+    #[test]
+    fn uses_the_functions_the_token_may_call() {
+        let matrix = CapabilityMatrix::from_site_info(&site_info());
+        assert_eq!(matrix.version, Some(2023100904));
+        assert_eq!(matrix.release.as_deref(), Some("4.3.4 (Build: 20240422)"));
+        assert_eq!(matrix.features.len(), FEATURES.len());
+
+        let batch = &matrix.features["batchCalls"];
+        assert!(batch.available && !batch.fallback);
+        assert_eq!(matrix.function_for("courseContents"), Some("core_course_get_contents"));
+
+        // Not listed for the token, however recent the site.
+        assert!(!matrix.is_available("tool_mobile_get_tokens_for_qr_login"));
+        let qr = &matrix.features["qrLogin"];
+        assert!(!qr.available && !qr.disabled_by_site);
+        assert_eq!(qr.function, None);
+
+        let timeline = &matrix.features["courseTimeline"];
+        assert!(timeline.available && timeline.fallback);
+        assert_eq!(timeline.function.as_deref(), Some("core_enrol_get_users_courses"));
+        assert_eq!(timeline.candidates.len(), 2);
+        assert!(!timeline.candidates[0].available);
+        assert_eq!(timeline.candidates[0].min_release, "3.6");
+        assert_eq!(matrix.function_for("unknownFeature"), None);
+    }
+
+    #[test]
+    fn honours_disabled_site_features() {
+        let mut info = site_info();
+        info.functions.push(SiteFunction {
+            name: "core_message_get_conversations".into(),
+            version: "2023100900".into(),
+        });
+        let matrix = CapabilityMatrix::from_site_info(&info);
+        assert_eq!(matrix.function_for("conversations"), Some("core_message_get_conversations"));
+
+        for feature in &mut info.advancedfeatures {
+            if feature.name == "messaging" {
+                feature.value = 0;
+            }
+        }
+        let matrix = CapabilityMatrix::from_site_info(&info);
+        let conversations = &matrix.features["conversations"];
+        assert!(conversations.disabled_by_site && !conversations.available);
+        assert_eq!(conversations.function, None);
+        assert!(conversations.candidates[0].available);
+        assert!(matrix.features["sendMessage"].disabled_by_site);
+        assert!(!matrix.features["notifications"].disabled_by_site);
+    }
+
+    #[test]
+    fn judges_by_version_without_a_function_list() {
+        let mut info = site_info();
+        info.functions.clear();
+        info.version = Some("2018120300".into());
+        let matrix = CapabilityMatrix::from_site_info(&info);
+        assert!(matrix.functions.is_empty());
+        assert!(!matrix.features["batchCalls"].available);
+        assert_eq!(
+            matrix.function_for("courseTimeline"),
+            Some("core_course_get_enrolled_courses_by_timeline_classification")
+        );
+        assert_eq!(
+            matrix.function_for("forumDiscussions"),
+            Some("mod_forum_get_forum_discussions_paginated")
+        );
+        assert!(matrix.features["forumDiscussions"].fallback);
+        assert!(!matrix.is_available("core_webservice_get_site_info"));
+
+        info.version = None;
+        let matrix = CapabilityMatrix::from_site_info(&info);
+        assert!(matrix.features.values().all(|feature| !feature.available));
+    }
+}
@@ -1,4 +1,33 @@
-use tauri::command;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use moodle_desktop_lib::capabilities::CapabilityMatrix;
+use moodle_desktop_lib::connectivity::{self, ConnectivityMonitor, SiteStatus, StatusChange};
+use moodle_desktop_lib::course_download::{self, CourseDownloadError, CourseDownloadReport};
+use moodle_desktop_lib::course_sync::{self, SyncReport};
+use moodle_desktop_lib::course_updates;
+use moodle_desktop_lib::data_key::{DataKey, KeyRing};
+use moodle_desktop_lib::downloads::{
+    self, DownloadError, DownloadHost, DownloadItem, DownloadManager, DownloadProgress,
+    DownloadRequest, DownloadRoots, RootChange,
+};
+use moodle_desktop_lib::login::{self, Credentials, LoginError, LoginResult};
+use moodle_desktop_lib::model::SiteInfo;
+use moodle_desktop_lib::outbox::{self, Outbox, OutboxError, OutboxItem, ReplayReport};
+use moodle_desktop_lib::pluginfile::{self, PluginfileCache};
+use moodle_desktop_lib::probe::{self, SiteProbe};
+use moodle_desktop_lib::qr_login::{self, QrLoginError};
+use moodle_desktop_lib::response_cache::{CacheError, CacheStats, CachedResponse, ResponseCache};
+use moodle_desktop_lib::scheduler::Priority;
+use moodle_desktop_lib::session::{self, Session, SessionHandle, SessionInfo, SessionStore};
+use moodle_desktop_lib::sso::{self, SsoError, SsoState};
+use moodle_desktop_lib::vault::{StoredAccount, Vault, VaultError};
+use moodle_desktop_lib::ws::{BatchCall, BatchResponse, WsClient, WsError};
+use tauri::{command, AppHandle, Emitter, Manager, State};
+use tauri_plugin_dialog::DialogExt;
+use tauri_plugin_fs::FsExt;
+use tauri_plugin_opener::OpenerExt;
+use zeroize::Zeroizing;
 
 /// Returns the application version from Cargo.toml.
 #[command]
@@ -6,33 +35,877 @@ pub fn get_app_version() -> String {
     env!("CARGO_PKG_VERSION").to_string()
 }
 
+/// Fetches a site's public configuration before login, so the login
+/// screen can pick the password, browser or embedded SSO flow.
+#[command]
+pub async fn probe_site(
+    client: State<'_, WsClient>,
+    site_url: String,
+) -> Result<SiteProbe, LoginError> {
+    probe::probe_site(&client, &site_url).await
+}
+
+/// Logs in with username and password via `login/token.php`.
+///
+/// Returns a session handle and the site info; the token stays in Rust and
+/// the password is dropped (and zeroed) as soon as the exchange is done.
+#[command]
+pub async fn moodle_login(
+    app: AppHandle,
+    client: State<'_, WsClient>,
+    sessions: State<'_, SessionStore>,
+    site_url: String,
+    username: String,
+    password: String,
+) -> Result<LoginResult, LoginError> {
+    let credentials = Credentials { username, password: password.into() };
+    let login = login::login(&client, &sessions, &site_url, credentials).await?;
+    session_opened(&app);
+    Ok(login)
+}
+
+/// Logs in with a Moodle "log in to the mobile app" QR code, given either
+/// as the scanned text or as the path of an image containing the code.
+#[command]
+pub async fn moodle_qr_login(
+    app: AppHandle,
+    client: State<'_, WsClient>,
+    sessions: State<'_, SessionStore>,
+    payload: Option<String>,
+    image_path: Option<String>,
+) -> Result<LoginResult, QrLoginError> {
+    let text = match (payload, image_path) {
+        (Some(text), _) => text,
+        (None, Some(path)) => {
+            tauri::async_runtime::spawn_blocking(move || {
+                qr_login::decode_image(std::path::Path::new(&path))
+            })
+            .await
+            .map_err(|e| QrLoginError::Image { message: e.to_string() })??
+        }
+        (None, None) => {
+            return Err(QrLoginError::InvalidPayload { message: "no QR code given".into() })
+        }
+    };
+    let payload = qr_login::parse_payload(&text)?;
+    let login = qr_login::login(&client, &sessions, payload).await?;
+    session_opened(&app);
+    Ok(login)
+}
+
+/// Starts a browser (SSO) login: opens `tool/mobile/launch.php` in the
+/// system browser. The result arrives later as an `sso://login` or
+/// `sso://error` event once the deep-link callback was handled.
+#[command]
+pub async fn sso_begin(
+    app: AppHandle,
+    client: State<'_, WsClient>,
+    sso: State<'_, SsoState>,
+    site_url: String,
+) -> Result<(), SsoError> {
+    let url = sso.begin(client.policy(), &site_url).await?;
+    app.opener()
+        .open_url(url, None::<&str>)
+        .map_err(|e| SsoError::Browser { message: e.to_string() })
+}
+
+/// Finishes a browser login from a `moodledesktop://token=...` callback and
+/// reports the outcome to the frontend. Called from the deep-link handler.
+pub fn complete_sso_login(app: AppHandle, url: String) {
+    tauri::async_runtime::spawn(async move {
+        let result = app
+            .state::<SsoState>()
+            .complete(&app.state::<WsClient>(), &app.state::<SessionStore>(), &url)
+            .await;
+        let _ = match result {
+            Ok(login) => {
+                session_opened(&app);
+                app.emit(sso::LOGIN_EVENT, login)
+            }
+            Err(err) => app.emit(sso::ERROR_EVENT, err),
+        };
+        if let Some(window) = app.get_webview_window("main") {
+            let _ = window.unminimize();
+            let _ = window.set_focus();
+        }
+    });
+}
+
+/// Starts the background check of cached courses via
+/// `core_course_check_updates`, emitting `course://updated` for every course
+/// with changed modules. Sessions Moodle refuses are skipped silently; the
+/// next foreground call reports the expiry.
+pub fn start_course_update_checks(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            tokio::time::sleep(course_updates::CHECK_INTERVAL).await;
+            let client = app.state::<WsClient>();
+            let cache = app.state::<ResponseCache>();
+            // One check per account, even with several open handles.
+            let mut seen = std::collections::HashSet::new();
+            for (handle, session) in app.state::<SessionStore>().all() {
+                let reachable = app.state::<ConnectivityMonitor>().is_online(session.site_url());
+                if !reachable || !seen.insert(session.scope().to_string()) {
+                    continue;
+                }
+                let Some(key) = data_key(&app, &session) else {
+                    continue;
+                };
+                let Ok(updates) =
+                    course_updates::check_session(&client, &cache, &key, &handle, &session).await
+                else {
+                    continue;
+                };
+                for update in updates {
+                    let _ = app.emit(course_updates::UPDATED_EVENT, update);
+                }
+            }
+        }
+    });
+}
+
+/// Starts probing every configured site (vault accounts and open sessions)
+/// and emits `connectivity://changed` on every status change. A site coming
+/// back online replays the outbox.
+pub fn start_connectivity_monitor(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            check_connectivity(&app).await;
+            tokio::time::sleep(connectivity::CHECK_INTERVAL).await;
+        }
+    });
+}
+
+/// One round of connectivity probes; returns the status changes.
+async fn check_connectivity(app: &AppHandle) -> Vec<StatusChange> {
+    let mut sites: Vec<String> =
+        app.state::<Vault>().list().into_iter().map(|account| account.site_url).collect();
+    sites.extend(
+        app.state::<SessionStore>()
+            .all()
+            .into_iter()
+            .map(|(_, session)| session.site_url().to_string()),
+    );
+    sites.sort();
+    sites.dedup();
+
+    let changes = app
+        .state::<ConnectivityMonitor>()
+        .check(&app.state::<WsClient>(), &sites)
+        .await;
+    for change in &changes {
+        let _ = app.emit(connectivity::CHANGED_EVENT, change);
+    }
+    if changes.iter().any(|c| c.status == SiteStatus::Online && c.previous.is_some()) {
+        let _ = replay_outbox(app).await;
+        app.state::<DownloadManager>().pump();
+    }
+    changes
+}
+
+/// Last known status of every probed site.
+#[command]
+pub fn connectivity_status(
+    connectivity: State<'_, ConnectivityMonitor>,
+) -> HashMap<String, SiteStatus> {
+    connectivity.snapshot()
+}
+
+/// Probes all configured sites now, e.g. when the OS reports a network
+/// change. Returns the current status of every site.
+#[command]
+pub async fn connectivity_check(app: AppHandle) -> HashMap<String, SiteStatus> {
+    check_connectivity(&app).await;
+    app.state::<ConnectivityMonitor>().snapshot()
+}
+
+/// Returns the token-free description of a session.
+#[command]
+pub fn session_info(
+    sessions: State<'_, SessionStore>,
+    session: SessionHandle,
+) -> Result<SessionInfo, WsError> {
+    Ok(sessions.info(&session)?)
+}
+
+/// Forgets a session and its token.
+#[command]
+pub fn session_close(sessions: State<'_, SessionStore>, session: SessionHandle) {
+    sessions.close(&session);
+}
+
+/// Lists stored accounts (without secrets) for the account switcher.
+#[command]
+pub fn vault_list_accounts(vault: State<'_, Vault>) -> Vec<StoredAccount> {
+    vault.list()
+}
+
+/// Returns one stored account (without secrets).
+#[command]
+pub fn vault_get_account(vault: State<'_, Vault>, id: String) -> Result<StoredAccount, VaultError> {
+    vault.get(&id)
+}
+
+/// Stores the account behind `session` in the vault, sealing its token.
+/// `account` supplies the profile details; its id and site must match the
+/// session's account.
+#[command]
+pub fn vault_add_account(
+    vault: State<'_, Vault>,
+    keys: State<'_, KeyRing>,
+    sessions: State<'_, SessionStore>,
+    session: SessionHandle,
+    account: StoredAccount,
+) -> Result<(), VaultError> {
+    let session = sessions.get(&session).map_err(|_| VaultError::UnknownSession)?;
+    // Data sealed before the account was stored stays readable.
+    vault.add(account, &session, keys.get(session.scope()).as_ref())
+}
+
+/// Removes a stored account and its secrets. This destroys the account's
+/// data key, so its cached data, queued writes and download index can no
+/// longer be decrypted; they are dropped as well, like the account's cached
+/// pluginfile media. Downloaded files stay.
+#[command]
+pub fn vault_remove_account(
+    vault: State<'_, Vault>,
+    keys: State<'_, KeyRing>,
+    cache: State<'_, ResponseCache>,
+    pluginfiles: State<'_, PluginfileCache>,
+    outbox: State<'_, Outbox>,
+    downloads: State<'_, DownloadManager>,
+    id: String,
+) -> Result<(), VaultError> {
+    // Before the key is gone: part files are found through the index.
+    let _ = downloads.purge(&id);
+    vault.remove(&id)?;
+    keys.forget(&id);
+    let _ = cache.clear(Some(&id));
+    let _ = pluginfiles.purge(&id);
+    let _ = outbox.purge(&id);
+    Ok(())
+}
+
+/// Opens a session for a stored account (account switch / app start).
+#[command]
+pub async fn vault_open_session(
+    app: AppHandle,
+    client: State<'_, WsClient>,
+    vault: State<'_, Vault>,
+    sessions: State<'_, SessionStore>,
+    id: String,
+) -> Result<SessionInfo, VaultError> {
+    let session = vault.session_for(client.policy(), &id).await?;
+    let handle = sessions.open(session.clone());
+    session_opened(&app);
+    Ok(session.info(&handle))
+}
+
+/// Starts the work that waited for a session of the account: downloads and
+/// write calls queued offline or in an earlier run. Accounts are restored
+/// through [`vault_open_session`] at app start, so this also replays what
+/// was left over from the last run.
+fn session_opened(app: &AppHandle) {
+    app.state::<DownloadManager>().pump();
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let _ = replay_outbox(&app).await;
+    });
+}
+
+/// Why the stored vault could not be opened (e.g. a corrupt file or a
+/// missing `vault.key`); `None` if it was. Until then accounts are only kept
+/// for this run.
+#[command]
+pub fn vault_open_error(vault: State<'_, Vault>) -> Option<VaultError> {
+    vault.open_error().cloned()
+}
+
+/// Whether the vault is waiting for its passphrase.
+#[command]
+pub fn vault_is_locked(vault: State<'_, Vault>) -> bool {
+    vault.is_locked()
+}
+
+/// Unlocks a passphrase-protected vault.
+#[command]
+pub fn vault_unlock(vault: State<'_, Vault>, passphrase: String) -> Result<(), VaultError> {
+    vault.unlock(&Zeroizing::new(passphrase))
+}
+
+/// Sets, changes or removes the optional vault passphrase.
+#[command]
+pub fn vault_set_passphrase(
+    vault: State<'_, Vault>,
+    passphrase: Option<String>,
+) -> Result<(), VaultError> {
+    let passphrase = passphrase.map(Zeroizing::new);
+    vault.set_passphrase(passphrase.as_deref().map(String::as_str))
+}
+
+/// Calls a Moodle Web Service function through the native REST client.
+///
+/// The token of `session` is attached server-side. `params` is flattened
+/// into Moodle's `key[0][field]` form; Moodle exceptions are returned as a
+/// typed [`WsError`]. If Moodle rejects the token, a `session://expired`
+/// event is emitted as well.
+///
+/// `priority` defaults to `user`; prefetch should pass `background`.
+/// Successful write calls evict the cached reads they made outdated.
+#[command]
+pub async fn moodle_call(
+    app: AppHandle,
+    client: State<'_, WsClient>,
+    sessions: State<'_, SessionStore>,
+    session: SessionHandle,
+    wsfunction: String,
+    params: Option<serde_json::Value>,
+    priority: Option<Priority>,
+) -> Result<serde_json::Value, WsError> {
+    let handle = session;
+    let session = sessions.get(&handle)?;
+    let params = params.unwrap_or_default();
+    let result = client
+        .call_with_priority(&session, &wsfunction, &params, priority.unwrap_or_default())
+        .await;
+    match &result {
+        Ok(_) => evict_dependents(&app, &session, &wsfunction, &params),
+        Err(err) => report_expiry(&app, &handle, &session, err),
+    }
+    result
+}
+
+/// Calls several WS functions in one request, falling back to parallel
+/// individual calls on sites without `tool_mobile_call_external_functions`.
+///
+/// Returns one `{status: "ok", data}` or `{status: "error", error}` entry
+/// per call, in order.
+#[command]
+pub async fn moodle_call_batch(
+    app: AppHandle,
+    client: State<'_, WsClient>,
+    sessions: State<'_, SessionStore>,
+    session: SessionHandle,
+    calls: Vec<BatchCall>,
+    priority: Option<Priority>,
+) -> Result<Vec<BatchResponse>, WsError> {
+    let handle = session;
+    let session = sessions.get(&handle)?;
+    let result = client
+        .call_batch(&session, &calls, priority.unwrap_or_default())
+        .await;
+    let first_error = match &result {
+        Ok(responses) => responses.iter().find_map(|r| match r {
+            BatchResponse::Error { error } if error.session_expiry().is_some() => Some(error),
+            _ => None,
+        }),
+        Err(err) => Some(err),
+    };
+    if let Some(err) = first_error {
+        report_expiry(&app, &handle, &session, err);
+    }
+    if let Ok(responses) = &result {
+        for (call, response) in calls.iter().zip(responses) {
+            if let BatchResponse::Ok { .. } = response {
+                evict_dependents(&app, &session, &call.wsfunction, &call.params);
+            }
+        }
+    }
+    result
+}
+
+/// Uploads a file into the user's draft area (`webservice/upload.php`).
+///
+/// The file is the raw request body; the `x-session` handle, the
+/// URI-encoded `x-filename` and the optional `x-itemid` come as headers.
+/// Returns Moodle's description of the stored files.
+#[command]
+pub async fn upload_draft_file(
+    app: AppHandle,
+    client: State<'_, WsClient>,
+    sessions: State<'_, SessionStore>,
+    request: tauri::ipc::Request<'_>,
+) -> Result<serde_json::Value, WsError> {
+    let header = |name: &str| request.headers().get(name).and_then(|v| v.to_str().ok());
+    let invalid = |message: &str| WsError::InvalidResponse { message: message.into() };
+
+    let handle = header("x-session").ok_or_else(|| invalid("missing session"))?.to_string();
+    let filename = header("x-filename")
+        .map(|name| percent_encoding::percent_decode_str(name).decode_utf8_lossy().into_owned())
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| invalid("missing file name"))?;
+    let itemid = match header("x-itemid") {
+        Some(id) => id.parse().map_err(|_| invalid("invalid item id"))?,
+        None => 0,
+    };
+    let tauri::ipc::InvokeBody::Raw(bytes) = request.body() else {
+        return Err(invalid("expected a raw file body"));
+    };
+
+    let session = sessions.get(&handle)?;
+    let result = client.upload_draft(&session, &filename, bytes, itemid).await;
+    if let Err(err) = &result {
+        report_expiry(&app, &handle, &session, err);
+    }
+    result
+}
+
+/// Drops cached reads made outdated by a successful write call. A cache
+/// failure must not turn the successful call into an error.
+fn evict_dependents(
+    app: &AppHandle,
+    session: &Session,
+    function: &str,
+    params: &serde_json::Value,
+) {
+    let key = data_key(app, session);
+    let cache = app.state::<ResponseCache>();
+    let _ = cache.invalidate(session.scope(), key.as_ref(), function, params);
+}
+
+/// The data key of `session`'s account, `None` while the vault is locked.
+fn data_key(app: &AppHandle, session: &Session) -> Option<DataKey> {
+    app.state::<KeyRing>().key_for(&app.state::<Vault>(), session).ok()
+}
+
+/// The data key of an account scope, whether or not it has an open session.
+fn scope_key(app: &AppHandle, scope: &str) -> Option<DataKey> {
+    let sessions = app.state::<SessionStore>().all();
+    match sessions.into_iter().find(|(_, session)| session.scope() == scope) {
+        Some((_, session)) => data_key(app, &session),
+        None => app.state::<KeyRing>().key_for_scope(&app.state::<Vault>(), scope),
+    }
+}
+
+/// Emits `session://expired` if `err` means the session is no longer usable.
+fn report_expiry(app: &AppHandle, handle: &str, session: &Session, err: &WsError) {
+    if let Some(reason) = err.session_expiry() {
+        let _ = app.emit(session::EXPIRED_EVENT, session.expired(handle, reason));
+    }
+}
+
+/// Builds the capability matrix of a session's site: which functions the
+/// token may call, the Moodle release and the function to use per feature.
+#[command]
+pub async fn site_capabilities(
+    app: AppHandle,
+    client: State<'_, WsClient>,
+    sessions: State<'_, SessionStore>,
+    session: SessionHandle,
+) -> Result<CapabilityMatrix, WsError> {
+    let handle = session;
+    let session = sessions.get(&handle)?;
+    let info = client
+        .call(&session, "core_webservice_get_site_info", &serde_json::Value::Null)
+        .await
+        .inspect_err(|err| report_expiry(&app, &handle, &session, err))?;
+    let info: SiteInfo = serde_json::from_value(info)
+        .map_err(|e| WsError::InvalidResponse { message: e.to_string() })?;
+    Ok(CapabilityMatrix::from_site_info(&info))
+}
+
+/// Returns a cached WS response for the session's account. Expired entries
+/// are only returned (with `stale: true`) if `allow_stale` is set; it
+/// defaults to whether the connectivity monitor considers the site
+/// unreachable.
+#[command]
+#[allow(clippy::too_many_arguments)]
+pub fn cache_get(
+    cache: State<'_, ResponseCache>,
+    keys: State<'_, KeyRing>,
+    vault: State<'_, Vault>,
+    connectivity: State<'_, ConnectivityMonitor>,
+    sessions: State<'_, SessionStore>,
+    session: SessionHandle,
+    wsfunction: String,
+    params: Option<serde_json::Value>,
+    allow_stale: Option<bool>,
+) -> Result<Option<CachedResponse>, CacheError> {
+    let session = sessions.get(&session)?;
+    let params = params.unwrap_or_default();
+    let allow_stale = allow_stale.unwrap_or_else(|| !connectivity.is_online(session.site_url()));
+    let key = keys.key_for(&vault, &session)?;
+    cache.get(session.scope(), &key, &wsfunction, &params, allow_stale)
+}
+
+/// Stores a WS response for the session's account.
+#[command]
+#[allow(clippy::too_many_arguments)]
+pub fn cache_put(
+    cache: State<'_, ResponseCache>,
+    keys: State<'_, KeyRing>,
+    vault: State<'_, Vault>,
+    sessions: State<'_, SessionStore>,
+    session: SessionHandle,
+    wsfunction: String,
+    params: Option<serde_json::Value>,
+    data: serde_json::Value,
+) -> Result<(), CacheError> {
+    let session = sessions.get(&session)?;
+    let params = params.unwrap_or_default();
+    let key = keys.key_for(&vault, &session)?;
+    cache.put(session.scope(), &key, &wsfunction, &params, &data)
+}
+
+/// Returns entry count and byte usage of the response cache.
+#[command]
+pub fn cache_stats(cache: State<'_, ResponseCache>) -> Result<CacheStats, CacheError> {
+    cache.stats()
+}
+
+/// Clears the cached responses of one session's account, or all of them.
+#[command]
+pub fn cache_clear(
+    cache: State<'_, ResponseCache>,
+    sessions: State<'_, SessionStore>,
+    session: Option<SessionHandle>,
+) -> Result<(), CacheError> {
+    match session {
+        Some(handle) => cache.clear(Some(sessions.get(&handle)?.scope())),
+        None => cache.clear(None),
+    }
+}
+
+/// Queues a write call of the session's account for when the site is
+/// reachable again.
+#[command]
+pub fn outbox_enqueue(
+    app: AppHandle,
+    outbox: State<'_, Outbox>,
+    sessions: State<'_, SessionStore>,
+    session: SessionHandle,
+    wsfunction: String,
+    params: Option<serde_json::Value>,
+) -> Result<OutboxItem, OutboxError> {
+    let session = sessions.get(&session)?;
+    let key = data_key(&app, &session).ok_or(OutboxError::Locked)?;
+    outbox.enqueue(&session, &key, &wsfunction, &params.unwrap_or_default())
+}
+
+/// Lists queued items of the session's account, or of all accounts.
+#[command]
+pub fn outbox_list(
+    app: AppHandle,
+    outbox: State<'_, Outbox>,
+    sessions: State<'_, SessionStore>,
+    session: Option<SessionHandle>,
+) -> Result<Vec<OutboxItem>, OutboxError> {
+    let key = |scope: &str| scope_key(&app, scope);
+    match session {
+        Some(handle) => outbox.list(Some(sessions.get(&handle)?.scope()), key),
+        None => outbox.list(None, key),
+    }
+}
+
+/// Replaces the parameters of a queued item and marks it pending again.
+#[command]
+pub fn outbox_update(
+    app: AppHandle,
+    outbox: State<'_, Outbox>,
+    id: i64,
+    params: serde_json::Value,
+) -> Result<OutboxItem, OutboxError> {
+    outbox.update(id, &params, |scope| scope_key(&app, scope))
+}
+
+/// Drops a queued item without sending it.
+#[command]
+pub fn outbox_discard(outbox: State<'_, Outbox>, id: i64) -> Result<(), OutboxError> {
+    outbox.discard(id)
+}
+
+/// Sends all pending items of accounts with an open session, in order.
+#[command]
+pub async fn outbox_replay(app: AppHandle) -> Result<ReplayReport, OutboxError> {
+    replay_outbox(&app).await
+}
+
+/// Replays the outbox and emits `outbox://replayed` if anything was sent or
+/// rejected.
+pub async fn replay_outbox(app: &AppHandle) -> Result<ReplayReport, OutboxError> {
+    let sessions = app.state::<SessionStore>().all();
+    let report = app
+        .state::<Outbox>()
+        .replay(
+            &app.state::<WsClient>(),
+            &app.state::<ResponseCache>(),
+            |session| data_key(app, session),
+            &sessions,
+        )
+        .await?;
+    if report.has_changes() {
+        let _ = app.emit(outbox::REPLAYED_EVENT, &report);
+    }
+    Ok(report)
+}
+
+/// Gives the download manager access to open sessions, data keys and the
+/// `download://progress` event.
+pub struct AppDownloadHost(pub AppHandle);
+
+impl DownloadHost for AppDownloadHost {
+    fn session(&self, scope: &str) -> Option<Session> {
+        let sessions = self.0.state::<SessionStore>().all();
+        sessions.into_iter().map(|(_, session)| session).find(|s| s.scope() == scope)
+    }
+
+    fn data_key(&self, scope: &str) -> Option<DataKey> {
+        scope_key(&self.0, scope)
+    }
+
+    fn default_root(&self) -> Option<PathBuf> {
+        self.0.path().download_dir().ok()
+    }
+
+    fn progress(&self, progress: &DownloadProgress) {
+        let _ = self.0.emit(downloads::PROGRESS_EVENT, progress);
+    }
+}
+
+/// Queues a Moodle file (`fileurl` from a WS response) for download into
+/// the download root of the account or course. Progress is reported as `download://progress`
+/// events keyed by the returned download's id.
+#[command]
+pub fn download_enqueue(
+    sessions: State<'_, SessionStore>,
+    downloads: State<'_, DownloadManager>,
+    session: SessionHandle,
+    file_url: String,
+    filename: String,
+    course_id: Option<u64>,
+    module_id: Option<u64>,
+) -> Result<DownloadItem, DownloadError> {
+    let session = sessions.get(&session)?;
+    let dir = downloads.root(session.scope(), course_id)?;
+    let request = DownloadRequest {
+        file_url,
+        filename,
+        dir,
+        course_id,
+        module_id,
+        replace: false,
+    };
+    downloads.enqueue(&session, request)
+}
+
+/// Queues every resource, folder and assignment intro file of a course, or
+/// of one section, into `<root>/<shortname>/<NN section>/<module>/` below
+/// the course's download root.
+/// The report lists queued, already present, skipped and refused files.
+#[command]
+pub async fn download_course(
+    app: AppHandle,
+    session: SessionHandle,
+    course_id: u64,
+    section_id: Option<u64>,
+) -> Result<CourseDownloadReport, CourseDownloadError> {
+    let handle = session;
+    let session = app.state::<SessionStore>().get(&handle)?;
+    let root = app.state::<DownloadManager>().root(session.scope(), Some(course_id))?;
+    let report = course_download::download_course(
+        &app.state::<WsClient>(),
+        &app.state::<DownloadManager>(),
+        &session,
+        &root,
+        course_id,
+        section_id,
+    )
+    .await;
+    if let Err(CourseDownloadError::Ws { error }) = &report {
+        report_expiry(&app, &handle, &session, error);
+    }
+    report
+}
+
+/// Brings the mirror of a course in its download root up to date: only
+/// new, changed or locally missing files are queued. With
+/// `archive_removed`, files no longer in the course are moved to
+/// `_archived`.
+#[command]
+pub async fn sync_course(
+    app: AppHandle,
+    session: SessionHandle,
+    course_id: u64,
+    archive_removed: Option<bool>,
+) -> Result<SyncReport, CourseDownloadError> {
+    let handle = session;
+    let session = app.state::<SessionStore>().get(&handle)?;
+    let root = app.state::<DownloadManager>().root(session.scope(), Some(course_id))?;
+    let report = course_sync::sync_course(
+        &app.state::<WsClient>(),
+        &app.state::<DownloadManager>(),
+        &session,
+        &root,
+        course_id,
+        archive_removed.unwrap_or(false),
+    )
+    .await;
+    if let Err(CourseDownloadError::Ws { error }) = &report {
+        report_expiry(&app, &handle, &session, error);
+    }
+    report
+}
+
+/// Lists downloads of all accounts, or of `session`'s account.
+#[command]
+pub fn downloads_list(
+    sessions: State<'_, SessionStore>,
+    downloads: State<'_, DownloadManager>,
+    session: Option<SessionHandle>,
+) -> Result<Vec<DownloadItem>, DownloadError> {
+    let scope = session.map(|handle| sessions.get(&handle)).transpose()?;
+    downloads.list(scope.as_ref().map(Session::scope))
+}
+
+/// Pauses a queued or running download.
+#[command]
+pub fn download_pause(
+    downloads: State<'_, DownloadManager>,
+    id: String,
+) -> Result<(), DownloadError> {
+    downloads.pause(&id)
+}
+
+/// Continues a paused or failed download where it stopped.
+#[command]
+pub fn download_resume(
+    downloads: State<'_, DownloadManager>,
+    id: String,
+) -> Result<(), DownloadError> {
+    downloads.resume(&id)
+}
+
+/// Cancels a download and deletes its partial file.
+#[command]
+pub fn download_cancel(
+    downloads: State<'_, DownloadManager>,
+    id: String,
+) -> Result<(), DownloadError> {
+    downloads.cancel(&id)
+}
+
+/// Sets how many downloads run at the same time; returns the value used.
+#[command]
+pub fn downloads_set_parallelism(
+    downloads: State<'_, DownloadManager>,
+    parallelism: usize,
+) -> Result<usize, DownloadError> {
+    downloads.set_parallelism(parallelism)
+}
+
+/// The download roots of `session`'s account.
+#[command]
+pub fn download_roots(
+    sessions: State<'_, SessionStore>,
+    downloads: State<'_, DownloadManager>,
+    session: SessionHandle,
+) -> Result<DownloadRoots, DownloadError> {
+    downloads.roots(sessions.get(&session)?.scope())
+}
+
+/// Lets the user pick the download root of `session`'s account, or of one
+/// of its courses, in a folder dialog. Returns `None` if the dialog was
+/// cancelled. With `move_files`, files already downloaded are moved to the
+/// new root and their downloads updated.
+///
+/// The folder is never taken from the webview: whatever it names becomes
+/// openable through `open_file`.
+#[command]
+pub async fn download_root_pick(
+    app: AppHandle,
+    sessions: State<'_, SessionStore>,
+    downloads: State<'_, DownloadManager>,
+    session: SessionHandle,
+    course_id: Option<u64>,
+    move_files: Option<bool>,
+) -> Result<Option<RootChange>, DownloadError> {
+    let scope = sessions.get(&session)?.scope().to_string();
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    let mut dialog = app.dialog().file().set_title("Download-Ordner wählen");
+    if let Ok(current) = downloads.root(&scope, course_id) {
+        dialog = dialog.set_directory(current);
+    }
+    dialog.pick_folder(move |folder| {
+        let _ = tx.send(folder);
+    });
+    let Some(folder) = rx.await.ok().flatten() else {
+        return Ok(None);
+    };
+    let path = folder
+        .into_path()
+        .map_err(|e| DownloadError::Io { message: e.to_string() })?;
+    set_root(&app, &downloads, scope, course_id, Some(path), move_files).await.map(Some)
+}
+
+/// Resets the download root of `session`'s account or one of its courses:
+/// the course uses the account's root again, the account the Downloads
+/// folder. With `move_files`, downloaded files are moved along.
+#[command]
+pub async fn download_root_reset(
+    app: AppHandle,
+    sessions: State<'_, SessionStore>,
+    downloads: State<'_, DownloadManager>,
+    session: SessionHandle,
+    course_id: Option<u64>,
+    move_files: Option<bool>,
+) -> Result<RootChange, DownloadError> {
+    let scope = sessions.get(&session)?.scope().to_string();
+    set_root(&app, &downloads, scope, course_id, None, move_files).await
+}
+
+/// Changes a root off the async runtime, since moving files may take long.
+async fn set_root(
+    app: &AppHandle,
+    downloads: &DownloadManager,
+    scope: String,
+    course_id: Option<u64>,
+    path: Option<PathBuf>,
+    move_files: Option<bool>,
+) -> Result<RootChange, DownloadError> {
+    let manager = downloads.clone();
+    let change = tauri::async_runtime::spawn_blocking(move || {
+        manager.set_root(&scope, course_id, path, move_files.unwrap_or(false))
+    })
+    .await
+    .map_err(|e| DownloadError::Io { message: e.to_string() })??;
+    // Lets the frontend check whether downloaded files still exist.
+    let _ = app.fs_scope().allow_directory(&change.root, true);
+    Ok(change)
+}
+
+/// Rewrites pluginfile URLs in rendered HTML to `moodle-file://` URLs bound
+/// to `session`, so no token ever ends up in the DOM.
+#[command]
+pub fn rewrite_pluginfile_urls(
+    sessions: State<'_, SessionStore>,
+    session: SessionHandle,
+    html: String,
+) -> Result<String, WsError> {
+    let site_url = sessions.get(&session)?.site_url().to_string();
+    Ok(pluginfile::rewrite_html(&html, &session, &site_url))
+}
+
+/// Deletes all cached pluginfile content.
+#[command]
+pub fn clear_pluginfile_cache(cache: State<'_, PluginfileCache>) -> Result<(), String> {
+    cache
+        .clear()
+        .map_err(|e| format!("Failed to clear file cache: {e}"))
+}
+
 /// Opens a file using the system default application.
 ///
-/// Security: only files inside the user's Downloads folder are allowed.
+/// Security: only files inside a download root are allowed.
 #[command]
-pub fn open_file(path: String) -> Result<(), String> {
+pub fn open_file(downloads: State<'_, DownloadManager>, path: String) -> Result<(), String> {
     let target = std::path::Path::new(&path);
 
     if !target.is_file() {
         return Err("File not found".into());
     }
 
-    // Canonicalize to resolve symlinks / relative segments
-    let canonical = target
-        .canonicalize()
-        .map_err(|e| format!("Cannot resolve path: {e}"))?;
-
-    // Determine Downloads directory from %USERPROFILE%
-    let user_profile = std::env::var("USERPROFILE")
-        .map_err(|_| "Cannot determine user profile directory".to_string())?;
-    let downloads = std::path::PathBuf::from(&user_profile).join("Downloads");
-    let downloads_canonical = downloads
-        .canonicalize()
-        .map_err(|e| format!("Cannot resolve Downloads directory: {e}"))?;
-
-    if !canonical.starts_with(&downloads_canonical) {
-        return Err("File must be inside the Downloads folder".into());
-    }
+    // Resolves symlinks / relative segments before checking the roots
+    let canonical = downloads.check_path(target).map_err(|e| e.to_string())?;
 
     #[cfg(target_os = "windows")]
     {
@@ -46,10 +919,27 @@ pub fn open_file(path: String) -> Result<(), String> {
             .spawn()
             .map_err(|e| format!("Failed to open file: {e}"))?;
     }
+    #[cfg(not(target_os = "windows"))]
+    let _ = canonical;
 
     Ok(())
 }
 
+/// Shows a downloaded file selected in its folder.
+///
+/// Security: only files inside a download root are allowed.
+#[command]
+pub fn reveal_file(
+    app: AppHandle,
+    downloads: State<'_, DownloadManager>,
+    path: String,
+) -> Result<(), String> {
+    let canonical = downloads.check_path(std::path::Path::new(&path)).map_err(|e| e.to_string())?;
+    app.opener()
+        .reveal_item_in_dir(&canonical)
+        .map_err(|e| format!("Failed to show file: {e}"))
+}
+
 /// Applies a window background effect (Mica, Acrylic, or None).
 #[command]
 pub fn set_window_effect(window: tauri::WebviewWindow, effect: String) -> Result<(), String> {
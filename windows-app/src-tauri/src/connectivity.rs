@@ -0,0 +1,272 @@
+//! Per-site connectivity monitor.
+//!
+//! `navigator.onLine` only says a network adapter is up; it stays `true`
+//! behind a captive portal or a broken VPN. The monitor instead probes every
+//! configured site with the tokenless public config call and classifies the
+//! result. When a site cannot be reached at all, a known reference page
+//! (the one Windows uses for its own network indicator) tells whether the
+//! machine is offline, caught by a captive portal, or only the site is down.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures_util::future::join_all;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::probe::PUBLIC_CONFIG_FUNCTION;
+use crate::response_cache;
+use crate::site_url;
+use crate::ws::{WsClient, AJAX_NOLOGIN_ENDPOINT};
+
+/// Event emitted with a [`StatusChange`] whenever a site's status changes.
+pub const CHANGED_EVENT: &str = "connectivity://changed";
+
+/// Time between two rounds of probes.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Timeout for a single probe; far below the WS timeout so a dead site is
+/// noticed quickly.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Reference page used when a site does not answer.
+pub const REFERENCE_URL: &str = "http://www.msftconnecttest.com/connecttest.txt";
+
+/// Exact body of [`REFERENCE_URL`]; anything else comes from a portal.
+const REFERENCE_BODY: &str = "Microsoft Connect Test";
+
+/// How a site can be reached right now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SiteStatus {
+    /// The site answers WS calls.
+    Online,
+    /// No network at all.
+    Offline,
+    /// A hotspot or proxy intercepts requests (login page, redirect).
+    CaptivePortal,
+    /// The network works but the site does not answer properly.
+    SiteDown,
+    /// The site is in maintenance mode.
+    Maintenance,
+}
+
+/// Payload of [`CHANGED_EVENT`].
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusChange {
+    pub site_url: String,
+    pub status: SiteStatus,
+    /// `None` on the first probe of a site.
+    pub previous: Option<SiteStatus>,
+    /// When the probe finished (ms since the epoch).
+    pub checked_at: i64,
+}
+
+/// Result of probing one site: either a verdict, or "no answer" which
+/// needs the reference check to tell offline from site-down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Probe {
+    Status(SiteStatus),
+    Unreachable,
+}
+
+/// Classifies a public config answer; also tells maintenance pages from
+/// other 503s elsewhere.
+pub(crate) fn classify_response(status: u16, body: &[u8]) -> SiteStatus {
+    let text = String::from_utf8_lossy(body).to_lowercase();
+    if status == 503 && text.contains("maintenance") {
+        return SiteStatus::Maintenance;
+    }
+    if status >= 400 {
+        return SiteStatus::SiteDown;
+    }
+    // Portals answer with their own HTML login page.
+    let Ok(data) = serde_json::from_slice::<Value>(body) else {
+        return SiteStatus::CaptivePortal;
+    };
+    let first = data.get(0).unwrap_or(&data);
+    // The AJAX endpoint nests the exception, a plain error has it on top.
+    let exception = first.get("exception").filter(|e| e.is_object()).unwrap_or(first);
+    let errorcode = exception.get("errorcode").and_then(Value::as_str);
+    let maintenance = first
+        .pointer("/data/maintenanceenabled")
+        .is_some_and(|v| v.as_bool().unwrap_or(false) || v.as_i64().unwrap_or(0) != 0);
+    if errorcode == Some("sitemaintenance") || maintenance {
+        SiteStatus::Maintenance
+    } else {
+        // Any JSON from Moodle, even an error for an old site without the
+        // function, means the site is reachable.
+        SiteStatus::Online
+    }
+}
+
+/// Sends the tokenless public config request to one site.
+async fn probe_site(client: &WsClient, site_url: &str) -> Probe {
+    let url = format!(
+        "{}/{AJAX_NOLOGIN_ENDPOINT}?info={PUBLIC_CONFIG_FUNCTION}",
+        site_url.trim_end_matches('/')
+    );
+    if client.check_url(&url).is_err() {
+        return Probe::Status(SiteStatus::SiteDown);
+    }
+    let request = json!([{ "index": 0, "methodname": PUBLIC_CONFIG_FUNCTION, "args": {} }]);
+    let response = client.http().post(&url).json(&request).timeout(PROBE_TIMEOUT).send().await;
+    let response = match response {
+        Ok(response) => response,
+        // A portal that redirects into the local network or hijacks DNS
+        // trips the SSRF guard.
+        Err(err) if site_url::blocked_cause(&err).is_some() || err.is_redirect() => {
+            return Probe::Status(SiteStatus::CaptivePortal)
+        }
+        Err(_) => return Probe::Unreachable,
+    };
+
+    let expected = reqwest::Url::parse(site_url).ok();
+    if response.url().host_str() != expected.as_ref().and_then(|u| u.host_str()) {
+        return Probe::Status(SiteStatus::CaptivePortal);
+    }
+    let status = response.status().as_u16();
+    match response.bytes().await {
+        Ok(body) => Probe::Status(classify_response(status, &body)),
+        Err(_) => Probe::Unreachable,
+    }
+}
+
+/// Tells offline from captive portal from "only the site is down".
+async fn reference_check(client: &WsClient) -> SiteStatus {
+    let response = client.http().get(REFERENCE_URL).timeout(PROBE_TIMEOUT).send().await;
+    let body = match response {
+        Ok(response) => response.text().await.unwrap_or_default(),
+        Err(err) if site_url::blocked_cause(&err).is_some() || err.is_redirect() => {
+            return SiteStatus::CaptivePortal
+        }
+        Err(_) => return SiteStatus::Offline,
+    };
+    if body.trim() == REFERENCE_BODY {
+        SiteStatus::SiteDown
+    } else {
+        SiteStatus::CaptivePortal
+    }
+}
+
+/// Last known status per site. Held in Tauri managed state.
+#[derive(Default)]
+pub struct ConnectivityMonitor {
+    statuses: Mutex<HashMap<String, SiteStatus>>,
+}
+
+impl ConnectivityMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Last known status of a site, `None` if it was never probed.
+    pub fn status(&self, site_url: &str) -> Option<SiteStatus> {
+        self.statuses.lock().unwrap().get(site_url).copied()
+    }
+
+    /// Whether calls to the site are expected to work. Sites that were
+    /// never probed count as online.
+    pub fn is_online(&self, site_url: &str) -> bool {
+        self.status(site_url).is_none_or(|status| status == SiteStatus::Online)
+    }
+
+    /// Last known status of every probed site.
+    pub fn snapshot(&self) -> HashMap<String, SiteStatus> {
+        self.statuses.lock().unwrap().clone()
+    }
+
+    /// Probes `sites` concurrently and returns the sites whose status changed.
+    pub async fn check(&self, client: &WsClient, sites: &[String]) -> Vec<StatusChange> {
+        let probes = join_all(sites.iter().map(|site| probe_site(client, site))).await;
+        let reference = if probes.contains(&Probe::Unreachable) {
+            Some(reference_check(client).await)
+        } else {
+            None
+        };
+
+        let checked_at = response_cache::now_ms();
+        let mut statuses = self.statuses.lock().unwrap();
+        sites
+            .iter()
+            .zip(probes)
+            .filter_map(|(site, probe)| {
+                let status = match probe {
+                    Probe::Status(status) => status,
+                    Probe::Unreachable => reference.unwrap_or(SiteStatus::Offline),
+                };
+                let previous = statuses.insert(site.clone(), status);
+                (previous != Some(status)).then(|| StatusChange {
+                    site_url: site.clone(),
+                    status,
+                    previous,
+                    checked_at,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::{test_client, MockServer};
+
+    fn classify(status: u16, body: &str) -> SiteStatus {
+        classify_response(status, body.as_bytes())
+    }
+
+    #[test]
+    fn classifies_responses() {
+        let config = r#"[{"error":false,"data":{"sitename":"S","maintenanceenabled":0}}]"#;
+        assert_eq!(classify(200, config), SiteStatus::Online);
+        // An old site without the function still answers with Moodle JSON.
+        let missing = r#"[{"error":true,"exception":{"errorcode":"invalidrecord"}}]"#;
+        assert_eq!(classify(200, missing), SiteStatus::Online);
+        assert_eq!(classify(200, r#"{"errorcode":"invalidparameter"}"#), SiteStatus::Online);
+
+        let portal = "<html><form>Hotspot login</form></html>";
+        assert_eq!(classify(200, portal), SiteStatus::CaptivePortal);
+        assert_eq!(classify(200, ""), SiteStatus::CaptivePortal);
+        assert_eq!(classify(500, "{}"), SiteStatus::SiteDown);
+        assert_eq!(classify(404, "Not found"), SiteStatus::SiteDown);
+        assert_eq!(classify(503, "Service unavailable"), SiteStatus::SiteDown);
+    }
+
+    #[test]
+    fn recognises_maintenance() {
+        let cases = [
+            (503, "<html>This site is undergoing Maintenance</html>"),
+            (200, r#"[{"error":true,"exception":{"errorcode":"sitemaintenance"}}]"#),
+            (200, r#"{"exception":"moodle_exception","errorcode":"sitemaintenance"}"#),
+            (200, r#"[{"error":false,"data":{"maintenanceenabled":1}}]"#),
+            (200, r#"[{"error":false,"data":{"maintenanceenabled":true}}]"#),
+        ];
+        for (status, body) in cases {
+            assert_eq!(classify(status, body), SiteStatus::Maintenance, "{status} {body}");
+        }
+        let off = r#"[{"error":false,"data":{"maintenanceenabled":false}}]"#;
+        assert_eq!(classify(200, off), SiteStatus::Online);
+    }
+
+    #[tokio::test]
+    async fn probes_the_public_config() {
+        let server = MockServer::start(|_| (200, r#"[{"error":false,"data":{}}]"#.into()));
+        let probe = probe_site(&test_client(), &server.url()).await;
+        assert_eq!(probe, Probe::Status(SiteStatus::Online));
+        let request = server.last_request();
+        assert_eq!(request.method, "POST");
+        assert_eq!(
+            request.path,
+            format!("/{AJAX_NOLOGIN_ENDPOINT}?info={PUBLIC_CONFIG_FUNCTION}")
+        );
+        assert!(request.body.contains(r#""methodname":"tool_mobile_get_public_config""#));
+
+        let maintenance = MockServer::start(|_| (503, "Site maintenance".into()));
+        let probe = probe_site(&test_client(), &maintenance.url()).await;
+        assert_eq!(probe, Probe::Status(SiteStatus::Maintenance));
+    }
+}
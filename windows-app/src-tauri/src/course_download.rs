@@ -0,0 +1,605 @@
+//! Bulk download of a whole course or one of its sections.
+//!
+//! Files of resources, folders and assignment intro attachments are queued
+//! with the [`DownloadManager`] into a folder tree that mirrors the course:
+//! `<root>/<course shortname>/<NN section name>/<module name>/<file>`, with
+//! the subfolders of a folder module kept below its module folder. Files
+//! already on disk or already in the queue are not queued twice.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::course_updates::CONTENTS_FUNCTION;
+use crate::downloads::{
+    DownloadError, DownloadItem, DownloadManager, DownloadRequest, DownloadStatus,
+};
+use crate::pluginfile;
+use crate::sanitize;
+use crate::session::{Session, SessionError};
+use crate::ws::{WsClient, WsError};
+
+/// WS function returning the course shortname.
+pub const COURSES_FUNCTION: &str = "core_course_get_courses_by_field";
+
+/// WS function returning assignment intro attachments, which are not part
+/// of the course contents.
+pub const ASSIGNMENTS_FUNCTION: &str = "mod_assign_get_assignments";
+
+/// Module types whose files are downloaded.
+const DOWNLOADABLE_MODULES: [&str; 3] = ["resource", "folder", "assign"];
+
+#[derive(Debug, thiserror::Error, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum CourseDownloadError {
+    #[error("Unknown or closed session")]
+    UnknownSession,
+
+    #[error("Course {course_id} not found")]
+    UnknownCourse { course_id: u64 },
+
+    #[error("Section {section_id} not found in course {course_id}")]
+    UnknownSection { course_id: u64, section_id: u64 },
+
+    /// The course folder already mirrors a course of another site or
+    /// another course with the same shortname.
+    #[error("{folder} belongs to another course")]
+    ForeignFolder { folder: PathBuf },
+
+    #[error("File system error: {message}")]
+    Io { message: String },
+
+    #[error("{error}")]
+    Ws { error: WsError },
+
+    #[error("{error}")]
+    Download { error: DownloadError },
+}
+
+impl From<WsError> for CourseDownloadError {
+    fn from(error: WsError) -> Self {
+        CourseDownloadError::Ws { error }
+    }
+}
+
+impl From<std::io::Error> for CourseDownloadError {
+    fn from(err: std::io::Error) -> Self {
+        CourseDownloadError::Io { message: err.to_string() }
+    }
+}
+
+impl From<DownloadError> for CourseDownloadError {
+    fn from(error: DownloadError) -> Self {
+        CourseDownloadError::Download { error }
+    }
+}
+
+impl From<SessionError> for CourseDownloadError {
+    fn from(err: SessionError) -> Self {
+        match err {
+            SessionError::UnknownSession => CourseDownloadError::UnknownSession,
+        }
+    }
+}
+
+/// A file of the course, placed in the mirrored folder tree.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlannedFile {
+    pub section_id: u64,
+    pub module_id: u64,
+    pub module_name: String,
+    /// Folder relative to the course folder.
+    pub dir: PathBuf,
+    pub filename: String,
+    pub file_url: String,
+    pub filesize: Option<u64>,
+    /// Seconds since the epoch, as reported by Moodle.
+    pub timemodified: Option<i64>,
+}
+
+impl PlannedFile {
+    /// The download of this file into the course folder `folder`.
+    pub fn request(&self, course_id: u64, folder: &Path, replace: bool) -> DownloadRequest {
+        DownloadRequest {
+            file_url: self.file_url.clone(),
+            filename: self.filename.clone(),
+            dir: folder.join(&self.dir),
+            course_id: Some(course_id),
+            module_id: Some(self.module_id),
+            replace,
+        }
+    }
+
+    /// Where the file lands in the course folder `folder`; `None` if the
+    /// folder is too deep for it.
+    pub fn local_path(&self, folder: &Path) -> Option<PathBuf> {
+        let dir = folder.join(&self.dir);
+        sanitize::fit(&dir, &self.filename).map(|name| dir.join(name))
+    }
+
+    pub fn skipped(&self, reason: SkipReason) -> Skipped {
+        Skipped {
+            module_id: self.module_id,
+            module_name: self.module_name.clone(),
+            filename: Some(self.filename.clone()),
+            reason,
+        }
+    }
+}
+
+/// Why a module or file was left out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SkipReason {
+    /// The module type has files, but not ones worth mirroring (pages,
+    /// books, SCORM packages, ...).
+    UnsupportedModule,
+    /// The module is hidden or restricted for the user, or its files could
+    /// not be listed.
+    NotAvailable,
+    /// The file lives on another site.
+    ExternalFile,
+    /// A download of the file is already queued.
+    AlreadyQueued,
+}
+
+/// A module or file that was not queued.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Skipped {
+    pub module_id: u64,
+    pub module_name: String,
+    /// `None` when the whole module was skipped.
+    pub filename: Option<String>,
+    pub reason: SkipReason,
+}
+
+/// A file the download manager refused.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FailedFile {
+    pub file: PlannedFile,
+    pub error: DownloadError,
+}
+
+/// Outcome of queueing a course or section.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CourseDownloadReport {
+    pub course_id: u64,
+    /// The course folder below the download root.
+    pub folder: PathBuf,
+    pub queued: Vec<DownloadItem>,
+    pub already_present: Vec<PlannedFile>,
+    pub skipped: Vec<Skipped>,
+    pub failed: Vec<FailedFile>,
+}
+
+/// The folder of a section: its number, zero-padded so the folders sort
+/// like the course page, and its name.
+fn section_dir(section: &Value) -> PathBuf {
+    let number = section.get("section").and_then(Value::as_u64).unwrap_or_default();
+    let name = section.get("name").and_then(Value::as_str).unwrap_or_default();
+    let name = if name.trim().is_empty() { format!("Section {number}") } else { name.to_string() };
+    PathBuf::from(sanitize::folder_name(&format!("{number:02} {name}")))
+}
+
+/// Files of a module's `contents[]` or `introattachments[]` below `dir`.
+fn planned_files(
+    section_id: u64,
+    (module_id, module_name): (u64, &str),
+    dir: &Path,
+    files: &[Value],
+) -> Vec<PlannedFile> {
+    files
+        .iter()
+        .filter(|file| file.get("type").and_then(Value::as_str).unwrap_or("file") == "file")
+        .filter_map(|file| {
+            let filename = file.get("filename")?.as_str()?;
+            let filepath = file.get("filepath").and_then(Value::as_str).unwrap_or("/");
+            let dir = dir.join(sanitize::relative_path(filepath));
+            Some(PlannedFile {
+                section_id,
+                module_id,
+                module_name: module_name.to_string(),
+                dir,
+                filename: sanitize::file_name(filename),
+                file_url: file.get("fileurl")?.as_str()?.to_string(),
+                filesize: file.get("filesize").and_then(Value::as_u64),
+                timemodified: file.get("timemodified").and_then(Value::as_i64),
+            })
+        })
+        .collect()
+}
+
+/// Lays out the files of `contents` (a `core_course_get_contents`
+/// response), optionally only of one section. `assign_files` maps an
+/// assignment's course module id to its intro attachments; `None` if they
+/// could not be fetched.
+pub fn plan(
+    contents: &Value,
+    section_id: Option<u64>,
+    assign_files: Option<&HashMap<u64, Vec<Value>>>,
+) -> (Vec<PlannedFile>, Vec<Skipped>) {
+    let mut files = Vec::new();
+    let mut skipped = Vec::new();
+    let sections = contents.as_array().into_iter().flatten();
+    for section in sections {
+        let id = section.get("id").and_then(Value::as_u64).unwrap_or_default();
+        if section_id.is_some_and(|wanted| wanted != id) {
+            continue;
+        }
+        let dir = section_dir(section);
+        let modules = section.get("modules").and_then(Value::as_array).into_iter().flatten();
+        for module in modules {
+            let Some(module_id) = module.get("id").and_then(Value::as_u64) else {
+                continue;
+            };
+            let name = module.get("name").and_then(Value::as_str).unwrap_or_default();
+            let modname = module.get("modname").and_then(Value::as_str).unwrap_or_default();
+            let contents = module.get("contents").and_then(Value::as_array);
+            let skip = |reason| Skipped {
+                module_id,
+                module_name: name.to_string(),
+                filename: None,
+                reason,
+            };
+
+            let module_files = match modname {
+                "assign" => assign_files.map(|files| files.get(&module_id)),
+                _ if DOWNLOADABLE_MODULES.contains(&modname) => Some(contents),
+                // Labels, forums etc. have nothing to download.
+                _ => {
+                    if contents.is_some_and(|c| !c.is_empty()) {
+                        skipped.push(skip(SkipReason::UnsupportedModule));
+                    }
+                    continue;
+                }
+            };
+            let visible = module.get("uservisible").and_then(Value::as_bool).unwrap_or(true);
+            let Some(module_files) = module_files.filter(|_| visible) else {
+                skipped.push(skip(SkipReason::NotAvailable));
+                continue;
+            };
+            let module_dir = dir.join(sanitize::folder_name(name));
+            let module_files = module_files.map(Vec::as_slice).unwrap_or_default();
+            files.extend(planned_files(id, (module_id, name), &module_dir, module_files));
+        }
+    }
+    (files, skipped)
+}
+
+/// Intro attachments of the course's assignments by course module id.
+async fn assign_files(
+    client: &WsClient,
+    session: &Session,
+    course_id: u64,
+) -> Result<HashMap<u64, Vec<Value>>, WsError> {
+    let params = json!({ "courseids": [course_id] });
+    let response = client.call(session, ASSIGNMENTS_FUNCTION, &params).await?;
+    let courses = response.get("courses").and_then(Value::as_array).into_iter().flatten();
+    Ok(courses
+        .filter_map(|course| course.get("assignments")?.as_array())
+        .flatten()
+        .filter_map(|assignment| {
+            let cmid = assignment.get("cmid")?.as_u64()?;
+            let files = assignment.get("introattachments")?.as_array()?.clone();
+            Some((cmid, files))
+        })
+        .collect())
+}
+
+/// The shortname of a course.
+async fn shortname(
+    client: &WsClient,
+    session: &Session,
+    course_id: u64,
+) -> Result<String, CourseDownloadError> {
+    let params = json!({ "field": "id", "value": course_id });
+    let response = client.call(session, COURSES_FUNCTION, &params).await?;
+    response
+        .pointer("/courses/0/shortname")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or(CourseDownloadError::UnknownCourse { course_id })
+}
+
+/// The files of a course (or one section), ready to be queued.
+#[derive(Debug)]
+pub struct CoursePlan {
+    /// Name of the course folder.
+    pub folder_name: String,
+    pub files: Vec<PlannedFile>,
+    pub skipped: Vec<Skipped>,
+}
+
+/// Fetches the course (or one section) and lays out its files.
+pub async fn fetch_plan(
+    client: &WsClient,
+    session: &Session,
+    course_id: u64,
+    section_id: Option<u64>,
+) -> Result<CoursePlan, CourseDownloadError> {
+    let shortname = shortname(client, session, course_id).await?;
+    let params = json!({ "courseid": course_id });
+    let contents = client.call(session, CONTENTS_FUNCTION, &params).await?;
+    let sections: Vec<&Value> = contents
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|s| section_id.is_none() || s.get("id").and_then(Value::as_u64) == section_id)
+        .collect();
+    if let (Some(section_id), true) = (section_id, sections.is_empty()) {
+        return Err(CourseDownloadError::UnknownSection { course_id, section_id });
+    }
+
+    let has_assign = sections
+        .iter()
+        .filter_map(|s| s.get("modules")?.as_array())
+        .flatten()
+        .any(|m| m.get("modname").and_then(Value::as_str) == Some("assign"));
+    let assign_files = match has_assign {
+        true => match assign_files(client, session, course_id).await {
+            Ok(files) => Some(files),
+            Err(err) if err.session_expiry().is_some() => return Err(err.into()),
+            // Without the function the assignments are reported as skipped.
+            Err(_) => None,
+        },
+        false => None,
+    };
+    let (files, skipped) = plan(&contents, section_id, assign_files.as_ref());
+    Ok(CoursePlan { folder_name: sanitize::folder_name(&shortname), files, skipped })
+}
+
+/// Downloads of `session`'s account that have not completed yet, as
+/// `(file URL, folder)` pairs.
+pub fn pending_downloads(
+    downloads: &DownloadManager,
+    session: &Session,
+) -> Result<HashSet<(String, PathBuf)>, DownloadError> {
+    Ok(downloads
+        .list(Some(session.scope()))?
+        .into_iter()
+        .filter(|item| item.status != DownloadStatus::Completed)
+        .map(|item| (item.file_url, item.dir))
+        .collect())
+}
+
+/// Fetches the course (or one section) and queues its files below `root`.
+pub async fn download_course(
+    client: &WsClient,
+    downloads: &DownloadManager,
+    session: &Session,
+    root: &Path,
+    course_id: u64,
+    section_id: Option<u64>,
+) -> Result<CourseDownloadReport, CourseDownloadError> {
+    let CoursePlan { folder_name, files, mut skipped } =
+        fetch_plan(client, session, course_id, section_id).await?;
+    let folder = root.join(folder_name);
+    let pending = pending_downloads(downloads, session)?;
+
+    let mut report = CourseDownloadReport {
+        course_id,
+        folder: folder.clone(),
+        queued: Vec::new(),
+        already_present: Vec::new(),
+        skipped: Vec::new(),
+        failed: Vec::new(),
+    };
+    for file in files {
+        let dir = folder.join(&file.dir);
+        let present = file
+            .local_path(&folder)
+            .and_then(|path| std::fs::metadata(path).ok())
+            .is_some_and(|m| m.is_file() && file.filesize.is_none_or(|size| size == m.len()));
+        if present {
+            report.already_present.push(file);
+            continue;
+        }
+        if pending.contains(&(pluginfile::without_token(&file.file_url), dir.clone())) {
+            skipped.push(file.skipped(SkipReason::AlreadyQueued));
+            continue;
+        }
+        match downloads.enqueue(session, file.request(course_id, &folder, false)) {
+            Ok(item) => report.queued.push(item),
+            Err(DownloadError::NotASiteFile { .. }) => {
+                skipped.push(file.skipped(SkipReason::ExternalFile))
+            }
+            Err(error) => report.failed.push(FailedFile { file, error }),
+        }
+    }
+    report.skipped = skipped;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::{test_client, MockServer};
+
+    const SITE: &str = "https://school.example";
+
+    fn file(filepath: &str, filename: &str) -> Value {
+        json!({
+            "type": "file",
+            "filepath": filepath,
+            "filename": filename,
+            "fileurl": format!("{SITE}/webservice/pluginfile.php/1{filepath}{filename}"),
+            "filesize": 10,
+            "timemodified": 1700000000,
+        })
+    }
+
+    fn contents() -> Value {
+        json!([
+            {
+                "id": 10,
+                "section": 0,
+                "name": "",
+                "modules": [
+                    { "id": 1, "name": "Syllabus", "modname": "resource",
+                      "contents": [file("/", "syllabus.pdf")] },
+                    { "id": 2, "name": "Announcements", "modname": "forum" },
+                    { "id": 3, "name": "Slides: part 1", "modname": "folder",
+                      "contents": [file("/", "intro.pdf"), file("/week 1/", "a?.pdf")] },
+                ],
+            },
+            {
+                "id": 11,
+                "section": 1,
+                "name": "Week 1",
+                "modules": [
+                    { "id": 4, "name": "Reading", "modname": "page",
+                      "contents": [file("/", "index.html")] },
+                    { "id": 5, "name": "Hidden", "modname": "resource", "uservisible": false,
+                      "contents": [file("/", "hidden.pdf")] },
+                    { "id": 6, "name": "Essay", "modname": "assign" },
+                ],
+            },
+        ])
+    }
+
+    fn paths(files: &[PlannedFile]) -> Vec<PathBuf> {
+        files.iter().map(|file| file.dir.join(&file.filename)).collect()
+    }
+
+    fn skips(skipped: &[Skipped]) -> Vec<(u64, SkipReason)> {
+        skipped.iter().map(|skip| (skip.module_id, skip.reason)).collect()
+    }
+
+    #[test]
+    fn names_section_folders() {
+        let dir = |section: Value| section_dir(&section);
+        assert_eq!(dir(json!({ "section": 3, "name": "Week: 3" })), PathBuf::from("03 Week_ 3"));
+        assert_eq!(dir(json!({ "section": 12, "name": "Exams" })), PathBuf::from("12 Exams"));
+        assert_eq!(dir(json!({ "section": 0, "name": " " })), PathBuf::from("00 Section 0"));
+        assert_eq!(dir(json!({})), PathBuf::from("00 Section 0"));
+    }
+
+    #[test]
+    fn plans_module_files() {
+        let files = [
+            file("/", "notes.pdf"),
+            file("/sub/../deeper/", "CON.txt"),
+            json!({ "type": "url", "filename": "link", "fileurl": "https://example.org" }),
+            json!({ "filename": "nourl.pdf" }),
+            json!({ "filename": "plain.txt", "fileurl": "https://school.example/f" }),
+        ];
+        let planned = planned_files(10, (3, "Folder"), Path::new("00 General/Folder"), &files);
+        assert_eq!(
+            paths(&planned),
+            vec![
+                Path::new("00 General/Folder").join("notes.pdf"),
+                Path::new("00 General/Folder").join("sub").join("deeper").join("CON_.txt"),
+                Path::new("00 General/Folder").join("plain.txt"),
+            ]
+        );
+        assert_eq!(planned[0].section_id, 10);
+        assert_eq!(planned[0].module_id, 3);
+        assert_eq!(planned[0].filesize, Some(10));
+        assert_eq!(planned[0].timemodified, Some(1700000000));
+        assert_eq!(planned[2].filesize, None);
+    }
+
+    #[test]
+    fn plans_a_course() {
+        let assignments = HashMap::from([(6, vec![file("/", "task.pdf")])]);
+        let (files, skipped) = plan(&contents(), None, Some(&assignments));
+        let general = Path::new("00 Section 0");
+        assert_eq!(
+            paths(&files),
+            vec![
+                general.join("Syllabus").join("syllabus.pdf"),
+                general.join("Slides_ part 1").join("intro.pdf"),
+                general.join("Slides_ part 1").join("week 1").join("a_.pdf"),
+                Path::new("01 Week 1").join("Essay").join("task.pdf"),
+            ]
+        );
+        assert_eq!(files[3].section_id, 11);
+        assert_eq!(
+            skips(&skipped),
+            vec![(4, SkipReason::UnsupportedModule), (5, SkipReason::NotAvailable)]
+        );
+
+        // Without the assignments function the assignment is reported.
+        let (files, skipped) = plan(&contents(), None, None);
+        assert_eq!(files.len(), 3);
+        assert_eq!(skips(&skipped).last(), Some(&(6, SkipReason::NotAvailable)));
+    }
+
+    #[test]
+    fn plans_one_section() {
+        let (files, skipped) = plan(&contents(), Some(10), None);
+        assert_eq!(files.len(), 3);
+        assert!(files.iter().all(|file| file.section_id == 10));
+        assert!(skipped.is_empty());
+
+        let (files, skipped) = plan(&contents(), Some(99), None);
+        assert!(files.is_empty() && skipped.is_empty());
+    }
+
+    #[test]
+    fn places_planned_files() {
+        let (files, _) = plan(&contents(), Some(10), None);
+        let folder = Path::new("root").join("BIO101");
+        let request = files[0].request(42, &folder, true);
+        assert_eq!(request.dir, folder.join("00 Section 0").join("Syllabus"));
+        assert_eq!(request.filename, "syllabus.pdf");
+        assert_eq!(request.course_id, Some(42));
+        assert_eq!(request.module_id, Some(1));
+        assert!(request.replace);
+        assert_eq!(files[0].local_path(&folder), Some(request.dir.join("syllabus.pdf")));
+
+        let skipped = files[0].skipped(SkipReason::AlreadyQueued);
+        assert_eq!(skipped.filename.as_deref(), Some("syllabus.pdf"));
+        assert_eq!(skipped.module_name, "Syllabus");
+    }
+
+    /// A site whose assignments call fails with `errorcode`.
+    fn site(errorcode: &'static str) -> MockServer {
+        MockServer::start(move |req| {
+            let body = if req.body.contains(COURSES_FUNCTION) {
+                json!({ "courses": [{ "id": 42, "shortname": "BIO: 101" }] })
+            } else if req.body.contains(CONTENTS_FUNCTION) {
+                contents()
+            } else {
+                json!({ "exception": "moodle_exception", "errorcode": errorcode })
+            };
+            (200, body.to_string())
+        })
+    }
+
+    #[tokio::test]
+    async fn fetches_a_plan() {
+        let server = site("invalidparameter");
+        let client = test_client();
+        let session = Session::new(&server.url(), "t");
+
+        let plan = fetch_plan(&client, &session, 42, None).await.unwrap();
+        assert_eq!(plan.folder_name, "BIO_ 101");
+        assert_eq!(plan.files.len(), 3);
+        assert!(skips(&plan.skipped).contains(&(6, SkipReason::NotAvailable)));
+
+        let plan = fetch_plan(&client, &session, 42, Some(10)).await.unwrap();
+        assert_eq!(plan.files.len(), 3);
+        let missing = fetch_plan(&client, &session, 42, Some(99)).await;
+        assert!(matches!(
+            missing,
+            Err(CourseDownloadError::UnknownSection { course_id: 42, section_id: 99 })
+        ));
+
+        // An expired session is not mistaken for a missing function.
+        let server = site("invalidtoken");
+        let session = Session::new(&server.url(), "t");
+        let expired = fetch_plan(&client, &session, 42, None).await;
+        assert!(matches!(
+            expired,
+            Err(CourseDownloadError::Ws { error }) if error.session_expiry().is_some()
+        ));
+    }
+}
@@ -0,0 +1,571 @@
+//! Incremental mirror of a course folder.
+//!
+//! A course folder from [`course_download`](crate::course_download) keeps a
+//! manifest of what Moodle reported for each file when it was last
+//! downloaded (`timemodified`, `filesize` and `fileurl`, which carries the
+//! file revision). An entry whose download is still outstanding carries the
+//! download id and only counts once that download completed; a failed or
+//! cancelled one is queued again by the next sync. A sync fetches the
+//! contents again and only queues files that are new, changed on the
+//! server, or missing or truncated locally; changed files replace the local
+//! copy once downloaded. Files that are no longer in the course are left in
+//! place or, on request, moved to an `_archived` folder. Files of modules
+//! that are only hidden for now are kept.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::course_download::{
+    self, CourseDownloadError, CoursePlan, FailedFile, PlannedFile, SkipReason, Skipped,
+};
+use crate::downloads::{DownloadError, DownloadManager, DownloadStatus};
+use crate::pluginfile;
+use crate::sanitize;
+use crate::session::Session;
+use crate::ws::WsClient;
+
+/// Manifest file in the course folder.
+pub const MANIFEST_FILE: &str = ".moodle-mirror.json";
+
+/// Folder in the course folder receiving files no longer in the course.
+pub const ARCHIVE_DIR: &str = "_archived";
+
+/// What Moodle reported for one mirrored file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestEntry {
+    pub module_id: u64,
+    /// Without token.
+    pub file_url: String,
+    pub filesize: Option<u64>,
+    pub timemodified: Option<i64>,
+    /// The download of this revision while it has not completed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_id: Option<String>,
+}
+
+impl ManifestEntry {
+    fn of(file: &PlannedFile) -> Self {
+        Self {
+            module_id: file.module_id,
+            file_url: pluginfile::without_token(&file.file_url),
+            filesize: file.filesize,
+            timemodified: file.timemodified,
+            download_id: None,
+        }
+    }
+
+    /// Whether both describe the same revision of a file.
+    fn same_revision(&self, other: &Self) -> bool {
+        self.module_id == other.module_id
+            && self.file_url == other.file_url
+            && self.filesize == other.filesize
+            && self.timemodified == other.timemodified
+    }
+}
+
+/// The manifest of a mirrored course folder.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub site_url: String,
+    pub course_id: u64,
+    /// Entries by path relative to the course folder, `/`-separated.
+    pub files: BTreeMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    /// Reads the manifest of `folder`; `None` if there is none or it is
+    /// unreadable, in which case files on disk with the expected size are
+    /// taken over as they are.
+    pub fn load(folder: &Path) -> Option<Self> {
+        let bytes = fs::read(folder.join(MANIFEST_FILE)).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    /// Writes the manifest via a temp file so a crash never leaves half of it.
+    pub fn save(&self, folder: &Path) -> std::io::Result<()> {
+        fs::create_dir_all(folder)?;
+        let tmp = folder.join(format!("{MANIFEST_FILE}.part"));
+        let json = serde_json::to_vec_pretty(self).expect("manifest is serialisable");
+        fs::write(&tmp, json)?;
+        fs::rename(tmp, folder.join(MANIFEST_FILE))
+    }
+}
+
+/// Manifest key of a planned file.
+fn manifest_key(file: &PlannedFile) -> String {
+    let path = file.dir.join(&file.filename);
+    let parts: Vec<_> = path.iter().map(|part| part.to_string_lossy()).collect();
+    parts.join("/")
+}
+
+/// Folder and file name of a manifest key below `folder`.
+fn key_path<'k>(folder: &Path, key: &'k str) -> (PathBuf, &'k str) {
+    let (dir, name) = key.rsplit_once('/').unwrap_or(("", key));
+    let dir = dir.split('/').filter(|part| !part.is_empty());
+    (dir.fold(folder.to_path_buf(), |path, part| path.join(part)), name)
+}
+
+/// How a file changed since the last sync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ChangeKind {
+    /// New in the course.
+    Added,
+    /// Changed on the server; the download replaces the local copy.
+    Updated,
+    /// Unchanged on the server but missing or truncated locally.
+    Restored,
+    /// No longer in the course; the local file was left in place.
+    Removed,
+    /// No longer in the course; the local file was moved to `_archived`.
+    Archived,
+}
+
+/// One changed file.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncChange {
+    pub kind: ChangeKind,
+    /// Path relative to the course folder, `/`-separated.
+    pub path: String,
+    pub module_id: u64,
+    /// The download queued for the file, if any.
+    pub download_id: Option<String>,
+}
+
+/// Outcome of a sync.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncReport {
+    pub course_id: u64,
+    pub folder: PathBuf,
+    pub changes: Vec<SyncChange>,
+    /// Number of files that were already up to date.
+    pub unchanged: usize,
+    pub skipped: Vec<Skipped>,
+    pub failed: Vec<FailedFile>,
+}
+
+/// Moves a file that left the course to the archive folder. `Ok(false)` if
+/// there was no local file.
+fn archive(folder: &Path, key: &str) -> std::io::Result<bool> {
+    let (dir, name) = key_path(folder, key);
+    let source = sanitize::fit(&dir, name).map(|name| dir.join(name));
+    let Some(source) = source.filter(|path| path.is_file()) else {
+        return Ok(false);
+    };
+    let (dir, name) = key_path(&folder.join(ARCHIVE_DIR), key);
+    fs::create_dir_all(&dir)?;
+    let name = sanitize::fit(&dir, name)
+        .ok_or_else(|| std::io::Error::other("The archive path is too long"))?;
+    fs::rename(&source, sanitize::unique_path(&dir, &name))?;
+    Ok(true)
+}
+
+/// Brings the mirror of a course below `root` up to date.
+pub async fn sync_course(
+    client: &WsClient,
+    downloads: &DownloadManager,
+    session: &Session,
+    root: &Path,
+    course_id: u64,
+    archive_removed: bool,
+) -> Result<SyncReport, CourseDownloadError> {
+    let CoursePlan { folder_name, files, mut skipped } =
+        course_download::fetch_plan(client, session, course_id, None).await?;
+    let folder = root.join(folder_name);
+    let mut manifest = Manifest::load(&folder).unwrap_or_else(|| Manifest {
+        site_url: session.site_url().to_string(),
+        course_id,
+        files: BTreeMap::new(),
+    });
+    if manifest.site_url != session.site_url() || manifest.course_id != course_id {
+        return Err(CourseDownloadError::ForeignFolder { folder });
+    }
+    let pending = course_download::pending_downloads(downloads, session)?;
+    for entry in manifest.files.values_mut() {
+        let completed = entry.download_id.as_deref().is_some_and(|id| {
+            downloads.get(id).is_ok_and(|item| item.status == DownloadStatus::Completed)
+        });
+        if completed {
+            entry.download_id = None;
+        }
+    }
+
+    let mut report = SyncReport {
+        course_id,
+        folder: folder.clone(),
+        changes: Vec::new(),
+        unchanged: 0,
+        skipped: Vec::new(),
+        failed: Vec::new(),
+    };
+    let mut listed = HashSet::new();
+    for file in files {
+        let key = manifest_key(&file);
+        listed.insert(key.clone());
+        let remote = ManifestEntry::of(&file);
+        let local_ok = file
+            .local_path(&folder)
+            .and_then(|path| fs::metadata(path).ok())
+            .is_some_and(|m| m.is_file() && remote.filesize.is_none_or(|size| size == m.len()));
+        let kind = match manifest.files.get(&key) {
+            Some(entry) if !entry.same_revision(&remote) => ChangeKind::Updated,
+            Some(entry) if local_ok && entry.download_id.is_none() => {
+                report.unchanged += 1;
+                continue;
+            }
+            // Missing locally, or its download never completed.
+            Some(_) => ChangeKind::Restored,
+            // E.g. downloaded with a plain course download before.
+            None if local_ok => {
+                manifest.files.insert(key, remote);
+                report.unchanged += 1;
+                continue;
+            }
+            None => ChangeKind::Added,
+        };
+
+        if pending.contains(&(remote.file_url.clone(), folder.join(&file.dir))) {
+            skipped.push(file.skipped(SkipReason::AlreadyQueued));
+            continue;
+        }
+        match downloads.enqueue(session, file.request(course_id, &folder, true)) {
+            Ok(item) => {
+                let entry = ManifestEntry { download_id: Some(item.id.clone()), ..remote };
+                manifest.files.insert(key.clone(), entry);
+                report.changes.push(SyncChange {
+                    kind,
+                    path: key,
+                    module_id: file.module_id,
+                    download_id: Some(item.id),
+                });
+            }
+            Err(DownloadError::NotASiteFile { .. }) => {
+                skipped.push(file.skipped(SkipReason::ExternalFile))
+            }
+            Err(error) => report.failed.push(FailedFile { file, error }),
+        }
+    }
+
+    // Modules that are hidden or restricted for now keep their files.
+    let kept: HashSet<u64> = skipped
+        .iter()
+        .filter(|s| s.filename.is_none() && s.reason == SkipReason::NotAvailable)
+        .map(|s| s.module_id)
+        .collect();
+    let gone: Vec<(String, u64)> = manifest
+        .files
+        .iter()
+        .filter(|(key, entry)| !listed.contains(*key) && !kept.contains(&entry.module_id))
+        .map(|(key, entry)| (key.clone(), entry.module_id))
+        .collect();
+    for (key, module_id) in gone {
+        manifest.files.remove(&key);
+        let archived = archive_removed && archive(&folder, &key)?;
+        report.changes.push(SyncChange {
+            kind: if archived { ChangeKind::Archived } else { ChangeKind::Removed },
+            path: key,
+            module_id,
+            download_id: None,
+        });
+    }
+
+    manifest.save(&folder)?;
+    report.skipped = skipped;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use serde_json::{json, Value};
+
+    use crate::course_download::COURSES_FUNCTION;
+    use crate::course_updates::CONTENTS_FUNCTION;
+    use crate::downloads::DATABASE_FILE;
+    use crate::test_support::{test_client, MockServer, RecordedRequest, TestHost};
+
+    /// A file of the mock course as the server currently describes it.
+    #[derive(Clone)]
+    struct RemoteFile {
+        module_id: u64,
+        name: &'static str,
+        revision: u32,
+        timemodified: i64,
+        body: &'static str,
+    }
+
+    fn remote(module_id: u64, name: &'static str, body: &'static str) -> RemoteFile {
+        RemoteFile { module_id, name, revision: 1, timemodified: 1700000000, body }
+    }
+
+    impl RemoteFile {
+        fn path(&self) -> String {
+            let (revision, name) = (self.revision, self.name);
+            format!("/webservice/pluginfile.php/5/mod_folder/content/{revision}/{name}")
+        }
+    }
+
+    /// Course 42 (`BIO101`) with the folders "Handouts" (module 1) and
+    /// "Slides" (module 2), the files of which the server serves.
+    struct Course {
+        server: MockServer,
+        files: Arc<Mutex<Vec<RemoteFile>>>,
+        /// Modules that are gone from the course.
+        removed: Arc<Mutex<HashSet<u64>>>,
+        /// Modules that are hidden from the user.
+        hidden: Arc<Mutex<HashSet<u64>>>,
+    }
+
+    impl Course {
+        fn start(files: Vec<RemoteFile>) -> Self {
+            let files = Arc::new(Mutex::new(files));
+            let removed = Arc::new(Mutex::new(HashSet::new()));
+            let hidden = Arc::new(Mutex::new(HashSet::new()));
+            let (state, gone, invisible) = (files.clone(), removed.clone(), hidden.clone());
+            let server = MockServer::start(move |req| {
+                let files = state.lock().unwrap();
+                let path = req.path.split('?').next().unwrap_or_default();
+                if let Some(file) = files.iter().find(|f| f.path() == path) {
+                    return (200, file.body.to_string());
+                }
+                let body = if req.body.contains(COURSES_FUNCTION) {
+                    json!({ "courses": [{ "id": 42, "shortname": "BIO101" }] })
+                } else if req.body.contains(CONTENTS_FUNCTION) {
+                    let modules: Vec<Value> = [(1, "Handouts"), (2, "Slides")]
+                        .into_iter()
+                        .filter(|(id, _)| !gone.lock().unwrap().contains(id))
+                        .map(|(id, name)| {
+                            let contents: Vec<Value> = files
+                                .iter()
+                                .filter(|f| f.module_id == id)
+                                .map(|f| {
+                                    json!({
+                                        "type": "file",
+                                        "filepath": "/",
+                                        "filename": f.name,
+                                        "fileurl": format!("{}{}", req_site(req), f.path()),
+                                        "filesize": f.body.len(),
+                                        "timemodified": f.timemodified,
+                                    })
+                                })
+                                .collect();
+                            let visible = !invisible.lock().unwrap().contains(&id);
+                            json!({ "id": id, "name": name, "modname": "folder",
+                                    "uservisible": visible, "contents": contents })
+                        })
+                        .collect();
+                    json!([{ "id": 10, "section": 0, "name": "", "modules": modules }])
+                } else {
+                    json!({ "exception": "moodle_exception", "errorcode": "invalidparameter" })
+                };
+                (200, body.to_string())
+            });
+            Self { server, files, removed, hidden }
+        }
+
+        fn update(&self, name: &str, change: impl FnOnce(&mut RemoteFile)) {
+            let mut files = self.files.lock().unwrap();
+            change(files.iter_mut().find(|f| f.name == name).unwrap());
+        }
+    }
+
+    /// The site URL the mock server was addressed with.
+    fn req_site(req: &RecordedRequest) -> String {
+        format!("http://{}", req.header("host").unwrap())
+    }
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("course-sync-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A download manager for `course` and the root the course is mirrored to.
+    fn mirror(course: &Course) -> (DownloadManager, Session, PathBuf) {
+        let dir = temp_dir();
+        let session = Session::new(&course.server.url(), "secret").with_account_id("2@site");
+        let host = Arc::new(TestHost::new(session.clone(), dir.join("Downloads")));
+        let downloads =
+            DownloadManager::open(&dir.join(DATABASE_FILE), test_client(), host).unwrap();
+        (downloads, session, dir.join("Courses"))
+    }
+
+    /// Syncs the course and waits until the queued downloads finished.
+    async fn sync(
+        downloads: &DownloadManager,
+        session: &Session,
+        root: &Path,
+        archive_removed: bool,
+    ) -> SyncReport {
+        let client = test_client();
+        let report =
+            sync_course(&client, downloads, session, root, 42, archive_removed).await.unwrap();
+        for id in report.changes.iter().filter_map(|c| c.download_id.as_deref()) {
+            let mut waited = 0;
+            while downloads.get(id).unwrap().status != DownloadStatus::Completed {
+                assert!(waited < 500, "download {id} did not finish");
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                waited += 1;
+            }
+        }
+        report
+    }
+
+    fn changes(report: &SyncReport) -> Vec<(ChangeKind, &str)> {
+        report.changes.iter().map(|c| (c.kind, c.path.as_str())).collect()
+    }
+
+    const HANDOUTS: &str = "00 Section 0/Handouts";
+    const NOTES: &str = "00 Section 0/Handouts/notes.pdf";
+    const WEEK1: &str = "00 Section 0/Slides/week1.pdf";
+
+    /// `notes.pdf` in "Handouts" and `week1.pdf` in "Slides".
+    fn two_modules() -> Course {
+        Course::start(vec![remote(1, "notes.pdf", "notes"), remote(2, "week1.pdf", "w1")])
+    }
+
+    #[tokio::test]
+    async fn downloads_what_changed_on_the_server() {
+        let course = Course::start(vec![
+            remote(1, "same.pdf", "same"),
+            remote(1, "edited.pdf", "edited"),
+            remote(1, "resized.pdf", "resized"),
+            remote(1, "revised.pdf", "revised"),
+        ]);
+        let (downloads, session, root) = mirror(&course);
+        let folder = root.join("BIO101");
+
+        let first = sync(&downloads, &session, &root, false).await;
+        assert_eq!(first.changes.len(), 4);
+        assert!(first.changes.iter().all(|c| c.kind == ChangeKind::Added));
+        let handouts = folder.join("00 Section 0").join("Handouts");
+        assert_eq!(fs::read_to_string(handouts.join("edited.pdf")).unwrap(), "edited");
+
+        // Nothing changed: nothing is downloaded again.
+        let again = sync(&downloads, &session, &root, false).await;
+        assert!(again.changes.is_empty());
+        assert_eq!(again.unchanged, 4);
+        let manifest = Manifest::load(&folder).unwrap();
+        assert!(manifest.files.values().all(|entry| entry.download_id.is_none()));
+
+        course.update("edited.pdf", |f| {
+            f.timemodified += 60;
+            f.body = "EDITED";
+        });
+        course.update("resized.pdf", |f| f.body = "resized, longer");
+        course.update("revised.pdf", |f| {
+            f.revision = 2;
+            f.body = "REVISED";
+        });
+        course.files.lock().unwrap().push(remote(1, "new.pdf", "new"));
+
+        let report = sync(&downloads, &session, &root, false).await;
+        let key = |name: &str| format!("{HANDOUTS}/{name}");
+        assert_eq!(
+            changes(&report),
+            vec![
+                (ChangeKind::Updated, key("edited.pdf").as_str()),
+                (ChangeKind::Updated, key("resized.pdf").as_str()),
+                (ChangeKind::Updated, key("revised.pdf").as_str()),
+                (ChangeKind::Added, key("new.pdf").as_str()),
+            ]
+        );
+        assert_eq!(report.unchanged, 1);
+        assert_eq!(fs::read_to_string(handouts.join("edited.pdf")).unwrap(), "EDITED");
+        assert_eq!(fs::read_to_string(handouts.join("resized.pdf")).unwrap(), "resized, longer");
+        assert_eq!(fs::read_to_string(handouts.join("revised.pdf")).unwrap(), "REVISED");
+        assert_eq!(fs::read_to_string(handouts.join("new.pdf")).unwrap(), "new");
+
+        let manifest = Manifest::load(&folder).unwrap();
+        let revised = &manifest.files[&key("revised.pdf")];
+        assert!(revised.file_url.ends_with("/content/2/revised.pdf"));
+        assert_eq!(revised.filesize, Some(7));
+        assert_eq!(manifest.files[&key("edited.pdf")].timemodified, Some(1700000060));
+    }
+
+    #[tokio::test]
+    async fn restores_files_missing_locally() {
+        let course = Course::start(vec![remote(1, "notes.pdf", "notes")]);
+        let (downloads, session, root) = mirror(&course);
+        sync(&downloads, &session, &root, false).await;
+        let path = root.join("BIO101").join("00 Section 0").join("Handouts").join("notes.pdf");
+
+        fs::write(&path, "no").unwrap();
+        let report = sync(&downloads, &session, &root, false).await;
+        assert_eq!(changes(&report), vec![(ChangeKind::Restored, NOTES)]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "notes");
+    }
+
+    #[tokio::test]
+    async fn keeps_or_archives_files_of_removed_modules() {
+        let course = two_modules();
+        let (downloads, session, root) = mirror(&course);
+        let folder = root.join("BIO101");
+        let slides = folder.join("00 Section 0").join("Slides").join("week1.pdf");
+        sync(&downloads, &session, &root, false).await;
+
+        // A hidden module keeps its files.
+        course.hidden.lock().unwrap().insert(2);
+        let report = sync(&downloads, &session, &root, true).await;
+        assert!(report.changes.is_empty());
+        assert!(slides.is_file());
+        course.hidden.lock().unwrap().clear();
+
+        // Removed without archiving: the file stays where it was.
+        course.removed.lock().unwrap().insert(2);
+        let report = sync(&downloads, &session, &root, false).await;
+        assert_eq!(changes(&report), vec![(ChangeKind::Removed, WEEK1)]);
+        assert!(slides.is_file());
+        assert_eq!(Manifest::load(&folder).unwrap().files.len(), 1);
+
+        // Back in the course, the local copy is taken over as it is.
+        course.removed.lock().unwrap().clear();
+        let report = sync(&downloads, &session, &root, false).await;
+        assert!(report.changes.is_empty());
+        assert_eq!(report.unchanged, 2);
+
+        // Removed with archiving: the file moves to `_archived`.
+        course.removed.lock().unwrap().insert(2);
+        let report = sync(&downloads, &session, &root, true).await;
+        assert_eq!(changes(&report), vec![(ChangeKind::Archived, WEEK1)]);
+        assert!(!slides.exists());
+        let (archive, name) = key_path(&folder.join(ARCHIVE_DIR), WEEK1);
+        assert_eq!(fs::read_to_string(archive.join(name)).unwrap(), "w1");
+        assert!(folder.join("00 Section 0").join("Handouts").join("notes.pdf").is_file());
+    }
+
+    #[tokio::test]
+    async fn reports_the_changes() {
+        let course = two_modules();
+        let (downloads, session, root) = mirror(&course);
+        sync(&downloads, &session, &root, false).await;
+
+        course.update("notes.pdf", |f| f.timemodified += 1);
+        course.removed.lock().unwrap().insert(2);
+        let report = sync(&downloads, &session, &root, true).await;
+
+        assert_eq!(report.course_id, 42);
+        assert_eq!(report.folder, root.join("BIO101"));
+        assert_eq!(report.unchanged, 0);
+        assert!(report.skipped.is_empty() && report.failed.is_empty());
+        let updated = report.changes[0].download_id.clone().unwrap();
+        assert_eq!(downloads.get(&updated).unwrap().module_id, Some(1));
+        assert_eq!(
+            serde_json::to_value(&report.changes).unwrap(),
+            json!([
+                { "kind": "updated", "path": NOTES, "moduleId": 1, "downloadId": updated },
+                { "kind": "archived", "path": WEEK1, "moduleId": 2, "downloadId": null },
+            ])
+        );
+    }
+}
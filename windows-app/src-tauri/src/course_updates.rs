@@ -0,0 +1,327 @@
+//! Server-driven freshness for cached course contents.
+//!
+//! Instead of refetching `core_course_get_contents` whenever its 30 minute
+//! TTL runs out, a background check asks `core_course_check_updates` which
+//! modules of each cached course changed since the course was fetched.
+//! Unchanged courses are marked fresh; for changed modules the course entry
+//! and the module's own cached reads (by `cmid` or `<modname>id`, e.g.
+//! `forumid`) are evicted and the frontend is told which modules to reload.
+//!
+//! Moodle only checks modules it is asked about, so modules added since the
+//! course was cached show up once the contents are refetched.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::data_key::DataKey;
+use crate::response_cache::{self, CacheEntry, ResponseCache};
+use crate::scheduler::Priority;
+use crate::session::{Session, SessionHandle};
+use crate::ws::{WsClient, WsError};
+
+/// WS function reporting changes per module.
+pub const CHECK_UPDATES_FUNCTION: &str = "core_course_check_updates";
+
+/// Cached function whose entries are kept fresh.
+pub const CONTENTS_FUNCTION: &str = "core_course_get_contents";
+
+/// Event emitted with a [`CourseUpdated`] for every course with changes.
+pub const UPDATED_EVENT: &str = "course://updated";
+
+/// Time between two background checks.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Changed modules of one cached course.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CourseUpdated {
+    pub handle: SessionHandle,
+    pub account_id: Option<String>,
+    pub site_url: String,
+    pub course_id: u64,
+    pub module_ids: Vec<u64>,
+}
+
+/// A module listed in cached course contents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedModule {
+    pub id: u64,
+    pub instance: Option<u64>,
+    pub modname: String,
+}
+
+/// Modules of a `core_course_get_contents` response.
+pub fn cached_modules(contents: &Value) -> Vec<CachedModule> {
+    contents
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|section| section.get("modules")?.as_array())
+        .flatten()
+        .filter_map(|module| {
+            Some(CachedModule {
+                id: module.get("id")?.as_u64()?,
+                instance: module.get("instance").and_then(Value::as_u64),
+                modname: module.get("modname").and_then(Value::as_str)?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Parameters for `core_course_check_updates` on `modules` since `since`
+/// (seconds since the epoch).
+pub fn check_params(course_id: u64, modules: &[CachedModule], since: i64) -> Value {
+    let tocheck: Vec<Value> = modules
+        .iter()
+        .map(|module| json!({ "contextlevel": "module", "id": module.id, "since": since }))
+        .collect();
+    json!({ "courseid": course_id, "tocheck": tocheck })
+}
+
+/// Ids of the modules with at least one update in a check response.
+pub fn changed_modules(response: &Value) -> Vec<u64> {
+    response
+        .get("instances")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter(|instance| {
+            instance.get("contextlevel").and_then(Value::as_str) == Some("module")
+                && instance
+                    .get("updates")
+                    .and_then(Value::as_array)
+                    .is_some_and(|updates| !updates.is_empty())
+        })
+        .filter_map(|instance| instance.get("id")?.as_u64())
+        .collect()
+}
+
+/// An id parameter of a cached read; the frontend passes some as strings.
+fn param_id(params: &Value, key: &str) -> Option<u64> {
+    params.get(key).and_then(|v| v.as_u64().or_else(|| v.as_str()?.parse().ok()))
+}
+
+/// Whether a cached read of `function(params)` belongs to one of `modules`.
+fn is_module_read(modules: &[&CachedModule], function: &str, params: &Value) -> bool {
+    modules.iter().any(|module| {
+        param_id(params, "cmid") == Some(module.id)
+            || (function.starts_with(&format!("mod_{}_", module.modname))
+                && module.instance.is_some()
+                && param_id(params, &format!("{}id", module.modname)) == module.instance)
+    })
+}
+
+/// Checks one cached course and applies the result to the cache. Returns
+/// the ids of changed modules, empty if the course is up to date.
+async fn check_course(
+    client: &WsClient,
+    cache: &ResponseCache,
+    session: &Session,
+    entry: &CacheEntry,
+) -> Result<Vec<u64>, WsError> {
+    let Some(course_id) = param_id(&entry.params, "courseid") else {
+        return Ok(Vec::new());
+    };
+    let modules = cached_modules(&entry.data);
+    if modules.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let checked_at = response_cache::now_ms();
+    let params = check_params(course_id, &modules, entry.fetched_at / 1000);
+    let response = client
+        .call_with_priority(session, CHECK_UPDATES_FUNCTION, &params, Priority::Background)
+        .await?;
+    let changed: HashSet<u64> = changed_modules(&response).into_iter().collect();
+
+    let scope = session.scope();
+    if changed.is_empty() {
+        let _ = cache.mark_fresh(scope, CONTENTS_FUNCTION, &entry.params, checked_at);
+        return Ok(Vec::new());
+    }
+    let changed_modules: Vec<&CachedModule> =
+        modules.iter().filter(|module| changed.contains(&module.id)).collect();
+    let _ = cache.evict_where(scope, |function, params| {
+        (function == CONTENTS_FUNCTION && *params == entry.params)
+            || is_module_read(&changed_modules, function, params)
+    });
+    Ok(changed_modules.iter().map(|module| module.id).collect())
+}
+
+/// Checks every cached course of `session`'s account. Courses whose check
+/// fails are skipped; if Moodle refuses the session, the remaining courses
+/// are left alone and the error is returned.
+pub async fn check_session(
+    client: &WsClient,
+    cache: &ResponseCache,
+    data_key: &DataKey,
+    handle: &str,
+    session: &Session,
+) -> Result<Vec<CourseUpdated>, WsError> {
+    let entries = cache.entries(session.scope(), data_key, CONTENTS_FUNCTION).unwrap_or_default();
+    let mut updates = Vec::new();
+    for entry in &entries {
+        match check_course(client, cache, session, entry).await {
+            Ok(module_ids) if !module_ids.is_empty() => updates.push(CourseUpdated {
+                handle: handle.to_string(),
+                account_id: session.account_id().map(str::to_string),
+                site_url: session.site_url().to_string(),
+                course_id: param_id(&entry.params, "courseid").unwrap_or_default(),
+                module_ids,
+            }),
+            Ok(_) => {}
+            Err(err) if err.session_expiry().is_some() => return Err(err),
+            Err(_) => {}
+        }
+    }
+    Ok(updates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::{test_client, MockServer};
+
+    fn contents() -> Value {
+        json!([
+            {
+                "id": 1,
+                "modules": [
+                    { "id": 5, "instance": 3, "modname": "forum" },
+                    { "id": 6, "modname": "label" },
+                    { "id": 7, "instance": 9 },
+                ],
+            },
+            { "id": 2, "modules": [{ "id": 8, "instance": 4, "modname": "resource" }] },
+            { "id": 3 },
+        ])
+    }
+
+    fn updates(ids: &[u64]) -> String {
+        let instances: Vec<Value> = ids
+            .iter()
+            .map(|id| {
+                json!({
+                    "contextlevel": "module",
+                    "id": id,
+                    "updates": [{ "name": "configuration", "timeupdated": 1700000000 }],
+                })
+            })
+            .collect();
+        json!({ "instances": instances, "warnings": [] }).to_string()
+    }
+
+    #[test]
+    fn reads_cached_modules() {
+        let modules = cached_modules(&contents());
+        assert_eq!(
+            modules,
+            vec![
+                CachedModule { id: 5, instance: Some(3), modname: "forum".into() },
+                CachedModule { id: 6, instance: None, modname: "label".into() },
+                CachedModule { id: 8, instance: Some(4), modname: "resource".into() },
+            ]
+        );
+        assert!(cached_modules(&json!({ "exception": "moodle_exception" })).is_empty());
+
+        let params = check_params(12, &modules[..2], 1700000000);
+        assert_eq!(
+            params,
+            json!({
+                "courseid": 12,
+                "tocheck": [
+                    { "contextlevel": "module", "id": 5, "since": 1700000000 },
+                    { "contextlevel": "module", "id": 6, "since": 1700000000 },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn finds_changed_modules() {
+        let response = json!({
+            "instances": [
+                { "contextlevel": "module", "id": 5, "updates": [{ "name": "posts" }] },
+                { "contextlevel": "module", "id": 6, "updates": [] },
+                { "contextlevel": "module", "id": 8 },
+                { "contextlevel": "course", "id": 12, "updates": [{ "name": "config" }] },
+            ],
+            "warnings": [],
+        });
+        assert_eq!(changed_modules(&response), vec![5]);
+        assert!(changed_modules(&json!({ "warnings": [] })).is_empty());
+    }
+
+    #[test]
+    fn matches_module_reads() {
+        let modules = cached_modules(&contents());
+        let forum = [&modules[0]];
+        let label = [&modules[1]];
+        let cases = [
+            ("mod_forum_get_forum_discussions", json!({ "forumid": 3 }), true),
+            ("mod_forum_get_forum_discussions", json!({ "forumid": "3" }), true),
+            ("mod_forum_get_forum_discussions", json!({ "forumid": 4 }), false),
+            ("mod_assign_get_submissions", json!({ "forumid": 3 }), false),
+            ("core_course_get_module", json!({ "cmid": 5 }), true),
+            ("core_course_get_module", json!({ "cmid": "5" }), true),
+            ("core_course_get_module", json!({ "cmid": 6 }), false),
+            ("core_course_get_contents", json!({ "courseid": 5 }), false),
+        ];
+        for (function, params, expected) in cases {
+            assert_eq!(is_module_read(&forum, function, &params), expected, "{function} {params}");
+        }
+        // Modules without an instance only match by cmid.
+        assert!(!is_module_read(&label, "mod_label_get_labels", &json!({ "labelid": 0 })));
+        assert!(is_module_read(&label, "core_course_get_module", &json!({ "cmid": 6 })));
+    }
+
+    #[tokio::test]
+    async fn evicts_changed_courses_cached_with_string_ids() {
+        let server = MockServer::start(|_| (200, updates(&[5])));
+        let client = test_client();
+        let cache = ResponseCache::in_memory(1 << 20).unwrap();
+        let key = DataKey::random();
+        let session = Session::new(&server.url(), "t");
+        let scope = session.scope();
+        let course = json!({ "courseid": "12" });
+        let forum = json!({ "forumid": 3 });
+        let other = json!({ "forumid": 30 });
+        cache.put(scope, &key, CONTENTS_FUNCTION, &course, &contents()).unwrap();
+        cache.put(scope, &key, "mod_forum_get_forum_discussions", &forum, &json!([])).unwrap();
+        cache.put(scope, &key, "mod_forum_get_forum_discussions", &other, &json!([])).unwrap();
+
+        let updated = check_session(&client, &cache, &key, "h1", &session).await.unwrap();
+        assert_eq!(updated.len(), 1);
+        assert_eq!(updated[0].course_id, 12);
+        assert_eq!(updated[0].module_ids, vec![5]);
+        let body = server.last_request().body;
+        assert!(body.contains("wsfunction=core_course_check_updates"));
+        assert!(body.contains("courseid=12"));
+
+        let cached = |function: &str, params: &Value| {
+            cache.get(scope, &key, function, params, true).unwrap().is_some()
+        };
+        assert!(!cached(CONTENTS_FUNCTION, &course));
+        assert!(!cached("mod_forum_get_forum_discussions", &forum));
+        assert!(cached("mod_forum_get_forum_discussions", &other));
+    }
+
+    #[tokio::test]
+    async fn keeps_unchanged_courses() {
+        let server = MockServer::start(|_| (200, updates(&[])));
+        let cache = ResponseCache::in_memory(1 << 20).unwrap();
+        let key = DataKey::random();
+        let session = Session::new(&server.url(), "t");
+        let course = json!({ "courseid": 12 });
+        cache.put(session.scope(), &key, CONTENTS_FUNCTION, &course, &contents()).unwrap();
+
+        let updated = check_session(&test_client(), &cache, &key, "h1", &session).await.unwrap();
+        assert!(updated.is_empty());
+        let entry = cache.get(session.scope(), &key, CONTENTS_FUNCTION, &course, false).unwrap();
+        assert!(entry.is_some_and(|entry| !entry.stale));
+    }
+}
@@ -0,0 +1,201 @@
+//! Per-account encryption of data kept at rest.
+//!
+//! Cached WS responses hold grades, private messages and profile data. They
+//! are sealed with ChaCha20-Poly1305 under a data key that lives in the
+//! account's vault secrets, so removing the account from the vault leaves
+//! nothing that can decrypt them. Sessions that were never stored in the
+//! vault get a random key that only lives in memory: their data is
+//! unreadable once the app exits.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use zeroize::Zeroizing;
+
+use crate::session::Session;
+use crate::vault::{Vault, VaultError};
+
+const NONCE_LEN: usize = 12;
+
+/// A symmetric key for one account's data.
+#[derive(Clone)]
+pub struct DataKey(Zeroizing<[u8; 32]>);
+
+impl DataKey {
+    pub fn new(key: Zeroizing<[u8; 32]>) -> Self {
+        Self(key)
+    }
+
+    /// The raw key, for storing it in the vault.
+    pub(crate) fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// A fresh random key.
+    pub fn random() -> Self {
+        Self(Zeroizing::new(ChaCha20Poly1305::generate_key(&mut OsRng).into()))
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(self.0.as_ref()))
+    }
+
+    /// Encrypts `plain`, bound to `aad`. Returns `nonce || ciphertext`.
+    pub fn seal(&self, plain: &[u8], aad: &[u8]) -> Vec<u8> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher()
+            .encrypt(&nonce, Payload { msg: plain, aad })
+            .expect("ChaCha20-Poly1305 encryption cannot fail for in-memory buffers");
+        let mut out = nonce.to_vec();
+        out.extend_from_slice(&ciphertext);
+        out
+    }
+
+    /// Decrypts a value from [`DataKey::seal`]; `None` if it was sealed with
+    /// another key or `aad`, or was tampered with.
+    pub fn open(&self, sealed: &[u8], aad: &[u8]) -> Option<Zeroizing<Vec<u8>>> {
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.cipher()
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+            .ok()
+            .map(Zeroizing::new)
+    }
+}
+
+/// Data keys of the accounts in use, by session scope. Held in Tauri
+/// managed state so the vault is only asked once per account.
+#[derive(Default)]
+pub struct KeyRing {
+    keys: Mutex<HashMap<String, DataKey>>,
+}
+
+impl KeyRing {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The data key for `session`'s account: from the vault for stored
+    /// accounts, an in-memory key otherwise. Sessions from a login carry an
+    /// account id before the account is stored; they get an in-memory key
+    /// too, which [`Vault::add`] keeps once the account is stored.
+    pub fn key_for(&self, vault: &Vault, session: &Session) -> Result<DataKey, VaultError> {
+        let scope = session.scope();
+        if let Some(key) = self.get(scope) {
+            return Ok(key);
+        }
+        let key = match session.account_id().map(|id| vault.data_key(id)) {
+            Some(Ok(key)) => DataKey::new(key),
+            Some(Err(VaultError::UnknownAccount { .. })) | None => DataKey::random(),
+            Some(Err(err)) => return Err(err),
+        };
+        let mut keys = self.keys.lock().unwrap();
+        Ok(keys.entry(scope.to_string()).or_insert(key).clone())
+    }
+
+    /// The data key for a session scope without a session at hand, e.g. to
+    /// read stored data after a restart. `None` for scopes that are neither
+    /// in use nor a stored account.
+    pub fn key_for_scope(&self, vault: &Vault, scope: &str) -> Option<DataKey> {
+        if let Some(key) = self.keys.lock().unwrap().get(scope) {
+            return Some(key.clone());
+        }
+        let key = DataKey::new(vault.data_key(scope).ok()?);
+        let mut keys = self.keys.lock().unwrap();
+        Some(keys.entry(scope.to_string()).or_insert(key).clone())
+    }
+
+    /// The key in use for a scope, if any.
+    pub fn get(&self, scope: &str) -> Option<DataKey> {
+        self.keys.lock().unwrap().get(scope).cloned()
+    }
+
+    /// Drops the key of a scope, e.g. after its account was removed.
+    pub fn forget(&self, scope: &str) {
+        self.keys.lock().unwrap().remove(scope);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault::StoredAccount;
+
+    const SITE: &str = "https://school.example";
+    const ID: &str = "3@https://school.example";
+
+    /// A vault in a fresh directory holding the account [`ID`].
+    fn vault() -> Vault {
+        let dir = std::env::temp_dir().join(format!("data-key-{}", uuid::Uuid::new_v4()));
+        let vault = Vault::open(&dir).unwrap();
+        let account = StoredAccount {
+            id: ID.into(),
+            site_url: SITE.into(),
+            fullname: "Sam Student".into(),
+            username: "student".into(),
+            userpictureurl: String::new(),
+            sitename: "School".into(),
+            userid: 3,
+        };
+        vault.add(account, &session(), None).unwrap();
+        vault
+    }
+
+    fn session() -> Session {
+        Session::new(SITE, "token").with_account_id(ID)
+    }
+
+    #[test]
+    fn seals_and_opens() {
+        let key = DataKey::random();
+        let sealed = key.seal(b"grades", b"scope");
+        assert_eq!(key.open(&sealed, b"scope").unwrap().as_slice(), b"grades");
+        assert!(key.open(&sealed, b"other scope").is_none());
+        assert!(DataKey::random().open(&sealed, b"scope").is_none());
+        assert!(key.open(&sealed[..NONCE_LEN - 1], b"scope").is_none());
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(key.open(&tampered, b"scope").is_none());
+    }
+
+    #[test]
+    fn derives_keys_of_stored_accounts_from_the_vault() {
+        let vault = vault();
+        let sealed = KeyRing::new().key_for(&vault, &session()).unwrap().seal(b"data", b"");
+
+        // Another run finds the same key through the vault.
+        let ring = KeyRing::new();
+        let key = ring.key_for_scope(&vault, ID).unwrap();
+        assert_eq!(key.as_bytes(), &*vault.data_key(ID).unwrap());
+        assert!(key.open(&sealed, b"").is_some());
+        assert!(ring.get(ID).is_some());
+
+        // Sessions without a stored account get an in-memory key.
+        let guest = Session::new(SITE, "token");
+        let key = ring.key_for(&vault, &guest).unwrap();
+        assert!(key.open(&sealed, b"").is_none());
+        let again = ring.key_for(&vault, &guest).unwrap();
+        assert_eq!(key.as_bytes(), again.as_bytes());
+        assert!(ring.key_for_scope(&vault, "9@https://school.example").is_none());
+    }
+
+    #[test]
+    fn data_of_forgotten_keys_cannot_be_opened() {
+        let vault = vault();
+        let ring = KeyRing::new();
+        let sealed = ring.key_for(&vault, &session()).unwrap().seal(b"messages", ID.as_bytes());
+
+        vault.remove(ID).unwrap();
+        ring.forget(ID);
+        assert!(ring.get(ID).is_none());
+        assert!(ring.key_for_scope(&vault, ID).is_none());
+        // A new session of the same account gets a new key.
+        let key = ring.key_for(&vault, &session()).unwrap();
+        assert!(key.open(&sealed, ID.as_bytes()).is_none());
+    }
+}
@@ -0,0 +1,1405 @@
+//! Download manager for course files.
+//!
+//! Downloads are queued in a SQLite database in the app data directory and
+//! run a few at a time. Each one writes to a hidden part file in its target
+//! folder and is renamed into place once complete, so the folder never
+//! shows half-written files. Pausing keeps the part file; resuming, also
+//! after an app restart, continues it with an HTTP `Range` request guarded
+//! by `If-Range`, so a file that changed on the server starts over.
+//!
+//! File URLs, names and paths tell what a user studies; like cached
+//! responses they are sealed with the account's data key.
+//!
+//! Files go below a download root: the system Downloads folder unless the
+//! account, or one of its courses, has a folder of its own. Roots are kept
+//! in plain text since paths handed in by the frontend are checked against
+//! the roots of all accounts, locked ones included. Changing a root can move
+//! the files already downloaded and update their index entries.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use reqwest::header::{self, HeaderMap};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+
+use crate::data_key::DataKey;
+use crate::pluginfile;
+use crate::response_cache;
+use crate::sanitize;
+use crate::session::{Session, SessionError};
+use crate::ws::WsClient;
+
+/// Database file in the app data directory.
+pub const DATABASE_FILE: &str = "downloads.sqlite3";
+
+/// Event emitted with a [`DownloadProgress`] on every status change and
+/// periodically while a download runs.
+pub const PROGRESS_EVENT: &str = "download://progress";
+
+/// Downloads running at the same time unless configured otherwise.
+pub const DEFAULT_PARALLELISM: usize = 3;
+
+/// Upper bound for the configured parallelism.
+pub const MAX_PARALLELISM: usize = 8;
+
+/// Minimum time between two progress events of one download.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Course id of an account's own root in the roots table; Moodle course
+/// ids start at 1.
+const ACCOUNT_ROOT: i64 = 0;
+
+/// Commands for a running download, checked between two chunks.
+/// System folders a download root must not lie in, besides the Windows
+/// ones read from the environment.
+const SYSTEM_DIRS: [&str; 12] = [
+    "/bin", "/boot", "/dev", "/etc", "/lib", "/lib64", "/proc", "/sbin", "/sys", "/usr",
+    "/System", "/Library",
+];
+
+/// Environment variables naming Windows system folders.
+const SYSTEM_DIR_VARS: [&str; 6] =
+    ["SystemRoot", "windir", "ProgramFiles", "ProgramFiles(x86)", "ProgramW6432", "ProgramData"];
+
+const RUN: u8 = 0;
+const PAUSE: u8 = 1;
+const CANCEL: u8 = 2;
+
+#[derive(Debug, thiserror::Error, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum DownloadError {
+    #[error("Download database error: {message}")]
+    Database { message: String },
+
+    #[error("File system error: {message}")]
+    Io { message: String },
+
+    #[error("Unknown or closed session")]
+    UnknownSession,
+
+    #[error("No download with id {id}")]
+    UnknownDownload { id: String },
+
+    /// Only pluginfile URLs of the session's own site are downloaded, so
+    /// the token is never sent anywhere else.
+    #[error("{url} is not a file of this site")]
+    NotASiteFile { url: String },
+
+    /// The target folder is too deep to hold a file within the Windows
+    /// path length limit.
+    #[error("The path of {dir} is too long for a file")]
+    PathTooLong { dir: PathBuf },
+
+    /// A path from the frontend lies outside every download root.
+    #[error("{path} is not inside a download folder")]
+    OutsideRoots { path: PathBuf },
+
+    #[error("{path} cannot be used as a download folder")]
+    InvalidRoot { path: PathBuf },
+
+    /// Files are not moved while one of their downloads runs.
+    #[error("Pause the running downloads before moving their folder")]
+    Running,
+
+    /// The account's data key is unavailable (locked vault).
+    #[error("The download index of this account is locked")]
+    Locked,
+}
+
+impl From<rusqlite::Error> for DownloadError {
+    fn from(err: rusqlite::Error) -> Self {
+        DownloadError::Database { message: err.to_string() }
+    }
+}
+
+impl From<std::io::Error> for DownloadError {
+    fn from(err: std::io::Error) -> Self {
+        DownloadError::Io { message: err.to_string() }
+    }
+}
+
+impl From<SessionError> for DownloadError {
+    fn from(err: SessionError) -> Self {
+        match err {
+            SessionError::UnknownSession => DownloadError::UnknownSession,
+        }
+    }
+}
+
+/// State of a download.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DownloadStatus {
+    /// Waiting for a free slot or an open session of its account.
+    Queued,
+    Downloading,
+    /// Stopped by the user; the part file is kept for resuming.
+    Paused,
+    Completed,
+    /// Stopped by an error; resuming continues the part file.
+    Failed,
+    /// Removed with its part file. Only reported in events.
+    Cancelled,
+}
+
+impl DownloadStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            DownloadStatus::Queued => "queued",
+            DownloadStatus::Downloading => "downloading",
+            DownloadStatus::Paused => "paused",
+            DownloadStatus::Completed => "completed",
+            DownloadStatus::Failed => "failed",
+            DownloadStatus::Cancelled => "cancelled",
+        }
+    }
+
+    fn parse(s: &str) -> Self {
+        match s {
+            "downloading" => DownloadStatus::Downloading,
+            "paused" => DownloadStatus::Paused,
+            "completed" => DownloadStatus::Completed,
+            "failed" => DownloadStatus::Failed,
+            "cancelled" => DownloadStatus::Cancelled,
+            _ => DownloadStatus::Queued,
+        }
+    }
+}
+
+/// The sealed part of a download.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Meta {
+    file_url: String,
+    filename: String,
+    dir: PathBuf,
+    /// Final path, once complete.
+    path: Option<PathBuf>,
+    course_id: Option<u64>,
+    module_id: Option<u64>,
+    #[serde(default)]
+    replace: bool,
+    /// `ETag` or `Last-Modified` of the response the part file came from.
+    validator: Option<String>,
+}
+
+/// What to download and where.
+#[derive(Debug, Clone, Default)]
+pub struct DownloadRequest {
+    /// A pluginfile URL (`fileurl` from a WS response).
+    pub file_url: String,
+    pub filename: String,
+    /// Folder to save the file to.
+    pub dir: PathBuf,
+    pub course_id: Option<u64>,
+    pub module_id: Option<u64>,
+    /// Replace a file of the same name instead of saving as `name (2).ext`;
+    /// used to update mirrored courses.
+    pub replace: bool,
+}
+
+/// A download as shown to the frontend.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadItem {
+    pub id: String,
+    pub site_url: String,
+    pub file_url: String,
+    pub filename: String,
+    /// Folder the file is saved to.
+    pub dir: PathBuf,
+    /// Where the file ended up (the name may have a ` (2)` suffix).
+    pub path: Option<PathBuf>,
+    pub course_id: Option<u64>,
+    pub module_id: Option<u64>,
+    pub status: DownloadStatus,
+    pub bytes_done: u64,
+    /// `None` while the server has not said how large the file is.
+    pub total_bytes: Option<u64>,
+    pub error: Option<String>,
+    /// When the download was queued / last changed (ms since the epoch).
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+/// Payload of [`PROGRESS_EVENT`].
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadProgress {
+    pub id: String,
+    pub status: DownloadStatus,
+    pub bytes_done: u64,
+    pub total_bytes: Option<u64>,
+    pub path: Option<PathBuf>,
+    pub error: Option<String>,
+}
+
+/// The download roots of an account.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadRoots {
+    /// The system Downloads folder, used unless a root is configured.
+    pub default: Option<PathBuf>,
+    pub account: Option<PathBuf>,
+    /// Courses with a root of their own.
+    pub courses: BTreeMap<u64, PathBuf>,
+}
+
+/// Outcome of changing a download root.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RootChange {
+    /// The root now in effect.
+    pub root: PathBuf,
+    /// Files and course folders moved to the new root, relative to it.
+    pub moved: Vec<PathBuf>,
+    /// Entries left in the old root: they also hold files of downloads that
+    /// keep it, or their name is taken in the new root.
+    pub kept: Vec<PathBuf>,
+    /// Number of downloads whose index entry now points to the new root.
+    pub updated: usize,
+}
+
+/// What the manager needs from the app: sessions to download with, the
+/// keys sealing the index, the default root and a way to report progress.
+pub trait DownloadHost: Send + Sync + 'static {
+    /// An open session of the account `scope`, if any.
+    fn session(&self, scope: &str) -> Option<Session>;
+
+    /// The data key of the account `scope`.
+    fn data_key(&self, scope: &str) -> Option<DataKey>;
+
+    /// The folder downloads go to unless a root is configured.
+    fn default_root(&self) -> Option<PathBuf>;
+
+    /// Reports progress of a download.
+    fn progress(&self, progress: &DownloadProgress);
+}
+
+/// A row before its metadata is opened.
+struct Record {
+    id: String,
+    scope: String,
+    site_url: String,
+    status: DownloadStatus,
+    bytes_done: u64,
+    total_bytes: Option<u64>,
+    error: Option<String>,
+    meta: Vec<u8>,
+    created_at: i64,
+    updated_at: i64,
+}
+
+impl Record {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        let status: String = row.get("status")?;
+        let bytes_done: i64 = row.get("bytes_done")?;
+        let total_bytes: Option<i64> = row.get("total_bytes")?;
+        Ok(Self {
+            id: row.get("id")?,
+            scope: row.get("scope")?,
+            site_url: row.get("site_url")?,
+            status: DownloadStatus::parse(&status),
+            bytes_done: bytes_done as u64,
+            total_bytes: total_bytes.map(|n| n as u64),
+            error: row.get("error")?,
+            meta: row.get("meta")?,
+            created_at: row.get("created_at")?,
+            updated_at: row.get("updated_at")?,
+        })
+    }
+
+    fn open(&self, key: &DataKey) -> Option<Meta> {
+        let plain = key.open(&self.meta, &aad(&self.scope, &self.id))?;
+        serde_json::from_slice(&plain).ok()
+    }
+
+    fn item(&self, meta: Meta) -> DownloadItem {
+        DownloadItem {
+            id: self.id.clone(),
+            site_url: self.site_url.clone(),
+            file_url: meta.file_url,
+            filename: meta.filename,
+            dir: meta.dir,
+            path: meta.path,
+            course_id: meta.course_id,
+            module_id: meta.module_id,
+            status: self.status,
+            bytes_done: self.bytes_done,
+            total_bytes: self.total_bytes,
+            error: self.error.clone(),
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        }
+    }
+}
+
+/// Binds sealed metadata to its account and download.
+fn aad(scope: &str, id: &str) -> Vec<u8> {
+    format!("{scope}\0{id}").into_bytes()
+}
+
+fn seal(key: &DataKey, scope: &str, id: &str, meta: &Meta) -> Vec<u8> {
+    let plain = serde_json::to_vec(meta).expect("download metadata is serialisable");
+    key.seal(&plain, &aad(scope, id))
+}
+
+/// The part file of download `id` in `dir`.
+fn part_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!(".{id}.part"))
+}
+
+/// `path` moved from below `from` to below `to`.
+fn rebase(path: &Path, from: &Path, to: &Path) -> PathBuf {
+    path.strip_prefix(from).map_or_else(|_| path.to_path_buf(), |rest| to.join(rest))
+}
+
+/// Whether `path` (canonical) is unfit as a download root: a drive or
+/// filesystem root, the user profile or a folder containing it, or a system
+/// folder. Files inside a root can be opened from the frontend.
+fn is_unsafe_root(path: &Path) -> bool {
+    if path.parent().is_none() {
+        return true;
+    }
+    let canonical = |dir: PathBuf| dir.canonicalize().ok();
+    let from_env = |var: &&str| std::env::var_os(var).map(PathBuf::from).and_then(canonical);
+    if ["USERPROFILE", "HOME"].iter().filter_map(from_env).any(|home| home.starts_with(path)) {
+        return true;
+    }
+    SYSTEM_DIR_VARS
+        .iter()
+        .filter_map(from_env)
+        .chain(SYSTEM_DIRS.iter().filter_map(|dir| canonical(PathBuf::from(dir))))
+        .any(|dir| path.starts_with(dir))
+}
+
+/// Moves a file or folder, copying it when `from` and `to` are on different
+/// volumes.
+fn move_entry(from: &Path, to: &Path) -> std::io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    if from.is_dir() {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            move_entry(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        fs::remove_dir(from)
+    } else {
+        fs::copy(from, to)?;
+        fs::remove_file(from)
+    }
+}
+
+/// Total size from `Content-Range: bytes start-end/total`.
+fn content_range_total(headers: &HeaderMap) -> Option<u64> {
+    let value = headers.get(header::CONTENT_RANGE)?.to_str().ok()?;
+    value.rsplit_once('/')?.1.trim().parse().ok()
+}
+
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers.get(header::CONTENT_LENGTH)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// Strong validator for `If-Range`.
+fn validator(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::ETAG)
+        .filter(|v| !v.as_bytes().starts_with(b"W/"))
+        .or_else(|| headers.get(header::LAST_MODIFIED))
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Downloads held back from starting while their files are moved; released
+/// on drop.
+struct Claim<'a> {
+    inner: &'a Inner,
+    ids: Vec<String>,
+}
+
+impl<'a> Claim<'a> {
+    fn new(inner: &'a Inner, ids: Vec<String>) -> Self {
+        inner.relocating.lock().unwrap().extend(ids.iter().cloned());
+        Self { inner, ids }
+    }
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        let mut relocating = self.inner.relocating.lock().unwrap();
+        for id in &self.ids {
+            relocating.remove(id);
+        }
+    }
+}
+
+/// How a transfer ended without an error.
+enum Stop {
+    Completed { path: PathBuf, bytes: u64 },
+    Paused { bytes: u64 },
+    Cancelled,
+}
+
+struct Inner {
+    conn: Mutex<Connection>,
+    client: WsClient,
+    host: Arc<dyn DownloadHost>,
+    parallelism: AtomicUsize,
+    /// Control flags of the running downloads.
+    active: Mutex<HashMap<String, Arc<AtomicU8>>>,
+    /// Downloads whose files are being moved to another root; not started
+    /// until the move is done.
+    relocating: Mutex<HashSet<String>>,
+    /// Serialises picking a free name and renaming into it.
+    finishing: Mutex<()>,
+}
+
+/// The download queue. Held in Tauri managed state.
+#[derive(Clone)]
+pub struct DownloadManager {
+    inner: Arc<Inner>,
+}
+
+impl DownloadManager {
+    /// Opens (or creates) the queue. Downloads that were running when the
+    /// app exited are queued again; they start once [`DownloadManager::pump`]
+    /// finds an open session of their account.
+    pub fn open(
+        path: &Path,
+        client: WsClient,
+        host: Arc<dyn DownloadHost>,
+    ) -> Result<Self, DownloadError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS downloads (
+                id TEXT PRIMARY KEY,
+                scope TEXT NOT NULL,
+                site_url TEXT NOT NULL,
+                status TEXT NOT NULL,
+                bytes_done INTEGER NOT NULL DEFAULT 0,
+                total_bytes INTEGER,
+                error TEXT,
+                meta BLOB NOT NULL,
+                created_at INTEGER NOT NULL,
+                updated_at INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS downloads_status ON downloads (status, created_at);
+            CREATE TABLE IF NOT EXISTS settings (
+                name TEXT PRIMARY KEY,
+                value INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS roots (
+                scope TEXT NOT NULL,
+                course_id INTEGER NOT NULL,
+                path TEXT NOT NULL,
+                PRIMARY KEY (scope, course_id)
+            );
+            UPDATE downloads SET status = 'queued' WHERE status = 'downloading';",
+        )?;
+        let parallelism: Option<i64> = conn
+            .query_row("SELECT value FROM settings WHERE name = 'parallelism'", [], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        let parallelism = parallelism.map_or(DEFAULT_PARALLELISM, |n| n as usize);
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                conn: Mutex::new(conn),
+                client,
+                host,
+                parallelism: AtomicUsize::new(parallelism.clamp(1, MAX_PARALLELISM)),
+                active: Mutex::new(HashMap::new()),
+                relocating: Mutex::new(HashSet::new()),
+                finishing: Mutex::new(()),
+            }),
+        })
+    }
+
+    /// How many downloads run at the same time.
+    pub fn parallelism(&self) -> usize {
+        self.inner.parallelism.load(Ordering::Relaxed)
+    }
+
+    /// Changes and stores the parallelism (clamped to 1..=[`MAX_PARALLELISM`]).
+    /// Running downloads beyond a lowered limit finish normally.
+    pub fn set_parallelism(&self, parallelism: usize) -> Result<usize, DownloadError> {
+        let parallelism = parallelism.clamp(1, MAX_PARALLELISM);
+        self.inner.conn.lock().unwrap().execute(
+            "INSERT INTO settings (name, value) VALUES ('parallelism', ?1)
+             ON CONFLICT (name) DO UPDATE SET value = excluded.value",
+            params![parallelism as i64],
+        )?;
+        self.inner.parallelism.store(parallelism, Ordering::Relaxed);
+        self.pump();
+        Ok(parallelism)
+    }
+
+    /// The folder new downloads of `scope` go to: the root of `course_id`,
+    /// else the account's, else the default.
+    pub fn root(&self, scope: &str, course_id: Option<u64>) -> Result<PathBuf, DownloadError> {
+        let course_id = course_id.map_or(ACCOUNT_ROOT, |id| id as i64);
+        let path: Option<String> = self
+            .inner
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT path FROM roots WHERE scope = ?1 AND course_id IN (?2, ?3)
+                 ORDER BY course_id DESC LIMIT 1",
+                params![scope, course_id, ACCOUNT_ROOT],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match path {
+            Some(path) => Ok(PathBuf::from(path)),
+            None => self.default_root(),
+        }
+    }
+
+    /// The configured roots of an account.
+    pub fn roots(&self, scope: &str) -> Result<DownloadRoots, DownloadError> {
+        let rows: Vec<(i64, String)> = {
+            let conn = self.inner.conn.lock().unwrap();
+            let mut stmt = conn.prepare("SELECT course_id, path FROM roots WHERE scope = ?1")?;
+            let rows = stmt.query_map(params![scope], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+        let mut roots = DownloadRoots {
+            default: self.inner.host.default_root(),
+            account: None,
+            courses: BTreeMap::new(),
+        };
+        for (course_id, path) in rows {
+            if course_id == ACCOUNT_ROOT {
+                roots.account = Some(PathBuf::from(path));
+            } else {
+                roots.courses.insert(course_id as u64, PathBuf::from(path));
+            }
+        }
+        Ok(roots)
+    }
+
+    /// The roots configured for any account or course. Roots that
+    /// [`DownloadManager::set_root`] would refuse are left out.
+    pub fn configured_roots(&self) -> Result<Vec<PathBuf>, DownloadError> {
+        let conn = self.inner.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT DISTINCT path FROM roots")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let roots: Vec<PathBuf> =
+            rows.map(|path| path.map(PathBuf::from)).collect::<rusqlite::Result<_>>()?;
+        Ok(roots
+            .into_iter()
+            .filter(|root| root.canonicalize().is_ok_and(|root| !is_unsafe_root(&root)))
+            .collect())
+    }
+
+    /// `path`, resolved, if it lies inside the default root or a root of
+    /// any account. Guards the commands that open or reveal files.
+    pub fn check_path(&self, path: &Path) -> Result<PathBuf, DownloadError> {
+        let outside = || DownloadError::OutsideRoots { path: path.to_path_buf() };
+        let resolved = path.canonicalize().map_err(|_| outside())?;
+        let inside = self
+            .configured_roots()?
+            .into_iter()
+            .chain(self.inner.host.default_root())
+            .filter_map(|root| root.canonicalize().ok())
+            .any(|root| resolved.starts_with(root));
+        if inside {
+            Ok(resolved)
+        } else {
+            Err(outside())
+        }
+    }
+
+    /// Sets the root of an account, or of one of its courses; `None` goes
+    /// back to the account's root or the default. With `move_files`, files
+    /// downloaded to the previous root are moved along and their downloads
+    /// point to the new one. Blocks while the files are moved.
+    ///
+    /// `path` must be an existing folder; drive roots, the user profile and
+    /// system folders are refused.
+    pub fn set_root(
+        &self,
+        scope: &str,
+        course_id: Option<u64>,
+        path: Option<PathBuf>,
+        move_files: bool,
+    ) -> Result<RootChange, DownloadError> {
+        if let Some(path) = &path {
+            let canonical = path.canonicalize().ok().filter(|p| p.is_dir());
+            if !path.is_absolute() || canonical.is_none_or(|p| is_unsafe_root(&p)) {
+                return Err(DownloadError::InvalidRoot { path: path.clone() });
+            }
+        }
+        let previous = self.root(scope, course_id)?;
+        let root = match &path {
+            Some(path) => path.clone(),
+            None if course_id.is_some() => self.root(scope, None)?,
+            None => self.default_root()?,
+        };
+        fs::create_dir_all(&root)?;
+        let change = match move_files && root != previous {
+            true => self.relocate(scope, course_id, &previous, &root)?,
+            false => RootChange { root, ..RootChange::default() },
+        };
+
+        let course_id = course_id.map_or(ACCOUNT_ROOT, |id| id as i64);
+        let conn = self.inner.conn.lock().unwrap();
+        match path {
+            Some(path) => conn.execute(
+                "INSERT INTO roots (scope, course_id, path) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (scope, course_id) DO UPDATE SET path = excluded.path",
+                params![scope, course_id, path.to_string_lossy()],
+            )?,
+            None => conn.execute(
+                "DELETE FROM roots WHERE scope = ?1 AND course_id = ?2",
+                params![scope, course_id],
+            )?,
+        };
+        drop(conn);
+        // Downloads held back during the move may start now.
+        self.pump();
+        Ok(change)
+    }
+
+    /// Moves the files of the downloads of `scope` (of `course_id`, or of
+    /// all courses without a root of their own) from `from` to `to` and
+    /// points their index entries there. Whole top-level entries of `from`
+    /// are moved, so course folders keep their mirror manifest and archive.
+    /// Downloads of locked accounts are not known and may be left behind.
+    fn relocate(
+        &self,
+        scope: &str,
+        course_id: Option<u64>,
+        from: &Path,
+        to: &Path,
+    ) -> Result<RootChange, DownloadError> {
+        let (own_roots, records) = {
+            let conn = self.inner.conn.lock().unwrap();
+            let mut stmt = conn.prepare("SELECT course_id FROM roots WHERE scope = ?1")?;
+            let rows = stmt.query_map(params![scope], |row| row.get::<_, i64>(0))?;
+            let own_roots: HashSet<u64> = rows
+                .collect::<rusqlite::Result<Vec<_>>>()?
+                .into_iter()
+                .map(|id| id as u64)
+                .collect();
+            let mut stmt = conn.prepare("SELECT * FROM downloads")?;
+            let rows = stmt.query_map([], Record::from_row)?;
+            (own_roots, rows.collect::<rusqlite::Result<Vec<_>>>()?)
+        };
+
+        let mut keys: HashMap<String, Option<DataKey>> = HashMap::new();
+        let mut moving = Vec::new();
+        // Entries also holding files of downloads that keep their folder.
+        let mut shared = HashSet::new();
+        for record in records {
+            let key = keys
+                .entry(record.scope.clone())
+                .or_insert_with(|| self.inner.host.data_key(&record.scope));
+            let Some(key) = key.clone() else {
+                continue;
+            };
+            let Some(meta) = record.open(&key) else {
+                continue;
+            };
+            let file = meta.path.clone().unwrap_or_else(|| part_path(&meta.dir, &record.id));
+            let Some(entry) = file.strip_prefix(from).ok().and_then(|rest| rest.iter().next())
+            else {
+                continue;
+            };
+            let entry = PathBuf::from(entry);
+            let mine = record.scope == scope
+                && match course_id {
+                    Some(course_id) => meta.course_id == Some(course_id),
+                    None => !meta.course_id.is_some_and(|id| own_roots.contains(&id)),
+                };
+            if !mine {
+                shared.insert(entry);
+                continue;
+            }
+            moving.push((record, key, meta, entry));
+        }
+
+        // Claims the downloads so none of them starts while its files move;
+        // `active` is only held for the check, not during the copy.
+        let _claim = {
+            let active = self.inner.active.lock().unwrap();
+            if moving.iter().any(|(record, ..)| active.contains_key(&record.id)) {
+                return Err(DownloadError::Running);
+            }
+            let ids = moving.iter().map(|(record, ..)| record.id.clone()).collect();
+            Claim::new(&self.inner, ids)
+        };
+
+        let mut change = RootChange { root: to.to_path_buf(), ..RootChange::default() };
+        let entries: BTreeSet<PathBuf> = moving.iter().map(|(.., entry)| entry.clone()).collect();
+        for entry in entries {
+            let (source, target) = (from.join(&entry), to.join(&entry));
+            if shared.contains(&entry) || target.exists() || to.starts_with(&source) {
+                change.kept.push(entry);
+            } else if source.exists() {
+                move_entry(&source, &target)?;
+                change.moved.push(entry);
+            }
+        }
+        for (record, key, mut meta, entry) in moving {
+            if change.kept.contains(&entry) {
+                continue;
+            }
+            meta.dir = rebase(&meta.dir, from, to);
+            meta.path = meta.path.map(|path| rebase(&path, from, to));
+            self.inner.set_meta(&record.id, &record.scope, &key, &meta)?;
+            change.updated += 1;
+        }
+        Ok(change)
+    }
+
+    fn default_root(&self) -> Result<PathBuf, DownloadError> {
+        let root = self.inner.host.default_root();
+        root.ok_or_else(|| DownloadError::Io { message: "No Downloads folder".to_string() })
+    }
+
+    /// Queues a pluginfile URL of `session`'s site for download.
+    pub fn enqueue(
+        &self,
+        session: &Session,
+        request: DownloadRequest,
+    ) -> Result<DownloadItem, DownloadError> {
+        if pluginfile::file_download_url(session, &request.file_url).is_none() {
+            return Err(DownloadError::NotASiteFile { url: request.file_url });
+        }
+        let meta = Meta {
+            file_url: pluginfile::without_token(&request.file_url),
+            filename: sanitize::fit(&request.dir, &sanitize::file_name(&request.filename))
+                .ok_or_else(|| DownloadError::PathTooLong { dir: request.dir.clone() })?,
+            dir: request.dir,
+            path: None,
+            course_id: request.course_id,
+            module_id: request.module_id,
+            replace: request.replace,
+            validator: None,
+        };
+        let scope = session.scope();
+        let key = self.inner.host.data_key(scope).ok_or(DownloadError::Locked)?;
+        let id = uuid::Uuid::new_v4().to_string();
+        let now = response_cache::now_ms();
+        self.inner.conn.lock().unwrap().execute(
+            "INSERT INTO downloads (id, scope, site_url, status, meta, created_at, updated_at)
+             VALUES (?1, ?2, ?3, 'queued', ?4, ?5, ?5)",
+            params![id, scope, session.site_url(), seal(&key, scope, &id, &meta), now],
+        )?;
+
+        let record = self.inner.record(&id)?.ok_or(DownloadError::UnknownDownload { id })?;
+        self.inner.report(&record.id, DownloadStatus::Queued, 0, None, None, None);
+        self.pump();
+        Ok(record.item(meta))
+    }
+
+    /// One download.
+    pub fn get(&self, id: &str) -> Result<DownloadItem, DownloadError> {
+        let record = self.inner.record(id)?;
+        let record = record.ok_or_else(|| DownloadError::UnknownDownload { id: id.to_string() })?;
+        let key = self.inner.host.data_key(&record.scope).ok_or(DownloadError::Locked)?;
+        let meta = record.open(&key).ok_or(DownloadError::Locked)?;
+        Ok(record.item(meta))
+    }
+
+    /// All downloads, or those of one account, oldest first. Downloads of
+    /// accounts whose key is unavailable are left out.
+    pub fn list(&self, scope: Option<&str>) -> Result<Vec<DownloadItem>, DownloadError> {
+        let records = {
+            let conn = self.inner.conn.lock().unwrap();
+            let mut stmt = conn.prepare(
+                "SELECT * FROM downloads WHERE ?1 IS NULL OR scope = ?1
+                 ORDER BY created_at, id",
+            )?;
+            let rows = stmt.query_map(params![scope], Record::from_row)?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+        let mut keys: HashMap<String, Option<DataKey>> = HashMap::new();
+        Ok(records
+            .iter()
+            .filter_map(|record| {
+                let key = keys
+                    .entry(record.scope.clone())
+                    .or_insert_with(|| self.inner.host.data_key(&record.scope));
+                Some(record.item(record.open(key.as_ref()?)?))
+            })
+            .collect())
+    }
+
+    /// Pauses a queued or running download.
+    pub fn pause(&self, id: &str) -> Result<(), DownloadError> {
+        let active = self.inner.active.lock().unwrap();
+        if let Some(control) = active.get(id) {
+            control.store(PAUSE, Ordering::Relaxed);
+            return Ok(());
+        }
+        let record = self.inner.record(id)?;
+        let record = record.ok_or_else(|| DownloadError::UnknownDownload { id: id.to_string() })?;
+        if record.status == DownloadStatus::Queued {
+            self.inner.set_status(id, DownloadStatus::Paused, record.bytes_done, None)?;
+            let total = record.total_bytes;
+            self.inner.report(id, DownloadStatus::Paused, record.bytes_done, total, None, None);
+        }
+        Ok(())
+    }
+
+    /// Queues a paused or failed download again; it continues where it
+    /// stopped.
+    pub fn resume(&self, id: &str) -> Result<(), DownloadError> {
+        let record = self.inner.record(id)?;
+        let record = record.ok_or_else(|| DownloadError::UnknownDownload { id: id.to_string() })?;
+        if matches!(record.status, DownloadStatus::Paused | DownloadStatus::Failed) {
+            self.inner.set_status(id, DownloadStatus::Queued, record.bytes_done, None)?;
+            let total = record.total_bytes;
+            self.inner.report(id, DownloadStatus::Queued, record.bytes_done, total, None, None);
+            self.pump();
+        }
+        Ok(())
+    }
+
+    /// Cancels a download and deletes its part file. For a completed
+    /// download only the entry is removed; the file stays.
+    pub fn cancel(&self, id: &str) -> Result<(), DownloadError> {
+        let active = self.inner.active.lock().unwrap();
+        if let Some(control) = active.get(id) {
+            control.store(CANCEL, Ordering::Relaxed);
+            return Ok(());
+        }
+        let record = self.inner.record(id)?;
+        let record = record.ok_or_else(|| DownloadError::UnknownDownload { id: id.to_string() })?;
+        if record.status != DownloadStatus::Completed {
+            let meta = self.inner.host.data_key(&record.scope).and_then(|key| record.open(&key));
+            if let Some(meta) = meta {
+                let _ = fs::remove_file(part_path(&meta.dir, id));
+            }
+        }
+        self.inner.delete(id)?;
+        self.inner.report(id, DownloadStatus::Cancelled, 0, None, None, None);
+        Ok(())
+    }
+
+    /// Drops every download of `scope`, e.g. when its account is removed:
+    /// running ones are cancelled, part files deleted and the entries and
+    /// the account's roots removed. Downloaded files stay.
+    pub fn purge(&self, scope: &str) -> Result<(), DownloadError> {
+        let ids: Vec<String> = {
+            let conn = self.inner.conn.lock().unwrap();
+            let mut stmt = conn.prepare("SELECT id FROM downloads WHERE scope = ?1")?;
+            let rows = stmt.query_map(params![scope], |row| row.get(0))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+        for id in ids {
+            let _ = self.cancel(&id);
+        }
+        let conn = self.inner.conn.lock().unwrap();
+        conn.execute("DELETE FROM downloads WHERE scope = ?1", params![scope])?;
+        conn.execute("DELETE FROM roots WHERE scope = ?1", params![scope])?;
+        Ok(())
+    }
+
+    /// Starts queued downloads while slots are free, oldest first. Called
+    /// after every change to the queue; the app also calls it when a session
+    /// is opened, since downloads wait for a session of their account.
+    pub fn pump(&self) {
+        Inner::pump(&self.inner);
+    }
+}
+
+impl Inner {
+    fn record(&self, id: &str) -> rusqlite::Result<Option<Record>> {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT * FROM downloads WHERE id = ?1", params![id], Record::from_row)
+            .optional()
+    }
+
+    fn set_status(
+        &self,
+        id: &str,
+        status: DownloadStatus,
+        bytes_done: u64,
+        error: Option<&str>,
+    ) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute(
+            "UPDATE downloads SET status = ?2, bytes_done = ?3, error = ?4, updated_at = ?5
+             WHERE id = ?1",
+            params![id, status.as_str(), bytes_done as i64, error, response_cache::now_ms()],
+        )?;
+        Ok(())
+    }
+
+    fn set_progress(&self, id: &str, bytes_done: u64, total: Option<u64>) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute(
+            "UPDATE downloads SET bytes_done = ?2, total_bytes = ?3, updated_at = ?4 WHERE id = ?1",
+            params![id, bytes_done as i64, total.map(|n| n as i64), response_cache::now_ms()],
+        )?;
+        Ok(())
+    }
+
+    fn set_meta(&self, id: &str, scope: &str, key: &DataKey, meta: &Meta) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute(
+            "UPDATE downloads SET meta = ?2 WHERE id = ?1",
+            params![id, seal(key, scope, id, meta)],
+        )?;
+        Ok(())
+    }
+
+    fn delete(&self, id: &str) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute("DELETE FROM downloads WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    fn report(
+        &self,
+        id: &str,
+        status: DownloadStatus,
+        bytes_done: u64,
+        total_bytes: Option<u64>,
+        path: Option<PathBuf>,
+        error: Option<String>,
+    ) {
+        self.host.progress(&DownloadProgress {
+            id: id.to_string(),
+            status,
+            bytes_done,
+            total_bytes,
+            path,
+            error,
+        });
+    }
+
+    fn pump(this: &Arc<Inner>) {
+        let mut active = this.active.lock().unwrap();
+        let limit = this.parallelism.load(Ordering::Relaxed);
+        if active.len() >= limit {
+            return;
+        }
+        let queued: Vec<(String, String)> = {
+            let conn = this.conn.lock().unwrap();
+            let Ok(mut stmt) = conn.prepare(
+                "SELECT id, scope FROM downloads WHERE status = 'queued' ORDER BY created_at, id",
+            ) else {
+                return;
+            };
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)));
+            rows.and_then(|rows| rows.collect()).unwrap_or_default()
+        };
+
+        for (id, scope) in queued {
+            if active.len() >= limit {
+                break;
+            }
+            // Still queued in the database until its task has started.
+            if active.contains_key(&id) || this.relocating.lock().unwrap().contains(&id) {
+                continue;
+            }
+            let (Some(session), Some(key)) = (this.host.session(&scope), this.host.data_key(&scope))
+            else {
+                continue;
+            };
+            let control = Arc::new(AtomicU8::new(RUN));
+            active.insert(id.clone(), control.clone());
+            let inner = this.clone();
+            tauri::async_runtime::spawn(async move {
+                inner.run(&id, &session, &key, &control).await;
+                inner.active.lock().unwrap().remove(&id);
+                Inner::pump(&inner);
+            });
+        }
+    }
+
+    /// Runs one download to its end and records the outcome.
+    async fn run(&self, id: &str, session: &Session, key: &DataKey, control: &AtomicU8) {
+        let Ok(Some(record)) = self.record(id) else {
+            return;
+        };
+        let Some(meta) = record.open(key) else {
+            let message = "The download entry cannot be decrypted".to_string();
+            let _ = self.set_status(id, DownloadStatus::Failed, 0, Some(&message));
+            self.report(id, DownloadStatus::Failed, 0, None, None, Some(message));
+            return;
+        };
+        let (done, total) = (record.bytes_done, record.total_bytes);
+        let _ = self.set_status(id, DownloadStatus::Downloading, done, None);
+        self.report(id, DownloadStatus::Downloading, done, total, None, None);
+
+        let part = part_path(&meta.dir, id);
+        let outcome = self.transfer(&record, meta, session, key, control).await;
+        let outcome = match outcome {
+            // A cancel wins over whatever stopped the transfer.
+            _ if control.load(Ordering::Relaxed) == CANCEL => Ok(Stop::Cancelled),
+            outcome => outcome,
+        };
+        let total = self.record(id).ok().flatten().and_then(|r| r.total_bytes);
+        match outcome {
+            Ok(Stop::Completed { path, bytes }) => {
+                let _ = self.set_status(id, DownloadStatus::Completed, bytes, None);
+                self.report(id, DownloadStatus::Completed, bytes, total, Some(path), None);
+            }
+            Ok(Stop::Paused { bytes }) => {
+                let _ = self.set_status(id, DownloadStatus::Paused, bytes, None);
+                self.report(id, DownloadStatus::Paused, bytes, total, None, None);
+            }
+            Ok(Stop::Cancelled) => {
+                let _ = fs::remove_file(&part);
+                let _ = self.delete(id);
+                self.report(id, DownloadStatus::Cancelled, 0, None, None, None);
+            }
+            Err(message) => {
+                let bytes = fs::metadata(&part).map_or(0, |m| m.len());
+                let _ = self.set_status(id, DownloadStatus::Failed, bytes, Some(&message));
+                self.report(id, DownloadStatus::Failed, bytes, total, None, Some(message));
+            }
+        }
+    }
+
+    /// Fetches the file into its part file and renames it into place.
+    /// Errors are messages for the user; they never contain the token.
+    async fn transfer(
+        &self,
+        record: &Record,
+        mut meta: Meta,
+        session: &Session,
+        key: &DataKey,
+        control: &AtomicU8,
+    ) -> Result<Stop, String> {
+        let id = &record.id;
+        let url = pluginfile::file_download_url(session, &meta.file_url)
+            .ok_or("The file does not belong to this site")?;
+        self.client.check_url(&url).map_err(|e| e.to_string())?;
+        fs::create_dir_all(&meta.dir).map_err(|e| e.to_string())?;
+        let part = part_path(&meta.dir, id);
+        let offset = fs::metadata(&part).map_or(0, |m| m.len());
+
+        let mut request = self.client.http().get(&url);
+        if offset > 0 {
+            request = request.header(header::RANGE, format!("bytes={offset}-"));
+            if let Some(validator) = &meta.validator {
+                request = request.header(header::IF_RANGE, validator);
+            }
+        }
+        let mut response = request.send().await.map_err(|e| e.without_url().to_string())?;
+        let headers = response.headers().clone();
+
+        let (mut file, mut done, total) = match response.status().as_u16() {
+            206 if offset > 0 => {
+                let file = OpenOptions::new().append(true).open(&part).map_err(|e| e.to_string())?;
+                let total = content_range_total(&headers)
+                    .or_else(|| content_length(&headers).map(|n| offset + n));
+                (file, offset, total)
+            }
+            200 => {
+                // Moodle answers a refused token with a JSON error; real
+                // files always come with a Content-Disposition header.
+                let is_json = headers
+                    .get(header::CONTENT_TYPE)
+                    .is_some_and(|v| v.as_bytes().starts_with(b"application/json"));
+                if is_json && !headers.contains_key(header::CONTENT_DISPOSITION) {
+                    return Err("Moodle refused the download".into());
+                }
+                meta.validator = validator(&headers);
+                let _ = self.set_meta(id, &record.scope, key, &meta);
+                (File::create(&part).map_err(|e| e.to_string())?, 0, content_length(&headers))
+            }
+            // The part file already holds the whole file.
+            416 if record.total_bytes == Some(offset) => {
+                let file = OpenOptions::new().append(true).open(&part).map_err(|e| e.to_string())?;
+                (file, offset, record.total_bytes)
+            }
+            416 => {
+                let _ = fs::remove_file(&part);
+                return Err("The file changed on the server; resume to start over".into());
+            }
+            status => return Err(format!("The server answered with HTTP {status}")),
+        };
+        let _ = self.set_progress(id, done, total);
+
+        let mut reported = Instant::now();
+        loop {
+            match control.load(Ordering::Relaxed) {
+                PAUSE => {
+                    file.flush().map_err(|e| e.to_string())?;
+                    return Ok(Stop::Paused { bytes: done });
+                }
+                CANCEL => return Ok(Stop::Cancelled),
+                _ => {}
+            }
+            let Some(chunk) = response.chunk().await.map_err(|e| e.without_url().to_string())?
+            else {
+                break;
+            };
+            file.write_all(&chunk).map_err(|e| e.to_string())?;
+            done += chunk.len() as u64;
+            if reported.elapsed() >= PROGRESS_INTERVAL {
+                reported = Instant::now();
+                let _ = self.set_progress(id, done, total);
+                self.report(id, DownloadStatus::Downloading, done, total, None, None);
+            }
+        }
+        file.sync_all().map_err(|e| e.to_string())?;
+        drop(file);
+        let _ = self.set_progress(id, done, total.or(Some(done)));
+        if total.is_some_and(|total| total != done) {
+            return Err("The connection closed before the file was complete".into());
+        }
+
+        let path = {
+            let _guard = self.finishing.lock().unwrap();
+            let path = match meta.replace {
+                true => meta.dir.join(&meta.filename),
+                false => sanitize::unique_path(&meta.dir, &meta.filename),
+            };
+            fs::rename(&part, &path).map_err(|e| e.to_string())?;
+            path
+        };
+        meta.path = Some(path.clone());
+        let _ = self.set_meta(id, &record.scope, key, &meta);
+        Ok(Stop::Completed { path, bytes: done })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::{test_client, MockServer, TestHost};
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("downloads-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn manager(site_url: &str) -> (DownloadManager, Arc<TestHost>, PathBuf) {
+        let dir = temp_dir();
+        let session = Session::new(site_url, "secret").with_account_id("2@site");
+        let host = Arc::new(TestHost::new(session, dir.join("Downloads")));
+        fs::create_dir_all(&host.default_root).unwrap();
+        let downloads =
+            DownloadManager::open(&dir.join(DATABASE_FILE), test_client(), host.clone()).unwrap();
+        (downloads, host, dir)
+    }
+
+    /// Queues `notes.txt` of the mock server while the host is offline.
+    fn queue(downloads: &DownloadManager, host: &TestHost, site_url: &str) -> DownloadItem {
+        host.online.store(false, Ordering::SeqCst);
+        let request = DownloadRequest {
+            file_url: format!("{site_url}/pluginfile.php/5/mod_resource/content/1/notes.txt"),
+            filename: "notes.txt".into(),
+            dir: host.default_root.clone(),
+            ..DownloadRequest::default()
+        };
+        downloads.enqueue(&host.session, request).unwrap()
+    }
+
+    /// Leaves `bytes` in the part file of `item`, as an interrupted download
+    /// of a response with `validator` would.
+    fn interrupt(downloads: &DownloadManager, host: &TestHost, item: &DownloadItem, bytes: &[u8]) {
+        fs::write(part_path(&item.dir, &item.id), bytes).unwrap();
+        let inner = &downloads.inner;
+        let record = inner.record(&item.id).unwrap().unwrap();
+        let meta = Meta { validator: Some("\"v1\"".into()), ..record.open(&host.key).unwrap() };
+        inner.set_meta(&item.id, &record.scope, &host.key, &meta).unwrap();
+        inner.set_status(&item.id, DownloadStatus::Paused, bytes.len() as u64, None).unwrap();
+        inner.set_progress(&item.id, bytes.len() as u64, Some(10)).unwrap();
+    }
+
+    /// Goes online, resumes the download and waits until it stopped.
+    async fn run(downloads: &DownloadManager, host: &TestHost, id: &str) -> DownloadItem {
+        host.online.store(true, Ordering::SeqCst);
+        downloads.resume(id).unwrap();
+        settle(downloads, id).await
+    }
+
+    /// Waits until the download is neither queued nor running.
+    async fn settle(downloads: &DownloadManager, id: &str) -> DownloadItem {
+        for _ in 0..500 {
+            let item = downloads.get(id).unwrap();
+            let running = downloads.inner.active.lock().unwrap().contains_key(id);
+            if !running && item.status != DownloadStatus::Queued {
+                return item;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("download {id} did not finish");
+    }
+
+    #[tokio::test]
+    async fn resumes_with_range_and_if_range() {
+        let server = MockServer::start_with_headers(|req| match req.header("range") {
+            Some("bytes=4-") if req.header("if-range") == Some("\"v1\"") => {
+                (206, vec![("Content-Range".into(), "bytes 4-9/10".into())], "456789".into())
+            }
+            _ => (200, vec![("ETag".into(), "\"v1\"".into())], "0123456789".into()),
+        });
+        let (downloads, host, _) = manager(&server.url());
+        let item = queue(&downloads, &host, &server.url());
+        interrupt(&downloads, &host, &item, b"0123");
+
+        let item = run(&downloads, &host, &item.id).await;
+
+        assert_eq!(item.status, DownloadStatus::Completed);
+        assert_eq!(fs::read_to_string(item.path.unwrap()).unwrap(), "0123456789");
+        assert_eq!((item.bytes_done, item.total_bytes), (10, Some(10)));
+        let req = server.last_request();
+        assert!(req.path.starts_with("/webservice/pluginfile.php/5/"));
+        assert_eq!(req.header("range"), Some("bytes=4-"));
+    }
+
+    #[tokio::test]
+    async fn restarts_when_the_server_ignores_the_range() {
+        let server = MockServer::start_with_headers(|_| {
+            (200, vec![("ETag".into(), "\"v2\"".into())], "abcdefghij".into())
+        });
+        let (downloads, host, _) = manager(&server.url());
+        let item = queue(&downloads, &host, &server.url());
+        interrupt(&downloads, &host, &item, b"0123");
+
+        let item = run(&downloads, &host, &item.id).await;
+
+        assert_eq!(item.status, DownloadStatus::Completed);
+        assert_eq!(fs::read_to_string(item.path.unwrap()).unwrap(), "abcdefghij");
+        assert_eq!(server.last_request().header("range"), Some("bytes=4-"));
+        let record = downloads.inner.record(&item.id).unwrap().unwrap();
+        assert_eq!(record.open(&host.key).unwrap().validator.as_deref(), Some("\"v2\""));
+    }
+
+    #[tokio::test]
+    async fn handles_range_not_satisfiable() {
+        let server = MockServer::start(|_| (416, String::new()));
+        let (downloads, host, _) = manager(&server.url());
+
+        // The part file already holds the whole file.
+        let complete = queue(&downloads, &host, &server.url());
+        interrupt(&downloads, &host, &complete, b"0123456789");
+        let complete = run(&downloads, &host, &complete.id).await;
+        assert_eq!(complete.status, DownloadStatus::Completed);
+        assert_eq!(fs::read_to_string(complete.path.unwrap()).unwrap(), "0123456789");
+
+        // The file shrank on the server: start over on the next resume.
+        let changed = queue(&downloads, &host, &server.url());
+        interrupt(&downloads, &host, &changed, b"0123456789ab");
+        let changed = run(&downloads, &host, &changed.id).await;
+        assert_eq!(changed.status, DownloadStatus::Failed);
+        assert!(changed.error.unwrap().contains("changed on the server"));
+        assert!(!part_path(&changed.dir, &changed.id).exists());
+    }
+
+    #[tokio::test]
+    async fn pauses_and_cancels_running_downloads() {
+        let server = MockServer::start(|_| {
+            std::thread::sleep(Duration::from_millis(100));
+            (200, "0123456789".into())
+        });
+        let (downloads, host, _) = manager(&server.url());
+
+        let paused = queue(&downloads, &host, &server.url());
+        host.online.store(true, Ordering::SeqCst);
+        downloads.pump();
+        downloads.pause(&paused.id).unwrap();
+        let paused = settle(&downloads, &paused.id).await;
+        assert_eq!(paused.status, DownloadStatus::Paused);
+        assert!(part_path(&paused.dir, &paused.id).exists());
+        let resumed = run(&downloads, &host, &paused.id).await;
+        assert_eq!(resumed.status, DownloadStatus::Completed);
+
+        let cancelled = queue(&downloads, &host, &server.url());
+        host.online.store(true, Ordering::SeqCst);
+        downloads.pump();
+        downloads.cancel(&cancelled.id).unwrap();
+        for _ in 0..500 {
+            if downloads.inner.record(&cancelled.id).unwrap().is_none() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(matches!(
+            downloads.get(&cancelled.id),
+            Err(DownloadError::UnknownDownload { .. })
+        ));
+        assert!(!part_path(&cancelled.dir, &cancelled.id).exists());
+        let events = host.events.lock().unwrap();
+        let last = events.iter().rev().find(|event| event.id == cancelled.id).unwrap();
+        assert_eq!(last.status, DownloadStatus::Cancelled);
+    }
+
+    #[test]
+    fn requeues_running_downloads_on_restart() {
+        let (downloads, host, dir) = manager("https://school.example");
+        let item = queue(&downloads, &host, "https://school.example");
+        downloads.inner.set_status(&item.id, DownloadStatus::Downloading, 4, None).unwrap();
+        drop(downloads);
+
+        let host: Arc<dyn DownloadHost> = host;
+        let downloads = DownloadManager::open(&dir.join(DATABASE_FILE), test_client(), host);
+        let item = downloads.unwrap().get(&item.id).unwrap();
+
+        assert_eq!(item.status, DownloadStatus::Queued);
+        assert_eq!(item.bytes_done, 4);
+    }
+
+    #[test]
+    fn checks_paths_against_the_roots() {
+        let (downloads, host, dir) = manager("https://school.example");
+        let inside = host.default_root.join("course").join("notes.txt");
+        fs::create_dir_all(inside.parent().unwrap()).unwrap();
+        fs::write(&inside, "notes").unwrap();
+        let outside = dir.join("secret.txt");
+        fs::write(&outside, "secret").unwrap();
+
+        assert_eq!(downloads.check_path(&inside).unwrap(), inside.canonicalize().unwrap());
+        let escaped = host.default_root.join("course").join("..").join("..").join("secret.txt");
+        for path in [outside, escaped, host.default_root.join("missing.txt")] {
+            assert!(
+                matches!(downloads.check_path(&path), Err(DownloadError::OutsideRoots { .. })),
+                "{}",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_unsafe_roots() {
+        let (downloads, _, dir) = manager("https://school.example");
+        let refused = |path: &Path| {
+            matches!(
+                downloads.set_root("2@site", None, Some(path.to_path_buf()), false),
+                Err(DownloadError::InvalidRoot { .. })
+            )
+        };
+
+        let filesystem_root = dir.ancestors().last().unwrap();
+        assert!(refused(filesystem_root));
+        assert!(refused(Path::new("relative/folder")));
+        assert!(refused(&dir.join("missing")));
+        if let Some(home) = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")) {
+            assert!(refused(Path::new(&home)));
+        }
+        if cfg!(unix) {
+            assert!(refused(Path::new("/usr/bin")));
+        }
+
+        let chosen = dir.join("Moodle");
+        fs::create_dir_all(&chosen).unwrap();
+        let change = downloads.set_root("2@site", None, Some(chosen.clone()), false).unwrap();
+        assert_eq!(change.root, chosen);
+        assert_eq!(downloads.root("2@site", Some(7)).unwrap(), chosen);
+        assert_eq!(downloads.configured_roots().unwrap(), vec![chosen]);
+    }
+}
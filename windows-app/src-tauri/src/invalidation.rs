@@ -0,0 +1,241 @@
+//! Which cached reads a successful write call makes outdated.
+//!
+//! The response cache would otherwise keep serving a discussion without the
+//! reply the user just posted until its TTL runs out. [`RULES`] lists, per
+//! write function, the read functions it affects and how to find the
+//! affected entries: by a matching parameter, by an id inside the cached
+//! response, or all entries of the function when the write parameters do
+//! not say which ones.
+
+use serde_json::Value;
+
+use self::Selector::{All, Listed, Param};
+
+/// How to pick the cached entries of a read function.
+#[derive(Debug, Clone, Copy)]
+pub enum Selector {
+    /// Every entry of the read function.
+    All,
+    /// Entries whose read parameter `read` equals write parameter `write`.
+    Param { write: &'static str, read: &'static str },
+    /// Entries whose cached response has an item in array `list` whose
+    /// `field` equals write parameter `write` (e.g. the post being replied
+    /// to when only its id is known).
+    Listed { write: &'static str, list: &'static str, field: &'static str },
+}
+
+/// Read functions made outdated by one write function.
+pub struct Rule {
+    pub write: &'static str,
+    pub evicts: &'static [(&'static str, Selector)],
+}
+
+const POSTS_OF_POST: &[(&str, Selector)] = &[
+    ("mod_forum_get_discussion_posts", Listed { write: "postid", list: "posts", field: "id" }),
+    (
+        "mod_forum_get_forum_discussion_posts",
+        Listed { write: "postid", list: "posts", field: "id" },
+    ),
+    // Reply counts and "last post" in the discussion list.
+    ("mod_forum_get_forum_discussions", All),
+];
+
+const ASSIGN_SUBMISSION: &[(&str, Selector)] = &[(
+    "mod_assign_get_submission_status",
+    Param { write: "assignmentid", read: "assignid" },
+)];
+
+const QUIZ_ATTEMPT: &[(&str, Selector)] = &[
+    ("mod_quiz_get_attempt_data", Param { write: "attemptid", read: "attemptid" }),
+    ("mod_quiz_get_attempt_summary", Param { write: "attemptid", read: "attemptid" }),
+    ("mod_quiz_get_attempt_review", Param { write: "attemptid", read: "attemptid" }),
+];
+
+/// The invalidation table.
+pub const RULES: &[Rule] = &[
+    Rule { write: "mod_forum_add_discussion_post", evicts: POSTS_OF_POST },
+    Rule { write: "mod_forum_update_discussion_post", evicts: POSTS_OF_POST },
+    Rule { write: "mod_forum_delete_post", evicts: POSTS_OF_POST },
+    Rule {
+        write: "mod_forum_add_discussion",
+        evicts: &[("mod_forum_get_forum_discussions", Param { write: "forumid", read: "forumid" })],
+    },
+    Rule { write: "mod_assign_save_submission", evicts: ASSIGN_SUBMISSION },
+    Rule { write: "mod_assign_submit_for_grading", evicts: ASSIGN_SUBMISSION },
+    Rule {
+        write: "core_message_send_messages_to_conversation",
+        evicts: &[
+            (
+                "core_message_get_conversation_messages",
+                Param { write: "conversationid", read: "convid" },
+            ),
+            ("core_message_get_conversations", All),
+        ],
+    },
+    Rule {
+        write: "core_message_send_instant_messages",
+        evicts: &[
+            ("core_message_get_conversation_messages", All),
+            ("core_message_get_conversation_between_users", All),
+            ("core_message_get_conversations", All),
+        ],
+    },
+    Rule {
+        write: "core_message_mark_notification_read",
+        evicts: &[("message_popup_get_popup_notifications", All)],
+    },
+    Rule { write: "core_comment_add_comments", evicts: &[("core_comment_get_comments", All)] },
+    Rule {
+        write: "core_completion_update_activity_completion_status_manually",
+        evicts: &[
+            // Module completion is part of the course contents; the write
+            // only knows the module, not its course.
+            ("core_course_get_contents", All),
+            (
+                "core_completion_get_activities_completion_status",
+                Listed { write: "cmid", list: "statuses", field: "cmid" },
+            ),
+            ("core_completion_get_course_completion_status", All),
+        ],
+    },
+    Rule {
+        write: "mod_quiz_start_attempt",
+        evicts: &[
+            ("mod_quiz_get_user_attempts", Param { write: "quizid", read: "quizid" }),
+            ("mod_quiz_get_user_quiz_attempts", Param { write: "quizid", read: "quizid" }),
+        ],
+    },
+    Rule { write: "mod_quiz_save_attempt", evicts: QUIZ_ATTEMPT },
+    Rule {
+        write: "mod_quiz_process_attempt",
+        evicts: &[
+            ("mod_quiz_get_attempt_data", Param { write: "attemptid", read: "attemptid" }),
+            ("mod_quiz_get_attempt_summary", Param { write: "attemptid", read: "attemptid" }),
+            ("mod_quiz_get_attempt_review", Param { write: "attemptid", read: "attemptid" }),
+            // Finishing an attempt changes the attempt list and best grade;
+            // the write only knows the attempt, not the quiz.
+            ("mod_quiz_get_user_attempts", All),
+            ("mod_quiz_get_user_quiz_attempts", All),
+            ("mod_quiz_get_user_best_grade", All),
+        ],
+    },
+];
+
+/// The read functions `write` makes outdated, empty for unknown functions.
+pub fn dependents(write: &str) -> &'static [(&'static str, Selector)] {
+    RULES.iter().find(|rule| rule.write == write).map_or(&[], |rule| rule.evicts)
+}
+
+/// Moodle ids arrive as numbers or numeric strings depending on the caller.
+fn same_id(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => a == b,
+        (Value::String(s), other) | (other, Value::String(s)) => {
+            other.as_i64().is_some_and(|n| s.trim().parse() == Ok(n))
+        }
+        _ => a == b,
+    }
+}
+
+impl Selector {
+    /// Whether the selector depends on the cached response, not just the
+    /// parameters of the entry.
+    pub fn needs_data(&self) -> bool {
+        matches!(self, Selector::Listed { .. })
+    }
+
+    /// Whether a cached entry with `params` and `data` is outdated by a write
+    /// with `write_params`. `data` is only consulted by [`Selector::Listed`];
+    /// an unreadable response counts as a match.
+    pub fn matches(&self, write_params: &Value, params: &Value, data: Option<&Value>) -> bool {
+        match self {
+            Selector::All => true,
+            Selector::Param { write, read } => match (write_params.get(write), params.get(read)) {
+                (Some(a), Some(b)) => same_id(a, b),
+                // Unknown value: evict rather than serve something outdated.
+                (None, _) => true,
+                (Some(_), None) => false,
+            },
+            Selector::Listed { write, list, field } => {
+                let Some(id) = write_params.get(write) else {
+                    return true;
+                };
+                let Some(data) = data else {
+                    return true;
+                };
+                data.get(list).and_then(Value::as_array).is_some_and(|items| {
+                    items.iter().any(|item| item.get(field).is_some_and(|v| same_id(v, id)))
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    use serde_json::json;
+
+    use crate::scheduler;
+
+    #[test]
+    fn rules_cover_each_write_once() {
+        let mut seen = HashSet::new();
+        for rule in RULES {
+            assert!(seen.insert(rule.write), "{} listed twice", rule.write);
+            assert!(!scheduler::is_read_function(rule.write), "{} is a read", rule.write);
+            assert!(!rule.evicts.is_empty(), "{} evicts nothing", rule.write);
+        }
+        assert!(dependents("core_course_get_contents").is_empty());
+        let completion = dependents("core_completion_update_activity_completion_status_manually");
+        let reads: Vec<_> = completion.iter().map(|(read, _)| *read).collect();
+        assert!(reads.contains(&"core_course_get_contents"));
+        assert!(reads.contains(&"core_completion_get_activities_completion_status"));
+    }
+
+    #[test]
+    fn compares_ids_across_types() {
+        let cases = [
+            (json!(7), json!(7), true),
+            (json!(7), json!("7"), true),
+            (json!(" 7 "), json!(7), true),
+            (json!("7"), json!("7"), true),
+            (json!(7), json!(8), false),
+            (json!("7"), json!("07"), false),
+            (json!("abc"), json!(7), false),
+            (json!(null), json!(7), false),
+        ];
+        for (a, b, same) in cases {
+            assert_eq!(same_id(&a, &b), same, "{a} vs {b}");
+            assert_eq!(same_id(&b, &a), same, "{b} vs {a}");
+        }
+    }
+
+    #[test]
+    fn selects_entries_by_param() {
+        let selector = Param { write: "forumid", read: "forumid" };
+        let entry = json!({ "forumid": 3, "page": 0 });
+        assert!(selector.matches(&json!({ "forumid": "3" }), &entry, None));
+        assert!(!selector.matches(&json!({ "forumid": 4 }), &entry, None));
+        // Unknown write value: evict. Entry without the parameter: keep.
+        assert!(selector.matches(&json!({}), &entry, None));
+        assert!(!selector.matches(&json!({ "forumid": 3 }), &json!({}), None));
+        assert!(All.matches(&json!({}), &entry, None));
+        assert!(!selector.needs_data() && !All.needs_data());
+    }
+
+    #[test]
+    fn selects_entries_by_listed_id() {
+        let selector = Listed { write: "postid", list: "posts", field: "id" };
+        let data = json!({ "posts": [{ "id": 11 }, { "id": 12 }] });
+        assert!(selector.needs_data());
+        assert!(selector.matches(&json!({ "postid": "12" }), &json!({}), Some(&data)));
+        assert!(!selector.matches(&json!({ "postid": 13 }), &json!({}), Some(&data)));
+        assert!(!selector.matches(&json!({ "postid": 11 }), &json!({}), Some(&json!([]))));
+        // Unknown id or unreadable response: evict.
+        assert!(selector.matches(&json!({}), &json!({}), Some(&data)));
+        assert!(selector.matches(&json!({ "postid": 13 }), &json!({}), None));
+    }
+}
@@ -1,5 +1,33 @@
 // Re-export library for Tauri mobile targets (unused on Windows desktop,
 // but required by cargo workspace conventions).
+//
+// Also hosts the native Moodle modules used by the desktop commands.
+
+pub mod capabilities;
+pub mod connectivity;
+pub mod course_download;
+pub mod course_sync;
+pub mod course_updates;
+pub mod data_key;
+pub mod downloads;
+pub mod invalidation;
+pub mod login;
+pub mod model;
+pub mod outbox;
+pub mod pluginfile;
+pub mod probe;
+pub mod qr_login;
+pub mod response_cache;
+pub mod sanitize;
+pub mod scheduler;
+pub mod session;
+pub mod site_url;
+pub mod sso;
+pub mod vault;
+pub mod ws;
+
+#[cfg(test)]
+pub(crate) mod test_support;
 
 pub fn run() {
     // Desktop entry point is in main.rs
@@ -0,0 +1,367 @@
+//! Username/password login via `login/token.php`.
+//!
+//! Replaces `AuthService.fetchToken`: the token exchange happens here, the
+//! resulting token goes straight into the [`SessionStore`] and the webview
+//! only receives a session handle plus the site info. The password is held
+//! in a [`Zeroizing`] buffer, never logged and never persisted.
+
+use serde::Serialize;
+use serde_json::Value;
+use zeroize::Zeroizing;
+
+use crate::connectivity::{self, SiteStatus};
+use crate::model::SiteInfo;
+use crate::session::{Session, SessionInfo, SessionStore};
+use crate::site_url::{self, SiteUrlError};
+use crate::ws::{parse_exception, WsClient, WsError};
+
+/// Token endpoint relative to the site root.
+pub const TOKEN_ENDPOINT: &str = "login/token.php";
+
+/// Service shortname of the official mobile app web service.
+pub const MOBILE_SERVICE: &str = "moodle_mobile_app";
+
+/// Why a login attempt failed.
+#[derive(Debug, Clone, thiserror::Error, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum LoginError {
+    #[error("{error}")]
+    Site { error: SiteUrlError },
+
+    #[error("Invalid username or password")]
+    InvalidCredentials,
+
+    #[error("The mobile web service is not enabled on this site")]
+    MobileServiceDisabled,
+
+    #[error("The site is in maintenance mode")]
+    SiteMaintenance,
+
+    #[error("Secure connection failed: {message}")]
+    Tls { message: String },
+
+    #[error("The address does not point to a Moodle site")]
+    NotMoodle,
+
+    #[error("Network error: {message}")]
+    Network { message: String },
+
+    #[error("HTTP {status}")]
+    Http { status: u16 },
+
+    /// Any other error reported by Moodle (locked account, password
+    /// change required, ...).
+    #[error("Moodle error [{errorcode}]: {message}")]
+    Moodle { errorcode: String, message: String },
+}
+
+impl LoginError {
+    /// Classifies a Moodle `errorcode` from `login/token.php` or the WS layer.
+    fn from_moodle(errorcode: &str, message: String) -> Self {
+        match errorcode {
+            "invalidlogin" => LoginError::InvalidCredentials,
+            "enablewsdescription" | "servicenotavailable" | "webservicesnotenabled" => {
+                LoginError::MobileServiceDisabled
+            }
+            "sitemaintenance" => LoginError::SiteMaintenance,
+            _ => LoginError::Moodle { errorcode: errorcode.to_string(), message },
+        }
+    }
+}
+
+impl From<SiteUrlError> for LoginError {
+    fn from(error: SiteUrlError) -> Self {
+        LoginError::Site { error }
+    }
+}
+
+impl From<reqwest::Error> for LoginError {
+    fn from(err: reqwest::Error) -> Self {
+        if let Some(error) = site_url::blocked_cause(&err) {
+            return LoginError::Site { error };
+        }
+        if is_tls_error(&err) {
+            return LoginError::Tls { message: err.to_string() };
+        }
+        match err.status() {
+            Some(status) => LoginError::Http { status: status.as_u16() },
+            None => LoginError::Network { message: err.to_string() },
+        }
+    }
+}
+
+impl From<WsError> for LoginError {
+    fn from(err: WsError) -> Self {
+        match err {
+            WsError::Moodle { errorcode, message, .. } => Self::from_moodle(&errorcode, message),
+            WsError::InvalidResponse { .. } => LoginError::NotMoodle,
+            WsError::Http { status, .. } => LoginError::Http { status },
+            WsError::Site { error } => LoginError::Site { error },
+            other => LoginError::Network { message: other.to_string() },
+        }
+    }
+}
+
+/// Whether a request failed during the TLS handshake or certificate checks.
+///
+/// rustls errors reach us wrapped in (nested) [`std::io::Error`]s, whose
+/// `source()` skips the wrapped error itself, so those are unwrapped
+/// explicitly.
+fn is_tls_error(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(cause) = source {
+        if cause.is::<rustls::Error>() {
+            return true;
+        }
+        source = match cause.downcast_ref::<std::io::Error>() {
+            Some(io) => io.get_ref().map(|inner| inner as _),
+            None => cause.source(),
+        };
+    }
+    false
+}
+
+/// Credentials for one login attempt. The password is wiped from memory on
+/// drop and redacted from `Debug` output.
+pub struct Credentials {
+    pub username: String,
+    pub password: Zeroizing<String>,
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+/// Token pair returned by `login/token.php`.
+pub(crate) struct TokenPair {
+    pub token: String,
+    pub private_token: Option<String>,
+}
+
+/// Outcome of a successful login.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginResult {
+    pub session: SessionInfo,
+    pub site_info: SiteInfo,
+}
+
+/// Exchanges credentials for a token at `login/token.php`.
+pub(crate) async fn fetch_token(
+    http: &reqwest::Client,
+    site_url: &str,
+    credentials: &Credentials,
+) -> Result<TokenPair, LoginError> {
+    let form = [
+        ("username", credentials.username.as_str()),
+        ("password", credentials.password.as_str()),
+        ("service", MOBILE_SERVICE),
+    ];
+    let response = http
+        .post(format!("{site_url}/{TOKEN_ENDPOINT}"))
+        .form(&form)
+        .send()
+        .await?;
+
+    match response.status().as_u16() {
+        200..=299 => {}
+        404 => return Err(LoginError::NotMoodle),
+        // A 503 is only maintenance if the page says so; proxies answer
+        // outages with it too.
+        503 => {
+            let body = response.bytes().await?;
+            return Err(match connectivity::classify_response(503, &body) {
+                SiteStatus::Maintenance => LoginError::SiteMaintenance,
+                _ => LoginError::Http { status: 503 },
+            });
+        }
+        status => return Err(LoginError::Http { status }),
+    }
+
+    let body = response.bytes().await?;
+    let data: Value = serde_json::from_slice(&body).map_err(|_| LoginError::NotMoodle)?;
+    parse_token_response(&data)
+}
+
+/// Interprets a `login/token.php` JSON response.
+pub(crate) fn parse_token_response(data: &Value) -> Result<TokenPair, LoginError> {
+    if let Some(token) = data.get("token").and_then(Value::as_str) {
+        return Ok(TokenPair {
+            token: token.to_string(),
+            private_token: data
+                .get("privatetoken")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+        });
+    }
+
+    match parse_exception(data) {
+        Some(err) => Err(err.into()),
+        None if data.get("error").is_some() => {
+            let message = data["error"].as_str().unwrap_or_default().to_string();
+            Err(LoginError::Moodle { errorcode: String::new(), message })
+        }
+        None => Err(LoginError::NotMoodle),
+    }
+}
+
+/// Finishes a login once a token is known: loads the site info, opens a
+/// session and tags it with the account id (`userid@siteurl`).
+pub(crate) async fn open_session(
+    client: &WsClient,
+    sessions: &SessionStore,
+    site_url: &str,
+    tokens: TokenPair,
+) -> Result<LoginResult, LoginError> {
+    let site_info = client
+        .call_with_token(site_url, &tokens.token, "core_webservice_get_site_info", &Value::Null)
+        .await?;
+    let site_info: SiteInfo = serde_json::from_value(site_info).map_err(|_| LoginError::NotMoodle)?;
+    let userid = site_info.userid;
+
+    let session = Session::new(site_url, &tokens.token)
+        .with_private_token(tokens.private_token.as_deref())
+        .with_account_id(&format!("{userid}@{site_url}"));
+    let handle = sessions.open(session.clone());
+
+    Ok(LoginResult { session: session.info(&handle), site_info })
+}
+
+/// Performs a full username/password login.
+pub async fn login(
+    client: &WsClient,
+    sessions: &SessionStore,
+    site_url: &str,
+    credentials: Credentials,
+) -> Result<LoginResult, LoginError> {
+    let site_url = client.policy().validate(site_url).await?;
+    let tokens = fetch_token(client.http(), &site_url, &credentials).await?;
+    drop(credentials);
+    open_session(client, sessions, &site_url, tokens).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    use serde_json::json;
+
+    use crate::test_support::{test_client, MockServer};
+
+    fn credentials() -> Credentials {
+        Credentials { username: "student".into(), password: Zeroizing::new("secret".into()) }
+    }
+
+    async fn fetch(site_url: &str) -> Result<TokenPair, LoginError> {
+        fetch_token(test_client().http(), site_url, &credentials()).await
+    }
+
+    #[test]
+    fn parses_token_responses() {
+        let pair = parse_token_response(&json!({"token": "abc", "privatetoken": "xyz"})).unwrap();
+        assert_eq!(pair.token, "abc");
+        assert_eq!(pair.private_token.as_deref(), Some("xyz"));
+        let pair = parse_token_response(&json!({"token": "abc"})).unwrap();
+        assert_eq!(pair.private_token, None);
+
+        let invalid = json!({
+            "error": "Invalid login, please try again",
+            "errorcode": "invalidlogin",
+            "exception": "moodle_exception",
+        });
+        assert!(matches!(parse_token_response(&invalid), Err(LoginError::InvalidCredentials)));
+        let bare = json!({"error": "Something went wrong"});
+        assert!(matches!(
+            parse_token_response(&bare),
+            Err(LoginError::Moodle { errorcode, message })
+                if errorcode.is_empty() && message == "Something went wrong"
+        ));
+        assert!(matches!(parse_token_response(&json!({})), Err(LoginError::NotMoodle)));
+        assert!(matches!(parse_token_response(&json!([])), Err(LoginError::NotMoodle)));
+    }
+
+    #[test]
+    fn classifies_moodle_errors() {
+        let cases = [
+            ("invalidlogin", "invalidCredentials"),
+            ("enablewsdescription", "mobileServiceDisabled"),
+            ("servicenotavailable", "mobileServiceDisabled"),
+            ("webservicesnotenabled", "mobileServiceDisabled"),
+            ("sitemaintenance", "siteMaintenance"),
+            ("usersuspended", "moodle"),
+        ];
+        for (errorcode, kind) in cases {
+            let err = LoginError::from_moodle(errorcode, "message".into());
+            assert_eq!(serde_json::to_value(&err).unwrap()["kind"], kind, "{errorcode}");
+        }
+        let err = LoginError::from_moodle("forcepasswordchangenotice", "Change it".into());
+        assert!(matches!(
+            err,
+            LoginError::Moodle { errorcode, message }
+                if errorcode == "forcepasswordchangenotice" && message == "Change it"
+        ));
+    }
+
+    #[tokio::test]
+    async fn posts_credentials_to_the_token_endpoint() {
+        let server = MockServer::start(|_| (200, r#"{"token":"abc"}"#.into()));
+        let pair = fetch(&server.url()).await.unwrap();
+        assert_eq!(pair.token, "abc");
+
+        let request = server.last_request();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, format!("/{TOKEN_ENDPOINT}"));
+        assert!(request.body.contains("username=student"));
+        assert!(request.body.contains("service=moodle_mobile_app"));
+    }
+
+    #[tokio::test]
+    async fn maps_http_statuses() {
+        let not_found = MockServer::start(|_| (404, "Not found".into()));
+        assert!(matches!(fetch(&not_found.url()).await, Err(LoginError::NotMoodle)));
+        let maintenance = MockServer::start(|_| (503, "Site under Maintenance".into()));
+        assert!(matches!(fetch(&maintenance.url()).await, Err(LoginError::SiteMaintenance)));
+        let body = r#"{"error":"Closed","errorcode":"sitemaintenance"}"#;
+        let maintenance = MockServer::start(move |_| (503, body.into()));
+        assert!(matches!(fetch(&maintenance.url()).await, Err(LoginError::SiteMaintenance)));
+        let unavailable = MockServer::start(|_| (503, "Service Unavailable".into()));
+        assert!(matches!(
+            fetch(&unavailable.url()).await,
+            Err(LoginError::Http { status: 503 })
+        ));
+        let forbidden = MockServer::start(|_| (403, "Forbidden".into()));
+        assert!(matches!(
+            fetch(&forbidden.url()).await,
+            Err(LoginError::Http { status: 403 })
+        ));
+        let html = MockServer::start(|_| (200, "<html></html>".into()));
+        assert!(matches!(fetch(&html.url()).await, Err(LoginError::NotMoodle)));
+    }
+
+    #[tokio::test]
+    async fn recognises_tls_failures() {
+        // Answers the TLS handshake with plain HTTP, which rustls rejects.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n");
+            }
+        });
+        let result = fetch(&format!("https://127.0.0.1:{port}")).await.err();
+        assert!(matches!(result, Some(LoginError::Tls { .. })), "{result:?}");
+
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = closed.local_addr().unwrap().port();
+        drop(closed);
+        let result = fetch(&format!("https://127.0.0.1:{port}")).await.err();
+        assert!(matches!(result, Some(LoginError::Network { .. })), "{result:?}");
+    }
+}
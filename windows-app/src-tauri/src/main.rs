@@ -4,27 +4,171 @@
 
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use std::sync::Arc;
+
+use moodle_desktop_lib::connectivity::ConnectivityMonitor;
+use moodle_desktop_lib::data_key::KeyRing;
+use moodle_desktop_lib::downloads::{self, DownloadManager};
+use moodle_desktop_lib::outbox::{self, Outbox};
+use moodle_desktop_lib::pluginfile::{self, PluginfileCache};
+use moodle_desktop_lib::response_cache::{self, ResponseCache};
+use moodle_desktop_lib::session::SessionStore;
+use moodle_desktop_lib::site_url::{self, SitePolicy};
+use moodle_desktop_lib::sso::SsoState;
+use moodle_desktop_lib::vault::Vault;
+use moodle_desktop_lib::ws::WsClient;
 use tauri::Manager;
+use tauri_plugin_deep_link::DeepLinkExt;
+use tauri_plugin_fs::FsExt;
 
 mod commands;
 
 fn main() {
     tauri::Builder::default()
+        // Must come first: forwards deep links from a second instance
+        .plugin(tauri_plugin_single_instance::init(|app, _args, _cwd| {
+            if let Some(window) = app.get_webview_window("main") {
+                let _ = window.set_focus();
+            }
+        }))
+        .plugin(tauri_plugin_deep_link::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_http::init())
         .plugin(tauri_plugin_notification::init())
+        .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_store::Builder::default().build())
         .plugin(tauri_plugin_updater::Builder::default().build())
         .plugin(tauri_plugin_upload::init())
         .plugin(tauri_plugin_process::init())
+        .manage(SessionStore::new())
+        .manage(SsoState::new())
+        .manage(ConnectivityMonitor::new())
+        .manage(KeyRing::new())
+        .register_asynchronous_uri_scheme_protocol(pluginfile::SCHEME, |ctx, request, responder| {
+            // Authenticated pluginfile content for rendered course HTML
+            let app = ctx.app_handle().clone();
+            tauri::async_runtime::spawn(async move {
+                let response = pluginfile::handle_request(
+                    &app.state::<WsClient>(),
+                    &app.state::<SessionStore>(),
+                    &app.state::<PluginfileCache>(),
+                    &request,
+                )
+                .await;
+                responder.respond(response);
+            });
+        })
         .invoke_handler(tauri::generate_handler![
             commands::get_app_version,
             commands::set_window_effect,
             commands::open_file,
+            commands::probe_site,
+            commands::moodle_login,
+            commands::moodle_qr_login,
+            commands::sso_begin,
+            commands::session_info,
+            commands::session_close,
+            commands::vault_list_accounts,
+            commands::vault_get_account,
+            commands::vault_add_account,
+            commands::vault_remove_account,
+            commands::vault_open_session,
+            commands::vault_is_locked,
+            commands::vault_open_error,
+            commands::vault_unlock,
+            commands::vault_set_passphrase,
+            commands::moodle_call,
+            commands::moodle_call_batch,
+            commands::upload_draft_file,
+            commands::site_capabilities,
+            commands::rewrite_pluginfile_urls,
+            commands::clear_pluginfile_cache,
+            commands::cache_get,
+            commands::cache_put,
+            commands::cache_stats,
+            commands::cache_clear,
+            commands::outbox_enqueue,
+            commands::outbox_list,
+            commands::outbox_update,
+            commands::outbox_discard,
+            commands::outbox_replay,
+            commands::connectivity_status,
+            commands::connectivity_check,
+            commands::download_enqueue,
+            commands::download_course,
+            commands::sync_course,
+            commands::downloads_list,
+            commands::download_pause,
+            commands::download_resume,
+            commands::download_cancel,
+            commands::downloads_set_parallelism,
+            commands::download_roots,
+            commands::download_root_pick,
+            commands::download_root_reset,
+            commands::reveal_file,
         ])
         .setup(|app| {
+            // SSRF guard with the admin allowlist for intranet sites
+            let config_dir = app.path().app_config_dir()?;
+            let policy = SitePolicy::load(&config_dir.join(site_url::POLICY_FILE))?;
+            app.manage(WsClient::new(policy));
+
+            let cache_dir = app.path().app_cache_dir()?;
+            app.manage(PluginfileCache::new(
+                cache_dir.join("pluginfile"),
+                pluginfile::DEFAULT_MAX_BYTES,
+            ));
+
+            // Offline WS response cache; a broken database must not stop the app
+            let responses = ResponseCache::open(
+                &cache_dir.join(response_cache::DATABASE_FILE),
+                response_cache::DEFAULT_MAX_BYTES,
+            )
+            .or_else(|_| ResponseCache::in_memory(response_cache::DEFAULT_MAX_BYTES))?;
+            app.manage(responses);
+            commands::start_course_update_checks(app.handle().clone());
+
+            // Credential vault; move plaintext tokens out of the legacy store.
+            // A vault that cannot be opened is reported to the UI and
+            // replaced by an in-memory one for this run
+            let data_dir = app.path().app_data_dir()?;
+            let vault = Vault::open(&data_dir).or_else(Vault::in_memory)?;
+            if !vault.is_locked() {
+                // A store that cannot be migrated keeps its tokens until the next start
+                let store = data_dir.join("moodle-desktop-store.json");
+                let _ = vault.migrate_plaintext_store(&store);
+            }
+            app.manage(vault);
+
+            // Write calls queued while offline; replayed whenever a session
+            // opens or a site comes back online
+            app.manage(Outbox::open(&data_dir.join(outbox::DATABASE_FILE))?);
+
+            // Download queue; interrupted downloads resume once their
+            // account has a session again
+            let host = Arc::new(commands::AppDownloadHost(app.handle().clone()));
+            let client = app.state::<WsClient>().inner().clone();
+            let downloads =
+                DownloadManager::open(&data_dir.join(downloads::DATABASE_FILE), client, host)?;
+            // Configured download roots outside the Downloads folder
+            for root in downloads.configured_roots()? {
+                let _ = app.fs_scope().allow_directory(root, true);
+            }
+            app.manage(downloads);
+            commands::start_connectivity_monitor(app.handle().clone());
+
+            // Browser (SSO) login callbacks: moodledesktop://token=...
+            #[cfg(debug_assertions)]
+            app.deep_link().register_all()?;
+            let handle = app.handle().clone();
+            app.deep_link().on_open_url(move |event| {
+                for url in event.urls() {
+                    commands::complete_sso_login(handle.clone(), url.to_string());
+                }
+            });
+
             let window = app.get_webview_window("main").unwrap();
 
             // Resize window to ~80% of the primary monitor
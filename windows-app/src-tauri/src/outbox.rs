@@ -0,0 +1,603 @@
+//! Persistent outbox for write calls made while offline.
+//!
+//! Forum replies, messages, online text submissions and completion toggles
+//! are queued with the account that made them and replayed in order once
+//! the site is reachable again. Every item keeps its own outcome: Moodle
+//! rejecting a call (the discussion was deleted, the submission is locked)
+//! is a conflict the user resolves by editing or discarding the item; a
+//! network failure or a refused session stops the replay for that account
+//! so later items never overtake earlier ones.
+//!
+//! Parameters hold what the user wrote, so they are sealed with the
+//! account's [`DataKey`] like cached responses, bound to their row.
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Mutex;
+
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::data_key::DataKey;
+use crate::response_cache::{self, ResponseCache};
+use crate::scheduler;
+use crate::session::{Session, SessionError, SessionHandle};
+use crate::ws::{WsClient, WsError};
+
+/// Database file in the app data directory.
+pub const DATABASE_FILE: &str = "outbox.sqlite3";
+
+/// Event emitted with a [`ReplayReport`] after every replay that sent or
+/// rejected at least one item.
+pub const REPLAYED_EVENT: &str = "outbox://replayed";
+
+#[derive(Debug, thiserror::Error, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum OutboxError {
+    #[error("Outbox database error: {message}")]
+    Database { message: String },
+
+    #[error("Unknown or closed session")]
+    UnknownSession,
+
+    #[error("No queued item with id {id}")]
+    UnknownItem { id: i64 },
+
+    /// The account's data key is unavailable (locked vault).
+    #[error("The outbox of this account is locked")]
+    Locked,
+
+    /// Only functions that change data are queued; reads are served from
+    /// the response cache instead.
+    #[error("{wsfunction} does not change data and cannot be queued")]
+    NotAWrite { wsfunction: String },
+}
+
+impl From<rusqlite::Error> for OutboxError {
+    fn from(err: rusqlite::Error) -> Self {
+        OutboxError::Database { message: err.to_string() }
+    }
+}
+
+impl From<SessionError> for OutboxError {
+    fn from(err: SessionError) -> Self {
+        match err {
+            SessionError::UnknownSession => OutboxError::UnknownSession,
+        }
+    }
+}
+
+/// State of a queued item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ItemStatus {
+    /// Waiting to be sent.
+    Pending,
+    /// Moodle rejected the call in its current form.
+    Conflict,
+    /// The call failed for another reason (bad response, blocked site).
+    Failed,
+}
+
+impl ItemStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            ItemStatus::Pending => "pending",
+            ItemStatus::Conflict => "conflict",
+            ItemStatus::Failed => "failed",
+        }
+    }
+
+    fn parse(s: &str) -> Self {
+        match s {
+            "conflict" => ItemStatus::Conflict,
+            "failed" => ItemStatus::Failed,
+            _ => ItemStatus::Pending,
+        }
+    }
+}
+
+/// A queued write call.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxItem {
+    pub id: i64,
+    #[serde(skip)]
+    scope: String,
+    /// Account the call was made with; only sessions of this account replay it.
+    pub account_id: Option<String>,
+    pub site_url: String,
+    pub wsfunction: String,
+    /// `null` if the account's data key was not available.
+    pub params: Value,
+    #[serde(skip)]
+    sealed: Vec<u8>,
+    /// When the item was queued / last edited (ms since the epoch).
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub status: ItemStatus,
+    /// The last error, serialised like a [`WsError`].
+    pub error: Option<Value>,
+    pub attempts: u32,
+}
+
+impl OutboxItem {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        let status: String = row.get("status")?;
+        let error: Option<String> = row.get("error")?;
+        Ok(Self {
+            id: row.get("id")?,
+            scope: row.get("scope")?,
+            account_id: row.get("account_id")?,
+            site_url: row.get("site_url")?,
+            wsfunction: row.get("wsfunction")?,
+            params: Value::Null,
+            sealed: row.get("params")?,
+            created_at: row.get("created_at")?,
+            updated_at: row.get("updated_at")?,
+            status: ItemStatus::parse(&status),
+            error: error.and_then(|e| serde_json::from_str(&e).ok()),
+            attempts: row.get("attempts")?,
+        })
+    }
+
+    /// Decrypts the parameters; `None` if they do not decrypt.
+    fn open_params(&self, data_key: &DataKey) -> Option<Value> {
+        let plain = data_key.open(&self.sealed, &aad(&self.scope, self.id))?;
+        serde_json::from_slice(&plain).ok()
+    }
+
+    /// The item with its parameters decrypted, `null` without the key.
+    fn opened(mut self, data_key: Option<&DataKey>) -> Self {
+        self.params = data_key.and_then(|key| self.open_params(key)).unwrap_or(Value::Null);
+        self
+    }
+}
+
+/// Associated data binding sealed parameters to their row.
+fn aad(scope: &str, id: i64) -> Vec<u8> {
+    [scope.as_bytes(), b"\0", id.to_string().as_bytes()].concat()
+}
+
+/// What happened to one item during a replay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReplayOutcome {
+    /// Sent and removed from the outbox.
+    Sent,
+    /// Rejected by Moodle; kept as [`ItemStatus::Conflict`].
+    Conflict,
+    /// Failed; kept as [`ItemStatus::Failed`].
+    Failed,
+    /// Not sent because the site is unreachable or the session was
+    /// refused; stays pending, as do all later items of the account.
+    Deferred,
+}
+
+/// Outcome of one item.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayResult {
+    pub id: i64,
+    pub wsfunction: String,
+    pub outcome: ReplayOutcome,
+    pub error: Option<WsError>,
+}
+
+/// Outcome of a replay, in queue order.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayReport {
+    pub results: Vec<ReplayResult>,
+    /// Items still pending afterwards, including those without an open session.
+    pub pending: u64,
+}
+
+impl ReplayReport {
+    /// Whether anything was sent or rejected.
+    pub fn has_changes(&self) -> bool {
+        self.results.iter().any(|r| r.outcome != ReplayOutcome::Deferred)
+    }
+}
+
+/// Failures that leave the item pending and stop the account's replay.
+fn defers(err: &WsError) -> bool {
+    match err {
+        WsError::Network { .. } => true,
+        WsError::Http { status, .. } => *status >= 500 || matches!(status, 408 | 429),
+        _ => err.session_expiry().is_some(),
+    }
+}
+
+/// The outbox. Held in Tauri managed state.
+pub struct Outbox {
+    conn: Mutex<Connection>,
+    /// Held for the whole replay so an item is never sent twice.
+    replaying: tokio::sync::Mutex<()>,
+}
+
+impl Outbox {
+    /// Opens (or creates) the outbox database at `path`.
+    pub fn open(path: &Path) -> Result<Self, OutboxError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| OutboxError::Database { message: e.to_string() })?;
+        }
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS outbox (
+                 id          INTEGER PRIMARY KEY AUTOINCREMENT,
+                 scope       TEXT    NOT NULL,
+                 account_id  TEXT,
+                 site_url    TEXT    NOT NULL,
+                 wsfunction  TEXT    NOT NULL,
+                 params      BLOB    NOT NULL,
+                 created_at  INTEGER NOT NULL,
+                 updated_at  INTEGER NOT NULL,
+                 status      TEXT    NOT NULL,
+                 error       TEXT,
+                 attempts    INTEGER NOT NULL DEFAULT 0
+             );",
+        )?;
+        Ok(Self { conn: Mutex::new(conn), replaying: tokio::sync::Mutex::new(()) })
+    }
+
+    /// Queues a write call of `session`'s account, sealing `params` with the
+    /// account's `data_key`.
+    pub fn enqueue(
+        &self,
+        session: &Session,
+        data_key: &DataKey,
+        wsfunction: &str,
+        params: &Value,
+    ) -> Result<OutboxItem, OutboxError> {
+        if scheduler::is_read_function(wsfunction) {
+            return Err(OutboxError::NotAWrite { wsfunction: wsfunction.to_string() });
+        }
+        let now = response_cache::now_ms();
+        let id = {
+            let conn = self.conn.lock().unwrap();
+            conn.execute(
+                "INSERT INTO outbox
+                     (scope, account_id, site_url, wsfunction, params, created_at, updated_at,
+                      status)
+                 VALUES (?1, ?2, ?3, ?4, X'', ?5, ?5, ?6)",
+                params![
+                    session.scope(),
+                    session.account_id(),
+                    session.site_url(),
+                    wsfunction,
+                    now,
+                    ItemStatus::Pending.as_str(),
+                ],
+            )?;
+            // The row id is part of the associated data.
+            let id = conn.last_insert_rowid();
+            let sealed = data_key.seal(params.to_string().as_bytes(), &aad(session.scope(), id));
+            conn.execute("UPDATE outbox SET params = ?2 WHERE id = ?1", params![id, sealed])?;
+            id
+        };
+        Ok(self.get(id)?.opened(Some(data_key)))
+    }
+
+    /// Returns one item, parameters still sealed.
+    fn get(&self, id: i64) -> Result<OutboxItem, OutboxError> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT * FROM outbox WHERE id = ?1", [id], OutboxItem::from_row)
+            .optional()?
+            .ok_or(OutboxError::UnknownItem { id })
+    }
+
+    /// Items of one account, or of all accounts, in queue order. `data_key`
+    /// looks up the key of an account scope to decrypt the parameters.
+    pub fn list(
+        &self,
+        scope: Option<&str>,
+        data_key: impl Fn(&str) -> Option<DataKey>,
+    ) -> Result<Vec<OutboxItem>, OutboxError> {
+        let items = self.sealed_items(scope)?;
+        Ok(items
+            .into_iter()
+            .map(|item| {
+                let key = data_key(&item.scope);
+                item.opened(key.as_ref())
+            })
+            .collect())
+    }
+
+    fn sealed_items(&self, scope: Option<&str>) -> Result<Vec<OutboxItem>, OutboxError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT * FROM outbox WHERE ?1 IS NULL OR scope = ?1 ORDER BY id",
+        )?;
+        let items = stmt
+            .query_map([scope], OutboxItem::from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(items)
+    }
+
+    /// Replaces the parameters of a queued item and queues it again; this
+    /// is how a conflict is resolved. The item keeps its place in the queue.
+    pub fn update(
+        &self,
+        id: i64,
+        params: &Value,
+        data_key: impl Fn(&str) -> Option<DataKey>,
+    ) -> Result<OutboxItem, OutboxError> {
+        let scope = self.get(id)?.scope;
+        let key = data_key(&scope).ok_or(OutboxError::Locked)?;
+        {
+            let conn = self.conn.lock().unwrap();
+            let updated = conn.execute(
+                "UPDATE outbox SET params = ?2, status = ?3, error = NULL, updated_at = ?4
+                 WHERE id = ?1",
+                params![
+                    id,
+                    key.seal(params.to_string().as_bytes(), &aad(&scope, id)),
+                    ItemStatus::Pending.as_str(),
+                    response_cache::now_ms(),
+                ],
+            )?;
+            if updated == 0 {
+                return Err(OutboxError::UnknownItem { id });
+            }
+        }
+        Ok(self.get(id)?.opened(Some(&key)))
+    }
+
+    /// Removes every item of an account, e.g. when it is removed.
+    pub fn purge(&self, scope: &str) -> Result<(), OutboxError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM outbox WHERE scope = ?1", [scope])?;
+        Ok(())
+    }
+
+    /// Removes an item without sending it.
+    pub fn discard(&self, id: i64) -> Result<(), OutboxError> {
+        let conn = self.conn.lock().unwrap();
+        if conn.execute("DELETE FROM outbox WHERE id = ?1", [id])? == 0 {
+            return Err(OutboxError::UnknownItem { id });
+        }
+        Ok(())
+    }
+
+    fn record(
+        &self,
+        id: i64,
+        outcome: ReplayOutcome,
+        error: Option<&WsError>,
+    ) -> Result<(), OutboxError> {
+        let conn = self.conn.lock().unwrap();
+        if outcome == ReplayOutcome::Sent {
+            conn.execute("DELETE FROM outbox WHERE id = ?1", [id])?;
+            return Ok(());
+        }
+        let status = match outcome {
+            ReplayOutcome::Conflict => ItemStatus::Conflict,
+            ReplayOutcome::Failed => ItemStatus::Failed,
+            _ => ItemStatus::Pending,
+        };
+        let error = error.and_then(|e| serde_json::to_string(e).ok());
+        conn.execute(
+            "UPDATE outbox SET status = ?2, error = ?3, attempts = attempts + 1,
+                 updated_at = ?4
+             WHERE id = ?1",
+            params![id, status.as_str(), error, response_cache::now_ms()],
+        )?;
+        Ok(())
+    }
+
+    /// Sends the pending items of every account with an open session, in
+    /// queue order. `data_key` looks up each account's key, which opens the
+    /// parameters; successful calls evict dependent cache entries like
+    /// direct calls do. Returns an empty report if a replay is already
+    /// running.
+    pub async fn replay(
+        &self,
+        client: &WsClient,
+        cache: &ResponseCache,
+        data_key: impl Fn(&Session) -> Option<DataKey>,
+        sessions: &[(SessionHandle, Session)],
+    ) -> Result<ReplayReport, OutboxError> {
+        let Ok(_guard) = self.replaying.try_lock() else {
+            return Ok(ReplayReport::default());
+        };
+
+        let mut report = ReplayReport::default();
+        let mut blocked = HashSet::new();
+        let pending = self
+            .sealed_items(None)?
+            .into_iter()
+            .filter(|item| item.status == ItemStatus::Pending);
+        for item in pending {
+            let scope = item.scope.as_str();
+            let Some((_, session)) = sessions.iter().find(|(_, s)| s.scope() == scope) else {
+                continue;
+            };
+            // Without the key the item waits, like one without a session.
+            let Some(key) = data_key(session) else {
+                continue;
+            };
+            let Some(params) = item.open_params(&key) else {
+                continue;
+            };
+            if blocked.contains(scope) {
+                report.results.push(ReplayResult {
+                    id: item.id,
+                    wsfunction: item.wsfunction,
+                    outcome: ReplayOutcome::Deferred,
+                    error: None,
+                });
+                continue;
+            }
+
+            let result = client.call(session, &item.wsfunction, &params).await;
+            let (outcome, error) = match result {
+                Ok(_) => {
+                    let _ = cache.invalidate(scope, Some(&key), &item.wsfunction, &params);
+                    (ReplayOutcome::Sent, None)
+                }
+                Err(err) if defers(&err) => {
+                    blocked.insert(scope.to_string());
+                    (ReplayOutcome::Deferred, Some(err))
+                }
+                Err(err @ WsError::Moodle { .. }) => (ReplayOutcome::Conflict, Some(err)),
+                Err(err) => (ReplayOutcome::Failed, Some(err)),
+            };
+            self.record(item.id, outcome, error.as_ref())?;
+            report.results.push(ReplayResult {
+                id: item.id,
+                wsfunction: item.wsfunction,
+                outcome,
+                error,
+            });
+        }
+
+        report.pending = self
+            .sealed_items(None)?
+            .iter()
+            .filter(|item| item.status == ItemStatus::Pending)
+            .count() as u64;
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    use crate::test_support::{test_client, MockServer};
+
+    fn outbox() -> Outbox {
+        let path = std::env::temp_dir().join(format!("outbox-{}.sqlite3", uuid::Uuid::new_v4()));
+        Outbox::open(&path).unwrap()
+    }
+
+    fn session(site_url: &str) -> (SessionHandle, Session) {
+        let session = Session::new(site_url, "t").with_account_id("2@site");
+        ("handle".to_string(), session)
+    }
+
+    /// The WS function of a recorded REST request.
+    fn function_of(body: &str) -> String {
+        let (_, rest) = body.split_once("wsfunction=").unwrap_or_default();
+        rest.split('&').next().unwrap_or_default().to_string()
+    }
+
+    async fn replay(
+        outbox: &Outbox,
+        key: &DataKey,
+        sessions: &[(SessionHandle, Session)],
+    ) -> ReplayReport {
+        let cache = ResponseCache::in_memory(response_cache::DEFAULT_MAX_BYTES).unwrap();
+        outbox.replay(&test_client(), &cache, |_| Some(key.clone()), sessions).await.unwrap()
+    }
+
+    const WRITES: [&str; 3] = [
+        "mod_forum_add_discussion_post",
+        "core_message_send_instant_messages",
+        "core_completion_update_activity_completion_status_manually",
+    ];
+
+    #[test]
+    fn seals_parameters() {
+        let outbox = outbox();
+        let (key, (_, session)) = (DataKey::random(), session("https://school.example"));
+        let params = json!({ "postid": 5, "message": "Meine Antwort" });
+
+        let item = outbox.enqueue(&session, &key, WRITES[0], &params).unwrap();
+        assert_eq!(item.params, params);
+        assert!(!String::from_utf8_lossy(&item.sealed).contains("Antwort"));
+        let locked = outbox.list(None, |_| None).unwrap();
+        assert_eq!(locked[0].params, Value::Null);
+        let other = outbox.list(None, |_| Some(DataKey::random())).unwrap();
+        assert_eq!(other[0].params, Value::Null);
+        let opened = outbox.list(Some(session.scope()), |_| Some(key.clone())).unwrap();
+        assert_eq!(opened[0].params, params);
+
+        let read = outbox.enqueue(&session, &key, "core_course_get_contents", &json!({}));
+        assert!(matches!(read, Err(OutboxError::NotAWrite { .. })));
+    }
+
+    #[tokio::test]
+    async fn replays_in_queue_order() {
+        let server = MockServer::start(|_| (200, "null".into()));
+        let (outbox, key, sessions) = (outbox(), DataKey::random(), [session(&server.url())]);
+        for (n, function) in WRITES.iter().enumerate() {
+            outbox.enqueue(&sessions[0].1, &key, function, &json!({ "n": n })).unwrap();
+        }
+
+        let report = replay(&outbox, &key, &sessions).await;
+
+        let sent: Vec<_> = server.requests().iter().map(|req| function_of(&req.body)).collect();
+        assert_eq!(sent, WRITES);
+        assert!(server.requests()[2].body.contains("n=2"));
+        assert!(report.results.iter().all(|r| r.outcome == ReplayOutcome::Sent));
+        assert_eq!(report.pending, 0);
+        assert!(outbox.list(None, |_| None).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn defers_the_account_while_offline() {
+        let server = MockServer::start(|_| (503, "Maintenance".into()));
+        let (outbox, key, sessions) = (outbox(), DataKey::random(), [session(&server.url())]);
+        for function in WRITES {
+            outbox.enqueue(&sessions[0].1, &key, function, &json!({})).unwrap();
+        }
+
+        let report = replay(&outbox, &key, &sessions).await;
+
+        // The first failure stops the account; nothing overtakes it.
+        assert_eq!(server.requests().len(), 1);
+        assert!(report.results.iter().all(|r| r.outcome == ReplayOutcome::Deferred));
+        assert!(!report.has_changes());
+        assert_eq!(report.pending, 3);
+        let items = outbox.list(None, |_| None).unwrap();
+        assert!(items.iter().all(|item| item.status == ItemStatus::Pending));
+        assert_eq!(items.iter().map(|item| item.attempts).collect::<Vec<_>>(), [1, 0, 0]);
+
+        // Without a session of the account nothing is sent at all.
+        let report = replay(&outbox, &key, &[]).await;
+        assert!(report.results.is_empty());
+        assert_eq!((report.pending, server.requests().len()), (3, 1));
+    }
+
+    #[tokio::test]
+    async fn keeps_conflicts_for_the_user() {
+        let server = MockServer::start(|req| match req.body.contains("postid=404") {
+            true => (
+                200,
+                r#"{"exception":"moodle_exception","errorcode":"invalidpostid","message":"Gone"}"#
+                    .into(),
+            ),
+            false => (200, "null".into()),
+        });
+        let (outbox, key, sessions) = (outbox(), DataKey::random(), [session(&server.url())]);
+        let session = &sessions[0].1;
+        let rejected =
+            outbox.enqueue(session, &key, WRITES[0], &json!({ "postid": 404 })).unwrap();
+        outbox.enqueue(session, &key, WRITES[1], &json!({})).unwrap();
+
+        let report = replay(&outbox, &key, &sessions).await;
+
+        let outcomes: Vec<_> = report.results.iter().map(|r| r.outcome).collect();
+        assert_eq!(outcomes, [ReplayOutcome::Conflict, ReplayOutcome::Sent]);
+        let items = outbox.list(None, |_| Some(key.clone())).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].status, ItemStatus::Conflict);
+        assert_eq!(items[0].error.as_ref().unwrap()["errorcode"], "invalidpostid");
+
+        // A conflict waits until the user edits the item.
+        assert!(replay(&outbox, &key, &sessions).await.results.is_empty());
+        let edited = outbox
+            .update(rejected.id, &json!({ "postid": 405 }), |_| Some(key.clone()))
+            .unwrap();
+        assert_eq!(edited.status, ItemStatus::Pending);
+        let report = replay(&outbox, &key, &sessions).await;
+        assert_eq!(report.results[0].outcome, ReplayOutcome::Sent);
+        assert!(server.last_request().body.contains("postid=405"));
+    }
+}
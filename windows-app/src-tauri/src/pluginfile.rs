@@ -0,0 +1,769 @@
+//! `moodle-file://` protocol for authenticated pluginfile content.
+//!
+//! Rendered course HTML references images, audio and video through
+//! `moodle-file://localhost/<session>/<site-relative path>` instead of raw
+//! `pluginfile.php` URLs with the token in the query string. The handler
+//! fetches the file with the token attached server-side, streams it into an
+//! on-disk cache and serves later requests — including offline ones — from
+//! there, reading only the requested range of large media files.
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use sha2::{Digest, Sha256};
+use tauri::http::{header, Request, Response, StatusCode};
+
+use crate::session::{Session, SessionStore};
+use crate::ws::WsClient;
+
+/// Name of the registered URI scheme.
+pub const SCHEME: &str = "moodle-file";
+
+/// Placeholder used by older Moodle versions in unprocessed HTML.
+const PLUGINFILE_PLACEHOLDER: &str = "@@PLUGINFILE@@";
+
+/// Site-relative prefixes the protocol is allowed to fetch.
+const ALLOWED_PREFIXES: [&str; 2] = ["webservice/pluginfile.php/", "pluginfile.php/"];
+
+/// Base URL under which the webview reaches the protocol. WebView2 exposes
+/// custom schemes as `http://<scheme>.localhost`.
+pub fn scheme_base() -> String {
+    if cfg!(target_os = "windows") {
+        format!("http://{SCHEME}.localhost")
+    } else {
+        format!("{SCHEME}://localhost")
+    }
+}
+
+/// Rewrites pluginfile URLs of `site_url` in `html` to protocol URLs bound to
+/// the session `handle`.
+///
+/// `@@PLUGINFILE@@` placeholders are resolved first. Existing `token=` query
+/// parameters are dropped, and `tokenpluginfile.php` URLs are left untouched
+/// since they carry their own authentication.
+pub fn rewrite_html(html: &str, handle: &str, site_url: &str) -> String {
+    let site_url = site_url.trim_end_matches('/');
+    if site_url.is_empty() {
+        return html.to_string();
+    }
+    let html = html.replace(
+        PLUGINFILE_PLACEHOLDER,
+        &format!("{site_url}/webservice/pluginfile.php"),
+    );
+    let base = scheme_base();
+
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html.as_str();
+    while let Some(pos) = rest.find(site_url) {
+        out.push_str(&rest[..pos]);
+        let candidate = &rest[pos + site_url.len()..];
+        let relative = candidate.strip_prefix('/').filter(|r| is_allowed_path(r));
+
+        match relative {
+            Some(relative) => {
+                let end = relative
+                    .find(|c: char| matches!(c, '"' | '\'' | '<' | '>') || c.is_whitespace())
+                    .unwrap_or(relative.len());
+                let (path, query) = split_query(&relative[..end]);
+                let query = strip_token_param(query);
+                out.push_str(&format!("{base}/{handle}/{path}"));
+                if !query.is_empty() {
+                    out.push('?');
+                    out.push_str(&query);
+                }
+                rest = &relative[end..];
+            }
+            None => {
+                out.push_str(site_url);
+                rest = candidate;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn is_allowed_path(relative: &str) -> bool {
+    ALLOWED_PREFIXES.iter().any(|p| relative.starts_with(p))
+}
+
+fn split_query(url: &str) -> (&str, &str) {
+    url.split_once('?').unwrap_or((url, ""))
+}
+
+/// Removes `token=...` pairs from a query string. `&amp;` separators from
+/// HTML attributes are handled as well.
+fn strip_token_param(query: &str) -> String {
+    query
+        .split('&')
+        .map(|pair| pair.strip_prefix("amp;").unwrap_or(pair))
+        .filter(|pair| !pair.is_empty() && !pair.starts_with("token="))
+        .collect::<Vec<_>>()
+        .join("&amp;")
+}
+
+/// Splits a protocol request path `/<handle>/<relative>` into its parts.
+pub fn parse_request_path(path: &str) -> Option<(&str, &str)> {
+    let (handle, relative) = path.trim_start_matches('/').split_once('/')?;
+    if handle.is_empty() || !is_allowed_path(relative) || relative.contains("..") {
+        return None;
+    }
+    Some((handle, relative))
+}
+
+/// Builds the authenticated download URL for a site-relative pluginfile path.
+fn download_url(session: &Session, relative: &str, query: Option<&str>) -> String {
+    let path = relative.strip_prefix("webservice/").unwrap_or(relative);
+    let mut url = format!("{}/webservice/{path}?token={}", session.site_url(), session.token());
+    if let Some(query) = query.filter(|q| !q.is_empty()) {
+        url.push('&');
+        url.push_str(query);
+    }
+    url
+}
+
+/// Authenticated download URL for an absolute pluginfile URL of `session`'s
+/// site, as found in WS responses (`fileurl`). `None` for URLs of another
+/// site or outside pluginfile.php. A token already in the URL is replaced.
+pub fn file_download_url(session: &Session, file_url: &str) -> Option<String> {
+    if !session.owns_url(file_url) {
+        return None;
+    }
+    let relative = file_url[session.site_url().len()..].trim_start_matches('/');
+    let (relative, query) = split_query(relative);
+    if !is_allowed_path(relative) || relative.contains("..") {
+        return None;
+    }
+    Some(download_url(session, relative, Some(&plain_query(query))))
+}
+
+/// `url` without a `token` parameter, for storing file URLs.
+pub fn without_token(url: &str) -> String {
+    match split_query(url) {
+        (path, "") => path.to_string(),
+        (path, query) => match plain_query(query) {
+            query if query.is_empty() => path.to_string(),
+            query => format!("{path}?{query}"),
+        },
+    }
+}
+
+/// A query string without `token` pairs, `&`-separated.
+fn plain_query(query: &str) -> String {
+    strip_token_param(query).replace("&amp;", "&")
+}
+
+/// Default size limit of the on-disk cache.
+pub const DEFAULT_MAX_BYTES: u64 = 512 * 1024 * 1024;
+
+/// Largest part of a file served per request. Media elements ask for open
+/// ranges (`bytes=0-`) and follow up with the next range, so a video is
+/// never read into memory as a whole.
+const MAX_CHUNK: u64 = 4 * 1024 * 1024;
+
+/// A cached pluginfile on disk plus the content type Moodle sent.
+pub struct CachedFile {
+    pub path: PathBuf,
+    pub len: u64,
+    pub content_type: String,
+}
+
+/// On-disk cache of pluginfile content, keyed by account and path so entries
+/// survive new session handles across restarts, and an account never gets a
+/// file that Moodle only served to another one. Each account has its own
+/// folder, removed with the account. Least recently used files are evicted
+/// once the cache grows beyond its size limit.
+pub struct PluginfileCache {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl PluginfileCache {
+    pub fn new(dir: PathBuf, max_bytes: u64) -> Self {
+        Self { dir, max_bytes }
+    }
+
+    /// Folder of an account's files, by session scope.
+    fn account_dir(&self, scope: &str) -> PathBuf {
+        self.dir.join(digest(&[scope.as_bytes()]))
+    }
+
+    /// Folder, file and content type file of a key.
+    fn paths(&self, key: &str) -> (PathBuf, PathBuf, PathBuf) {
+        let (account, file) = key.split_once('/').unwrap_or(("", key));
+        let dir = self.dir.join(account);
+        let paths = (dir.join(format!("{file}.bin")), dir.join(format!("{file}.type")));
+        (dir, paths.0, paths.1)
+    }
+
+    /// Cache key for a file as seen by `session`'s account:
+    /// `<account folder>/<file>`.
+    pub fn key(session: &Session, relative: &str, query: Option<&str>) -> String {
+        let relative = relative.strip_prefix("webservice/").unwrap_or(relative);
+        let file = digest(&[
+            session.site_url().as_bytes(),
+            relative.as_bytes(),
+            query.unwrap_or_default().as_bytes(),
+        ]);
+        format!("{}/{file}", digest(&[session.scope().as_bytes()]))
+    }
+
+    /// Looks up a file and marks it as recently used.
+    pub fn get(&self, key: &str) -> Option<CachedFile> {
+        let (_, bin, mime) = self.paths(key);
+        let file = fs::File::options().append(true).open(&bin).ok()?;
+        let _ = file.set_modified(SystemTime::now());
+        let len = file.metadata().ok()?.len();
+        let content_type = fs::read_to_string(mime)
+            .unwrap_or_else(|_| "application/octet-stream".into());
+        Some(CachedFile { path: bin, len, content_type })
+    }
+
+    /// Stores a downloaded response body chunk by chunk. Written to a temp
+    /// file first so a failed download or a crash never leaves a truncated
+    /// entry behind.
+    async fn put(
+        &self,
+        key: &str,
+        mut response: reqwest::Response,
+        content_type: String,
+    ) -> std::io::Result<CachedFile> {
+        let (dir, bin, mime) = self.paths(key);
+        fs::create_dir_all(&dir)?;
+        let tmp = bin.with_extension(format!("{}.part", uuid::Uuid::new_v4()));
+        let written = async {
+            let mut file = fs::File::create(&tmp)?;
+            let mut len = 0;
+            while let Some(chunk) = response.chunk().await.map_err(std::io::Error::other)? {
+                file.write_all(&chunk)?;
+                len += chunk.len() as u64;
+            }
+            file.sync_all()?;
+            fs::write(&mime, &content_type)?;
+            fs::rename(&tmp, &bin)?;
+            Ok::<_, std::io::Error>(len)
+        }
+        .await;
+        let len = written.inspect_err(|_| {
+            let _ = fs::remove_file(&tmp);
+        })?;
+        self.evict(key);
+        Ok(CachedFile { path: bin, len, content_type })
+    }
+
+    /// Removes least recently used files until the cache fits its limit.
+    /// The file `keep` was just stored and stays.
+    fn evict(&self, keep: &str) {
+        let Ok(accounts) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut files: Vec<(SystemTime, u64, String)> = Vec::new();
+        for account in accounts.flatten() {
+            let (Ok(entries), Some(account)) =
+                (fs::read_dir(account.path()), account.file_name().to_str().map(str::to_string))
+            else {
+                continue;
+            };
+            files.extend(entries.flatten().filter_map(|entry| {
+                let name = entry.file_name().to_str()?.strip_suffix(".bin")?.to_string();
+                let meta = entry.metadata().ok()?;
+                Some((meta.modified().ok()?, meta.len(), format!("{account}/{name}")))
+            }));
+        }
+        let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+        files.sort();
+        for (_, len, key) in files {
+            if total <= self.max_bytes {
+                break;
+            }
+            if key == keep {
+                continue;
+            }
+            let (_, bin, mime) = self.paths(&key);
+            if fs::remove_file(bin).is_ok() {
+                total -= len;
+            }
+            let _ = fs::remove_file(mime);
+        }
+    }
+
+    /// Removes every cached file.
+    pub fn clear(&self) -> std::io::Result<()> {
+        remove_dir(&self.dir)
+    }
+
+    /// Removes the cached files of one account, by session scope.
+    pub fn purge(&self, scope: &str) -> std::io::Result<()> {
+        remove_dir(&self.account_dir(scope))
+    }
+}
+
+/// Hex SHA-256 of `parts`, separated so that no two lists collide.
+fn digest(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+fn remove_dir(dir: &Path) -> std::io::Result<()> {
+    match fs::remove_dir_all(dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Answers one protocol request.
+pub async fn handle_request(
+    client: &WsClient,
+    sessions: &SessionStore,
+    cache: &PluginfileCache,
+    request: &Request<Vec<u8>>,
+) -> Response<Vec<u8>> {
+    let Some((handle, relative)) = parse_request_path(request.uri().path()) else {
+        return status(StatusCode::FORBIDDEN);
+    };
+    let Ok(session) = sessions.get(handle) else {
+        return status(StatusCode::UNAUTHORIZED);
+    };
+    let query = request.uri().query();
+    let key = PluginfileCache::key(&session, relative, query);
+
+    let file = match cache.get(&key) {
+        Some(file) => file,
+        None => match fetch(client, cache, &key, &session, relative, query).await {
+            Ok(file) => file,
+            Err(code) => return status(code),
+        },
+    };
+    let range = request
+        .headers()
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .map_or(ByteRange::Whole, |v| parse_range(v, file.len));
+    serve(&file, range).unwrap_or_else(|_| status(StatusCode::INTERNAL_SERVER_ERROR))
+}
+
+/// Reads the requested part of a cached file into a response.
+fn serve(file: &CachedFile, range: ByteRange) -> std::io::Result<Response<Vec<u8>>> {
+    let builder = Response::builder()
+        .header(header::CONTENT_TYPE, &file.content_type)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CACHE_CONTROL, "private, max-age=86400");
+    let response = match range {
+        ByteRange::Whole => builder.status(StatusCode::OK).body(fs::read(&file.path)?),
+        ByteRange::Part(start, end) => {
+            let end = end.min(start + MAX_CHUNK - 1);
+            let mut body = Vec::with_capacity((end - start + 1) as usize);
+            let mut reader = fs::File::open(&file.path)?;
+            reader.seek(SeekFrom::Start(start))?;
+            reader.take(end - start + 1).read_to_end(&mut body)?;
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_RANGE, format!("bytes {start}-{end}/{}", file.len))
+                .body(body)
+        }
+        ByteRange::Unsatisfiable => builder
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{}", file.len))
+            .body(Vec::new()),
+    };
+    response.map_err(std::io::Error::other)
+}
+
+/// Downloads a file into the cache.
+async fn fetch(
+    client: &WsClient,
+    cache: &PluginfileCache,
+    key: &str,
+    session: &Session,
+    relative: &str,
+    query: Option<&str>,
+) -> Result<CachedFile, StatusCode> {
+    let url = download_url(session, relative, query);
+    client.check_url(&url).map_err(|_| StatusCode::FORBIDDEN)?;
+    let response = client
+        .http()
+        .get(url)
+        .send()
+        .await
+        .map_err(|_| StatusCode::BAD_GATEWAY)?;
+    if !response.status().is_success() {
+        return Err(StatusCode::from_u16(response.status().as_u16())
+            .unwrap_or(StatusCode::BAD_GATEWAY));
+    }
+
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    // Moodle answers auth failures on pluginfile.php with a JSON error body.
+    if content_type.starts_with("application/json") {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    cache.put(key, response, content_type).await.map_err(|_| StatusCode::BAD_GATEWAY)
+}
+
+/// What a `Range` header asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ByteRange {
+    /// No usable range (malformed, several ranges or another unit): the
+    /// whole file.
+    Whole,
+    /// Inclusive bounds within the file.
+    Part(u64, u64),
+    /// A valid range that lies beyond the end of the file.
+    Unsatisfiable,
+}
+
+/// Parses a single `bytes=start-end` range of a file of `len` bytes.
+fn parse_range(value: &str, len: u64) -> ByteRange {
+    let Some(spec) = value.strip_prefix("bytes=").filter(|spec| !spec.contains(',')) else {
+        return ByteRange::Whole;
+    };
+    let Some((start, end)) = spec.split_once('-') else {
+        return ByteRange::Whole;
+    };
+    let bounds = match (start.trim(), end.trim()) {
+        ("", suffix) => suffix.parse::<u64>().ok().map(|suffix| match suffix {
+            0 => (len, len),
+            suffix => (len.saturating_sub(suffix), len.saturating_sub(1)),
+        }),
+        (start, "") => start.parse().ok().map(|start| (start, len.saturating_sub(1))),
+        (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => Some((start, end.min(len.saturating_sub(1)))),
+            _ => None,
+        },
+    };
+    match bounds {
+        None => ByteRange::Whole,
+        Some((start, _)) if start >= len => ByteRange::Unsatisfiable,
+        Some((start, end)) => ByteRange::Part(start, end),
+    }
+}
+
+fn status(code: StatusCode) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(code)
+        .body(Vec::new())
+        .expect("static response")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::test_support::{test_client, MockServer};
+
+    const SITE: &str = "https://school.example/moodle";
+
+    /// A fresh empty directory.
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pluginfile-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A site serving `body` for every pluginfile, and an open session on it.
+    fn site(body: String, content_type: &'static str) -> (MockServer, SessionStore, String) {
+        let server = MockServer::start_with_headers(move |_| {
+            (200, vec![("Content-Type".into(), content_type.into())], body.clone())
+        });
+        let sessions = SessionStore::new();
+        let handle = sessions.open(Session::new(&server.url(), "t").with_account_id("2@site"));
+        (server, sessions, handle)
+    }
+
+    async fn get(
+        sessions: &SessionStore,
+        cache: &PluginfileCache,
+        path: &str,
+        range: Option<&str>,
+    ) -> Response<Vec<u8>> {
+        let mut request = Request::builder().uri(format!("{SCHEME}://localhost/{path}"));
+        if let Some(range) = range {
+            request = request.header(header::RANGE, range);
+        }
+        let request = request.body(Vec::new()).unwrap();
+        handle_request(&test_client(), sessions, cache, &request).await
+    }
+
+    fn content_range(response: &Response<Vec<u8>>) -> Option<&str> {
+        response.headers().get(header::CONTENT_RANGE).and_then(|v| v.to_str().ok())
+    }
+
+    /// Names of the files in the account folders of the cache.
+    fn cached_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|account| fs::read_dir(account.path()).ok())
+            .flatten()
+            .flatten()
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn rewrites_pluginfile_urls() {
+        let base = scheme_base();
+        let html = [
+            r#"<img src="@@PLUGINFILE@@/12/mod_page/content/3/a.png">"#,
+            r#"<a href='SITE/pluginfile.php/12/b.pdf?forcedownload=1&amp;token=abc&amp;x=2'>"#,
+            r#"<img src="SITE/tokenpluginfile.php/t0k/12/c.png">"#,
+            r#"<a href="SITE/course/view.php?id=2">"#,
+            r#"<img src="https://other.example/pluginfile.php/1/d.png">"#,
+        ]
+        .concat()
+        .replace("SITE", SITE);
+
+        let out = rewrite_html(&html, "h1", &format!("{SITE}/"));
+
+        let page = format!(r#""{base}/h1/webservice/pluginfile.php/12/mod_page/content/3/a.png""#);
+        assert!(out.contains(&page));
+        let pdf = format!("href='{base}/h1/pluginfile.php/12/b.pdf?forcedownload=1&amp;x=2'");
+        assert!(out.contains(&pdf));
+        assert!(out.contains(&format!(r#"src="{SITE}/tokenpluginfile.php/t0k/12/c.png""#)));
+        assert!(out.contains(&format!(r#"href="{SITE}/course/view.php?id=2""#)));
+        assert!(out.contains(r#"src="https://other.example/pluginfile.php/1/d.png""#));
+        assert!(!out.contains("token=abc"));
+    }
+
+    #[test]
+    fn strips_tokens_from_queries() {
+        assert_eq!(strip_token_param("token=abc"), "");
+        assert_eq!(strip_token_param("a=1&amp;token=abc&amp;b=2"), "a=1&amp;b=2");
+        assert_eq!(plain_query("token=abc&a=1&amp;b=2"), "a=1&b=2");
+        let url = format!("{SITE}/pluginfile.php/1/x.pdf");
+        assert_eq!(without_token(&format!("{url}?token=abc")), url);
+        assert_eq!(without_token(&format!("{url}?token=abc&rev=2")), format!("{url}?rev=2"));
+    }
+
+    #[test]
+    fn parses_request_paths() {
+        assert_eq!(
+            parse_request_path("/h1/webservice/pluginfile.php/12/a.png"),
+            Some(("h1", "webservice/pluginfile.php/12/a.png"))
+        );
+        let rejected = [
+            "/h1/pluginfile.php/12/../../login/token.php",
+            "/h1/pluginfile.php/..%2f/x",
+            "//pluginfile.php/12/a.png",
+            "/h1/login/token.php",
+            "/h1",
+        ];
+        for path in rejected {
+            assert!(parse_request_path(path).is_none(), "{path}");
+        }
+    }
+
+    #[test]
+    fn builds_download_urls_for_own_files_only() {
+        let session = Session::new(SITE, "secret");
+        assert_eq!(
+            file_download_url(&session, &format!("{SITE}/pluginfile.php/1/x.pdf?token=old&a=1")),
+            Some(format!("{SITE}/webservice/pluginfile.php/1/x.pdf?token=secret&a=1"))
+        );
+        let foreign = [
+            "https://other.example/pluginfile.php/1/x.pdf".to_string(),
+            format!("{SITE}evil/pluginfile.php/1/x.pdf"),
+            format!("{SITE}/pluginfile.php/1/../../x"),
+            format!("{SITE}/login/token.php"),
+        ];
+        for url in foreign {
+            assert!(file_download_url(&session, &url).is_none(), "{url}");
+        }
+    }
+
+    #[test]
+    fn keys_cache_entries_by_account() {
+        let anna = Session::new(SITE, "a").with_account_id("2@school");
+        let ben = Session::new(SITE, "b").with_account_id("3@school");
+        let key = |session: &Session, relative: &str| PluginfileCache::key(session, relative, None);
+
+        assert_ne!(key(&anna, "pluginfile.php/1/x.pdf"), key(&ben, "pluginfile.php/1/x.pdf"));
+        assert_eq!(
+            key(&anna, "pluginfile.php/1/x.pdf"),
+            key(&anna, "webservice/pluginfile.php/1/x.pdf")
+        );
+        assert_ne!(
+            PluginfileCache::key(&anna, "pluginfile.php/1/x.pdf", Some("rev=2")),
+            key(&anna, "pluginfile.php/1/x.pdf")
+        );
+    }
+
+    #[test]
+    fn parses_byte_ranges() {
+        use ByteRange::{Part, Unsatisfiable, Whole};
+        let cases = [
+            ("bytes=0-99", 1000, Part(0, 99)),
+            ("bytes=900-", 1000, Part(900, 999)),
+            ("bytes=-100", 1000, Part(900, 999)),
+            ("bytes=-5000", 1000, Part(0, 999)),
+            ("bytes=990-2000", 1000, Part(990, 999)),
+            ("bytes=1000-", 1000, Unsatisfiable),
+            ("bytes=1000-2000", 1000, Unsatisfiable),
+            ("bytes=-0", 1000, Unsatisfiable),
+            ("bytes=0-", 0, Unsatisfiable),
+            ("bytes=50-10", 1000, Whole),
+            ("bytes=0-1,5-9", 1000, Whole),
+            ("items=0-1", 1000, Whole),
+            ("bytes=a-b", 1000, Whole),
+            ("bytes=5", 1000, Whole),
+        ];
+        for (value, len, expected) in cases {
+            assert_eq!(parse_range(value, len), expected, "{value} of {len}");
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_files_from_the_cache() {
+        let (server, sessions, handle) = site("0123456789".into(), "text/plain");
+        let dir = temp_dir();
+        let cache = PluginfileCache::new(dir.clone(), DEFAULT_MAX_BYTES);
+        let path = format!("{handle}/pluginfile.php/1/mod_page/content/a.txt");
+
+        for _ in 0..2 {
+            let response = get(&sessions, &cache, &path, None).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.body(), b"0123456789");
+            assert_eq!(response.headers()[header::CONTENT_TYPE], "text/plain");
+        }
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].path,
+            "/webservice/pluginfile.php/1/mod_page/content/a.txt?token=t"
+        );
+        assert!(cached_names(&dir).iter().all(|name| !name.ends_with(".part")));
+        assert_eq!(cached_names(&dir).len(), 2);
+
+        // Offline, the cached copy is still served.
+        drop(server);
+        let response = get(&sessions, &cache, &path, Some("bytes=0-3")).await;
+        assert_eq!(response.body(), b"0123");
+    }
+
+    #[tokio::test]
+    async fn serves_ranges_from_disk() {
+        let (_server, sessions, handle) = site("0123456789".into(), "text/plain");
+        let cache = PluginfileCache::new(temp_dir(), DEFAULT_MAX_BYTES);
+        let path = format!("{handle}/pluginfile.php/1/a.txt");
+
+        let response = get(&sessions, &cache, &path, Some("bytes=2-5")).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.body(), b"2345");
+        assert_eq!(content_range(&response), Some("bytes 2-5/10"));
+
+        let response = get(&sessions, &cache, &path, Some("bytes=-3")).await;
+        assert_eq!(response.body(), b"789");
+        assert_eq!(content_range(&response), Some("bytes 7-9/10"));
+
+        let response = get(&sessions, &cache, &path, Some("bytes=20-")).await;
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert!(response.body().is_empty());
+        assert_eq!(content_range(&response), Some("bytes */10"));
+
+        let response = get(&sessions, &cache, &path, Some("bytes=5-2")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), b"0123456789");
+    }
+
+    #[tokio::test]
+    async fn serves_large_files_in_chunks() {
+        let len = MAX_CHUNK + 10;
+        let (_server, sessions, handle) = site("x".repeat(len as usize), "video/mp4");
+        let cache = PluginfileCache::new(temp_dir(), DEFAULT_MAX_BYTES);
+        let path = format!("{handle}/pluginfile.php/1/lecture.mp4");
+
+        let response = get(&sessions, &cache, &path, Some("bytes=0-")).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.body().len() as u64, MAX_CHUNK);
+        let expected = format!("bytes 0-{}/{len}", MAX_CHUNK - 1);
+        assert_eq!(content_range(&response), Some(expected.as_str()));
+
+        let response = get(&sessions, &cache, &path, Some(&format!("bytes={MAX_CHUNK}-"))).await;
+        assert_eq!(response.body().len(), 10);
+        let expected = format!("bytes {MAX_CHUNK}-{}/{len}", len - 1);
+        assert_eq!(content_range(&response), Some(expected.as_str()));
+    }
+
+    #[tokio::test]
+    async fn does_not_cache_failures() {
+        let (_server, sessions, handle) =
+            site(r#"{"errorcode":"invalidtoken"}"#.into(), "application/json");
+        let dir = temp_dir();
+        let cache = PluginfileCache::new(dir.clone(), DEFAULT_MAX_BYTES);
+        let response = get(&sessions, &cache, &format!("{handle}/pluginfile.php/1/a"), None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(cached_names(&dir).is_empty());
+
+        let response = get(&sessions, &cache, "unknown/pluginfile.php/1/a", None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = get(&sessions, &cache, &format!("{handle}/login/token.php"), None).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_used_files() {
+        let (server, sessions, handle) = site("0123456789".into(), "text/plain");
+        let cache = PluginfileCache::new(temp_dir(), 25);
+        let session = sessions.get(&handle).unwrap();
+        let bin = |name: &str| {
+            let key = PluginfileCache::key(&session, &format!("pluginfile.php/1/{name}"), None);
+            cache.paths(&key).1
+        };
+        let backdate = |name: &str, secs: u64| {
+            let file = fs::File::options().append(true).open(bin(name)).unwrap();
+            file.set_modified(SystemTime::now() - Duration::from_secs(secs)).unwrap();
+        };
+
+        for name in ["a", "b"] {
+            get(&sessions, &cache, &format!("{handle}/pluginfile.php/1/{name}"), None).await;
+        }
+        backdate("a", 200);
+        backdate("b", 100);
+        // Reading `a` makes `b` the least recently used file.
+        get(&sessions, &cache, &format!("{handle}/pluginfile.php/1/a"), None).await;
+        get(&sessions, &cache, &format!("{handle}/pluginfile.php/1/c"), None).await;
+
+        assert!(bin("a").exists());
+        assert!(!bin("b").exists());
+        assert!(bin("c").exists());
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn purges_the_files_of_one_account() {
+        let server = MockServer::start(|_| (200, "content".into()));
+        let sessions = SessionStore::new();
+        let anna = sessions.open(Session::new(&server.url(), "a").with_account_id("2@site"));
+        let ben = sessions.open(Session::new(&server.url(), "b").with_account_id("3@site"));
+        let dir = temp_dir();
+        let cache = PluginfileCache::new(dir.clone(), DEFAULT_MAX_BYTES);
+        for handle in [&anna, &ben] {
+            get(&sessions, &cache, &format!("{handle}/pluginfile.php/1/a.png"), None).await;
+        }
+        assert_eq!(cached_names(&dir).len(), 4);
+
+        cache.purge("2@site").unwrap();
+        let key = |handle: &str| {
+            PluginfileCache::key(&sessions.get(handle).unwrap(), "pluginfile.php/1/a.png", None)
+        };
+        assert!(cache.get(&key(&anna)).is_none());
+        assert!(cache.get(&key(&ben)).is_some());
+        assert_eq!(cached_names(&dir).len(), 2);
+        cache.purge("2@site").unwrap();
+    }
+}
@@ -0,0 +1,234 @@
+//! Pre-login site discovery via `tool_mobile_get_public_config`.
+//!
+//! The public config is available without a token through
+//! `lib/ajax/service-nologin.php` and tells the login screen which flow the
+//! site expects (password form, browser SSO or embedded SSO) before the
+//! user has typed anything.
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::login::LoginError;
+use crate::ws::{WsClient, WsError};
+
+/// WS function returning the site's public configuration.
+pub const PUBLIC_CONFIG_FUNCTION: &str = "tool_mobile_get_public_config";
+
+/// How the site wants users to log in (`typeoflogin`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LoginType {
+    /// Username and password inside the app.
+    App,
+    /// SSO in the system browser via `launch.php`.
+    Browser,
+    /// SSO in an embedded browser window.
+    Embedded,
+}
+
+impl LoginType {
+    fn from_code(code: i64) -> Self {
+        match code {
+            2 => LoginType::Browser,
+            3 => LoginType::Embedded,
+            _ => LoginType::App,
+        }
+    }
+}
+
+/// An OAuth2 / SAML issuer offered on the login page.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdentityProvider {
+    pub name: String,
+    pub icon_url: Option<String>,
+    pub url: String,
+}
+
+/// What the login screen needs to know about a site.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SiteProbe {
+    /// Normalised URL that was probed.
+    pub site_url: String,
+    pub site_name: String,
+    pub logo_url: Option<String>,
+    pub compact_logo_url: Option<String>,
+    pub login_type: LoginType,
+    /// Launch URL for browser/embedded SSO, if the site provides one.
+    pub launch_url: Option<String>,
+    pub identity_providers: Vec<IdentityProvider>,
+    pub maintenance_enabled: bool,
+    pub maintenance_message: Option<String>,
+    /// Human-readable Moodle release, e.g. `4.3.2 (Build: 20231222)`.
+    pub release: Option<String>,
+    pub version: Option<String>,
+    pub web_services_enabled: bool,
+    pub mobile_service_enabled: bool,
+}
+
+/// Builds a [`SiteProbe`] from a `tool_mobile_get_public_config` response.
+pub fn parse_public_config(site_url: &str, config: &Value) -> SiteProbe {
+    let text = |key: &str| {
+        config
+            .get(key)
+            .and_then(Value::as_str)
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+    };
+    let int = |key: &str| {
+        config.get(key).and_then(|v| {
+            v.as_i64()
+                .or_else(|| v.as_bool().map(i64::from))
+                .or_else(|| v.as_str().and_then(|s| s.parse().ok()))
+        })
+    };
+    let flag = |key: &str| int(key).unwrap_or(0) != 0;
+
+    let identity_providers = config
+        .get("identityproviders")
+        .and_then(Value::as_array)
+        .map(|providers| {
+            providers
+                .iter()
+                .filter_map(|p| {
+                    Some(IdentityProvider {
+                        name: p.get("name")?.as_str()?.to_string(),
+                        icon_url: p
+                            .get("iconurl")
+                            .and_then(Value::as_str)
+                            .filter(|s| !s.is_empty())
+                            .map(str::to_string),
+                        url: p.get("url")?.as_str()?.to_string(),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    SiteProbe {
+        site_url: site_url.to_string(),
+        site_name: text("sitename").unwrap_or_default(),
+        logo_url: text("logourl"),
+        compact_logo_url: text("compactlogourl"),
+        login_type: LoginType::from_code(int("typeoflogin").unwrap_or(1)),
+        launch_url: text("launchurl"),
+        identity_providers,
+        maintenance_enabled: flag("maintenanceenabled"),
+        maintenance_message: text("maintenancemessage"),
+        release: text("release"),
+        version: text("version").or_else(|| int("version").map(|v| v.to_string())),
+        web_services_enabled: flag("enablewebservices"),
+        mobile_service_enabled: flag("enablemobilewebservice"),
+    }
+}
+
+/// Validates `site_url` and fetches its public configuration.
+pub async fn probe_site(client: &WsClient, site_url: &str) -> Result<SiteProbe, LoginError> {
+    let site_url = client.policy().validate(site_url).await?;
+    let config = client
+        .call_ajax_nologin(&site_url, PUBLIC_CONFIG_FUNCTION, &json!({}))
+        .await
+        .map_err(|err| match err {
+            // No AJAX endpoint at all: not a Moodle site (or Moodle < 3.2).
+            WsError::Http { status: 404, .. } => LoginError::NotMoodle,
+            other => LoginError::from(other),
+        })?;
+    if !config.is_object() {
+        return Err(LoginError::NotMoodle);
+    }
+    Ok(parse_public_config(&site_url, &config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SITE: &str = "https://moodle.example.org";
+
+    #[test]
+    fn parses_public_config() {
+        let config = json!({
+            "wwwroot": SITE,
+            "sitename": "Example University",
+            "logourl": "https://moodle.example.org/logo.png",
+            "compactlogourl": "",
+            "typeoflogin": 2,
+            "launchurl": "https://moodle.example.org/admin/tool/mobile/launch.php",
+            "identityproviders": [
+                {
+                    "name": "Microsoft",
+                    "iconurl": "https://login.example/ms.png",
+                    "url": "https://moodle.example.org/auth/oauth2/login.php?id=1",
+                },
+                {
+                    "name": "Google",
+                    "iconurl": "",
+                    "url": "https://moodle.example.org/auth/oauth2/login.php?id=2",
+                },
+                {"name": "Broken"},
+            ],
+            "maintenanceenabled": 0,
+            "maintenancemessage": "",
+            "release": "4.3.2 (Build: 20231222)",
+            "version": "2023100902",
+            "enablewebservices": 1,
+            "enablemobilewebservice": 1,
+        });
+        let probe = parse_public_config(SITE, &config);
+        assert_eq!(probe.site_url, SITE);
+        assert_eq!(probe.site_name, "Example University");
+        assert_eq!(probe.logo_url.as_deref(), Some("https://moodle.example.org/logo.png"));
+        assert_eq!(probe.compact_logo_url, None);
+        assert_eq!(probe.login_type, LoginType::Browser);
+        assert!(probe.launch_url.is_some());
+        assert_eq!(probe.identity_providers.len(), 2);
+        assert_eq!(
+            probe.identity_providers[0].icon_url.as_deref(),
+            Some("https://login.example/ms.png")
+        );
+        assert_eq!(probe.identity_providers[1].icon_url, None);
+        assert!(!probe.maintenance_enabled);
+        assert_eq!(probe.maintenance_message, None);
+        assert_eq!(probe.release.as_deref(), Some("4.3.2 (Build: 20231222)"));
+        assert_eq!(probe.version.as_deref(), Some("2023100902"));
+        assert!(probe.web_services_enabled);
+        assert!(probe.mobile_service_enabled);
+    }
+
+    #[test]
+    fn reads_loosely_typed_values() {
+        let config = json!({
+            "typeoflogin": "3",
+            "maintenanceenabled": true,
+            "maintenancemessage": "Back at noon",
+            "version": 2023100902,
+            "enablewebservices": "1",
+            "enablemobilewebservice": "0",
+        });
+        let probe = parse_public_config(SITE, &config);
+        assert_eq!(probe.login_type, LoginType::Embedded);
+        assert!(probe.maintenance_enabled);
+        assert_eq!(probe.maintenance_message.as_deref(), Some("Back at noon"));
+        assert_eq!(probe.version.as_deref(), Some("2023100902"));
+        assert!(probe.web_services_enabled);
+        assert!(!probe.mobile_service_enabled);
+    }
+
+    #[test]
+    fn defaults_missing_values() {
+        let probe = parse_public_config(SITE, &json!({}));
+        assert_eq!(probe.site_name, "");
+        assert_eq!(probe.login_type, LoginType::App);
+        assert_eq!(probe.launch_url, None);
+        assert!(probe.identity_providers.is_empty());
+        assert!(!probe.maintenance_enabled);
+        assert_eq!(probe.release, None);
+        assert_eq!(probe.version, None);
+        assert!(!probe.web_services_enabled);
+        assert!(!probe.mobile_service_enabled);
+
+        let probe = parse_public_config(SITE, &json!({"typeoflogin": 9}));
+        assert_eq!(probe.login_type, LoginType::App);
+    }
+}
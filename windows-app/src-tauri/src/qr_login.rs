@@ -0,0 +1,257 @@
+//! QR-code login via `tool_mobile_get_tokens_for_qr_login`.
+//!
+//! Moodle's "log in to the mobile app" QR code encodes
+//! `<urlscheme>://<siteurl>?qrlogin=<key>&userid=<id>`. The payload can be
+//! passed as text (scanned elsewhere) or as an image file that is decoded
+//! here. The key is exchanged for a token without any password.
+
+use std::path::Path;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::login::{self, LoginError, LoginResult, TokenPair};
+use crate::session::SessionStore;
+use crate::site_url;
+use crate::ws::WsClient;
+
+/// WS function exchanging a QR login key for tokens.
+pub const QR_LOGIN_FUNCTION: &str = "tool_mobile_get_tokens_for_qr_login";
+
+#[derive(Debug, thiserror::Error, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum QrLoginError {
+    #[error("Invalid QR login code: {message}")]
+    InvalidPayload { message: String },
+
+    #[error("Cannot read image: {message}")]
+    Image { message: String },
+
+    #[error("No QR code found in the image")]
+    NoQrCode,
+
+    #[error("Login failed: {error}")]
+    Login { error: LoginError },
+}
+
+impl From<LoginError> for QrLoginError {
+    fn from(error: LoginError) -> Self {
+        QrLoginError::Login { error }
+    }
+}
+
+/// Validated content of a QR login code.
+#[derive(Debug, PartialEq)]
+pub struct QrLoginPayload {
+    pub site_url: String,
+    pub qr_login_key: String,
+    pub user_id: i64,
+}
+
+/// Parses and validates the text of a QR login code.
+pub fn parse_payload(text: &str) -> Result<QrLoginPayload, QrLoginError> {
+    let invalid = |message: &str| QrLoginError::InvalidPayload { message: message.to_string() };
+
+    // Drop the app URL scheme in front of the site URL, if present.
+    let text = text.trim();
+    let site_part = match text.split_once("://") {
+        Some((_, rest)) if rest.starts_with("http://") || rest.starts_with("https://") => rest,
+        _ => text,
+    };
+
+    let url = reqwest::Url::parse(site_part).map_err(|_| invalid("not a URL"))?;
+    let mut key = None;
+    let mut user_id = None;
+    for (name, value) in url.query_pairs() {
+        match name.as_ref() {
+            "qrlogin" => key = Some(value.into_owned()),
+            "userid" => user_id = value.parse::<i64>().ok(),
+            _ => {}
+        }
+    }
+
+    let qr_login_key = key
+        .filter(|k| !k.is_empty() && k.chars().all(|c| c.is_ascii_alphanumeric()))
+        .ok_or_else(|| invalid("missing or malformed login key"))?;
+    let user_id = user_id
+        .filter(|id| *id > 0)
+        .ok_or_else(|| invalid("missing or malformed user id"))?;
+
+    let mut site = url.clone();
+    site.set_query(None);
+    site.set_fragment(None);
+    let site_url = site_url::normalise(site.as_str())
+        .map_err(|e| QrLoginError::InvalidPayload { message: e.to_string() })?;
+
+    Ok(QrLoginPayload { site_url, qr_login_key, user_id })
+}
+
+/// Decodes the first QR code found in an image file.
+pub fn decode_image(path: &Path) -> Result<String, QrLoginError> {
+    let image = image::open(path)
+        .map_err(|e| QrLoginError::Image { message: e.to_string() })?
+        .to_luma8();
+    let mut prepared = rqrr::PreparedImage::prepare(image);
+    prepared
+        .detect_grids()
+        .iter()
+        .find_map(|grid| grid.decode().ok())
+        .map(|(_, content)| content)
+        .ok_or(QrLoginError::NoQrCode)
+}
+
+/// Exchanges a QR login key for tokens at `site_url`. Moodle only answers
+/// clients that identify as the mobile app, see [`crate::ws::USER_AGENT`].
+pub(crate) async fn fetch_token(
+    client: &WsClient,
+    site_url: &str,
+    payload: &QrLoginPayload,
+) -> Result<TokenPair, LoginError> {
+    let args = json!({ "qrloginkey": payload.qr_login_key, "userid": payload.user_id });
+    let data = client.call_ajax_nologin(site_url, QR_LOGIN_FUNCTION, &args).await?;
+
+    let token = data
+        .get("token")
+        .and_then(Value::as_str)
+        .ok_or(LoginError::NotMoodle)?;
+    Ok(TokenPair {
+        token: token.to_string(),
+        private_token: data.get("privatetoken").and_then(Value::as_str).map(str::to_string),
+    })
+}
+
+/// Exchanges a QR login key for a token and opens a session.
+pub async fn login(
+    client: &WsClient,
+    sessions: &SessionStore,
+    payload: QrLoginPayload,
+) -> Result<LoginResult, QrLoginError> {
+    let site_url = client
+        .policy()
+        .validate(&payload.site_url)
+        .await
+        .map_err(LoginError::from)?;
+    let tokens = fetch_token(client, &site_url, &payload).await?;
+    Ok(login::open_session(client, sessions, &site_url, tokens).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::{test_client, MockServer};
+    use crate::ws::{AJAX_NOLOGIN_ENDPOINT, USER_AGENT};
+
+    fn payload() -> QrLoginPayload {
+        QrLoginPayload {
+            site_url: "https://moodle.example.org".into(),
+            qr_login_key: "Ab12".into(),
+            user_id: 7,
+        }
+    }
+
+    /// A site answering the QR login call with `result` and the site info
+    /// with the recorded fixture.
+    fn site(result: &'static str) -> MockServer {
+        MockServer::start(move |req| {
+            if req.path.contains(AJAX_NOLOGIN_ENDPOINT) {
+                (200, result.into())
+            } else {
+                let info = include_str!("../tests/fixtures/core_webservice_get_site_info.json");
+                (200, info.into())
+            }
+        })
+    }
+
+    fn rejects(text: &str) -> bool {
+        matches!(parse_payload(text), Err(QrLoginError::InvalidPayload { .. }))
+    }
+
+    #[test]
+    fn parses_payloads() {
+        let payload =
+            parse_payload(" moodlemobile://https://Moodle.Example.org/lms?qrlogin=Ab12&userid=7 ")
+                .unwrap();
+        assert_eq!(
+            payload,
+            QrLoginPayload {
+                site_url: "https://moodle.example.org/lms".into(),
+                qr_login_key: "Ab12".into(),
+                user_id: 7,
+            }
+        );
+
+        // Without the app scheme, and with extra parameters and a fragment.
+        let payload =
+            parse_payload("https://moodle.example.org/?lang=de&userid=3&qrlogin=xyz#top").unwrap();
+        assert_eq!(payload.site_url, "https://moodle.example.org");
+        assert_eq!(payload.qr_login_key, "xyz");
+        assert_eq!(payload.user_id, 3);
+    }
+
+    #[test]
+    fn rejects_malformed_payloads() {
+        let cases = [
+            "",
+            "not a url",
+            "https://moodle.example.org?userid=7",
+            "https://moodle.example.org?qrlogin=&userid=7",
+            "https://moodle.example.org?qrlogin=ab-12&userid=7",
+            "https://moodle.example.org?qrlogin=ab12",
+            "https://moodle.example.org?qrlogin=ab12&userid=0",
+            "https://moodle.example.org?qrlogin=ab12&userid=seven",
+            "moodlemobile://http://moodle.example.org?qrlogin=ab12&userid=7",
+        ];
+        for text in cases {
+            assert!(rejects(text), "{text}");
+        }
+    }
+
+    #[tokio::test]
+    async fn exchanges_the_key_as_the_mobile_app() {
+        let server = site(r#"[{"error":false,"data":{"token":"abc","privatetoken":"xyz"}}]"#);
+        let client = test_client();
+        let tokens = fetch_token(&client, &server.url(), &payload()).await.unwrap();
+        assert_eq!(tokens.token, "abc");
+        assert_eq!(tokens.private_token.as_deref(), Some("xyz"));
+
+        let request = server.last_request();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, format!("/{AJAX_NOLOGIN_ENDPOINT}?info={QR_LOGIN_FUNCTION}"));
+        assert_eq!(request.header("user-agent"), Some(USER_AGENT));
+        assert!(USER_AGENT.contains("MoodleMobile"));
+        let body: Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(body[0]["methodname"], QR_LOGIN_FUNCTION);
+        assert_eq!(body[0]["args"], json!({ "qrloginkey": "Ab12", "userid": 7 }));
+
+        // The tokens open a session tagged with the account id.
+        let sessions = SessionStore::new();
+        let result = login::open_session(&client, &sessions, &server.url(), tokens).await.unwrap();
+        let session = sessions.get(&result.session.handle).unwrap();
+        assert_eq!(session.token(), "abc");
+        assert_eq!(session.private_token(), Some("xyz"));
+        assert_eq!(session.account_id(), Some(format!("3@{}", server.url()).as_str()));
+        assert_eq!(server.last_request().header("user-agent"), Some(USER_AGENT));
+    }
+
+    #[tokio::test]
+    async fn reports_refused_keys() {
+        let server = site(r#"[{"error":false,"data":{"token":"abc"}}]"#);
+        let tokens = fetch_token(&test_client(), &server.url(), &payload()).await.unwrap();
+        assert_eq!(tokens.private_token, None);
+
+        let refused = site(
+            r#"[{"error":true,"exception":{"errorcode":"apprequired",
+                "message":"This functionality is only available from the Moodle app"}}]"#,
+        );
+        let result = fetch_token(&test_client(), &refused.url(), &payload()).await;
+        assert!(matches!(
+            result,
+            Err(LoginError::Moodle { errorcode, .. }) if errorcode == "apprequired"
+        ));
+
+        let empty = site(r#"[{"error":false,"data":{"warnings":[]}}]"#);
+        let result = fetch_token(&test_client(), &empty.url(), &payload()).await;
+        assert!(matches!(result, Err(LoginError::NotMoodle)));
+    }
+}
@@ -0,0 +1,605 @@
+//! SQLite-backed cache of WS responses for offline use.
+//!
+//! Replaces `OfflineCacheService`, which kept every response as its own key
+//! in `moodle-desktop-store.json`. Entries are keyed by account scope,
+//! function name and canonical (key-sorted) parameters, expire after the
+//! per-function TTL from [`ttl_for`], and may still be read after expiry
+//! when the app is offline. The cache keeps a byte budget and evicts the
+//! least recently used entries once it is exceeded; every write runs in a
+//! transaction.
+//!
+//! Response bodies are sealed with the account's [`DataKey`] and bound to
+//! their scope and key. Keys stay in clear, so invalidation can match on
+//! ids; they hold function names and parameters, not user content. An entry
+//! that no longer decrypts (the account was removed and re-added) is a miss.
+
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::data_key::DataKey;
+use crate::invalidation;
+use crate::session::SessionError;
+use crate::vault::VaultError;
+
+/// Database file in the app cache directory.
+pub const DATABASE_FILE: &str = "responses.sqlite3";
+
+/// TTL for functions without an entry in [`ttl_for`].
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Default byte budget before LRU eviction starts.
+pub const DEFAULT_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+const MINUTE: u64 = 60;
+
+/// Per-function TTL, same values as `OfflineCacheService.ttlMap`.
+pub fn ttl_for(function: &str) -> Duration {
+    let secs = match function {
+        "core_enrol_get_users_courses" => 30 * MINUTE,
+        "core_course_get_contents" => 30 * MINUTE,
+        "core_webservice_get_site_info" => 24 * 60 * MINUTE,
+        "gradereport_overview_get_course_grades" => 15 * MINUTE,
+        "core_calendar_get_calendar_monthly_view" => 15 * MINUTE,
+        "message_popup_get_popup_notifications" => 5 * MINUTE,
+        "core_message_get_conversations" => 5 * MINUTE,
+        "core_user_get_users_by_field" => 60 * MINUTE,
+        "core_files_get_files" => 30 * MINUTE,
+        "mod_forum_get_forums_by_courses" => 5 * MINUTE,
+        "mod_forum_get_forum_discussions" => 2 * MINUTE,
+        "mod_forum_get_discussion_posts" => MINUTE,
+        "mod_forum_get_forum_discussion_posts" => MINUTE,
+        "mod_quiz_get_quizzes_by_courses" => 10 * MINUTE,
+        "mod_quiz_get_quiz_access_information" => 5 * MINUTE,
+        "mod_quiz_get_user_attempts" => MINUTE,
+        "mod_quiz_get_user_quiz_attempts" => MINUTE,
+        "mod_quiz_get_user_best_grade" => 2 * MINUTE,
+        "core_comment_get_comments" => 2 * MINUTE,
+        _ => return DEFAULT_TTL,
+    };
+    Duration::from_secs(secs)
+}
+
+/// Builds the cache key for a call: `function::{params}` with object keys
+/// sorted at every level, so the order the frontend used does not matter.
+pub fn cache_key(function: &str, params: &Value) -> String {
+    format!("{function}::{}", canonical(params))
+}
+
+fn canonical(value: &Value) -> String {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            let fields: Vec<String> = entries
+                .into_iter()
+                .map(|(k, v)| format!("{}:{}", Value::String(k.clone()), canonical(v)))
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        }
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(canonical).collect();
+            format!("[{}]", items.join(","))
+        }
+        Value::Null => "{}".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Recovers the parameters from a key built by [`cache_key`].
+fn params_of_key(key: &str) -> Value {
+    key.split_once("::")
+        .and_then(|(_, json)| serde_json::from_str(json).ok())
+        .unwrap_or(Value::Null)
+}
+
+/// Associated data binding a sealed body to its row.
+fn aad(scope: &str, key: &str) -> Vec<u8> {
+    [scope.as_bytes(), b"\0", key.as_bytes()].concat()
+}
+
+/// Decrypts and parses a stored body; `None` if it does not decrypt.
+fn open_body(data_key: &DataKey, scope: &str, key: &str, sealed: &[u8]) -> Option<Value> {
+    let plain = data_key.open(sealed, &aad(scope, key))?;
+    serde_json::from_slice(&plain).ok()
+}
+
+pub fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
+#[derive(Debug, thiserror::Error, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum CacheError {
+    #[error("Cache database error: {message}")]
+    Database { message: String },
+
+    #[error("Cached data is corrupt: {message}")]
+    Corrupt { message: String },
+
+    #[error("Unknown or closed session")]
+    UnknownSession,
+
+    /// The account's data key is unavailable (vault locked, account removed).
+    #[error("{error}")]
+    Vault { error: VaultError },
+}
+
+impl From<VaultError> for CacheError {
+    fn from(error: VaultError) -> Self {
+        CacheError::Vault { error }
+    }
+}
+
+impl From<SessionError> for CacheError {
+    fn from(err: SessionError) -> Self {
+        match err {
+            SessionError::UnknownSession => CacheError::UnknownSession,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for CacheError {
+    fn from(err: rusqlite::Error) -> Self {
+        CacheError::Database { message: err.to_string() }
+    }
+}
+
+/// A cached response.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedResponse {
+    pub data: Value,
+    /// When the response was stored (ms since the epoch).
+    pub timestamp: i64,
+    /// Whether the TTL has passed; only returned when stale reads are allowed.
+    pub stale: bool,
+}
+
+/// A cached response with the parameters it was fetched with.
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub params: Value,
+    pub data: Value,
+    /// When the response was stored or last confirmed fresh (ms since the epoch).
+    pub fetched_at: i64,
+}
+
+/// Size of the cache entries of one function.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionStats {
+    pub wsfunction: String,
+    pub entries: u64,
+    pub bytes: u64,
+}
+
+/// Overall cache usage.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStats {
+    pub entries: u64,
+    pub bytes: u64,
+    pub max_bytes: u64,
+    pub by_function: Vec<FunctionStats>,
+}
+
+/// The response cache. Held in Tauri managed state.
+pub struct ResponseCache {
+    conn: Mutex<Connection>,
+    max_bytes: u64,
+}
+
+impl ResponseCache {
+    /// Opens (or creates) the cache database at `path`.
+    pub fn open(path: &Path, max_bytes: u64) -> Result<Self, CacheError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| CacheError::Database { message: e.to_string() })?;
+        }
+        Self::init(Connection::open(path)?, max_bytes)
+    }
+
+    /// An in-memory cache, used when the database file cannot be opened.
+    pub fn in_memory(max_bytes: u64) -> Result<Self, CacheError> {
+        Self::init(Connection::open_in_memory()?, max_bytes)
+    }
+
+    fn init(conn: Connection, max_bytes: u64) -> Result<Self, CacheError> {
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;
+             CREATE TABLE IF NOT EXISTS responses (
+                 scope       TEXT    NOT NULL,
+                 key         TEXT    NOT NULL,
+                 wsfunction  TEXT    NOT NULL,
+                 data        BLOB    NOT NULL,
+                 size        INTEGER NOT NULL,
+                 fetched_at  INTEGER NOT NULL,
+                 ttl_ms      INTEGER NOT NULL,
+                 last_access INTEGER NOT NULL,
+                 PRIMARY KEY (scope, key)
+             );
+             CREATE INDEX IF NOT EXISTS responses_lru ON responses (last_access);",
+        )?;
+        Ok(Self { conn: Mutex::new(conn), max_bytes })
+    }
+
+    /// Returns the cached response for a call. Expired entries are only
+    /// returned (marked `stale`) if `allow_stale` is set, e.g. when offline.
+    pub fn get(
+        &self,
+        scope: &str,
+        data_key: &DataKey,
+        function: &str,
+        params: &Value,
+        allow_stale: bool,
+    ) -> Result<Option<CachedResponse>, CacheError> {
+        let key = cache_key(function, params);
+        let conn = self.conn.lock().unwrap();
+        let row: Option<(Vec<u8>, i64, i64)> = conn
+            .query_row(
+                "SELECT data, fetched_at, ttl_ms FROM responses WHERE scope = ?1 AND key = ?2",
+                params![scope, key],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?;
+        let Some((data, fetched_at, ttl_ms)) = row else {
+            return Ok(None);
+        };
+
+        let now = now_ms();
+        let stale = now.saturating_sub(fetched_at) > ttl_ms;
+        if stale && !allow_stale {
+            return Ok(None);
+        }
+        let Some(data) = open_body(data_key, scope, &key, &data) else {
+            conn.execute(
+                "DELETE FROM responses WHERE scope = ?1 AND key = ?2",
+                params![scope, key],
+            )?;
+            return Ok(None);
+        };
+        conn.execute(
+            "UPDATE responses SET last_access = ?3 WHERE scope = ?1 AND key = ?2",
+            params![scope, key, now],
+        )?;
+        Ok(Some(CachedResponse { data, timestamp: fetched_at, stale }))
+    }
+
+    /// Stores a response and evicts least recently used entries while the
+    /// cache is over its byte budget.
+    pub fn put(
+        &self,
+        scope: &str,
+        data_key: &DataKey,
+        function: &str,
+        params: &Value,
+        data: &Value,
+    ) -> Result<(), CacheError> {
+        let key = cache_key(function, params);
+        let plain = zeroize::Zeroizing::new(
+            serde_json::to_vec(data).map_err(|e| CacheError::Corrupt { message: e.to_string() })?,
+        );
+        let bytes = data_key.seal(&plain, &aad(scope, &key));
+        let ttl_ms = ttl_for(function).as_millis() as i64;
+        let now = now_ms();
+
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO responses
+                 (scope, key, wsfunction, data, size, fetched_at, ttl_ms, last_access)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?6)",
+            params![scope, key, function, bytes, bytes.len() as i64, now, ttl_ms],
+        )?;
+        evict_lru(&tx, self.max_bytes)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Evicts the entries of `scope` that the successful write call
+    /// `function(params)` made outdated, per [`invalidation::RULES`].
+    /// Without `data_key`, rules that look into cached responses evict
+    /// every entry of their read function. Returns the number of evicted
+    /// entries.
+    pub fn invalidate(
+        &self,
+        scope: &str,
+        data_key: Option<&DataKey>,
+        function: &str,
+        params: &Value,
+    ) -> Result<usize, CacheError> {
+        let dependents = invalidation::dependents(function);
+        if dependents.is_empty() {
+            return Ok(0);
+        }
+
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let mut evicted = 0;
+        for (read, selector) in dependents {
+            let entries = {
+                let mut stmt = tx.prepare(
+                    "SELECT key, data FROM responses WHERE scope = ?1 AND wsfunction = ?2",
+                )?;
+                let rows = stmt.query_map(params![scope, read], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+                })?;
+                rows.collect::<Result<Vec<_>, _>>()?
+            };
+            for (key, data) in entries {
+                let entry_params = params_of_key(&key);
+                let data = data_key
+                    .filter(|_| selector.needs_data())
+                    .and_then(|data_key| open_body(data_key, scope, &key, &data));
+                if selector.matches(params, &entry_params, data.as_ref()) {
+                    tx.execute(
+                        "DELETE FROM responses WHERE scope = ?1 AND key = ?2",
+                        params![scope, key],
+                    )?;
+                    evicted += 1;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(evicted)
+    }
+
+    /// All readable entries of `function` in `scope`, expired ones included.
+    pub fn entries(
+        &self,
+        scope: &str,
+        data_key: &DataKey,
+        function: &str,
+    ) -> Result<Vec<CacheEntry>, CacheError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT key, data, fetched_at FROM responses WHERE scope = ?1 AND wsfunction = ?2",
+        )?;
+        let rows = stmt
+            .query_map(params![scope, function], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?, row.get(2)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|(key, data, fetched_at)| {
+                Some(CacheEntry {
+                    data: open_body(data_key, scope, &key, &data)?,
+                    params: params_of_key(&key),
+                    fetched_at,
+                })
+            })
+            .collect())
+    }
+
+    /// Marks an entry as fresh as of `fetched_at` after the server confirmed
+    /// it is unchanged. Returns whether the entry still existed.
+    pub fn mark_fresh(
+        &self,
+        scope: &str,
+        function: &str,
+        params: &Value,
+        fetched_at: i64,
+    ) -> Result<bool, CacheError> {
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            "UPDATE responses SET fetched_at = ?3 WHERE scope = ?1 AND key = ?2",
+            params![scope, cache_key(function, params), fetched_at],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Evicts the entries of `scope` for which `matches(function, params)`
+    /// holds. Returns the number of evicted entries.
+    pub fn evict_where(
+        &self,
+        scope: &str,
+        matches: impl Fn(&str, &Value) -> bool,
+    ) -> Result<usize, CacheError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let entries = {
+            let mut stmt = tx.prepare("SELECT key, wsfunction FROM responses WHERE scope = ?1")?;
+            let rows = stmt.query_map([scope], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?;
+            rows.collect::<Result<Vec<_>, _>>()?
+        };
+        let mut evicted = 0;
+        for (key, function) in entries {
+            if matches(&function, &params_of_key(&key)) {
+                tx.execute(
+                    "DELETE FROM responses WHERE scope = ?1 AND key = ?2",
+                    params![scope, key],
+                )?;
+                evicted += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(evicted)
+    }
+
+    /// Current usage, overall and per function.
+    pub fn stats(&self) -> Result<CacheStats, CacheError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT wsfunction, COUNT(*), SUM(size) FROM responses
+             GROUP BY wsfunction ORDER BY SUM(size) DESC",
+        )?;
+        let by_function = stmt
+            .query_map([], |row| {
+                Ok(FunctionStats {
+                    wsfunction: row.get(0)?,
+                    entries: row.get::<_, i64>(1)? as u64,
+                    bytes: row.get::<_, i64>(2)? as u64,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(CacheStats {
+            entries: by_function.iter().map(|f| f.entries).sum(),
+            bytes: by_function.iter().map(|f| f.bytes).sum(),
+            max_bytes: self.max_bytes,
+            by_function,
+        })
+    }
+
+    /// Deletes the entries of one scope, or everything if `scope` is `None`.
+    pub fn clear(&self, scope: Option<&str>) -> Result<(), CacheError> {
+        let conn = self.conn.lock().unwrap();
+        match scope {
+            Some(scope) => conn.execute("DELETE FROM responses WHERE scope = ?1", [scope])?,
+            None => conn.execute("DELETE FROM responses", [])?,
+        };
+        Ok(())
+    }
+}
+
+/// Deletes least recently used entries until the total size fits `max_bytes`.
+fn evict_lru(tx: &rusqlite::Transaction<'_>, max_bytes: u64) -> Result<(), CacheError> {
+    let total: i64 =
+        tx.query_row("SELECT COALESCE(SUM(size), 0) FROM responses", [], |r| r.get(0))?;
+    let mut excess = total - max_bytes as i64;
+    if excess <= 0 {
+        return Ok(());
+    }
+
+    let victims = {
+        let mut stmt =
+            tx.prepare("SELECT scope, key, size FROM responses ORDER BY last_access ASC")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?))
+        })?;
+        let mut victims = Vec::new();
+        for row in rows {
+            let (scope, key, size) = row?;
+            victims.push((scope, key));
+            excess -= size;
+            if excess <= 0 {
+                break;
+            }
+        }
+        victims
+    };
+    for (scope, key) in victims {
+        tx.execute("DELETE FROM responses WHERE scope = ?1 AND key = ?2", params![scope, key])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SCOPE: &str = "2@https://school.example";
+
+    fn put(cache: &ResponseCache, key: &DataKey, function: &str, params: &Value, data: &Value) {
+        cache.put(SCOPE, key, function, params, data).unwrap();
+    }
+
+    /// Backdates the last access of an entry, for LRU order.
+    fn touch(cache: &ResponseCache, function: &str, params: &Value, last_access: i64) {
+        cache
+            .conn
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE responses SET last_access = ?2 WHERE key = ?1",
+                params![cache_key(function, params), last_access],
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn builds_canonical_keys() {
+        let a = json!({ "courseid": 7, "options": [{ "value": 1, "name": "x" }] });
+        let b = json!({ "options": [{ "name": "x", "value": 1 }], "courseid": 7 });
+        let function = "core_course_get_contents";
+        assert_eq!(cache_key(function, &a), cache_key(function, &b));
+        assert_eq!(
+            cache_key(function, &a),
+            r#"core_course_get_contents::{"courseid":7,"options":[{"name":"x","value":1}]}"#
+        );
+        assert_eq!(cache_key(function, &Value::Null), "core_course_get_contents::{}");
+        assert_ne!(
+            cache_key("mod_forum_get_forum_discussions", &json!({ "forumid": 1 })),
+            cache_key("mod_forum_get_forum_discussions", &json!({ "forumid": "1" }))
+        );
+    }
+
+    #[test]
+    fn recovers_params_from_keys() {
+        let params = json!({ "b": [1, 2], "a": { "d": "x::y", "c": null } });
+        let key = cache_key("core_course_get_contents", &params);
+        assert_eq!(params_of_key(&key), json!({ "a": { "c": {}, "d": "x::y" }, "b": [1, 2] }));
+        assert_eq!(params_of_key(&cache_key("f", &Value::Null)), json!({}));
+        assert_eq!(params_of_key("garbage"), Value::Null);
+    }
+
+    #[test]
+    fn expires_entries_after_their_ttl() {
+        let cache = ResponseCache::in_memory(DEFAULT_MAX_BYTES).unwrap();
+        let key = DataKey::random();
+        let (function, params) = ("core_course_get_contents", json!({ "courseid": 7 }));
+        put(&cache, &key, function, &params, &json!([{ "id": 1 }]));
+
+        let fresh = cache.get(SCOPE, &key, function, &params, false).unwrap().unwrap();
+        assert!(!fresh.stale);
+        assert_eq!(fresh.data, json!([{ "id": 1 }]));
+
+        let ttl = ttl_for(function).as_millis() as i64;
+        assert!(cache.mark_fresh(SCOPE, function, &params, now_ms() - ttl - 1_000).unwrap());
+        assert!(cache.get(SCOPE, &key, function, &params, false).unwrap().is_none());
+
+        // Offline, the expired entry is still served, marked stale.
+        let stale = cache.get(SCOPE, &key, function, &params, true).unwrap().unwrap();
+        assert!(stale.stale);
+        assert_eq!(stale.data, json!([{ "id": 1 }]));
+    }
+
+    #[test]
+    fn drops_entries_that_do_not_decrypt() {
+        let cache = ResponseCache::in_memory(DEFAULT_MAX_BYTES).unwrap();
+        let (function, params) = ("core_webservice_get_site_info", json!({}));
+        put(&cache, &DataKey::random(), function, &params, &json!({ "sitename": "Demo" }));
+
+        let other = DataKey::random();
+        assert!(cache.get(SCOPE, &other, function, &params, true).unwrap().is_none());
+        assert_eq!(cache.stats().unwrap().entries, 0);
+    }
+
+    #[test]
+    fn evicts_least_recently_used_entries_by_size() {
+        let key = DataKey::random();
+        let function = "core_course_get_contents";
+        let data = json!({ "payload": "x".repeat(1_000) });
+        let size = {
+            let probe = ResponseCache::in_memory(DEFAULT_MAX_BYTES).unwrap();
+            put(&probe, &key, function, &json!({ "courseid": 1 }), &data);
+            probe.stats().unwrap().bytes
+        };
+        let cache = ResponseCache::in_memory(size * 5 / 2).unwrap();
+        let course = |id: u64| json!({ "courseid": id });
+
+        put(&cache, &key, function, &course(1), &data);
+        put(&cache, &key, function, &course(2), &data);
+        touch(&cache, function, &course(1), 2);
+        touch(&cache, function, &course(2), 1);
+        put(&cache, &key, function, &course(3), &data);
+
+        let stats = cache.stats().unwrap();
+        assert_eq!((stats.entries, stats.bytes), (2, size * 2));
+        assert!(cache.get(SCOPE, &key, function, &course(2), false).unwrap().is_none());
+        assert!(cache.get(SCOPE, &key, function, &course(1), false).unwrap().is_some());
+        assert!(cache.get(SCOPE, &key, function, &course(3), false).unwrap().is_some());
+    }
+}
@@ -0,0 +1,495 @@
+//! Safe file and folder names for downloads.
+//!
+//! File, folder, section and course names come from Moodle and may contain
+//! anything. Every path the download code writes to is built from them
+//! through this module, which maps names to what Windows accepts (the
+//! strictest of the supported platforms):
+//!
+//! - characters Windows forbids (`<>:"/\|?*`, control characters) become `_`;
+//!   invisible bidi controls, which can disguise an extension, are dropped
+//! - trailing dots and spaces, which Windows silently strips, are removed
+//! - reserved device names (`CON`, `NUL`, `COM1`, ...) get a `_` appended
+//! - names are NFC-normalised, so a name typed on macOS and on Windows maps
+//!   to the same file
+//! - names and whole paths stay within the Windows length limits
+//! - relative paths from Moodle (`filepath`) cannot climb out with `..`
+
+use std::path::{Path, PathBuf};
+
+use icu_normalizer::ComposingNormalizerBorrowed;
+
+/// Longest file or folder name, in UTF-16 code units (NTFS limit).
+pub const MAX_COMPONENT: usize = 255;
+
+/// Longest full path, in UTF-16 code units: `MAX_PATH` without the
+/// terminating NUL. Paths beyond it break Explorer and most applications.
+pub const MAX_PATH: usize = 259;
+
+/// Longest suffix still treated as an extension when shortening names.
+const MAX_EXTENSION: usize = 10;
+
+const REPLACEMENT: char = '_';
+
+const FORBIDDEN: &str = r#"<>:"/\|?*"#;
+
+/// Device names Windows reserves, with or without an extension.
+const RESERVED: [&str; 4] = ["CON", "PRN", "AUX", "NUL"];
+
+/// Prefixes of the numbered device names (`COM1`, `LPT²`, ...).
+const RESERVED_NUMBERED: [&str; 2] = ["COM", "LPT"];
+
+/// Characters that reorder or hide text without being visible.
+fn is_invisible(c: char) -> bool {
+    matches!(c, '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+        || c == '\u{FEFF}'
+}
+
+fn utf16_len(s: &str) -> usize {
+    s.chars().map(char::len_utf16).sum()
+}
+
+/// The longest prefix of `s` with at most `max` UTF-16 code units.
+fn truncate_utf16(s: &str, max: usize) -> &str {
+    let mut len = 0;
+    for (i, c) in s.char_indices() {
+        len += c.len_utf16();
+        if len > max {
+            return &s[..i];
+        }
+    }
+    s
+}
+
+/// Removes what Windows strips from the end of a name, and surrounding
+/// whitespace.
+fn trim(name: &str) -> &str {
+    name.trim().trim_end_matches(['.', ' ']).trim_end()
+}
+
+/// Splits `name` into stem and extension (with its dot). Only a short,
+/// alphanumeric suffix counts as an extension, so `Lecture 1. Introduction`
+/// has none; neither has a name starting with its only dot (`.htaccess`).
+fn split_extension(name: &str) -> (&str, &str) {
+    match name.rfind('.') {
+        Some(i)
+            if i > 0
+                && (1..=MAX_EXTENSION).contains(&(name.len() - i - 1))
+                && name[i + 1..].chars().all(char::is_alphanumeric) =>
+        {
+            name.split_at(i)
+        }
+        _ => (name, ""),
+    }
+}
+
+fn is_reserved(name: &str) -> bool {
+    let base = name
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .trim_end()
+        .to_uppercase();
+    RESERVED.contains(&base.as_str())
+        || RESERVED_NUMBERED.iter().any(|prefix| {
+            base.strip_prefix(prefix).is_some_and(|digit| {
+                matches!(
+                    digit,
+                    "0" | "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9"
+                ) || matches!(digit, "\u{B9}" | "\u{B2}" | "\u{B3}")
+            })
+        })
+}
+
+/// Shortens `name` to `max` UTF-16 code units, keeping its extension.
+fn shorten(name: &str, max: usize, keep_extension: bool) -> Option<String> {
+    if utf16_len(name) <= max {
+        return Some(name.to_string());
+    }
+    let (stem, extension) = match keep_extension {
+        true => split_extension(name),
+        false => (name, ""),
+    };
+    let room = max.checked_sub(utf16_len(extension))?;
+    let stem = trim(truncate_utf16(stem, room));
+    (!stem.is_empty()).then(|| format!("{stem}{extension}"))
+}
+
+/// Normalises, maps characters and trims; never returns an empty name.
+fn clean(name: &str) -> String {
+    let normalized = ComposingNormalizerBorrowed::new_nfc().normalize(name);
+    let mapped: String = normalized
+        .chars()
+        .filter(|&c| !is_invisible(c))
+        .map(|c| {
+            if c.is_control() || FORBIDDEN.contains(c) {
+                REPLACEMENT
+            } else {
+                c
+            }
+        })
+        .collect();
+    let trimmed = trim(&mapped);
+    if trimmed.is_empty() {
+        return REPLACEMENT.to_string();
+    }
+    trimmed.to_string()
+}
+
+/// Appends `_` to reserved device names, before the extension.
+fn unreserve(name: String) -> String {
+    if !is_reserved(&name) {
+        return name;
+    }
+    match name.find('.') {
+        Some(i) => format!("{}{REPLACEMENT}{}", name[..i].trim_end(), &name[i..]),
+        None => format!("{name}{REPLACEMENT}"),
+    }
+}
+
+/// A safe folder name.
+pub fn folder_name(name: &str) -> String {
+    let name = clean(name);
+    let name = shorten(&name, MAX_COMPONENT, false).unwrap_or_else(|| REPLACEMENT.to_string());
+    unreserve(name)
+}
+
+/// A safe file name; shortening keeps the extension.
+pub fn file_name(name: &str) -> String {
+    let name = clean(name);
+    let name = shorten(&name, MAX_COMPONENT, true).unwrap_or_else(|| REPLACEMENT.to_string());
+    unreserve(name)
+}
+
+/// A safe relative folder path from a Moodle `filepath` (`/sub/dir/`).
+/// Empty, `.` and `..` segments are dropped, so the result never leaves the
+/// folder it is joined to.
+pub fn relative_path(path: &str) -> PathBuf {
+    path.split(['/', '\\'])
+        .map(str::trim)
+        .filter(|segment| !trim(segment).is_empty())
+        .filter(|segment| !segment.chars().all(|c| c == '.'))
+        .map(folder_name)
+        .collect()
+}
+
+/// `name`, shortened if needed so that `dir/name` stays within
+/// [`MAX_PATH`]. `None` if `dir` leaves no room for a name.
+pub fn fit(dir: &Path, name: &str) -> Option<String> {
+    let dir_len = utf16_len(&dir.to_string_lossy());
+    let room = MAX_PATH.checked_sub(dir_len + 1)?.min(MAX_COMPONENT);
+    let name = shorten(name, room, true)?;
+    Some(unreserve(name))
+}
+
+/// `dir/name`, or the first free `dir/name (2).ext`, `dir/name (3).ext`,
+/// ... if that exists. Suffixed names are shortened to stay within the
+/// length limits.
+pub fn unique_path(dir: &Path, name: &str) -> PathBuf {
+    let candidate = dir.join(name);
+    if !candidate.exists() {
+        return candidate;
+    }
+    let (stem, extension) = split_extension(name);
+    let dir_len = utf16_len(&dir.to_string_lossy());
+    let room = MAX_PATH.saturating_sub(dir_len + 1).min(MAX_COMPONENT);
+    (2..)
+        .map(|n| {
+            let suffix = format!(" ({n})");
+            let stem_room = room.saturating_sub(utf16_len(&suffix) + utf16_len(extension));
+            let stem = match trim(truncate_utf16(stem, stem_room)) {
+                "" => REPLACEMENT.to_string(),
+                stem => stem.to_string(),
+            };
+            dir.join(format!("{stem}{suffix}{extension}"))
+        })
+        .find(|path| !path.exists())
+        .expect("some suffix is free")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    /// A fresh empty directory.
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sanitize-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn keeps_ordinary_names() {
+        for name in [
+            "Skript.pdf",
+            "Übungsblatt 3 – Lösungen.docx",
+            "講義ノート.pdf",
+            "Résumé (final).odt",
+            "notes 😀.txt",
+            ".htaccess",
+            "archive.tar.gz",
+            "a",
+        ] {
+            assert_eq!(file_name(name), name);
+        }
+    }
+
+    #[test]
+    fn replaces_forbidden_characters() {
+        for c in FORBIDDEN.chars() {
+            assert_eq!(file_name(&format!("a{c}b.txt")), "a_b.txt", "{c:?}");
+        }
+        assert_eq!(file_name("Week 1: Intro?.pdf"), "Week 1_ Intro_.pdf");
+        assert_eq!(file_name(r#"<"a|b">*.txt"#), "__a_b___.txt");
+    }
+
+    #[test]
+    fn replaces_control_characters() {
+        assert_eq!(file_name("a\0b.txt"), "a_b.txt");
+        assert_eq!(file_name("line\nbreak\t.txt"), "line_break_.txt");
+        assert_eq!(file_name("bell\u{7}.txt"), "bell_.txt");
+        assert_eq!(file_name("c1\u{85}.txt"), "c1_.txt");
+    }
+
+    #[test]
+    fn drops_invisible_characters() {
+        // "invoice\u{202E}fdp.exe" displays as "invoiceexe.pdf".
+        assert_eq!(file_name("invoice\u{202E}fdp.exe"), "invoicefdp.exe");
+        assert_eq!(file_name("zero\u{200B}width.txt"), "zerowidth.txt");
+        assert_eq!(file_name("\u{FEFF}bom.txt"), "bom.txt");
+        assert_eq!(file_name("iso\u{2066}late\u{2069}.txt"), "isolate.txt");
+    }
+
+    #[test]
+    fn trims_trailing_dots_and_spaces() {
+        assert_eq!(file_name("name."), "name");
+        assert_eq!(file_name("name..."), "name");
+        assert_eq!(file_name("name . . "), "name");
+        assert_eq!(file_name("  padded.txt  "), "padded.txt");
+        assert_eq!(folder_name("Week 3."), "Week 3");
+        assert_eq!(folder_name("Etc. "), "Etc");
+    }
+
+    #[test]
+    fn never_returns_empty_or_dot_names() {
+        for name in ["", " ", "   ", ".", "..", "...", ". .", "\u{200B}"] {
+            assert_eq!(file_name(name), "_", "{name:?}");
+            assert_eq!(folder_name(name), "_", "{name:?}");
+        }
+    }
+
+    #[test]
+    fn guards_reserved_device_names() {
+        for name in [
+            "CON", "PRN", "AUX", "NUL", "COM1", "COM9", "LPT1", "LPT9", "COM0", "LPT0",
+        ] {
+            assert_eq!(file_name(name), format!("{name}_"));
+            assert_eq!(folder_name(name), format!("{name}_"));
+        }
+        assert_eq!(file_name("con"), "con_");
+        assert_eq!(file_name("Nul.txt"), "Nul_.txt");
+        assert_eq!(file_name("CON.tar.gz"), "CON_.tar.gz");
+        assert_eq!(file_name("aux .txt"), "aux_.txt");
+        assert_eq!(file_name("COM\u{B9}.txt"), "COM\u{B9}_.txt");
+        assert_eq!(file_name("LPT\u{B3}"), "LPT\u{B3}_");
+        assert_eq!(file_name("CON."), "CON_");
+    }
+
+    #[test]
+    fn leaves_names_that_only_resemble_device_names() {
+        for name in [
+            "CONSOLE.txt",
+            "COM10",
+            "LPT",
+            "COMA.pdf",
+            "NULL",
+            "auxiliary.doc",
+            "xCON",
+        ] {
+            assert_eq!(file_name(name), name);
+        }
+    }
+
+    #[test]
+    fn normalises_to_nfc() {
+        let decomposed = "Re\u{301}sume\u{301}.pdf";
+        assert_eq!(file_name(decomposed), "R\u{E9}sum\u{E9}.pdf");
+        assert_eq!(file_name(decomposed), file_name("Résumé.pdf"));
+        assert_eq!(folder_name("U\u{308}bung"), "\u{DC}bung");
+    }
+
+    #[test]
+    fn sanitising_is_idempotent() {
+        for name in [
+            "Week 1: Intro?.pdf",
+            "CON.txt",
+            "name . . ",
+            "Re\u{301}sume\u{301}.pdf",
+            "..",
+            &"x".repeat(400),
+            &format!("{}.pdf", "ü".repeat(300)),
+        ] {
+            let once = file_name(name);
+            assert_eq!(file_name(&once), once, "{name:?}");
+            let once = folder_name(name);
+            assert_eq!(folder_name(&once), once, "{name:?}");
+        }
+    }
+
+    #[test]
+    fn shortens_long_names_keeping_the_extension() {
+        let name = format!("{}.pdf", "a".repeat(300));
+        let safe = file_name(&name);
+        assert_eq!(utf16_len(&safe), MAX_COMPONENT);
+        assert!(safe.ends_with("a.pdf"));
+
+        let folder = folder_name(&"b".repeat(300));
+        assert_eq!(utf16_len(&folder), MAX_COMPONENT);
+    }
+
+    #[test]
+    fn shortening_counts_utf16_units_and_keeps_characters_whole() {
+        // Each emoji is two UTF-16 units.
+        let name = format!("{}.txt", "😀".repeat(200));
+        let safe = file_name(&name);
+        assert!(utf16_len(&safe) <= MAX_COMPONENT);
+        assert!(safe.ends_with("😀.txt"));
+        assert_eq!(
+            safe.chars().filter(|&c| c == '😀').count(),
+            (MAX_COMPONENT - 4) / 2
+        );
+    }
+
+    #[test]
+    fn shortening_does_not_leave_trailing_dots() {
+        let name = format!("{}. {}.txt", "a".repeat(249), "b".repeat(50));
+        let safe = file_name(&name);
+        assert_eq!(safe, format!("{}.txt", "a".repeat(249)));
+    }
+
+    #[test]
+    fn only_short_alphanumeric_suffixes_are_extensions() {
+        assert_eq!(split_extension("report.pdf"), ("report", ".pdf"));
+        assert_eq!(split_extension("archive.tar.gz"), ("archive.tar", ".gz"));
+        assert_eq!(split_extension(".htaccess"), (".htaccess", ""));
+        assert_eq!(split_extension("no extension"), ("no extension", ""));
+        assert_eq!(
+            split_extension("Lecture 1. Introduction"),
+            ("Lecture 1. Introduction", "")
+        );
+        assert_eq!(
+            split_extension("data.verylongextension"),
+            ("data.verylongextension", "")
+        );
+        assert_eq!(split_extension("trailing."), ("trailing.", ""));
+    }
+
+    #[test]
+    fn long_suffixes_are_shortened_like_the_rest() {
+        let name = format!("Lecture 1. {}", "x".repeat(300));
+        let safe = file_name(&name);
+        assert_eq!(utf16_len(&safe), MAX_COMPONENT);
+        assert!(safe.starts_with("Lecture 1. x"));
+    }
+
+    #[test]
+    fn relative_paths_stay_inside() {
+        assert_eq!(relative_path("/"), PathBuf::new());
+        assert_eq!(relative_path(""), PathBuf::new());
+        assert_eq!(relative_path("/sub/dir/"), PathBuf::from("sub").join("dir"));
+        assert_eq!(
+            relative_path("../../etc/passwd"),
+            PathBuf::from("etc").join("passwd")
+        );
+        assert_eq!(relative_path(r"a\..\b"), PathBuf::from("a").join("b"));
+        assert_eq!(relative_path("/./a/.../b/"), PathBuf::from("a").join("b"));
+        assert_eq!(
+            relative_path("C:/Windows"),
+            PathBuf::from("C_").join("Windows")
+        );
+        assert_eq!(
+            relative_path("/Week 1: Intro/CON/"),
+            PathBuf::from("Week 1_ Intro").join("CON_")
+        );
+        assert_eq!(relative_path("/ a . /"), PathBuf::from("a"));
+        for path in ["..", "/../..", r"..\..\", "/. /.. /"] {
+            assert_eq!(relative_path(path), PathBuf::new(), "{path:?}");
+        }
+    }
+
+    #[test]
+    fn fit_keeps_names_that_fit() {
+        let dir = PathBuf::from("C:/Users/student/Downloads");
+        assert_eq!(fit(&dir, "Skript.pdf").as_deref(), Some("Skript.pdf"));
+    }
+
+    #[test]
+    fn fit_shortens_names_to_the_path_limit() {
+        let dir = PathBuf::from(format!("/{}", "d".repeat(199)));
+        let name = format!("{}.pdf", "n".repeat(100));
+        let fitted = fit(&dir, &name).unwrap();
+        assert_eq!(utf16_len(&dir.join(&fitted).to_string_lossy()), MAX_PATH);
+        assert!(fitted.ends_with(".pdf"));
+
+        let exact = "n".repeat(MAX_PATH - 200 - 1);
+        assert_eq!(fit(&dir, &exact).as_deref(), Some(exact.as_str()));
+    }
+
+    #[test]
+    fn fit_fails_without_room() {
+        let dir = PathBuf::from(format!("/{}", "d".repeat(MAX_PATH)));
+        assert_eq!(fit(&dir, "a.txt"), None);
+        // Room for the extension only.
+        let dir = PathBuf::from(format!("/{}", "d".repeat(MAX_PATH - 6)));
+        assert_eq!(fit(&dir, "abc.txt"), None);
+        assert_eq!(fit(&dir, "a"), Some("a".to_string()));
+    }
+
+    #[test]
+    fn fit_guards_reserved_names_after_shortening() {
+        let dir = PathBuf::from(format!("/{}", "d".repeat(MAX_PATH - 10)));
+        assert_eq!(fit(&dir, "NUL_.txt").as_deref(), Some("NUL_.txt"));
+        assert_eq!(fit(&dir, "NUL      x.txt").as_deref(), Some("NUL_.txt"));
+    }
+
+    #[test]
+    fn unique_path_returns_free_names_unchanged() {
+        let dir = temp_dir();
+        assert_eq!(unique_path(&dir, "a.pdf"), dir.join("a.pdf"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unique_path_counts_up() {
+        let dir = temp_dir();
+        fs::write(dir.join("a.pdf"), "").unwrap();
+        assert_eq!(unique_path(&dir, "a.pdf"), dir.join("a (2).pdf"));
+        fs::write(dir.join("a (2).pdf"), "").unwrap();
+        assert_eq!(unique_path(&dir, "a.pdf"), dir.join("a (3).pdf"));
+
+        fs::write(dir.join("README"), "").unwrap();
+        assert_eq!(unique_path(&dir, "README"), dir.join("README (2)"));
+        fs::write(dir.join(".htaccess"), "").unwrap();
+        assert_eq!(unique_path(&dir, ".htaccess"), dir.join(".htaccess (2)"));
+        fs::write(dir.join("archive.tar.gz"), "").unwrap();
+        assert_eq!(
+            unique_path(&dir, "archive.tar.gz"),
+            dir.join("archive.tar (2).gz")
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unique_path_stays_within_the_limits() {
+        let dir = temp_dir();
+        let name = file_name(&format!("{}.pdf", "a".repeat(300)));
+        fs::write(dir.join(&name), "").unwrap();
+        let path = unique_path(&dir, &name);
+        let unique = path.file_name().unwrap().to_string_lossy();
+        assert!(unique.ends_with("a (2).pdf"));
+        assert!(utf16_len(&unique) <= MAX_COMPONENT);
+        assert!(utf16_len(&path.to_string_lossy()) <= MAX_PATH);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,625 @@
+//! Request scheduling for outgoing WS calls.
+//!
+//! Every REST call passes through a [`Scheduler`] that caps the number of
+//! concurrent requests per site, lets user-initiated calls overtake
+//! background prefetch, and retries idempotent read functions on transient
+//! failures with exponential backoff, jitter and `Retry-After`. Write
+//! functions are never retried: a timeout does not tell whether Moodle
+//! already applied the change.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::sync::Notify;
+
+use crate::ws::WsError;
+
+/// Default number of concurrent requests per site.
+pub const DEFAULT_SITE_CONCURRENCY: usize = 6;
+
+/// Who is waiting for a call.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Priority {
+    /// The user is looking at a spinner.
+    #[default]
+    User,
+    /// Prefetch and sync; yields to any waiting user call and never takes
+    /// the last free slot.
+    Background,
+}
+
+/// Retry behaviour for idempotent calls.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt.
+    pub max_retries: u32,
+    /// Delay before the first retry; doubled on every further attempt.
+    pub base_delay: Duration,
+    /// Upper bound for a computed delay.
+    pub max_delay: Duration,
+    /// Longest `Retry-After` that is still waited for; longer ones give up.
+    pub max_retry_after: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            max_retry_after: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `attempt` (0-based): exponential with
+    /// "equal jitter", i.e. between half and the full backoff.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_delay
+            .saturating_mul(1u32.checked_shl(attempt).unwrap_or(u32::MAX))
+            .min(self.max_delay);
+        let half = exp / 2;
+        half + half.mul_f64(fastrand::f64())
+    }
+
+    /// How long to wait before retrying after `err`, or `None` to give up.
+    fn delay_for(&self, err: &WsError, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_retries || !is_transient(err) {
+            return None;
+        }
+        match err {
+            WsError::Http { retry_after: Some(secs), .. } => {
+                let wait = Duration::from_secs(*secs);
+                (wait <= self.max_retry_after).then_some(wait)
+            }
+            _ => Some(self.backoff(attempt)),
+        }
+    }
+}
+
+/// Whether a failed call may succeed if simply repeated.
+fn is_transient(err: &WsError) -> bool {
+    match err {
+        WsError::Network { .. } => true,
+        WsError::Http { status, .. } => matches!(status, 408 | 429 | 502 | 503 | 504),
+        _ => false,
+    }
+}
+
+/// Functions the app calls that only read data and may safely be sent
+/// twice, sorted for binary search. Anything else counts as a write:
+/// `view` functions trigger log events and completion, some getters consume
+/// a one-time key (`tool_mobile_get_autologin_key`) or start an attempt
+/// (`mod_quiz_get_attempt_data`), and nothing is known about functions a
+/// site plugin adds.
+const READ_FUNCTIONS: &[&str] = &[
+    "auth_email_get_signup_settings",
+    "block_recentlyaccesseditems_get_recent_items",
+    "block_starredcourses_get_starred_courses",
+    "core_auth_is_age_digital_consent_verification_enabled",
+    "core_auth_is_minor",
+    "core_badges_get_badge",
+    "core_badges_get_user_badge_by_hash",
+    "core_badges_get_user_badges",
+    "core_block_get_course_blocks",
+    "core_block_get_dashboard_blocks",
+    "core_blog_get_entries",
+    "core_calendar_get_action_events_by_course",
+    "core_calendar_get_action_events_by_courses",
+    "core_calendar_get_action_events_by_timesort",
+    "core_calendar_get_allowed_event_types",
+    "core_calendar_get_calendar_access_information",
+    "core_calendar_get_calendar_day_view",
+    "core_calendar_get_calendar_event_by_id",
+    "core_calendar_get_calendar_events",
+    "core_calendar_get_calendar_monthly_view",
+    "core_calendar_get_calendar_upcoming_view",
+    "core_comment_get_comments",
+    "core_completion_get_activities_completion_status",
+    "core_completion_get_course_completion_status",
+    "core_course_check_updates",
+    "core_course_get_categories",
+    "core_course_get_contents",
+    "core_course_get_course_module",
+    "core_course_get_course_module_by_instance",
+    "core_course_get_courses",
+    "core_course_get_courses_by_field",
+    "core_course_get_enrolled_courses_by_timeline_classification",
+    "core_course_get_recent_courses",
+    "core_course_get_user_administration_options",
+    "core_course_get_user_navigation_options",
+    "core_course_search_courses",
+    "core_courseformat_get_overview_information",
+    "core_enrol_get_course_enrolment_methods",
+    "core_enrol_get_enrolled_users",
+    "core_enrol_get_users_courses",
+    "core_enrol_search_users",
+    "core_files_get_files",
+    "core_filters_get_all_states",
+    "core_filters_get_available_in_context",
+    "core_group_get_activity_allowed_groups",
+    "core_group_get_activity_groupmode",
+    "core_group_get_course_user_groups",
+    "core_h5p_get_trusted_h5p_file",
+    "core_message_data_for_messagearea_conversations",
+    "core_message_data_for_messagearea_search_messages",
+    "core_message_get_contact_requests",
+    "core_message_get_conversation",
+    "core_message_get_conversation_between_users",
+    "core_message_get_conversation_counts",
+    "core_message_get_conversation_members",
+    "core_message_get_conversation_messages",
+    "core_message_get_conversations",
+    "core_message_get_member_info",
+    "core_message_get_messages",
+    "core_message_get_received_contact_requests_count",
+    "core_message_get_self_conversation",
+    "core_message_get_unread_conversation_counts",
+    "core_message_get_unread_notification_count",
+    "core_message_get_user_contacts",
+    "core_message_get_user_message_preferences",
+    "core_message_get_user_notification_preferences",
+    "core_message_message_search_users",
+    "core_message_search_contacts",
+    "core_notes_get_course_notes",
+    "core_rating_get_item_ratings",
+    "core_reportbuilder_list_reports",
+    "core_reportbuilder_retrieve_report",
+    "core_search_get_results",
+    "core_search_get_search_areas_list",
+    "core_tag_get_tag_cloud",
+    "core_tag_get_tag_collections",
+    "core_tag_get_tagindex_per_area",
+    "core_user_get_course_user_profiles",
+    "core_user_get_private_files_info",
+    "core_user_get_user_preferences",
+    "core_user_get_users_by_field",
+    "core_webservice_get_site_info",
+    "core_xapi_get_state",
+    "core_xapi_get_states",
+    "enrol_guest_get_instance_info",
+    "enrol_self_get_instance_info",
+    "gradereport_overview_get_course_grades",
+    "gradereport_user_get_access_information",
+    "gradereport_user_get_grade_items",
+    "gradereport_user_get_grades_table",
+    "message_airnotifier_get_user_devices",
+    "message_airnotifier_is_system_configured",
+    "message_popup_get_popup_notifications",
+    "mod_assign_get_assignments",
+    "mod_assign_get_grades",
+    "mod_assign_get_submission_status",
+    "mod_assign_get_submissions",
+    "mod_assign_get_user_mappings",
+    "mod_assign_list_participants",
+    "mod_bigbluebuttonbn_get_bigbluebuttonbns_by_courses",
+    "mod_bigbluebuttonbn_get_recordings",
+    "mod_bigbluebuttonbn_meeting_info",
+    "mod_book_get_books_by_courses",
+    "mod_certificate_get_issued_certificates",
+    "mod_chat_get_chat_latest_messages",
+    "mod_chat_get_chat_users",
+    "mod_chat_get_chats_by_courses",
+    "mod_chat_get_session_messages",
+    "mod_chat_get_sessions",
+    "mod_choice_get_choice_options",
+    "mod_choice_get_choice_results",
+    "mod_choice_get_choices_by_courses",
+    "mod_data_get_data_access_information",
+    "mod_data_get_databases_by_courses",
+    "mod_data_get_entries",
+    "mod_data_get_entry",
+    "mod_data_get_fields",
+    "mod_data_search_entries",
+    "mod_feedback_get_analysis",
+    "mod_feedback_get_current_completed_tmp",
+    "mod_feedback_get_feedback_access_information",
+    "mod_feedback_get_feedbacks_by_courses",
+    "mod_feedback_get_finished_responses",
+    "mod_feedback_get_items",
+    "mod_feedback_get_last_completed",
+    "mod_feedback_get_non_respondents",
+    "mod_feedback_get_page_items",
+    "mod_feedback_get_responses_analysis",
+    "mod_feedback_get_unfinished_responses",
+    "mod_folder_get_folders_by_courses",
+    "mod_forum_can_add_discussion",
+    "mod_forum_get_discussion_post",
+    "mod_forum_get_discussion_posts",
+    "mod_forum_get_forum_access_information",
+    "mod_forum_get_forum_discussion_posts",
+    "mod_forum_get_forum_discussions",
+    "mod_forum_get_forum_discussions_paginated",
+    "mod_forum_get_forums_by_courses",
+    "mod_glossary_get_categories",
+    "mod_glossary_get_entries_by_author",
+    "mod_glossary_get_entries_by_category",
+    "mod_glossary_get_entries_by_date",
+    "mod_glossary_get_entries_by_letter",
+    "mod_glossary_get_entries_by_search",
+    "mod_glossary_get_entry_by_id",
+    "mod_glossary_get_glossaries_by_courses",
+    "mod_h5pactivity_get_attempts",
+    "mod_h5pactivity_get_h5pactivities_by_courses",
+    "mod_h5pactivity_get_h5pactivity_access_information",
+    "mod_h5pactivity_get_results",
+    "mod_h5pactivity_get_user_attempts",
+    "mod_imscp_get_imscps_by_courses",
+    "mod_label_get_labels_by_courses",
+    "mod_lesson_get_attempts_overview",
+    "mod_lesson_get_content_pages_viewed",
+    "mod_lesson_get_lesson",
+    "mod_lesson_get_lesson_access_information",
+    "mod_lesson_get_lessons_by_courses",
+    "mod_lesson_get_page_data",
+    "mod_lesson_get_pages",
+    "mod_lesson_get_pages_possible_jumps",
+    "mod_lesson_get_questions_attempts",
+    "mod_lesson_get_user_attempt",
+    "mod_lesson_get_user_timers",
+    "mod_lti_get_ltis_by_courses",
+    "mod_page_get_pages_by_courses",
+    "mod_quiz_get_attempt_access_information",
+    "mod_quiz_get_attempt_review",
+    "mod_quiz_get_attempt_summary",
+    "mod_quiz_get_combined_review_options",
+    "mod_quiz_get_quiz_access_information",
+    "mod_quiz_get_quiz_feedback_for_grade",
+    "mod_quiz_get_quiz_required_qtypes",
+    "mod_quiz_get_quizzes_by_courses",
+    "mod_quiz_get_user_attempts",
+    "mod_quiz_get_user_best_grade",
+    "mod_quiz_get_user_quiz_attempts",
+    "mod_resource_get_resources_by_courses",
+    "mod_scorm_get_scorm_access_information",
+    "mod_scorm_get_scorm_attempt_count",
+    "mod_scorm_get_scorm_scoes",
+    "mod_scorm_get_scorm_user_data",
+    "mod_scorm_get_scorms_by_courses",
+    "mod_survey_get_questions",
+    "mod_survey_get_surveys_by_courses",
+    "mod_url_get_urls_by_courses",
+    "mod_wiki_get_page_contents",
+    "mod_wiki_get_subwiki_files",
+    "mod_wiki_get_subwiki_pages",
+    "mod_wiki_get_subwikis",
+    "mod_wiki_get_wikis_by_courses",
+    "mod_workshop_get_assessment",
+    "mod_workshop_get_assessment_form_definition",
+    "mod_workshop_get_grades",
+    "mod_workshop_get_grades_report",
+    "mod_workshop_get_reviewer_assessments",
+    "mod_workshop_get_submission",
+    "mod_workshop_get_submission_assessments",
+    "mod_workshop_get_submissions",
+    "mod_workshop_get_user_plan",
+    "mod_workshop_get_workshop_access_information",
+    "mod_workshop_get_workshops_by_courses",
+    "tool_catalogue_get_user_catalogue",
+    "tool_dataprivacy_get_access_information",
+    "tool_dataprivacy_get_data_requests",
+    "tool_lp_data_for_course_competencies_page",
+    "tool_lp_data_for_plan_page",
+    "tool_lp_data_for_plans_page",
+    "tool_lp_data_for_user_competency_summary",
+    "tool_lp_data_for_user_competency_summary_in_course",
+    "tool_lp_data_for_user_competency_summary_in_plan",
+    "tool_mobile_get_config",
+    "tool_mobile_get_plugins_supporting_mobile",
+    "tool_mobile_get_public_config",
+    "tool_policy_get_user_acceptances",
+    "tool_program_get_user_programs",
+];
+
+/// Whether `function` only reads data and may safely be sent twice.
+pub fn is_read_function(function: &str) -> bool {
+    READ_FUNCTIONS.binary_search(&function).is_ok()
+}
+
+/// Concurrency gate for one site.
+#[derive(Default)]
+struct SiteGate {
+    state: Mutex<GateState>,
+    released: Notify,
+}
+
+#[derive(Default)]
+struct GateState {
+    active: usize,
+    waiting_user: usize,
+}
+
+impl SiteGate {
+    async fn acquire(self: &Arc<Self>, limit: usize, priority: Priority) -> Permit {
+        let mut queued = QueuedUser { gate: self, counted: false };
+        loop {
+            let notified = self.released.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            {
+                let mut state = self.state.lock().unwrap();
+                let free = match priority {
+                    Priority::User => state.active < limit,
+                    Priority::Background => {
+                        state.waiting_user == 0 && state.active < limit.saturating_sub(1).max(1)
+                    }
+                };
+                if free {
+                    state.active += 1;
+                    if queued.counted {
+                        state.waiting_user -= 1;
+                        queued.counted = false;
+                    }
+                    return Permit { gate: Arc::clone(self) };
+                }
+                if priority == Priority::User && !queued.counted {
+                    state.waiting_user += 1;
+                    queued.counted = true;
+                }
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Keeps `waiting_user` correct when a waiting user call is cancelled.
+struct QueuedUser<'a> {
+    gate: &'a SiteGate,
+    counted: bool,
+}
+
+impl Drop for QueuedUser<'_> {
+    fn drop(&mut self) {
+        if self.counted {
+            self.gate.state.lock().unwrap().waiting_user -= 1;
+            self.gate.released.notify_waiters();
+        }
+    }
+}
+
+/// A slot in a site's gate, released on drop.
+struct Permit {
+    gate: Arc<SiteGate>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.gate.state.lock().unwrap().active -= 1;
+        self.gate.released.notify_waiters();
+    }
+}
+
+/// Per-site concurrency limits and retry policy shared by all WS calls.
+pub struct Scheduler {
+    per_site: usize,
+    retry: RetryPolicy,
+    sites: Mutex<HashMap<String, Arc<SiteGate>>>,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new(DEFAULT_SITE_CONCURRENCY, RetryPolicy::default())
+    }
+}
+
+impl Scheduler {
+    pub fn new(per_site: usize, retry: RetryPolicy) -> Self {
+        Self {
+            per_site: per_site.max(1),
+            retry,
+            sites: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn gate(&self, site_url: &str) -> Arc<SiteGate> {
+        let mut sites = self.sites.lock().unwrap();
+        Arc::clone(sites.entry(site_url.to_string()).or_default())
+    }
+
+    /// Runs `request` within the site's concurrency limit. If `idempotent`,
+    /// transient failures are retried; the slot is released while waiting.
+    pub async fn run<T, F, Fut>(
+        &self,
+        site_url: &str,
+        priority: Priority,
+        idempotent: bool,
+        mut request: F,
+    ) -> Result<T, WsError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, WsError>>,
+    {
+        let gate = self.gate(site_url);
+        let mut attempt = 0;
+        loop {
+            let permit = gate.acquire(self.per_site, priority).await;
+            let result = request().await;
+            drop(permit);
+
+            let err = match result {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            let delay = idempotent.then(|| self.retry.delay_for(&err, attempt)).flatten();
+            match delay {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return Err(err),
+            }
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Instant;
+
+    use futures_util::future::join_all;
+    use serde_json::json;
+
+    use crate::session::Session;
+    use crate::test_support::{test_client, MockServer};
+    use crate::ws::WsClient;
+
+    fn client(per_site: usize, retry: RetryPolicy) -> WsClient {
+        test_client().with_scheduler(Scheduler::new(per_site, retry))
+    }
+
+    fn fast_retry() -> RetryPolicy {
+        RetryPolicy { base_delay: Duration::from_millis(1), ..RetryPolicy::default() }
+    }
+
+    /// The WS function of a recorded REST request.
+    fn function_of(body: &str) -> String {
+        let (_, rest) = body.split_once("wsfunction=").unwrap_or_default();
+        rest.split('&').next().unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn read_functions_are_listed_explicitly() {
+        assert!(READ_FUNCTIONS.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(is_read_function("core_course_get_contents"));
+        assert!(is_read_function("core_course_check_updates"));
+        assert!(is_read_function("tool_lp_data_for_plans_page"));
+        assert!(!is_read_function("tool_mobile_get_autologin_key"));
+        assert!(!is_read_function("mod_quiz_get_attempt_data"));
+        assert!(!is_read_function("core_course_view_course"));
+        assert!(!is_read_function("local_plugin_get_and_delete"));
+    }
+
+    #[tokio::test]
+    async fn limits_concurrent_requests_per_site() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (r, p) = (Arc::clone(&running), Arc::clone(&peak));
+        let server = MockServer::start_with_headers(move |_| {
+            let now = r.fetch_add(1, Ordering::SeqCst) + 1;
+            p.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(50));
+            r.fetch_sub(1, Ordering::SeqCst);
+            (200, Vec::new(), "{}".into())
+        });
+        let client = client(2, fast_retry());
+        let (session, params) = (Session::new(&server.url(), "t"), json!({}));
+
+        let calls = (0..6).map(|_| client.call(&session, "core_webservice_get_site_info", &params));
+        let results = join_all(calls).await;
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(server.requests().len(), 6);
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn user_calls_overtake_background_calls() {
+        let server = MockServer::start_with_headers(|req| {
+            if function_of(&req.body) == "core_course_get_courses" {
+                std::thread::sleep(Duration::from_millis(150));
+            }
+            (200, Vec::new(), "{}".into())
+        });
+        let client = client(1, fast_retry());
+        let session = Session::new(&server.url(), "t");
+        let call = |function: &'static str, priority, after: u64| {
+            let (client, session) = (&client, &session);
+            async move {
+                tokio::time::sleep(Duration::from_millis(after)).await;
+                client.call_with_priority(session, function, &json!({}), priority).await
+            }
+        };
+
+        let (slow, background, user) = tokio::join!(
+            call("core_course_get_courses", Priority::User, 0),
+            call("core_course_get_contents", Priority::Background, 30),
+            call("core_webservice_get_site_info", Priority::User, 60),
+        );
+
+        assert!(slow.is_ok() && background.is_ok() && user.is_ok());
+        let order: Vec<_> = server.requests().iter().map(|req| function_of(&req.body)).collect();
+        assert_eq!(
+            order,
+            ["core_course_get_courses", "core_webservice_get_site_info", "core_course_get_contents"]
+        );
+    }
+
+    #[tokio::test]
+    async fn background_calls_leave_a_slot_free() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (r, p) = (Arc::clone(&running), Arc::clone(&peak));
+        let server = MockServer::start_with_headers(move |_| {
+            let now = r.fetch_add(1, Ordering::SeqCst) + 1;
+            p.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(30));
+            r.fetch_sub(1, Ordering::SeqCst);
+            (200, Vec::new(), "{}".into())
+        });
+        let client = client(3, fast_retry());
+        let (session, params) = (Session::new(&server.url(), "t"), json!({}));
+
+        let background = Priority::Background;
+        let calls = (0..6).map(|_| {
+            client.call_with_priority(&session, "core_course_get_contents", &params, background)
+        });
+        join_all(calls).await;
+
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn waits_for_retry_after() {
+        let server = MockServer::start_with_headers(|_| {
+            (503, vec![("Retry-After".into(), "1".into())], "Maintenance".into())
+        });
+        let succeeding = {
+            let count = AtomicUsize::new(0);
+            MockServer::start_with_headers(move |_| match count.fetch_add(1, Ordering::SeqCst) {
+                0 => (429, vec![("Retry-After".into(), "1".into())], "Slow down".into()),
+                _ => (200, Vec::new(), "{}".into()),
+            })
+        };
+
+        let started = Instant::now();
+        client(2, fast_retry())
+            .call(&Session::new(&succeeding.url(), "t"), "core_course_get_contents", &json!({}))
+            .await
+            .unwrap();
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert_eq!(succeeding.requests().len(), 2);
+
+        // A Retry-After beyond the limit gives up at once.
+        let impatient = RetryPolicy { max_retry_after: Duration::ZERO, ..fast_retry() };
+        let err = client(2, impatient)
+            .call(&Session::new(&server.url(), "t"), "core_course_get_contents", &json!({}))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, WsError::Http { status: 503, retry_after: Some(1) }));
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_reads_but_never_writes() {
+        let server = MockServer::start_with_headers(|_| (502, Vec::new(), "Bad Gateway".into()));
+        let client = client(2, fast_retry());
+        let session = Session::new(&server.url(), "t");
+
+        let err = client
+            .call(&session, "core_message_send_instant_messages", &json!({}))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, WsError::Http { status: 502, .. }));
+        assert_eq!(server.requests().len(), 1);
+
+        client.call(&session, "core_course_get_contents", &json!({})).await.unwrap_err();
+        let retries = RetryPolicy::default().max_retries as usize;
+        assert_eq!(server.requests().len(), 2 + retries);
+    }
+}
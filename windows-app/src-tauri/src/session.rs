@@ -0,0 +1,212 @@
+//! In-memory session store for WS tokens.
+//!
+//! Tokens never leave the Rust side: the webview only receives an opaque
+//! [`SessionHandle`] and asks the backend to perform requests on its behalf.
+//! There is deliberately no command that returns a token.
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use serde::Serialize;
+
+/// Opaque identifier handed to the frontend in place of a token.
+pub type SessionHandle = String;
+
+/// Event emitted with a [`SessionExpired`] when Moodle rejects a session.
+pub const EXPIRED_EVENT: &str = "session://expired";
+
+/// An authenticated connection to one Moodle site.
+#[derive(Clone)]
+pub struct Session {
+    site_url: String,
+    token: String,
+    private_token: Option<String>,
+    account_id: Option<String>,
+}
+
+impl Session {
+    pub fn new(site_url: &str, token: &str) -> Self {
+        Self {
+            site_url: site_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            private_token: None,
+            account_id: None,
+        }
+    }
+
+    /// Attaches the private token used for auto-login / SSO key refresh.
+    pub fn with_private_token(mut self, private_token: Option<&str>) -> Self {
+        self.private_token = private_token.filter(|t| !t.is_empty()).map(str::to_string);
+        self
+    }
+
+    /// Tags the session with its account id (`userid@siteurl`).
+    pub fn with_account_id(mut self, account_id: &str) -> Self {
+        self.account_id = Some(account_id.to_string());
+        self
+    }
+
+    /// Token-free description of this session under `handle`.
+    pub fn info(&self, handle: &str) -> SessionInfo {
+        SessionInfo {
+            handle: handle.to_string(),
+            site_url: self.site_url.clone(),
+            account_id: self.account_id.clone(),
+        }
+    }
+
+    /// Builds the [`EXPIRED_EVENT`] payload for this session under `handle`.
+    pub fn expired(&self, handle: &str, reason: ExpiryReason) -> SessionExpired {
+        SessionExpired {
+            handle: handle.to_string(),
+            account_id: self.account_id.clone(),
+            site_url: self.site_url.clone(),
+            reason,
+        }
+    }
+
+    /// Site root URL without trailing slash.
+    pub fn site_url(&self) -> &str {
+        &self.site_url
+    }
+
+    /// Account id (`userid@siteurl`) once known.
+    pub fn account_id(&self) -> Option<&str> {
+        self.account_id.as_deref()
+    }
+
+    pub(crate) fn token(&self) -> &str {
+        &self.token
+    }
+
+    pub(crate) fn private_token(&self) -> Option<&str> {
+        self.private_token.as_deref()
+    }
+
+    /// Key under which per-account data (cache, downloads) is stored: the
+    /// account id, or the site URL for sessions opened without one.
+    pub fn scope(&self) -> &str {
+        self.account_id.as_deref().unwrap_or(&self.site_url)
+    }
+
+    /// Whether `url` points at this session's site, i.e. the token may be
+    /// attached to a request for it.
+    pub fn owns_url(&self, url: &str) -> bool {
+        url.strip_prefix(&self.site_url)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/') || rest.starts_with('?'))
+    }
+}
+
+impl std::fmt::Debug for Session {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Session")
+            .field("site_url", &self.site_url)
+            .field("account_id", &self.account_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Token-free view of a session that is safe to return to the webview.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionInfo {
+    pub handle: SessionHandle,
+    pub site_url: String,
+    pub account_id: Option<String>,
+}
+
+/// Why Moodle refused to serve a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ExpiryReason {
+    /// The token was revoked or has expired (`invalidtoken`).
+    InvalidToken,
+    /// The token is no longer accepted for the service: `accessexception`
+    /// from `core_webservice_get_site_info`, which every valid token may call.
+    AccessException,
+    /// The user must log in again (`servicerequireslogin`).
+    ServiceRequiresLogin,
+    /// The site is in maintenance mode (`sitemaintenance`).
+    SiteMaintenance,
+}
+
+impl ExpiryReason {
+    /// Recognises the Moodle error codes that invalidate a session whatever
+    /// function raised them. `accessexception` is not one of them, as a
+    /// function missing from the service raises it too.
+    pub fn from_errorcode(errorcode: &str) -> Option<Self> {
+        match errorcode {
+            "invalidtoken" => Some(ExpiryReason::InvalidToken),
+            "servicerequireslogin" => Some(ExpiryReason::ServiceRequiresLogin),
+            "sitemaintenance" => Some(ExpiryReason::SiteMaintenance),
+            _ => None,
+        }
+    }
+}
+
+/// Payload of [`EXPIRED_EVENT`]: the app should ask the user to log in to
+/// this account again instead of falling back to cached data.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionExpired {
+    pub handle: SessionHandle,
+    pub account_id: Option<String>,
+    pub site_url: String,
+    pub reason: ExpiryReason,
+}
+
+#[derive(Debug, thiserror::Error, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum SessionError {
+    #[error("Unknown or closed session")]
+    UnknownSession,
+}
+
+/// All open sessions, keyed by handle. Held in Tauri managed state.
+#[derive(Default)]
+pub struct SessionStore {
+    sessions: RwLock<HashMap<SessionHandle, Session>>,
+}
+
+impl SessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a session and returns its new handle.
+    pub fn open(&self, session: Session) -> SessionHandle {
+        let handle = uuid::Uuid::new_v4().to_string();
+        self.sessions.write().unwrap().insert(handle.clone(), session);
+        handle
+    }
+
+    /// Returns a copy of the session behind `handle`.
+    pub fn get(&self, handle: &str) -> Result<Session, SessionError> {
+        self.sessions
+            .read()
+            .unwrap()
+            .get(handle)
+            .cloned()
+            .ok_or(SessionError::UnknownSession)
+    }
+
+    /// Returns the token-free description of a session.
+    pub fn info(&self, handle: &str) -> Result<SessionInfo, SessionError> {
+        Ok(self.get(handle)?.info(handle))
+    }
+
+    /// Copies of all open sessions with their handles.
+    pub fn all(&self) -> Vec<(SessionHandle, Session)> {
+        self.sessions
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(handle, session)| (handle.clone(), session.clone()))
+            .collect()
+    }
+
+    /// Drops a session; its token is forgotten. Closing twice is a no-op.
+    pub fn close(&self, handle: &str) {
+        self.sessions.write().unwrap().remove(handle);
+    }
+}
@@ -0,0 +1,331 @@
+//! Browser (SSO) login via `admin/tool/mobile/launch.php`.
+//!
+//! For sites that only allow SAML/OAuth2, the app opens `launch.php` in the
+//! system browser with a random passport and our URL scheme. After the user
+//! signs in, Moodle redirects to `moodledesktop://token=<base64>` where the
+//! payload is `signature:::token[:::privatetoken]` and the signature is
+//! `md5(siteurl + passport)`. Parsing and verification are pure functions;
+//! [`SsoState`] remembers the pending launch between the two steps.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE};
+use base64::Engine;
+use md5::{Digest, Md5};
+use serde::Serialize;
+
+use crate::login::{self, LoginError, LoginResult, TokenPair};
+use crate::session::SessionStore;
+use crate::site_url::SitePolicy;
+use crate::ws::WsClient;
+
+/// Custom URL scheme registered for the login callback.
+pub const URL_SCHEME: &str = "moodledesktop";
+
+/// Launch endpoint relative to the site root.
+pub const LAUNCH_ENDPOINT: &str = "admin/tool/mobile/launch.php";
+
+/// Event emitted with a [`LoginResult`] when a browser login completed.
+pub const LOGIN_EVENT: &str = "sso://login";
+
+/// Event emitted with an [`SsoError`] when a browser login failed.
+pub const ERROR_EVENT: &str = "sso://error";
+
+/// How long a launched browser login stays valid.
+const LAUNCH_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug, Clone, thiserror::Error, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum SsoError {
+    #[error("No browser login is in progress")]
+    NoPendingLogin,
+
+    #[error("Invalid login callback: {message}")]
+    InvalidCallback { message: String },
+
+    #[error("The login callback signature does not match this site")]
+    SignatureMismatch,
+
+    #[error("Could not open the browser: {message}")]
+    Browser { message: String },
+
+    #[error("Login failed: {error}")]
+    Login { error: LoginError },
+}
+
+impl From<LoginError> for SsoError {
+    fn from(error: LoginError) -> Self {
+        SsoError::Login { error }
+    }
+}
+
+/// Decoded content of a `token=` callback.
+#[derive(Debug, PartialEq)]
+pub struct CallbackTokens {
+    pub signature: String,
+    pub token: String,
+    pub private_token: Option<String>,
+}
+
+/// Builds the `launch.php` URL to open in the system browser.
+pub fn launch_url(site_url: &str, passport: &str, url_scheme: &str) -> String {
+    let mut url = reqwest::Url::parse(&format!("{site_url}/{LAUNCH_ENDPOINT}"))
+        .expect("normalised site URL");
+    url.query_pairs_mut()
+        .append_pair("service", login::MOBILE_SERVICE)
+        .append_pair("passport", passport)
+        .append_pair("urlscheme", url_scheme);
+    url.to_string()
+}
+
+/// Generates a random passport for one launch.
+pub fn new_passport() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// Extracts and decodes the token payload from a callback URL such as
+/// `moodledesktop://token=<base64>`.
+pub fn parse_callback(url: &str, url_scheme: &str) -> Result<CallbackTokens, SsoError> {
+    let invalid = |message: &str| SsoError::InvalidCallback { message: message.to_string() };
+
+    let rest = url
+        .strip_prefix(url_scheme)
+        .and_then(|r| r.strip_prefix("://"))
+        .ok_or_else(|| invalid("unexpected URL scheme"))?;
+    let encoded = rest
+        .split_once("token=")
+        .map(|(_, v)| v)
+        .ok_or_else(|| invalid("missing token"))?;
+    // Browsers may append a slash or percent-encode the padding.
+    let encoded = encoded
+        .trim_end_matches('/')
+        .replace("%3D", "=")
+        .replace("%3d", "=")
+        .replace("%2B", "+")
+        .replace("%2F", "/");
+
+    let decoded = STANDARD
+        .decode(&encoded)
+        .or_else(|_| URL_SAFE.decode(&encoded))
+        .map_err(|_| invalid("token is not valid base64"))?;
+    let decoded = String::from_utf8(decoded).map_err(|_| invalid("token is not UTF-8"))?;
+
+    let mut parts = decoded.split(":::");
+    let signature = parts.next().unwrap_or_default().to_string();
+    let token = parts.next().unwrap_or_default().to_string();
+    if signature.is_empty() || token.is_empty() {
+        return Err(invalid("incomplete token payload"));
+    }
+    let private_token = parts.next().filter(|t| !t.is_empty()).map(str::to_string);
+
+    Ok(CallbackTokens { signature, token, private_token })
+}
+
+/// Checks `signature == md5(siteurl + passport)`.
+///
+/// Like the official app, the other of `http`/`https` is accepted too,
+/// since `wwwroot` may differ from the URL the user typed.
+pub fn verify_signature(signature: &str, site_url: &str, passport: &str) -> bool {
+    let alternate = match site_url.split_once("://") {
+        Some(("https", rest)) => format!("http://{rest}"),
+        Some(("http", rest)) => format!("https://{rest}"),
+        _ => site_url.to_string(),
+    };
+    [site_url, alternate.as_str()]
+        .iter()
+        .any(|url| md5_hex(&format!("{url}{passport}")).eq_ignore_ascii_case(signature))
+}
+
+fn md5_hex(input: &str) -> String {
+    hex::encode(Md5::digest(input.as_bytes()))
+}
+
+struct PendingLaunch {
+    site_url: String,
+    passport: String,
+    started: Instant,
+}
+
+/// The browser login currently waiting for its callback.
+#[derive(Default)]
+pub struct SsoState {
+    pending: Mutex<Option<PendingLaunch>>,
+}
+
+impl SsoState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a launch for `site_url` and returns the URL to open. A new
+    /// launch replaces any earlier one.
+    pub async fn begin(&self, policy: &SitePolicy, site_url: &str) -> Result<String, LoginError> {
+        let site_url = policy.validate(site_url).await?;
+        let passport = new_passport();
+        let url = launch_url(&site_url, &passport, URL_SCHEME);
+        *self.pending.lock().unwrap() = Some(PendingLaunch {
+            site_url,
+            passport,
+            started: Instant::now(),
+        });
+        Ok(url)
+    }
+
+    /// Verifies a callback against the pending launch and opens a session.
+    /// The launch is consumed only by a callback that passes verification,
+    /// so a forged or malformed callback cannot cancel a real login.
+    pub async fn complete(
+        &self,
+        client: &WsClient,
+        sessions: &SessionStore,
+        callback_url: &str,
+    ) -> Result<LoginResult, SsoError> {
+        let (site_url, passport) = {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.as_ref().is_some_and(|p| p.started.elapsed() >= LAUNCH_TIMEOUT) {
+                *pending = None;
+            }
+            let launch = pending.as_ref().ok_or(SsoError::NoPendingLogin)?;
+            (launch.site_url.clone(), launch.passport.clone())
+        };
+
+        let tokens = parse_callback(callback_url, URL_SCHEME)?;
+        if !verify_signature(&tokens.signature, &site_url, &passport) {
+            return Err(SsoError::SignatureMismatch);
+        }
+        // Another callback may have used the launch meanwhile.
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.as_ref().is_none_or(|p| p.passport != passport) {
+                return Err(SsoError::NoPendingLogin);
+            }
+            *pending = None;
+        }
+
+        let pair = TokenPair { token: tokens.token, private_token: tokens.private_token };
+        Ok(login::open_session(client, sessions, &site_url, pair).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SITE: &str = "https://school.example.org";
+    const PASSPORT: &str = "12345";
+
+    fn callback(payload: &str) -> String {
+        format!("{URL_SCHEME}://token={}", STANDARD.encode(payload))
+    }
+
+    #[test]
+    fn builds_launch_url() {
+        let url = launch_url(SITE, PASSPORT, URL_SCHEME);
+        assert_eq!(
+            url,
+            "https://school.example.org/admin/tool/mobile/launch.php\
+             ?service=moodle_mobile_app&passport=12345&urlscheme=moodledesktop"
+        );
+    }
+
+    #[test]
+    fn parses_token_and_private_token() {
+        let tokens = parse_callback(&callback("sig:::tok:::priv"), URL_SCHEME).unwrap();
+        assert_eq!(
+            tokens,
+            CallbackTokens {
+                signature: "sig".into(),
+                token: "tok".into(),
+                private_token: Some("priv".into()),
+            }
+        );
+    }
+
+    #[test]
+    fn parses_without_private_token_and_with_trailing_slash() {
+        let url = format!("{}/", callback("sig:::tok"));
+        let tokens = parse_callback(&url, URL_SCHEME).unwrap();
+        assert_eq!(tokens.token, "tok");
+        assert_eq!(tokens.private_token, None);
+    }
+
+    #[test]
+    fn parses_percent_encoded_padding() {
+        let url = callback("sig:::t").replace('=', "%3D").replacen("token%3D", "token=", 1);
+        assert_eq!(parse_callback(&url, URL_SCHEME).unwrap().token, "t");
+    }
+
+    #[test]
+    fn rejects_malformed_callbacks() {
+        for url in [
+            "otherscheme://token=c2lnOjo6dG9r".to_string(),
+            format!("{URL_SCHEME}://login"),
+            format!("{URL_SCHEME}://token=!!!"),
+            callback("onlysignature"),
+            callback(":::tok"),
+        ] {
+            assert!(
+                matches!(parse_callback(&url, URL_SCHEME), Err(SsoError::InvalidCallback { .. })),
+                "accepted {url}"
+            );
+        }
+    }
+
+    #[test]
+    fn verifies_signature() {
+        let signature = md5_hex(&format!("{SITE}{PASSPORT}"));
+        assert!(verify_signature(&signature, SITE, PASSPORT));
+        assert!(verify_signature(&signature.to_uppercase(), SITE, PASSPORT));
+        assert!(!verify_signature(&signature, SITE, "other"));
+        assert!(!verify_signature(&signature, "https://evil.example.org", PASSPORT));
+    }
+
+    #[test]
+    fn accepts_http_wwwroot_signature() {
+        let signature = md5_hex(&format!("http://school.example.org{PASSPORT}"));
+        assert!(verify_signature(&signature, SITE, PASSPORT));
+    }
+
+    #[tokio::test]
+    async fn complete_requires_pending_launch() {
+        let state = SsoState::new();
+        let err = state
+            .complete(&WsClient::default(), &SessionStore::new(), &callback("sig:::tok"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SsoError::NoPendingLogin));
+    }
+
+    #[tokio::test]
+    async fn complete_rejects_forged_signature() {
+        let state = SsoState::new();
+        *state.pending.lock().unwrap() = Some(PendingLaunch {
+            site_url: SITE.into(),
+            passport: PASSPORT.into(),
+            started: Instant::now(),
+        });
+        let err = state
+            .complete(&WsClient::default(), &SessionStore::new(), &callback("forged:::tok"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SsoError::SignatureMismatch));
+        // The real callback can still complete the launch.
+        assert!(state.pending.lock().unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn malformed_callback_keeps_launch() {
+        let state = SsoState::new();
+        *state.pending.lock().unwrap() = Some(PendingLaunch {
+            site_url: SITE.into(),
+            passport: PASSPORT.into(),
+            started: Instant::now(),
+        });
+        let result = state
+            .complete(&WsClient::default(), &SessionStore::new(), "moodledesktop://token=%%%")
+            .await;
+        assert!(result.is_err());
+        assert!(state.pending.lock().unwrap().is_some());
+    }
+}
@@ -0,0 +1,198 @@
+//! Helpers shared by unit tests.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::data_key::DataKey;
+use crate::downloads::{DownloadHost, DownloadProgress};
+use crate::scheduler::{RetryPolicy, Scheduler, DEFAULT_SITE_CONCURRENCY};
+use crate::session::Session;
+use crate::site_url::SitePolicy;
+use crate::ws::WsClient;
+
+/// A [`WsClient`] whose policy allows loopback, for talking to [`MockServer`].
+/// Retries back off by milliseconds so tests stay fast.
+pub fn test_client() -> WsClient {
+    let retry = RetryPolicy { base_delay: Duration::from_millis(1), ..RetryPolicy::default() };
+    WsClient::new(SitePolicy {
+        allowed_networks: vec!["127.0.0.0/8".parse().unwrap()],
+        ..SitePolicy::default()
+    })
+    .with_scheduler(Scheduler::new(DEFAULT_SITE_CONCURRENCY, retry))
+}
+
+/// Download host with one session, open while `online`, and a fixed data key.
+pub struct TestHost {
+    pub session: Session,
+    pub online: AtomicBool,
+    pub key: DataKey,
+    pub default_root: PathBuf,
+    pub events: Mutex<Vec<DownloadProgress>>,
+}
+
+impl TestHost {
+    pub fn new(session: Session, default_root: PathBuf) -> Self {
+        Self {
+            session,
+            online: AtomicBool::new(true),
+            key: DataKey::random(),
+            default_root,
+            events: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl DownloadHost for TestHost {
+    fn session(&self, scope: &str) -> Option<Session> {
+        let open = self.online.load(Ordering::SeqCst) && scope == self.session.scope();
+        open.then(|| self.session.clone())
+    }
+
+    fn data_key(&self, _scope: &str) -> Option<DataKey> {
+        Some(self.key.clone())
+    }
+
+    fn default_root(&self) -> Option<PathBuf> {
+        Some(self.default_root.clone())
+    }
+
+    fn progress(&self, progress: &DownloadProgress) {
+        self.events.lock().unwrap().push(progress.clone());
+    }
+}
+
+/// A request captured by [`MockServer`].
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl RecordedRequest {
+    /// Returns the value of a header (case-insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+
+/// Minimal blocking HTTP/1.1 server on a random localhost port.
+///
+/// Every request is answered by `handler` with `Connection: close`.
+pub struct MockServer {
+    port: u16,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl MockServer {
+    pub fn start<F>(handler: F) -> Self
+    where
+        F: Fn(&RecordedRequest) -> (u16, String) + Send + Sync + 'static,
+    {
+        Self::start_with_headers(move |req| {
+            let (status, body) = handler(req);
+            (status, Vec::new(), body)
+        })
+    }
+
+    /// Like [`MockServer::start`], but the handler may add response headers.
+    pub fn start_with_headers<F>(handler: F) -> Self
+    where
+        F: Fn(&RecordedRequest) -> (u16, Vec<(String, String)>, String) + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock server");
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+        let handler: Arc<
+            dyn Fn(&RecordedRequest) -> (u16, Vec<(String, String)>, String) + Send + Sync,
+        > = Arc::new(handler);
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { break };
+                let handler = Arc::clone(&handler);
+                let recorded = Arc::clone(&recorded);
+                thread::spawn(move || {
+                    let Some(req) = read_request(&mut stream) else { return };
+                    recorded.lock().unwrap().push(req.clone());
+                    let (status, headers, body) = handler(&req);
+                    let mut head = format!(
+                        "HTTP/1.1 {status} Mock\r\nContent-Length: {}\r\nConnection: close\r\n",
+                        body.len()
+                    );
+                    for (name, value) in headers {
+                        head.push_str(&format!("{name}: {value}\r\n"));
+                    }
+                    head.push_str("\r\n");
+                    let _ = stream.write_all(head.as_bytes());
+                    let _ = stream.write_all(body.as_bytes());
+                });
+            }
+        });
+
+        Self { port, requests }
+    }
+
+    /// Base URL of the server, without trailing slash.
+    pub fn url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.port)
+    }
+
+    /// All requests received so far.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// The most recent request. Panics if none was received.
+    pub fn last_request(&self) -> RecordedRequest {
+        self.requests().pop().expect("no request received")
+    }
+}
+
+fn read_request(stream: &mut std::net::TcpStream) -> Option<RecordedRequest> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).ok()?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    let length = headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, v)| v.parse::<usize>().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+
+    Some(RecordedRequest {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
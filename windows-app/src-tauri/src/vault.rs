@@ -0,0 +1,802 @@
+//! Encrypted credential vault for stored accounts.
+//!
+//! Account metadata (name, site, avatar) is kept in clear so the account
+//! switcher can render without unlocking, while each account's token and
+//! private token are sealed with ChaCha20-Poly1305. The encryption key is
+//! derived from a random master secret in `vault.key` (owner-only
+//! permissions) and, if the user set one, a passphrase stretched with
+//! Argon2id. Without the passphrase the vault stays locked.
+//!
+//! Each account's secrets also hold a random data key that encrypts the
+//! account's cached data at rest (see [`crate::data_key`]); removing the
+//! account destroys the key and with it every way to read that data.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::data_key::DataKey;
+use crate::session::Session;
+use crate::site_url::{SitePolicy, SiteUrlError};
+
+/// File holding the random master secret.
+pub const MASTER_KEY_FILE: &str = "vault.key";
+
+/// File holding the encrypted accounts.
+pub const VAULT_FILE: &str = "vault.json";
+
+/// Store keys written by the frontend before the vault existed.
+const LEGACY_ACCOUNTS_KEY: &str = "moodle_accounts";
+const LEGACY_SESSION_KEY: &str = "moodle_session";
+
+const VAULT_VERSION: u32 = 1;
+const NONCE_LEN: usize = 12;
+const VERIFIER_PLAINTEXT: &[u8] = b"moodle-desktop-vault";
+
+#[derive(Debug, Clone, thiserror::Error, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum VaultError {
+    #[error("The vault is locked")]
+    Locked,
+
+    #[error("Wrong passphrase")]
+    WrongPassphrase,
+
+    #[error("Unknown account: {id}")]
+    UnknownAccount { id: String },
+
+    #[error("Unknown or closed session")]
+    UnknownSession,
+
+    /// The account sent along does not describe the session's account.
+    #[error("The account does not belong to the session")]
+    AccountMismatch,
+
+    /// A stored site address no longer passes the SSRF guard.
+    #[error("{error}")]
+    Site { error: SiteUrlError },
+
+    /// `vault.key` is gone while the vault still holds sealed accounts,
+    /// which can no longer be decrypted.
+    #[error("The vault key is missing; stored accounts cannot be decrypted")]
+    MissingKey,
+
+    #[error("Vault data is corrupt: {message}")]
+    Corrupt { message: String },
+
+    #[error("Vault I/O error: {message}")]
+    Io { message: String },
+}
+
+impl From<SiteUrlError> for VaultError {
+    fn from(error: SiteUrlError) -> Self {
+        VaultError::Site { error }
+    }
+}
+
+impl From<std::io::Error> for VaultError {
+    fn from(err: std::io::Error) -> Self {
+        VaultError::Io { message: err.to_string() }
+    }
+}
+
+/// Non-secret account details shown in the account switcher.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredAccount {
+    /// Unique key: `userid@siteurl`.
+    pub id: String,
+    pub site_url: String,
+    pub fullname: String,
+    pub username: String,
+    pub userpictureurl: String,
+    pub sitename: String,
+    pub userid: i64,
+}
+
+/// The sealed part of an account.
+#[derive(Serialize, Deserialize)]
+struct Secrets {
+    token: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    private_token: Option<String>,
+    /// Hex data key; created on first use for accounts stored before it existed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data_key: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VaultEntry {
+    #[serde(flatten)]
+    account: StoredAccount,
+    /// Hex of `nonce || ciphertext`, bound to the account id as AAD.
+    secret: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VaultFile {
+    version: u32,
+    /// Hex salt for the passphrase KDF.
+    salt: String,
+    has_passphrase: bool,
+    /// Known plaintext sealed with the current key, to detect a wrong
+    /// passphrase before touching any account.
+    verifier: String,
+    accounts: Vec<VaultEntry>,
+}
+
+/// Thread-safe vault held in Tauri managed state.
+pub struct Vault {
+    /// `None` for a vault that only lives in memory.
+    dir: Option<PathBuf>,
+    /// Why the vault on disk could not be opened, for an in-memory vault.
+    open_error: Option<VaultError>,
+    master: Zeroizing<Vec<u8>>,
+    file: Mutex<VaultFile>,
+    key: Mutex<Option<Zeroizing<[u8; 32]>>>,
+}
+
+impl Vault {
+    /// Opens (or creates) the vault in `dir`. A vault without passphrase is
+    /// unlocked right away. Fails with [`VaultError::MissingKey`] rather than
+    /// creating a new master secret if `vault.key` is gone but accounts are
+    /// still sealed with it.
+    pub fn open(dir: &Path) -> Result<Self, VaultError> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(VAULT_FILE);
+        let mut stored: Option<VaultFile> = match fs::read(&path) {
+            Ok(bytes) => Some(
+                serde_json::from_slice(&bytes)
+                    .map_err(|e| VaultError::Corrupt { message: e.to_string() })?,
+            ),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        let key_path = dir.join(MASTER_KEY_FILE);
+        let master = match load_master(&key_path)? {
+            Some(master) => master,
+            None if stored.as_ref().is_some_and(|file| !file.accounts.is_empty()) => {
+                return Err(VaultError::MissingKey)
+            }
+            // Nothing was sealed with the lost key: start over.
+            None => {
+                stored = None;
+                create_master(&key_path)?
+            }
+        };
+
+        let created = stored.is_none();
+        let (file, key) = if let Some(file) = stored {
+            let key = if file.has_passphrase {
+                None
+            } else {
+                let key = derive_key(&master, None, &decode_hex(&file.salt)?);
+                check_verifier(&key, &file.verifier)?;
+                Some(key)
+            };
+            (file, key)
+        } else {
+            let (file, key) = empty_file(&master)?;
+            (file, Some(key))
+        };
+
+        let vault = Self {
+            dir: Some(dir.to_path_buf()),
+            open_error: None,
+            master,
+            file: Mutex::new(file),
+            key: Mutex::new(key),
+        };
+        if created {
+            vault.save(&vault.file.lock().unwrap())?;
+        }
+        Ok(vault)
+    }
+
+    /// An empty vault that is never written, used when the vault on disk
+    /// cannot be opened. The files on disk stay untouched, so a repaired
+    /// vault opens again on the next start; `error` is reported by
+    /// [`Vault::open_error`] meanwhile.
+    pub fn in_memory(error: VaultError) -> Result<Self, VaultError> {
+        let master = Zeroizing::new(random_bytes(32));
+        let (file, key) = empty_file(&master)?;
+        Ok(Self {
+            dir: None,
+            open_error: Some(error),
+            master,
+            file: Mutex::new(file),
+            key: Mutex::new(Some(key)),
+        })
+    }
+
+    /// Why the vault on disk could not be opened; `None` if it was.
+    pub fn open_error(&self) -> Option<&VaultError> {
+        self.open_error.as_ref()
+    }
+
+    /// Whether a passphrase is required before secrets can be read.
+    pub fn is_locked(&self) -> bool {
+        self.key.lock().unwrap().is_none()
+    }
+
+    /// Unlocks a passphrase-protected vault.
+    pub fn unlock(&self, passphrase: &str) -> Result<(), VaultError> {
+        let file = self.file.lock().unwrap();
+        let key = derive_key(&self.master, Some(passphrase), &decode_hex(&file.salt)?);
+        check_verifier(&key, &file.verifier)?;
+        *self.key.lock().unwrap() = Some(key);
+        Ok(())
+    }
+
+    /// Sets, changes or (with `None`) removes the passphrase. All secrets are
+    /// re-encrypted under the new key with a fresh salt.
+    pub fn set_passphrase(&self, passphrase: Option<&str>) -> Result<(), VaultError> {
+        let passphrase = passphrase.filter(|p| !p.is_empty());
+        let mut file = self.file.lock().unwrap();
+        let mut key_slot = self.key.lock().unwrap();
+        let old_key = key_slot.as_ref().ok_or(VaultError::Locked)?;
+
+        let salt = random_bytes(16);
+        let new_key = derive_key(&self.master, passphrase, &salt);
+        let resealed = file
+            .accounts
+            .iter()
+            .map(|entry| {
+                let aad = entry.account.id.as_bytes();
+                seal(&new_key, &open_sealed(old_key, &entry.secret, aad)?, aad)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for (entry, secret) in file.accounts.iter_mut().zip(resealed) {
+            entry.secret = secret;
+        }
+        file.salt = hex::encode(&salt);
+        file.has_passphrase = passphrase.is_some();
+        file.verifier = seal(&new_key, VERIFIER_PLAINTEXT, b"")?;
+
+        self.save(&file)?;
+        *key_slot = Some(new_key);
+        Ok(())
+    }
+
+    /// All stored accounts, without secrets. Available while locked.
+    pub fn list(&self) -> Vec<StoredAccount> {
+        let file = self.file.lock().unwrap();
+        file.accounts.iter().map(|e| e.account.clone()).collect()
+    }
+
+    /// One stored account, without secrets.
+    pub fn get(&self, id: &str) -> Result<StoredAccount, VaultError> {
+        let file = self.file.lock().unwrap();
+        file.accounts
+            .iter()
+            .find(|e| e.account.id == id)
+            .map(|e| e.account.clone())
+            .ok_or_else(|| VaultError::UnknownAccount { id: id.to_string() })
+    }
+
+    /// Adds or replaces the account behind `session`, sealing its token. The
+    /// id and site come from the session; `account` only adds the profile
+    /// details and must name the same account. A replaced account keeps its
+    /// data key, so its cache stays readable; a new one takes `data_key`,
+    /// the in-memory key its data was sealed with so far, if given.
+    pub fn add(
+        &self,
+        mut account: StoredAccount,
+        session: &Session,
+        data_key: Option<&DataKey>,
+    ) -> Result<(), VaultError> {
+        let id = session.account_id().ok_or(VaultError::AccountMismatch)?;
+        let site_url = session.site_url();
+        if account.id != id
+            || account.site_url.trim_end_matches('/') != site_url
+            || format!("{}@{site_url}", account.userid) != id
+        {
+            return Err(VaultError::AccountMismatch);
+        }
+        account.id = id.to_string();
+        account.site_url = site_url.to_string();
+
+        let key = self.key.lock().unwrap().clone().ok_or(VaultError::Locked)?;
+        // Read and written under one lock so a data key created meanwhile
+        // is not overwritten.
+        let mut file = self.file.lock().unwrap();
+        let existing = file
+            .accounts
+            .iter()
+            .find(|e| e.account.id == account.id)
+            .map(|entry| open_secrets(&key, entry))
+            .transpose()?;
+        let secrets = Secrets {
+            token: session.token().to_string(),
+            private_token: session.private_token().map(str::to_string),
+            data_key: existing
+                .and_then(|secrets| secrets.data_key)
+                .or_else(|| data_key.map(|k| hex::encode(k.as_bytes()))),
+        };
+        let secret = seal_secrets(&key, &secrets, &account.id)?;
+        file.accounts.retain(|e| e.account.id != account.id);
+        file.accounts.push(VaultEntry { account, secret });
+        self.save(&file)
+    }
+
+    fn secrets(&self, id: &str) -> Result<Secrets, VaultError> {
+        let key = self.key.lock().unwrap().clone().ok_or(VaultError::Locked)?;
+        let file = self.file.lock().unwrap();
+        let entry = file
+            .accounts
+            .iter()
+            .find(|e| e.account.id == id)
+            .ok_or_else(|| VaultError::UnknownAccount { id: id.to_string() })?;
+        open_secrets(&key, entry)
+    }
+
+    /// The key encrypting an account's data at rest, created on first use.
+    pub fn data_key(&self, id: &str) -> Result<Zeroizing<[u8; 32]>, VaultError> {
+        let key = self.key.lock().unwrap().clone().ok_or(VaultError::Locked)?;
+        // Created and saved under the file lock, so two first uses cannot
+        // each create a key and one of them lose its data.
+        let mut file = self.file.lock().unwrap();
+        let entry = file
+            .accounts
+            .iter_mut()
+            .find(|e| e.account.id == id)
+            .ok_or_else(|| VaultError::UnknownAccount { id: id.to_string() })?;
+        let mut secrets = open_secrets(&key, entry)?;
+        let hex_key = match &secrets.data_key {
+            Some(hex_key) => hex_key.clone(),
+            None => {
+                let hex_key = hex::encode(Zeroizing::new(random_bytes(32)).as_slice());
+                secrets.data_key = Some(hex_key.clone());
+                entry.secret = seal_secrets(&key, &secrets, id)?;
+                self.save(&file)?;
+                hex_key
+            }
+        };
+        let bytes = Zeroizing::new(decode_hex(&hex_key)?);
+        let key: [u8; 32] = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| VaultError::Corrupt { message: "invalid data key".into() })?;
+        Ok(Zeroizing::new(key))
+    }
+
+    fn insert(&self, account: StoredAccount, secrets: &Secrets) -> Result<(), VaultError> {
+        let key = self.key.lock().unwrap().clone().ok_or(VaultError::Locked)?;
+        let secret = seal_secrets(&key, secrets, &account.id)?;
+
+        let mut file = self.file.lock().unwrap();
+        file.accounts.retain(|e| e.account.id != account.id);
+        file.accounts.push(VaultEntry { account, secret });
+        self.save(&file)
+    }
+
+    /// Removes an account and its secrets. Removing an unknown id is a no-op.
+    pub fn remove(&self, id: &str) -> Result<(), VaultError> {
+        let mut file = self.file.lock().unwrap();
+        file.accounts.retain(|e| e.account.id != id);
+        self.save(&file)
+    }
+
+    /// Decrypts an account's token into a new [`Session`]. The stored site
+    /// address is checked against `policy` again, since the vault file may
+    /// predate the policy or have been edited.
+    pub async fn session_for(&self, policy: &SitePolicy, id: &str) -> Result<Session, VaultError> {
+        let secrets = self.secrets(id)?;
+        let account = self.get(id)?;
+        let site_url = policy.validate(&account.site_url).await?;
+        Ok(Session::new(&site_url, &secrets.token)
+            .with_private_token(secrets.private_token.as_deref())
+            .with_account_id(id))
+    }
+
+    /// Moves plaintext tokens written by the old `AuthService` out of the
+    /// store file at `store_path` into the vault.
+    ///
+    /// Accounts under `moodle_accounts` are imported, then `token` and
+    /// `privateToken` are stripped from them, and from `moodle_session` if
+    /// its token belongs to an imported account. Entries that cannot be
+    /// imported keep their tokens. Returns the number of imported accounts;
+    /// a missing store is not an error. An in-memory vault imports nothing,
+    /// since the tokens would be gone after the next start.
+    pub fn migrate_plaintext_store(&self, store_path: &Path) -> Result<usize, VaultError> {
+        if self.dir.is_none() {
+            return Ok(0);
+        }
+        let raw = match fs::read(store_path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        let mut store: Value = serde_json::from_slice(&raw)
+            .map_err(|e| VaultError::Corrupt { message: e.to_string() })?;
+
+        let mut imported = Vec::new();
+        let mut changed = false;
+        if let Some(accounts) = store.get_mut(LEGACY_ACCOUNTS_KEY).and_then(Value::as_array_mut) {
+            for entry in accounts.iter_mut() {
+                let Some(token) = entry.get("token").and_then(Value::as_str).map(str::to_string)
+                else {
+                    continue;
+                };
+                let secrets = Secrets {
+                    token,
+                    private_token: entry
+                        .get("privateToken")
+                        .and_then(Value::as_str)
+                        .filter(|t| !t.is_empty())
+                        .map(str::to_string),
+                    data_key: None,
+                };
+                let Ok(account) = serde_json::from_value::<StoredAccount>(entry.clone()) else {
+                    continue;
+                };
+                self.insert(account, &secrets)?;
+                imported.push(secrets.token);
+                changed |= strip_tokens(entry);
+            }
+        }
+        if let Some(session) = store.get_mut(LEGACY_SESSION_KEY) {
+            let token = session.get("token").and_then(Value::as_str);
+            if token.is_some_and(|token| imported.iter().any(|t| t == token)) {
+                changed |= strip_tokens(session);
+            }
+        }
+
+        if changed {
+            let bytes = serde_json::to_vec_pretty(&store)
+                .map_err(|e| VaultError::Corrupt { message: e.to_string() })?;
+            write_atomic(store_path, &bytes)?;
+        }
+        Ok(imported.len())
+    }
+
+    fn save(&self, file: &VaultFile) -> Result<(), VaultError> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        let bytes = serde_json::to_vec_pretty(file)
+            .map_err(|e| VaultError::Corrupt { message: e.to_string() })?;
+        write_atomic(&dir.join(VAULT_FILE), &bytes)
+    }
+}
+
+/// A vault file without accounts or passphrase, and its key.
+fn empty_file(master: &[u8]) -> Result<(VaultFile, Zeroizing<[u8; 32]>), VaultError> {
+    let salt = random_bytes(16);
+    let key = derive_key(master, None, &salt);
+    let file = VaultFile {
+        version: VAULT_VERSION,
+        salt: hex::encode(&salt),
+        has_passphrase: false,
+        verifier: seal(&key, VERIFIER_PLAINTEXT, b"")?,
+        accounts: Vec::new(),
+    };
+    Ok((file, key))
+}
+
+fn strip_tokens(value: &mut Value) -> bool {
+    let Some(obj) = value.as_object_mut() else {
+        return false;
+    };
+    let had_token = obj.remove("token").is_some();
+    let had_private = obj.remove("privateToken").is_some();
+    had_token || had_private
+}
+
+/// Loads the master secret; `None` if there is none yet.
+fn load_master(path: &Path) -> Result<Option<Zeroizing<Vec<u8>>>, VaultError> {
+    match fs::read(path) {
+        Ok(bytes) if bytes.len() == 32 => Ok(Some(Zeroizing::new(bytes))),
+        Ok(_) => Err(VaultError::Corrupt { message: "invalid master key".into() }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Creates the master secret with owner-only permissions. On Windows the
+/// per-user AppData ACL provides the same guarantee.
+fn create_master(path: &Path) -> Result<Zeroizing<Vec<u8>>, VaultError> {
+    let secret = Zeroizing::new(random_bytes(32));
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(&secret)?;
+    Ok(secret)
+}
+
+fn derive_key(master: &[u8], passphrase: Option<&str>, salt: &[u8]) -> Zeroizing<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"moodle-desktop-vault-v1");
+    hasher.update(master);
+    if let Some(passphrase) = passphrase {
+        let mut stretched = Zeroizing::new([0u8; 32]);
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, stretched.as_mut())
+            .expect("valid Argon2 parameters");
+        hasher.update(stretched.as_ref());
+    }
+    hasher.update(salt);
+    Zeroizing::new(hasher.finalize().into())
+}
+
+fn open_secrets(key: &[u8; 32], entry: &VaultEntry) -> Result<Secrets, VaultError> {
+    let plain = open_sealed(key, &entry.secret, entry.account.id.as_bytes())?;
+    serde_json::from_slice(&plain).map_err(|e| VaultError::Corrupt { message: e.to_string() })
+}
+
+fn seal_secrets(key: &[u8; 32], secrets: &Secrets, id: &str) -> Result<String, VaultError> {
+    let plain = Zeroizing::new(
+        serde_json::to_vec(secrets).map_err(|e| VaultError::Corrupt { message: e.to_string() })?,
+    );
+    seal(key, &plain, id.as_bytes())
+}
+
+fn seal(key: &[u8; 32], plain: &[u8], aad: &[u8]) -> Result<String, VaultError> {
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: plain, aad })
+        .map_err(|_| VaultError::Corrupt { message: "encryption failed".into() })?;
+
+    let mut out = nonce.to_vec();
+    out.extend_from_slice(&ciphertext);
+    Ok(hex::encode(out))
+}
+
+fn open_sealed(key: &[u8; 32], sealed: &str, aad: &[u8]) -> Result<Zeroizing<Vec<u8>>, VaultError> {
+    let bytes = decode_hex(sealed)?;
+    if bytes.len() < NONCE_LEN {
+        return Err(VaultError::Corrupt { message: "sealed value too short".into() });
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+        .map(Zeroizing::new)
+        .map_err(|_| VaultError::WrongPassphrase)
+}
+
+fn check_verifier(key: &[u8; 32], verifier: &str) -> Result<(), VaultError> {
+    match open_sealed(key, verifier, b"")? {
+        plain if plain.as_slice() == VERIFIER_PLAINTEXT => Ok(()),
+        _ => Err(VaultError::WrongPassphrase),
+    }
+}
+
+fn decode_hex(value: &str) -> Result<Vec<u8>, VaultError> {
+    hex::decode(value).map_err(|e| VaultError::Corrupt { message: e.to_string() })
+}
+
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+/// Writes via a temp file and rename so a crash never truncates the target.
+fn write_atomic(path: &Path, bytes: &[u8]) -> Result<(), VaultError> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, bytes)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SITE: &str = "https://school.example";
+    const ID: &str = "3@https://school.example";
+
+    /// A fresh empty directory.
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vault-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn account() -> StoredAccount {
+        StoredAccount {
+            id: ID.into(),
+            site_url: SITE.into(),
+            fullname: "Sam Student".into(),
+            username: "student".into(),
+            userpictureurl: String::new(),
+            sitename: "School".into(),
+            userid: 3,
+        }
+    }
+
+    fn session(token: &str) -> Session {
+        Session::new(SITE, token).with_private_token(Some("private")).with_account_id(ID)
+    }
+
+    #[test]
+    fn seals_and_opens() {
+        let key = Zeroizing::new([7u8; 32]);
+        let sealed = seal(&key, b"secret", b"aad").unwrap();
+        assert_ne!(sealed, seal(&key, b"secret", b"aad").unwrap(), "nonces repeat");
+        assert_eq!(open_sealed(&key, &sealed, b"aad").unwrap().as_slice(), b"secret");
+
+        let other = Zeroizing::new([8u8; 32]);
+        assert!(matches!(open_sealed(&other, &sealed, b"aad"), Err(VaultError::WrongPassphrase)));
+        assert!(matches!(open_sealed(&key, &sealed, b"other"), Err(VaultError::WrongPassphrase)));
+        let mut tampered = decode_hex(&sealed).unwrap();
+        *tampered.last_mut().unwrap() ^= 1;
+        let tampered = hex::encode(tampered);
+        assert!(matches!(open_sealed(&key, &tampered, b"aad"), Err(VaultError::WrongPassphrase)));
+        assert!(matches!(open_sealed(&key, "00", b"aad"), Err(VaultError::Corrupt { .. })));
+    }
+
+    #[test]
+    fn stores_accounts_sealed() {
+        let dir = temp_dir();
+        let vault = Vault::open(&dir).unwrap();
+        assert!(!vault.is_locked());
+        vault.add(account(), &session("token-1"), None).unwrap();
+        let data_key = vault.data_key(ID).unwrap();
+
+        let on_disk = fs::read_to_string(dir.join(VAULT_FILE)).unwrap();
+        assert!(on_disk.contains("Sam Student"));
+        assert!(!on_disk.contains("token-1") && !on_disk.contains("private"));
+
+        // Replacing the account keeps its data key.
+        vault.add(account(), &session("token-2"), Some(&DataKey::random())).unwrap();
+        let vault = Vault::open(&dir).unwrap();
+        assert_eq!(vault.list(), vec![account()]);
+        let secrets = vault.secrets(ID).unwrap();
+        assert_eq!(secrets.token, "token-2");
+        assert_eq!(secrets.private_token.as_deref(), Some("private"));
+        assert_eq!(vault.data_key(ID).unwrap(), data_key);
+
+        let mut other = account();
+        other.userid = 4;
+        assert!(matches!(
+            vault.add(other, &session("t"), None),
+            Err(VaultError::AccountMismatch)
+        ));
+        vault.remove(ID).unwrap();
+        assert!(matches!(vault.get(ID), Err(VaultError::UnknownAccount { .. })));
+    }
+
+    #[test]
+    fn locks_with_a_passphrase() {
+        let dir = temp_dir();
+        let vault = Vault::open(&dir).unwrap();
+        vault.add(account(), &session("token"), None).unwrap();
+        vault.set_passphrase(Some("correct horse")).unwrap();
+
+        let vault = Vault::open(&dir).unwrap();
+        assert!(vault.is_locked());
+        assert_eq!(vault.list(), vec![account()]);
+        assert!(matches!(vault.secrets(ID), Err(VaultError::Locked)));
+        assert!(matches!(vault.data_key(ID), Err(VaultError::Locked)));
+        assert!(matches!(vault.unlock("wrong horse"), Err(VaultError::WrongPassphrase)));
+        assert!(vault.is_locked());
+        vault.unlock("correct horse").unwrap();
+        assert_eq!(vault.secrets(ID).unwrap().token, "token");
+
+        // Removing the passphrase unlocks the vault on the next start.
+        vault.set_passphrase(None).unwrap();
+        let vault = Vault::open(&dir).unwrap();
+        assert!(!vault.is_locked());
+        assert_eq!(vault.secrets(ID).unwrap().token, "token");
+    }
+
+    #[test]
+    fn refuses_to_rekey_without_the_master_key() {
+        let dir = temp_dir();
+        let vault = Vault::open(&dir).unwrap();
+        vault.add(account(), &session("token"), None).unwrap();
+        drop(vault);
+        let sealed = fs::read(dir.join(VAULT_FILE)).unwrap();
+
+        fs::remove_file(dir.join(MASTER_KEY_FILE)).unwrap();
+        assert!(matches!(Vault::open(&dir), Err(VaultError::MissingKey)));
+        assert!(!dir.join(MASTER_KEY_FILE).exists());
+        assert_eq!(fs::read(dir.join(VAULT_FILE)).unwrap(), sealed);
+
+        // Without accounts there is nothing to lose.
+        let empty = temp_dir();
+        drop(Vault::open(&empty).unwrap());
+        fs::remove_file(empty.join(MASTER_KEY_FILE)).unwrap();
+        let vault = Vault::open(&empty).unwrap();
+        assert!(!vault.is_locked() && vault.list().is_empty());
+    }
+
+    #[test]
+    fn falls_back_to_memory() {
+        let dir = temp_dir();
+        fs::write(dir.join(VAULT_FILE), b"{ not json").unwrap();
+        let error = Vault::open(&dir).err().unwrap();
+        assert!(matches!(error, VaultError::Corrupt { .. }));
+
+        let vault = Vault::in_memory(error).unwrap();
+        assert!(matches!(vault.open_error(), Some(VaultError::Corrupt { .. })));
+        vault.add(account(), &session("token"), None).unwrap();
+        assert_eq!(vault.secrets(ID).unwrap().token, "token");
+        assert_eq!(fs::read(dir.join(VAULT_FILE)).unwrap(), b"{ not json");
+
+        let store = dir.join("store.json");
+        let legacy = json!({ LEGACY_ACCOUNTS_KEY: [{ "token": "t" }] }).to_string();
+        fs::write(&store, &legacy).unwrap();
+        assert_eq!(vault.migrate_plaintext_store(&store).unwrap(), 0);
+        assert_eq!(fs::read_to_string(&store).unwrap(), legacy);
+    }
+
+    #[test]
+    fn migrates_plaintext_tokens() {
+        let dir = temp_dir();
+        let vault = Vault::open(&dir).unwrap();
+        let store = dir.join("store.json");
+        let mut good = serde_json::to_value(account()).unwrap();
+        good["token"] = json!("good-token");
+        good["privateToken"] = json!("good-private");
+        let malformed = json!({ "id": "5@https://other.example", "token": "kept-token" });
+        fs::write(
+            &store,
+            json!({
+                LEGACY_ACCOUNTS_KEY: [good, malformed],
+                LEGACY_SESSION_KEY: { "siteUrl": SITE, "token": "good-token" },
+                "theme": "dark",
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(vault.migrate_plaintext_store(&store).unwrap(), 1);
+        let secrets = vault.secrets(ID).unwrap();
+        assert_eq!(secrets.token, "good-token");
+        assert_eq!(secrets.private_token.as_deref(), Some("good-private"));
+
+        let migrated: Value = serde_json::from_slice(&fs::read(&store).unwrap()).unwrap();
+        let accounts = migrated[LEGACY_ACCOUNTS_KEY].as_array().unwrap();
+        assert_eq!(accounts[0]["fullname"], "Sam Student");
+        assert!(accounts[0].get("token").is_none() && accounts[0].get("privateToken").is_none());
+        assert_eq!(accounts[1]["token"], "kept-token");
+        assert!(migrated[LEGACY_SESSION_KEY].get("token").is_none());
+        assert_eq!(migrated["theme"], "dark");
+
+        // Nothing left to import; a missing store is not an error either.
+        assert_eq!(vault.migrate_plaintext_store(&store).unwrap(), 0);
+        assert_eq!(vault.migrate_plaintext_store(&dir.join("missing.json")).unwrap(), 0);
+    }
+
+    #[test]
+    fn keeps_sessions_of_accounts_it_could_not_import() {
+        let dir = temp_dir();
+        let vault = Vault::open(&dir).unwrap();
+        let store = dir.join("store.json");
+        let session = json!({ "siteUrl": SITE, "token": "other-token" });
+        fs::write(
+            &store,
+            json!({
+                LEGACY_ACCOUNTS_KEY: [{ "id": "broken", "token": "other-token" }],
+                LEGACY_SESSION_KEY: session,
+            })
+            .to_string(),
+        )
+        .unwrap();
+        assert_eq!(vault.migrate_plaintext_store(&store).unwrap(), 0);
+        let kept: Value = serde_json::from_slice(&fs::read(&store).unwrap()).unwrap();
+        assert_eq!(kept[LEGACY_SESSION_KEY], session);
+        assert_eq!(kept[LEGACY_ACCOUNTS_KEY][0]["token"], "other-token");
+    }
+}
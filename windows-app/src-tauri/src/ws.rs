@@ -0,0 +1,990 @@
+//! Moodle Web Service REST client.
+//!
+//! Mirrors what `MoodleApiService.fetchFromNetwork` used to do in the webview:
+//! POSTs to `webservice/rest/server.php`, flattens nested parameters into
+//! Moodle's `key[0][field]` form and turns `exception`/`errorcode` payloads
+//! into a typed [`WsError`].
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::future::join_all;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::scheduler::{self, Priority, Scheduler};
+use crate::session::{ExpiryReason, Session, SessionError};
+use crate::site_url::{self, GuardedResolver, SitePolicy, SiteUrlError};
+
+/// REST endpoint relative to the site root.
+pub const REST_ENDPOINT: &str = "webservice/rest/server.php";
+
+/// AJAX endpoint for functions that are callable without login.
+pub const AJAX_NOLOGIN_ENDPOINT: &str = "lib/ajax/service-nologin.php";
+
+/// Endpoint storing files in the user's draft area.
+pub const UPLOAD_ENDPOINT: &str = "webservice/upload.php";
+
+/// WS function running several functions in one request.
+pub const BATCH_FUNCTION: &str = "tool_mobile_call_external_functions";
+
+/// WS function every valid token may call.
+pub const SITE_INFO_FUNCTION: &str = "core_webservice_get_site_info";
+
+/// Default timeout for a single WS request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// User agent of every request. Moodle only serves some functions, such as
+/// `tool_mobile_get_tokens_for_qr_login`, to clients whose user agent
+/// contains `MoodleMobile` (`core_useragent::is_moodle_app`).
+pub const USER_AGENT: &str = concat!("MoodleMobile ", env!("CARGO_PKG_VERSION"), " (Windows)");
+
+/// Errors returned by a WS call. Serialised with a `kind` tag so the
+/// frontend can branch on the variant instead of parsing messages.
+#[derive(Debug, thiserror::Error, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum WsError {
+    /// The request never produced an HTTP response (DNS, TLS, timeout, ...).
+    #[error("Network error: {message}")]
+    Network { message: String },
+
+    /// The server answered with a non-success HTTP status. `retry_after`
+    /// carries the server's `Retry-After` in seconds, if it sent one.
+    #[error("HTTP {status}")]
+    #[serde(rename_all = "camelCase")]
+    Http { status: u16, retry_after: Option<u64> },
+
+    /// The body was not valid JSON.
+    #[error("Invalid response: {message}")]
+    InvalidResponse { message: String },
+
+    /// The site address was rejected by the SSRF guard.
+    #[error("{error}")]
+    Site { error: SiteUrlError },
+
+    /// The session handle does not refer to an open session.
+    #[error("Unknown or closed session")]
+    UnknownSession,
+
+    /// Moodle reported an exception for the called function.
+    #[error("Moodle WS Error [{errorcode}]: {message}")]
+    #[serde(rename_all = "camelCase")]
+    Moodle {
+        errorcode: String,
+        message: String,
+        exception: Option<String>,
+        debuginfo: Option<String>,
+    },
+
+    /// Moodle refused the token: a call failed with `accessexception` and
+    /// so did `core_webservice_get_site_info`.
+    #[error("Moodle WS Error [accessexception]: {message}")]
+    TokenRefused { message: String },
+}
+
+impl WsError {
+    /// Returns why the session is unusable if Moodle rejected the token or
+    /// the site went into maintenance, as opposed to a per-call failure.
+    pub fn session_expiry(&self) -> Option<ExpiryReason> {
+        match self {
+            WsError::Moodle { errorcode, .. } => ExpiryReason::from_errorcode(errorcode),
+            WsError::TokenRefused { .. } => Some(ExpiryReason::AccessException),
+            _ => None,
+        }
+    }
+
+    /// Whether Moodle answered `accessexception`, which it raises both for a
+    /// refused token and for a function missing from the service.
+    fn is_access_exception(&self) -> bool {
+        matches!(self, WsError::Moodle { errorcode, .. } if errorcode == "accessexception")
+    }
+}
+
+impl From<reqwest::Error> for WsError {
+    fn from(err: reqwest::Error) -> Self {
+        if let Some(error) = site_url::blocked_cause(&err) {
+            return WsError::Site { error };
+        }
+        match err.status() {
+            Some(status) => WsError::Http { status: status.as_u16(), retry_after: None },
+            None => WsError::Network { message: err.to_string() },
+        }
+    }
+}
+
+impl From<SiteUrlError> for WsError {
+    fn from(error: SiteUrlError) -> Self {
+        WsError::Site { error }
+    }
+}
+
+impl From<SessionError> for WsError {
+    fn from(err: SessionError) -> Self {
+        match err {
+            SessionError::UnknownSession => WsError::UnknownSession,
+        }
+    }
+}
+
+/// Returns the Moodle error contained in `data`, if any.
+///
+/// Moodle signals failures with HTTP 200 and an object carrying `exception`
+/// and/or `errorcode`; `login/token.php` uses `error` instead of `message`.
+pub fn parse_exception(data: &Value) -> Option<WsError> {
+    let obj = data.as_object()?;
+    if !obj.contains_key("exception") && !obj.contains_key("errorcode") {
+        return None;
+    }
+
+    let text = |key: &str| obj.get(key).and_then(Value::as_str).map(str::to_string);
+    Some(WsError::Moodle {
+        errorcode: text("errorcode").unwrap_or_default(),
+        message: text("message")
+            .or_else(|| text("error"))
+            .unwrap_or_else(|| "Unknown Moodle error".into()),
+        exception: text("exception"),
+        debuginfo: text("debuginfo"),
+    })
+}
+
+/// Whether a failed batch request should be retried as individual calls:
+/// any Moodle error except those that would fail every call anyway. A
+/// function missing from the service surfaces as `accessexception` or
+/// `invalidrecord`.
+fn batch_unsupported(err: &WsError) -> bool {
+    matches!(err, WsError::Moodle { .. }) && err.session_expiry().is_none()
+}
+
+/// Splits a `tool_mobile_call_external_functions` response into per-call
+/// results. `data` and `exception` are JSON-encoded strings.
+fn parse_batch_responses(data: &Value, expected: usize) -> Result<Vec<BatchResponse>, WsError> {
+    let responses = data
+        .get("responses")
+        .and_then(Value::as_array)
+        .filter(|r| r.len() == expected)
+        .ok_or_else(|| WsError::InvalidResponse {
+            message: "batch response does not match the request".into(),
+        })?;
+
+    let decode = |field: Option<&Value>| -> Result<Value, WsError> {
+        match field {
+            Some(Value::String(text)) => serde_json::from_str(text)
+                .map_err(|e| WsError::InvalidResponse { message: e.to_string() }),
+            Some(other) => Ok(other.clone()),
+            None => Ok(Value::Null),
+        }
+    };
+
+    Ok(responses
+        .iter()
+        .map(|response| {
+            if !response.get("error").and_then(Value::as_bool).unwrap_or(false) {
+                return decode(response.get("data")).into();
+            }
+            let exception = decode(response.get("exception")).unwrap_or(Value::Null);
+            BatchResponse::Error {
+                error: parse_exception(&exception).unwrap_or(WsError::Moodle {
+                    errorcode: String::new(),
+                    message: "Unknown Moodle error".into(),
+                    exception: None,
+                    debuginfo: None,
+                }),
+            }
+        })
+        .collect())
+}
+
+/// Turns a non-success response into [`WsError::Http`], keeping the
+/// `Retry-After` header (seconds or HTTP date).
+fn check_status(response: reqwest::Response) -> Result<reqwest::Response, WsError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let retry_after = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_retry_after);
+    Err(WsError::Http { status: status.as_u16(), retry_after })
+}
+
+fn parse_retry_after(value: &str) -> Option<u64> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(secs);
+    }
+    let at = httpdate::parse_http_date(value).ok()?;
+    Some(at.duration_since(std::time::SystemTime::now()).map_or(0, |d| d.as_secs()))
+}
+
+/// Flattens nested objects/arrays into Moodle WS parameter format,
+/// e.g. `{"courses": [{"id": 2}]}` becomes `courses[0][id]=2`.
+///
+/// Booleans are sent as `1`/`0` and `null` values are omitted.
+pub fn flatten_params(params: &Value) -> Vec<(String, String)> {
+    let mut out = Vec::new();
+    if let Value::Object(map) = params {
+        for (key, value) in map {
+            flatten_into(key.clone(), value, &mut out);
+        }
+    }
+    out
+}
+
+fn flatten_into(key: String, value: &Value, out: &mut Vec<(String, String)>) {
+    match value {
+        Value::Null => {}
+        Value::Bool(b) => out.push((key, if *b { "1" } else { "0" }.into())),
+        Value::Number(n) => out.push((key, n.to_string())),
+        Value::String(s) => out.push((key, s.clone())),
+        Value::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                flatten_into(format!("{key}[{index}]"), item, out);
+            }
+        }
+        Value::Object(map) => {
+            for (child, item) in map {
+                flatten_into(format!("{key}[{child}]"), item, out);
+            }
+        }
+    }
+}
+
+/// Builds a `multipart/form-data` body with plain `fields` followed by one
+/// file part. reqwest is built without its multipart feature.
+fn multipart_body(
+    boundary: &str,
+    fields: &[(&str, &str)],
+    filename: &str,
+    bytes: &[u8],
+) -> Vec<u8> {
+    let mut body = Vec::with_capacity(bytes.len() + 512);
+    for (name, value) in fields {
+        body.extend_from_slice(
+            format!(
+                "--{boundary}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n\
+                 {value}\r\n"
+            )
+            .as_bytes(),
+        );
+    }
+    let filename = filename.replace(['"', '\r', '\n'], "_");
+    body.extend_from_slice(
+        format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"file_1\"; \
+             filename=\"{filename}\"\r\nContent-Type: application/octet-stream\r\n\r\n"
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(bytes);
+    body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+    body
+}
+
+/// Builds the absolute REST endpoint URL for a site.
+pub fn endpoint_url(site_url: &str) -> String {
+    format!("{}/{REST_ENDPOINT}", site_url.trim_end_matches('/'))
+}
+
+/// One function call in a batch.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BatchCall {
+    pub wsfunction: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+/// Outcome of one call in a batch, in request order.
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum BatchResponse {
+    Ok { data: Value },
+    Error { error: WsError },
+}
+
+impl From<Result<Value, WsError>> for BatchResponse {
+    fn from(result: Result<Value, WsError>) -> Self {
+        match result {
+            Ok(data) => BatchResponse::Ok { data },
+            Err(error) => BatchResponse::Error { error },
+        }
+    }
+}
+
+/// HTTP client for Moodle Web Service calls. Held in Tauri managed state so
+/// all commands share one connection pool and one SSRF policy.
+#[derive(Clone)]
+pub struct WsClient {
+    http: reqwest::Client,
+    policy: Arc<SitePolicy>,
+    scheduler: Arc<Scheduler>,
+}
+
+impl Default for WsClient {
+    fn default() -> Self {
+        Self::new(SitePolicy::default())
+    }
+}
+
+impl WsClient {
+    /// Creates a client enforcing `policy` on every request, including
+    /// redirects and DNS results.
+    pub fn new(policy: SitePolicy) -> Self {
+        let policy = Arc::new(policy);
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .user_agent(USER_AGENT)
+            .dns_resolver(Arc::new(GuardedResolver::new(Arc::clone(&policy))))
+            .redirect(site_url::redirect_policy(Arc::clone(&policy)))
+            .build()
+            .expect("failed to build HTTP client");
+        Self { http, policy, scheduler: Arc::new(Scheduler::default()) }
+    }
+
+    /// Replaces the default request scheduler (concurrency and retries).
+    pub fn with_scheduler(mut self, scheduler: Scheduler) -> Self {
+        self.scheduler = Arc::new(scheduler);
+        self
+    }
+
+    /// The SSRF policy applied by this client.
+    pub fn policy(&self) -> &SitePolicy {
+        &self.policy
+    }
+
+    /// Rejects URLs whose literal host the policy forbids; hostnames are
+    /// checked by the resolver when connecting.
+    pub fn check_url(&self, url: &str) -> Result<(), WsError> {
+        let parsed = reqwest::Url::parse(url)
+            .map_err(|e| SiteUrlError::Invalid { message: e.to_string() })?;
+        Ok(self.policy.check_url(&parsed)?)
+    }
+
+    /// The underlying HTTP client, for non-WS requests that should share the
+    /// connection pool (e.g. pluginfile downloads).
+    pub fn http(&self) -> &reqwest::Client {
+        &self.http
+    }
+
+    /// Calls a Moodle WS function on behalf of `session` and returns the
+    /// parsed JSON response.
+    ///
+    /// `params` should be a JSON object; anything else is treated as empty.
+    pub async fn call(
+        &self,
+        session: &Session,
+        function: &str,
+        params: &Value,
+    ) -> Result<Value, WsError> {
+        self.call_with_priority(session, function, params, Priority::User)
+            .await
+    }
+
+    /// Like [`WsClient::call`], queued behind user calls if `priority` is
+    /// [`Priority::Background`].
+    pub async fn call_with_priority(
+        &self,
+        session: &Session,
+        function: &str,
+        params: &Value,
+        priority: Priority,
+    ) -> Result<Value, WsError> {
+        let idempotent = scheduler::is_read_function(function);
+        let (site_url, token) = (session.site_url(), session.token());
+        match self.scheduled(site_url, token, function, params, priority, idempotent).await {
+            Err(err) => Err(self.recheck_access(session, function, err, priority).await),
+            result => result,
+        }
+    }
+
+    /// Tells the two meanings of an `accessexception` from `function` apart.
+    /// If `core_webservice_get_site_info` fails too, the token was refused
+    /// and [`WsError::TokenRefused`] (or the site info's own expiry error)
+    /// is returned; otherwise the function is just not available and `err`
+    /// is returned as it is. Other errors pass through unchanged.
+    async fn recheck_access(
+        &self,
+        session: &Session,
+        function: &str,
+        err: WsError,
+        priority: Priority,
+    ) -> WsError {
+        if !err.is_access_exception() {
+            return err;
+        }
+        if function != SITE_INFO_FUNCTION {
+            let (site_url, token) = (session.site_url(), session.token());
+            let recheck = self
+                .scheduled(site_url, token, SITE_INFO_FUNCTION, &Value::Null, priority, true)
+                .await;
+            match recheck {
+                Err(recheck) if recheck.session_expiry().is_some() => return recheck,
+                Err(recheck) if recheck.is_access_exception() => {}
+                // The token works, or the site cannot tell right now.
+                _ => return err,
+            }
+        }
+        match err {
+            WsError::Moodle { message, .. } => WsError::TokenRefused { message },
+            err => err,
+        }
+    }
+
+    /// Runs several functions in one `tool_mobile_call_external_functions`
+    /// request and returns one result per call, in order.
+    ///
+    /// Sites without the batch function get the calls individually and in
+    /// parallel instead. Only failures of the whole request (network, an
+    /// expired session) are returned as `Err`.
+    pub async fn call_batch(
+        &self,
+        session: &Session,
+        calls: &[BatchCall],
+        priority: Priority,
+    ) -> Result<Vec<BatchResponse>, WsError> {
+        if calls.is_empty() {
+            return Ok(Vec::new());
+        }
+        let requests: Vec<Value> = calls
+            .iter()
+            .map(|call| {
+                let arguments = match &call.params {
+                    Value::Object(_) => call.params.to_string(),
+                    _ => "{}".to_string(),
+                };
+                json!({ "function": call.wsfunction, "arguments": arguments })
+            })
+            .collect();
+
+        // The batch may be retried only if every call in it may.
+        let idempotent = calls.iter().all(|call| scheduler::is_read_function(&call.wsfunction));
+        let params = json!({ "requests": requests });
+        let (site_url, token) = (session.site_url(), session.token());
+        let batch = self
+            .scheduled(site_url, token, BATCH_FUNCTION, &params, priority, idempotent)
+            .await;
+        let batch = match batch {
+            Err(err) => Err(self.recheck_access(session, BATCH_FUNCTION, err, priority).await),
+            batch => batch,
+        };
+
+        match batch {
+            Ok(data) => parse_batch_responses(&data, calls.len()),
+            Err(err) if batch_unsupported(&err) => Ok(join_all(calls.iter().map(|call| {
+                self.call_with_priority(session, &call.wsfunction, &call.params, priority)
+            }))
+            .await
+            .into_iter()
+            .map(BatchResponse::from)
+            .collect()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Like [`WsClient::call`], for a token that is not (yet) in a session,
+    /// e.g. right after `login/token.php`.
+    pub(crate) async fn call_with_token(
+        &self,
+        site_url: &str,
+        token: &str,
+        function: &str,
+        params: &Value,
+    ) -> Result<Value, WsError> {
+        let idempotent = scheduler::is_read_function(function);
+        self.scheduled(site_url, token, function, params, Priority::User, idempotent)
+            .await
+    }
+
+    /// Uploads a file into the user's draft area and returns Moodle's
+    /// description of the stored files. `itemid` 0 starts a new draft area.
+    /// Uploads are writes and never retried.
+    pub async fn upload_draft(
+        &self,
+        session: &Session,
+        filename: &str,
+        bytes: &[u8],
+        itemid: i64,
+    ) -> Result<Value, WsError> {
+        let site_url = session.site_url();
+        self.scheduler
+            .run(site_url, Priority::User, false, || {
+                self.post_upload(site_url, session.token(), filename, bytes, itemid)
+            })
+            .await
+    }
+
+    async fn post_upload(
+        &self,
+        site_url: &str,
+        token: &str,
+        filename: &str,
+        bytes: &[u8],
+        itemid: i64,
+    ) -> Result<Value, WsError> {
+        let url = format!("{}/{UPLOAD_ENDPOINT}", site_url.trim_end_matches('/'));
+        self.check_url(&url)?;
+        let boundary = format!("moodle-desktop-{}", uuid::Uuid::new_v4().simple());
+        let itemid = itemid.to_string();
+        let fields = [("token", token), ("filearea", "draft"), ("itemid", itemid.as_str())];
+        let body = multipart_body(&boundary, &fields, filename, bytes);
+
+        let response = self
+            .http
+            .post(url)
+            .header(
+                reqwest::header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={boundary}"),
+            )
+            .body(body)
+            .send()
+            .await?;
+        let response = check_status(response)?;
+        let body = response.bytes().await?;
+        let data: Value = serde_json::from_slice(&body)
+            .map_err(|e| WsError::InvalidResponse { message: e.to_string() })?;
+
+        match parse_exception(&data) {
+            Some(err) => Err(err),
+            None => Ok(data),
+        }
+    }
+
+    /// Sends a REST call through the scheduler.
+    async fn scheduled(
+        &self,
+        site_url: &str,
+        token: &str,
+        function: &str,
+        params: &Value,
+        priority: Priority,
+        idempotent: bool,
+    ) -> Result<Value, WsError> {
+        self.scheduler
+            .run(site_url, priority, idempotent, || {
+                self.post_rest(site_url, token, function, params)
+            })
+            .await
+    }
+
+    /// One POST to the REST endpoint, without scheduling.
+    async fn post_rest(
+        &self,
+        site_url: &str,
+        token: &str,
+        function: &str,
+        params: &Value,
+    ) -> Result<Value, WsError> {
+        let mut form = vec![
+            ("wstoken".to_string(), token.to_string()),
+            ("wsfunction".to_string(), function.to_string()),
+            ("moodlewsrestformat".to_string(), "json".to_string()),
+        ];
+        form.extend(flatten_params(params));
+
+        let url = endpoint_url(site_url);
+        self.check_url(&url)?;
+        let response = self
+            .http
+            .post(url)
+            .form(&form)
+            .send()
+            .await?;
+        let response = check_status(response)?;
+
+        let body = response.bytes().await?;
+        let data: Value = serde_json::from_slice(&body)
+            .map_err(|e| WsError::InvalidResponse { message: e.to_string() })?;
+
+        match parse_exception(&data) {
+            Some(err) => Err(err),
+            None => Ok(data),
+        }
+    }
+
+    /// Calls a function through `lib/ajax/service-nologin.php`, used before
+    /// a token exists (public config, QR login).
+    pub async fn call_ajax_nologin(
+        &self,
+        site_url: &str,
+        function: &str,
+        args: &Value,
+    ) -> Result<Value, WsError> {
+        let idempotent = scheduler::is_read_function(function);
+        self.scheduler
+            .run(site_url, Priority::User, idempotent, || {
+                self.post_ajax_nologin(site_url, function, args)
+            })
+            .await
+    }
+
+    async fn post_ajax_nologin(
+        &self,
+        site_url: &str,
+        function: &str,
+        args: &Value,
+    ) -> Result<Value, WsError> {
+        let url = format!(
+            "{}/{AJAX_NOLOGIN_ENDPOINT}?info={function}",
+            site_url.trim_end_matches('/')
+        );
+        self.check_url(&url)?;
+        let request = serde_json::json!([{ "index": 0, "methodname": function, "args": args }]);
+
+        let response = self
+            .http
+            .post(url)
+            .json(&request)
+            .send()
+            .await?;
+        let response = check_status(response)?;
+        let body = response.bytes().await?;
+        let data: Value = serde_json::from_slice(&body)
+            .map_err(|e| WsError::InvalidResponse { message: e.to_string() })?;
+
+        // A failure of the whole request comes back as a single error object,
+        // per-call failures as `{ "error": true, "exception": {...} }`.
+        if let Some(err) = parse_exception(&data) {
+            return Err(err);
+        }
+        let first = data
+            .get(0)
+            .ok_or_else(|| WsError::InvalidResponse { message: "empty AJAX response".into() })?;
+        if first.get("error").and_then(Value::as_bool).unwrap_or(false) {
+            let exception = first.get("exception").cloned().unwrap_or(Value::Null);
+            return Err(parse_exception(&exception).unwrap_or(WsError::Moodle {
+                errorcode: String::new(),
+                message: "Unknown Moodle error".into(),
+                exception: None,
+                debuginfo: None,
+            }));
+        }
+        Ok(first.get("data").cloned().unwrap_or(Value::Null))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{test_client, MockServer};
+    use serde_json::json;
+
+    /// A Moodle exception body.
+    fn exception(errorcode: &str, message: &str) -> String {
+        json!({ "exception": "moodle_exception", "errorcode": errorcode, "message": message })
+            .to_string()
+    }
+
+    fn calls(functions: &[&str]) -> Vec<BatchCall> {
+        let call = |(n, wsfunction): (usize, &&str)| BatchCall {
+            wsfunction: wsfunction.to_string(),
+            params: json!({ "n": n }),
+        };
+        functions.iter().enumerate().map(call).collect()
+    }
+
+    #[test]
+    fn flattens_nested_params() {
+        let params = json!({
+            "courseid": 7,
+            "options": [{ "name": "excludemodules", "value": true }],
+            "ids": [1, 2],
+            "skip": null,
+        });
+
+        let flat = flatten_params(&params);
+        assert!(flat.contains(&("courseid".into(), "7".into())));
+        assert!(flat.contains(&("options[0][name]".into(), "excludemodules".into())));
+        assert!(flat.contains(&("options[0][value]".into(), "1".into())));
+        assert!(flat.contains(&("ids[0]".into(), "1".into())));
+        assert!(flat.contains(&("ids[1]".into(), "2".into())));
+        assert!(!flat.iter().any(|(k, _)| k == "skip"));
+    }
+
+    #[test]
+    fn non_error_payloads_are_not_exceptions() {
+        assert!(parse_exception(&json!([])).is_none());
+        assert!(parse_exception(&json!(null)).is_none());
+        assert!(parse_exception(&json!({ "sitename": "Demo" })).is_none());
+    }
+
+    #[tokio::test]
+    async fn posts_form_to_rest_endpoint() {
+        let server = MockServer::start(|_| (200, r#"{"sitename":"Demo"}"#.into()));
+
+        let session = Session::new(&server.url(), "secret");
+        let data = test_client()
+            .call(&session, "core_webservice_get_site_info", &json!({ "ids": [3] }))
+            .await
+            .unwrap();
+
+        assert_eq!(data["sitename"], "Demo");
+        let req = server.last_request();
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.header("content-type"), Some("application/x-www-form-urlencoded"));
+        assert_eq!(req.path, "/webservice/rest/server.php");
+        assert!(req.body.contains("wstoken=secret"));
+        assert!(req.body.contains("wsfunction=core_webservice_get_site_info"));
+        assert!(req.body.contains("ids%5B0%5D=3"));
+    }
+
+    #[tokio::test]
+    async fn maps_moodle_exception() {
+        let server = MockServer::start(|_| (200, exception("invalidrecord", "Not found")));
+
+        let err = test_client()
+            .call(&Session::new(&server.url(), "t"), "core_course_get_contents", &json!({}))
+            .await
+            .unwrap_err();
+
+        match err {
+            WsError::Moodle { errorcode, message, .. } => {
+                assert_eq!(errorcode, "invalidrecord");
+                assert_eq!(message, "Not found");
+            }
+            other => panic!("unexpected error: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_loopback_without_allowlist() {
+        let server = MockServer::start(|_| (200, "{}".into()));
+
+        let err = WsClient::default()
+            .call(&Session::new(&server.url(), "t"), "core_webservice_get_site_info", &json!({}))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, WsError::Site { error: SiteUrlError::BlockedAddress { .. } }));
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn maps_http_status() {
+        let server = MockServer::start(|_| (502, "Bad Gateway".into()));
+
+        let err = test_client()
+            .call(&Session::new(&server.url(), "t"), "core_webservice_get_site_info", &json!({}))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, WsError::Http { status: 502, .. }));
+    }
+
+    #[tokio::test]
+    async fn uploads_multipart_to_draft_area() {
+        let server = MockServer::start(|_| (200, r#"[{"itemid":42,"filename":"a.txt"}]"#.into()));
+
+        let data = test_client()
+            .upload_draft(&Session::new(&server.url(), "secret"), "a\".txt", b"hello", 0)
+            .await
+            .unwrap();
+
+        assert_eq!(data[0]["itemid"], 42);
+        let req = server.last_request();
+        assert_eq!(req.path, "/webservice/upload.php");
+        assert!(req.header("content-type").unwrap().starts_with("multipart/form-data; boundary="));
+        assert!(req.body.contains("name=\"token\"\r\n\r\nsecret\r\n"));
+        assert!(req.body.contains("name=\"itemid\"\r\n\r\n0\r\n"));
+        assert!(req.body.contains("filename=\"a_.txt\""));
+        assert!(req.body.contains("\r\n\r\nhello\r\n--"));
+    }
+
+    #[tokio::test]
+    async fn batches_calls_into_one_request() {
+        let server = MockServer::start(|_| {
+            let responses = json!({ "responses": [
+                { "error": false, "data": r#"{"sitename":"Demo"}"# },
+                { "error": true, "exception": exception("nopermissions", "No access") },
+                { "error": false, "data": "null" },
+            ]});
+            (200, responses.to_string())
+        });
+        let functions = ["core_webservice_get_site_info", "core_course_get_contents", "f"];
+
+        let results = test_client()
+            .call_batch(&Session::new(&server.url(), "t"), &calls(&functions), Priority::User)
+            .await
+            .unwrap();
+
+        assert_eq!(server.requests().len(), 1);
+        let body = server.last_request().body;
+        assert!(body.contains("wsfunction=tool_mobile_call_external_functions"));
+        assert!(body.contains("requests%5B1%5D%5Bfunction%5D=core_course_get_contents"));
+        assert!(body.contains("requests%5B1%5D%5Barguments%5D=%7B%22n%22%3A1%7D"));
+        assert!(matches!(&results[0], BatchResponse::Ok { data } if data["sitename"] == "Demo"));
+        match &results[1] {
+            BatchResponse::Error { error: WsError::Moodle { errorcode, message, .. } } => {
+                assert_eq!((errorcode.as_str(), message.as_str()), ("nopermissions", "No access"));
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+        assert!(matches!(&results[2], BatchResponse::Ok { data: Value::Null }));
+    }
+
+    #[test]
+    fn rejects_batch_responses_of_the_wrong_length() {
+        let data = json!({ "responses": [{ "error": false, "data": "1" }] });
+        assert!(matches!(parse_batch_responses(&data, 2), Err(WsError::InvalidResponse { .. })));
+        let empty = parse_batch_responses(&json!({}), 0);
+        assert!(matches!(empty, Err(WsError::InvalidResponse { .. })));
+
+        // An unreadable exception still fails only its own call.
+        let data = json!({ "responses": [{ "error": true, "exception": "{" }] });
+        let results = parse_batch_responses(&data, 1).unwrap();
+        assert!(matches!(&results[0], BatchResponse::Error { error: WsError::Moodle { .. } }));
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_single_calls_without_the_batch_function() {
+        let server = MockServer::start(|req| {
+            if req.body.contains("wsfunction=tool_mobile_call_external_functions") {
+                return (200, exception("accessexception", "Access control exception"));
+            }
+            match req.body.contains("wsfunction=core_course_get_contents") {
+                true => (200, exception("invalidrecord", "Not found")),
+                false => (200, r#"{"sitename":"Demo"}"#.into()),
+            }
+        });
+        let functions = ["core_webservice_get_site_info", "core_course_get_contents"];
+
+        let results = test_client()
+            .call_batch(&Session::new(&server.url(), "t"), &calls(&functions), Priority::User)
+            .await
+            .unwrap();
+
+        // The batch, the site info re-check and the two single calls.
+        assert_eq!(server.requests().len(), 4);
+        assert!(matches!(&results[0], BatchResponse::Ok { data } if data["sitename"] == "Demo"));
+        match &results[1] {
+            BatchResponse::Error { error: WsError::Moodle { errorcode, .. } } => {
+                assert_eq!(errorcode, "invalidrecord");
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn does_not_fall_back_when_the_session_is_gone() {
+        for errorcode in ["invalidtoken", "servicerequireslogin", "sitemaintenance"] {
+            let server = MockServer::start(move |_| (200, exception(errorcode, "Gone")));
+
+            let err = test_client()
+                .call_batch(&Session::new(&server.url(), "t"), &calls(&["a", "b"]), Priority::User)
+                .await
+                .unwrap_err();
+
+            assert!(matches!(&err, WsError::Moodle { errorcode: code, .. } if code == errorcode));
+            assert_eq!(server.requests().len(), 1, "{errorcode}");
+        }
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(120));
+        assert_eq!(parse_retry_after(" 0 "), Some(0));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(0));
+        let later = std::time::SystemTime::now() + Duration::from_secs(90);
+        let secs = parse_retry_after(&httpdate::fmt_http_date(later)).unwrap();
+        assert!((85..=90).contains(&secs), "{secs}");
+        assert_eq!(parse_retry_after("soon"), None);
+        assert_eq!(parse_retry_after("-5"), None);
+    }
+
+    #[tokio::test]
+    async fn keeps_retry_after_of_http_errors() {
+        let server = MockServer::start_with_headers(|_| {
+            (503, vec![("Retry-After".into(), "3600".into())], "Maintenance".into())
+        });
+
+        let err = test_client()
+            .call(&Session::new(&server.url(), "t"), "core_course_get_contents", &json!({}))
+            .await
+            .unwrap_err();
+
+        // Longer than the scheduler waits for: given up after one request.
+        assert!(matches!(err, WsError::Http { status: 503, retry_after: Some(3600) }));
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn recognises_expired_sessions() {
+        let moodle = |errorcode: &str| WsError::Moodle {
+            errorcode: errorcode.into(),
+            message: String::new(),
+            exception: None,
+            debuginfo: None,
+        };
+        let cases = [
+            ("invalidtoken", Some(ExpiryReason::InvalidToken)),
+            ("accessexception", None),
+            ("servicerequireslogin", Some(ExpiryReason::ServiceRequiresLogin)),
+            ("sitemaintenance", Some(ExpiryReason::SiteMaintenance)),
+            ("invalidrecord", None),
+            ("nopermissions", None),
+            ("", None),
+        ];
+        for (errorcode, reason) in cases {
+            assert_eq!(moodle(errorcode).session_expiry(), reason, "{errorcode}");
+        }
+
+        // Only Moodle's own verdict ends a session; HTTP 503 is an outage.
+        let others = [
+            WsError::Http { status: 503, retry_after: None },
+            WsError::Http { status: 401, retry_after: None },
+            WsError::Network { message: "timeout".into() },
+            WsError::UnknownSession,
+        ];
+        for err in others {
+            assert_eq!(err.session_expiry(), None, "{err:?}");
+        }
+        let refused = WsError::TokenRefused { message: String::new() };
+        assert_eq!(refused.session_expiry(), Some(ExpiryReason::AccessException));
+    }
+
+    #[tokio::test]
+    async fn rechecks_access_exceptions_with_the_site_info() {
+        let site_info = |body: &str| body.contains("wsfunction=core_webservice_get_site_info");
+        let client = test_client();
+
+        // A function missing from the service: the token still works.
+        let server = MockServer::start(move |req| match site_info(&req.body) {
+            true => (200, r#"{"sitename":"Demo"}"#.into()),
+            false => (200, exception("accessexception", "Access control exception")),
+        });
+        let session = Session::new(&server.url(), "t");
+        let err = client.call(&session, "local_missing", &json!({})).await.unwrap_err();
+        let code = |err: &WsError| match err {
+            WsError::Moodle { errorcode, .. } => errorcode.clone(),
+            _ => String::new(),
+        };
+        assert_eq!(code(&err), "accessexception");
+        assert_eq!(err.session_expiry(), None);
+        assert_eq!(server.requests().len(), 2);
+
+        // The site info fails the same way: the token was refused.
+        let server = MockServer::start(|_| (200, exception("accessexception", "Refused")));
+        let session = Session::new(&server.url(), "t");
+        let err = client.call(&session, "local_missing", &json!({})).await.unwrap_err();
+        assert!(matches!(&err, WsError::TokenRefused { message } if message == "Refused"));
+        assert_eq!(server.requests().len(), 2);
+        let err = client.call(&session, SITE_INFO_FUNCTION, &Value::Null).await.unwrap_err();
+        assert_eq!(err.session_expiry(), Some(ExpiryReason::AccessException));
+        assert_eq!(server.requests().len(), 3);
+        let err = client
+            .call_batch(&session, &calls(&["a", "b"]), Priority::User)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, WsError::TokenRefused { .. }));
+        assert_eq!(server.requests().len(), 5);
+
+        // The site info names the actual reason.
+        let server = MockServer::start(move |req| match site_info(&req.body) {
+            true => (200, exception("invalidtoken", "Invalid token")),
+            false => (200, exception("accessexception", "Access control exception")),
+        });
+        let session = Session::new(&server.url(), "t");
+        let err = client.call(&session, "local_missing", &json!({})).await.unwrap_err();
+        assert_eq!(err.session_expiry(), Some(ExpiryReason::InvalidToken));
+    }
+}
@@ -0,0 +1,101 @@
+// Generated from src-tauri/src/model.rs by `cargo test`. Do not edit.
+
+export type SiteFunction = { name: string, version: string, };
+
+export type AdvancedFeature = { name: string, value: number, };
+
+export type SiteInfo = { sitename: string, username: string, firstname: string, lastname: string, fullname: string, lang: string, userid: number, siteurl: string, userpictureurl: string, functions: Array<SiteFunction>, downloadfiles: number | null, uploadfiles: number | null, 
+/**
+ * Human-readable release, e.g. `4.3.2+ (Build: 20240112)`.
+ */
+release: string | null, 
+/**
+ * Numeric version as a string, e.g. `2023100902.05`.
+ */
+version: string | null, mobilecssurl: string | null, advancedfeatures: Array<AdvancedFeature>, usercanmanageownfiles: boolean | null, userquota: number | null, usermaxuploadfilesize: number | null, userhomepage: number | null, userprivateaccesskey: string | null, siteid: number | null, sitecalendartype: string | null, usercalendartype: string | null, userissiteadmin: boolean | null, theme: string | null, limitconcurrentlogins: number | null, policyagreed: number | null, };
+
+export type MoodleFile = { filename: string, filepath: string | null, filesize: number, fileurl: string, timemodified: number | null, mimetype: string | null, isexternalfile: boolean | null, };
+
+export type Course = { id: number, shortname: string, fullname: string, displayname: string | null, enrolledusercount: number | null, idnumber: string | null, visible: number, summary: string | null, summaryformat: number | null, format: string | null, courseimage: string | null, showgrades: boolean | null, lang: string | null, enablecompletion: boolean | null, completionhascriteria: boolean | null, completionusertracked: boolean | null, category: number | null, 
+/**
+ * Completion percentage, `null` if completion is not tracked.
+ */
+progress: number | null, completed: boolean | null, startdate: number | null, enddate: number | null, marker: number | null, lastaccess: number | null, isfavourite: boolean | null, hidden: boolean | null, overviewfiles: Array<MoodleFile>, timemodified: number | null, };
+
+export type CourseSection = { id: number, name: string, visible: number | null, summary: string, summaryformat: number, section: number | null, hiddenbynumsections: number | null, uservisible: boolean | null, modules: Array<CourseModule>, };
+
+export type CourseModule = { id: number, url: string | null, name: string, instance: number | null, contextid: number | null, description: string | null, visible: number | null, uservisible: boolean | null, visibleoncoursepage: number | null, modicon: string, modname: string, modplural: string, indent: number, availability: string | null, noviewlink: boolean | null, completion: number | null, 
+/**
+ * Completion state; its shape changed across Moodle versions.
+ */
+completiondata: Record<string, unknown> | null, downloadcontent: number | null, contents: Array<ModuleContent>, contentsinfo: ModuleContentsInfo | null, };
+
+export type ModuleContent = { 
+/**
+ * `file`, `url` or `content`.
+ */
+type: string, filename: string, filepath: string | null, filesize: number, fileurl: string | null, content: string | null, timecreated: number | null, timemodified: number | null, sortorder: number | null, mimetype: string | null, isexternalfile: boolean | null, userid: number | null, author: string | null, license: string | null, };
+
+export type ModuleContentsInfo = { filescount: number, filessize: number, lastmodified: number, mimetypes: Array<string>, repositorytype: string | null, };
+
+export type CalendarEventCourse = { id: number, fullname: string, shortname: string, viewurl: string | null, courseimage: string | null, };
+
+export type CalendarEventAction = { name: string, url: string, itemcount: number, actionable: boolean, showitemcount: boolean, };
+
+export type CalendarEvent = { id: number, name: string, description: string | null, descriptionformat: number | null, location: string | null, categoryid: number | null, groupid: number | null, userid: number | null, component: string | null, modulename: string | null, instance: number | null, 
+/**
+ * `site`, `course`, `category`, `group`, `user`, `due`, `open`, ...
+ */
+eventtype: string, timestart: number, timeduration: number, timesort: number | null, timeusermidnight: number | null, visible: number | null, course: CalendarEventCourse | null, action: CalendarEventAction | null, url: string | null, viewurl: string | null, formattedtime: string | null, isactionevent: boolean | null, iscourseevent: boolean | null, iscategoryevent: boolean | null, normalisedeventtype: string | null, normalisedeventtypetext: string | null, purpose: string | null, };
+
+export type CalendarEvents = { events: Array<CalendarEvent>, firstid: number | null, lastid: number | null, };
+
+export type GradeItem = { id: number, itemname: string | null, 
+/**
+ * `course`, `category`, `mod` or `manual`.
+ */
+itemtype: string, itemmodule: string | null, iteminstance: number | null, categoryid: number | null, cmid: number | null, weightraw: number | null, weightformatted: string | null, 
+/**
+ * Raw grade, `null` while ungraded.
+ */
+graderaw: number | null, gradedategraded: number | null, gradeishidden: boolean | null, gradeformatted: string | null, grademin: number | null, grademax: number | null, rangeformatted: string | null, percentageformatted: string | null, lettergradeformatted: string | null, feedback: string | null, feedbackformat: number | null, };
+
+export type UserGrades = { courseid: number, userid: number, userfullname: string, maxdepth: number, gradeitems: Array<GradeItem>, };
+
+export type GradeItems = { usergrades: Array<UserGrades>, };
+
+export type CourseGrade = { courseid: number, 
+/**
+ * Formatted grade, e.g. `85.00` or `A`.
+ */
+grade: string, rawgrade: string | null, rank: number | null, };
+
+export type CourseGrades = { grades: Array<CourseGrade>, };
+
+export type ConversationMember = { id: number, fullname: string, profileurl: string | null, profileimageurl: string | null, profileimageurlsmall: string | null, 
+/**
+ * `null` if the user hides their online status.
+ */
+isonline: boolean | null, showonlinestatus: boolean | null, isblocked: boolean | null, iscontact: boolean | null, isdeleted: boolean | null, canmessage: boolean | null, requirescontact: boolean | null, };
+
+export type Message = { id: number, useridfrom: number, text: string, timecreated: number, };
+
+export type Conversation = { id: number, name: string | null, subname: string | null, imageurl: string | null, 
+/**
+ * 1 = individual, 2 = group, 3 = self.
+ */
+type: number, membercount: number, ismuted: boolean, isfavourite: boolean, isread: boolean, unreadcount: number | null, members: Array<ConversationMember>, messages: Array<Message>, candeletemessagesforallusers: boolean | null, };
+
+export type Conversations = { conversations: Array<Conversation>, };
+
+export type Notification = { id: number, useridfrom: number, useridto: number, subject: string, shortenedsubject: string | null, text: string, fullmessage: string | null, fullmessageformat: number | null, fullmessagehtml: string | null, smallmessage: string | null, contexturl: string | null, contexturlname: string | null, timecreated: number, timecreatedpretty: string | null, timeread: number | null, read: boolean, deleted: boolean, iconurl: string | null, component: string | null, eventtype: string | null, 
+/**
+ * JSON-encoded extra data from the sending component.
+ */
+customdata: string | null, };
+
+export type Notifications = { notifications: Array<Notification>, unreadcount: number, };
+
+export type UserCustomField = { type: string, value: string | null, displayvalue: string | null, name: string, shortname: string, };
+
+export type UserProfile = { id: number, username: string | null, fullname: string, firstname: string | null, lastname: string | null, email: string | null, department: string | null, institution: string | null, city: string | null, country: string | null, description: string | null, descriptionformat: number | null, firstaccess: number | null, lastaccess: number | null, profileimageurl: string | null, profileimageurlsmall: string | null, customfields: Array<UserCustomField>, };
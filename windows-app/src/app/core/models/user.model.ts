@@ -1,48 +1,38 @@
-/** Moodle user profile returned by core_webservice_get_site_info. */
-export type SiteInfo = {
-    sitename: string;
-    username: string;
-    firstname: string;
-    lastname: string;
-    fullname: string;
-    lang: string;
-    userid: number;
-    siteurl: string;
-    userpictureurl: string;
-    /** User email address. */
-    email: string;
-    /** Moodle release string, e.g. "4.3 (Build: 20231113)". */
-    release: string;
-    version: string;
-    functions: SiteFunction[];
-};
+import type { SiteInfo } from './moodle.generated';
+
+/** Site and user info of core_webservice_get_site_info, generated from the backend model. */
+export type { SiteFunction, SiteInfo } from './moodle.generated';
 
-export type SiteFunction = {
-    name: string;
-    version: string;
+/** Backend session description; the token never leaves the backend. */
+export type SessionInfo = {
+    /** Opaque handle passed to backend commands. */
+    handle: string;
+    siteUrl: string;
+    /** Vault account id (`userid@siteurl`), if the session belongs to one. */
+    accountId: string | null;
 };
 
-/** Token pair returned after successful authentication. */
-export type AuthToken = {
-    token: string;
-    privateToken: string;
+/** Result of the backend login commands. */
+export type LoginResult = {
+    session: SessionInfo;
+    siteInfo: SiteInfo;
 };
 
-/** Stored session that combines site URL, token, and user info. */
+/** Active session: backend handle, site URL, and user info. */
 export type Session = {
+    handle: string;
     siteUrl: string;
-    token: string;
-    privateToken: string;
     siteInfo: SiteInfo;
 };
 
+/** What is persisted of the active session; the handle is per run. */
+export type StoredSession = Omit<Session, 'handle'>;
+
 /** A stored account entry for the account switcher. */
 export type StoredAccount = {
     /** Unique key: `userid@siteurl` */
     id: string;
     siteUrl: string;
-    token: string;
-    privateToken: string;
     fullname: string;
     username: string;
     userpictureurl: string;
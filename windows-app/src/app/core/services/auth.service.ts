@@ -1,21 +1,23 @@
 import { Injectable, signal, computed } from '@angular/core';
 import { Router } from '@angular/router';
 
-import { MoodleApiService } from './moodle-api.service';
+import { MoodleApiService, toError } from './moodle-api.service';
 import { StorageService } from './storage.service';
-import type { AuthToken, Session, SiteInfo, StoredAccount } from '../models/user.model';
+import type {
+    LoginResult, Session, SessionInfo, SiteInfo, StoredAccount, StoredSession,
+} from '../models/user.model';
 
 const SESSION_KEY = 'moodle_session';
-const ACCOUNTS_KEY = 'moodle_accounts';
 
 /**
  * Handles authentication against a Moodle site.
  *
  * Flow:
  * 1. User provides site URL + credentials
- * 2. Service calls `login/token.php` to obtain a token
- * 3. Token is used to fetch `core_webservice_get_site_info`
- * 4. Session (URL + token + siteInfo) is persisted via StorageService
+ * 2. The backend (`moodle_login`) obtains the token and fetches the site info
+ * 3. The token is sealed into the backend vault; the webview only gets a
+ *    session handle
+ * 4. Session (URL + siteInfo) is persisted via StorageService
  */
 @Injectable({ providedIn: 'root' })
 export class AuthService {
@@ -37,7 +39,7 @@ export class AuthService {
     /** The connected site name. */
     readonly siteName = computed(() => this.sessionSignal()?.siteInfo.sitename ?? '');
 
-    /** All stored accounts for the account switcher. */
+    /** All accounts in the backend vault, for the account switcher. */
     readonly storedAccounts = signal<StoredAccount[]>([]);
 
     /** The active account ID (`userid@siteurl`). */
@@ -55,16 +57,20 @@ export class AuthService {
     /** Restores a previously stored session (call once at app start). */
     async restoreSession(): Promise<boolean> {
         // Load stored accounts list
-        const accounts = await this.storage.get<StoredAccount[]>(ACCOUNTS_KEY);
-        this.storedAccounts.set(accounts ?? []);
+        await this.loadAccounts();
 
-        const stored = await this.storage.get<Session>(SESSION_KEY);
+        const stored = await this.storage.get<StoredSession>(SESSION_KEY);
         if (!stored) {
             return false;
         }
-        this.sessionSignal.set(stored);
-        this.api.configure(stored.siteUrl, stored.token);
-        return true;
+        try {
+            const info = await this.openVaultSession(accountId(stored));
+            await this.activate({ ...stored, handle: info.handle });
+            return true;
+        } catch {
+            // Vault locked or account gone – log in again
+            return false;
+        }
     }
 
     /**
@@ -77,28 +83,25 @@ export class AuthService {
      * @throws Error with Moodle error message on failure
      */
     async login(siteUrl: string, username: string, password: string): Promise<Session> {
-        const normalised = this.normaliseSiteUrl(siteUrl);
+        const { invoke } = await import('@tauri-apps/api/core' as string);
 
-        // 1. Obtain token
-        const tokenResult = await this.fetchToken(normalised, username, password);
-
-        // 2. Configure API with new token
-        this.api.configure(normalised, tokenResult.token);
-
-        // 3. Fetch site info
-        const siteInfo = await this.api.call<SiteInfo>('core_webservice_get_site_info');
+        // 1. Obtain token and site info; the token stays in the backend
+        let result: LoginResult;
+        try {
+            result = await invoke('moodle_login', { siteUrl, username, password }) as LoginResult;
+        } catch (err) {
+            throw loginError(err);
+        }
 
-        // 4. Build & store session
+        // 2. Build & store session
         const session: Session = {
-            siteUrl: normalised,
-            token: tokenResult.token,
-            privateToken: tokenResult.privateToken,
-            siteInfo,
+            handle: result.session.handle,
+            siteUrl: result.session.siteUrl,
+            siteInfo: result.siteInfo,
         };
-        this.sessionSignal.set(session);
-        await this.storage.set(SESSION_KEY, session);
+        await this.activate(session);
 
-        // Save to accounts list
+        // 3. Seal the token into the vault and save to accounts list
         await this.upsertAccount(session);
 
         return session;
@@ -106,55 +109,47 @@ export class AuthService {
 
     /** Switches to a previously stored account. */
     async switchAccount(account: StoredAccount): Promise<void> {
+        const info = await this.openVaultSession(account.id);
         const session: Session = {
+            handle: info.handle,
             siteUrl: account.siteUrl,
-            token: account.token,
-            privateToken: account.privateToken,
-            siteInfo: {
-                sitename: account.sitename,
-                username: account.username,
-                firstname: account.fullname.split(' ')[0] ?? '',
-                lastname: account.fullname.split(' ').slice(1).join(' ') ?? '',
-                fullname: account.fullname,
-                lang: 'de',
-                userid: account.userid,
-                siteurl: account.siteUrl,
-                userpictureurl: account.userpictureurl,
-                email: '',
-                release: '',
-                version: '',
-                functions: [],
-            },
+            siteInfo: storedSiteInfo(account),
         };
 
-        this.sessionSignal.set(session);
-        this.api.configure(session.siteUrl, session.token);
-        await this.storage.set(SESSION_KEY, session);
+        await this.activate(session);
 
         // Refresh site info in background to get fresh data
         try {
             const freshInfo = await this.api.call<SiteInfo>('core_webservice_get_site_info');
             session.siteInfo = freshInfo;
-            this.sessionSignal.set({ ...session });
-            await this.storage.set(SESSION_KEY, session);
+            await this.activate({ ...session });
             await this.upsertAccount(session);
         } catch {
             // Use cached data if network unavailable
         }
     }
 
-    /** Removes a stored account. */
+    /** Removes a stored account together with its sealed token. */
     async removeAccount(accountId: string): Promise<void> {
-        const accounts = this.storedAccounts().filter((a) => a.id !== accountId);
-        this.storedAccounts.set(accounts);
-        await this.storage.set(ACCOUNTS_KEY, accounts);
+        const { invoke } = await import('@tauri-apps/api/core' as string);
+        try {
+            await invoke('vault_remove_account', { id: accountId });
+        } catch (err) {
+            throw toError(err);
+        }
+        await this.loadAccounts();
     }
 
     /** Logs out and clears stored session (account stays in list for switching). */
     async logout(): Promise<void> {
+        const session = this.sessionSignal();
         this.sessionSignal.set(null);
         await this.storage.remove(SESSION_KEY);
-        this.api.configure('', '');
+        this.api.configure(null);
+        if (session) {
+            const { invoke } = await import('@tauri-apps/api/core' as string);
+            await invoke('session_close', { session: session.handle }).catch(() => undefined);
+        }
         await this.router.navigate(['/login']);
     }
 
@@ -167,14 +162,39 @@ export class AuthService {
         await this.logout();
     }
 
-    /** Adds or updates an account in the stored accounts list. */
+    /** Makes `session` the active one and persists it (without the handle). */
+    private async activate(session: Session): Promise<void> {
+        this.sessionSignal.set(session);
+        this.api.configure({ handle: session.handle, siteUrl: session.siteUrl });
+        const stored: StoredSession = { siteUrl: session.siteUrl, siteInfo: session.siteInfo };
+        await this.storage.set(SESSION_KEY, stored);
+    }
+
+    /** Opens a backend session for an account stored in the vault. */
+    private async openVaultSession(id: string): Promise<SessionInfo> {
+        const { invoke } = await import('@tauri-apps/api/core' as string);
+        try {
+            return await invoke('vault_open_session', { id }) as SessionInfo;
+        } catch (err) {
+            throw toError(err);
+        }
+    }
+
+    /** Reloads the account list from the vault (empty while it is locked). */
+    private async loadAccounts(): Promise<void> {
+        const { invoke } = await import('@tauri-apps/api/core' as string);
+        const accounts = await invoke('vault_list_accounts').catch(() => []) as StoredAccount[];
+        this.storedAccounts.set(accounts);
+    }
+
+    /**
+     * Adds or updates the session's account in the vault. The backend takes
+     * the id, site and token from the session itself.
+     */
     private async upsertAccount(session: Session): Promise<void> {
-        const id = `${session.siteInfo.userid}@${session.siteUrl}`;
         const account: StoredAccount = {
-            id,
+            id: accountId(session),
             siteUrl: session.siteUrl,
-            token: session.token,
-            privateToken: session.privateToken,
             fullname: session.siteInfo.fullname,
             username: session.siteInfo.username,
             userpictureurl: session.siteInfo.userpictureurl,
@@ -182,92 +202,72 @@ export class AuthService {
             userid: session.siteInfo.userid,
         };
 
-        const existing = this.storedAccounts();
-        const idx = existing.findIndex((a) => a.id === id);
-        const updated = [...existing];
-        if (idx >= 0) {
-            updated[idx] = account;
-        } else {
-            updated.push(account);
-        }
-        this.storedAccounts.set(updated);
-        await this.storage.set(ACCOUNTS_KEY, updated);
-    }
-
-    /**
-     * Fetches a token from login/token.php.
-     *
-     * Uses POST to prevent credentials leaking into URL/logs/history.
-     */
-    private async fetchToken(siteUrl: string, username: string, password: string): Promise<AuthToken> {
-        const url = `${siteUrl}/login/token.php`;
-        const body = new URLSearchParams({
-            username,
-            password,
-            service: 'moodle_mobile_app',
-        });
-
-        const response = await fetch(url, {
-            method: 'POST',
-            headers: { 'Content-Type': 'application/x-www-form-urlencoded' },
-            body: body.toString(),
-        });
-
-        if (!response.ok) {
-            throw new Error(`HTTP ${response.status}: Verbindung fehlgeschlagen`);
-        }
-
-        const data = await response.json() as Record<string, unknown>;
-
-        if (data['error']) {
-            throw new Error(data['error'] as string);
-        }
-
-        return {
-            token: data['token'] as string,
-            privateToken: (data['privatetoken'] as string) ?? '',
-        };
-    }
-
-    /**
-     * Normalises and validates a site URL.
-     *
-     * - Strips trailing slashes
-     * - Enforces HTTPS (rejects plain HTTP)
-     * - Validates URL structure to prevent SSRF / injection
-     */
-    private normaliseSiteUrl(url: string): string {
-        let cleaned = url.trim().replace(/\/+$/, '');
-
-        if (!/^https?:\/\//i.test(cleaned)) {
-            cleaned = `https://${cleaned}`;
-        }
-
-        // Validate URL structure
-        let parsed: URL;
+        const { invoke } = await import('@tauri-apps/api/core' as string);
         try {
-            parsed = new URL(cleaned);
-        } catch {
-            throw new Error('Ungültige URL. Bitte eine gültige Moodle-Adresse eingeben.');
-        }
-
-        // Enforce HTTPS in production
-        if (parsed.protocol !== 'https:') {
-            throw new Error('Nur HTTPS-Verbindungen sind erlaubt.');
+            await invoke('vault_add_account', { session: session.handle, account });
+        } catch (err) {
+            throw toError(err);
         }
+        await this.loadAccounts();
+    }
+}
 
-        // Reject localhost, private IPs, and non-standard ports to prevent SSRF
-        const hostname = parsed.hostname.toLowerCase();
-        if (hostname === 'localhost' || hostname === '127.0.0.1' || hostname === '::1') {
-            throw new Error('Lokale Adressen sind nicht erlaubt.');
-        }
+/** Vault account id of a session: `userid@siteurl`. */
+function accountId(session: StoredSession): string {
+    return `${session.siteInfo.userid}@${session.siteUrl}`;
+}
 
-        // Block private IP ranges (10.x, 172.16-31.x, 192.168.x)
-        if (/^(10\.|172\.(1[6-9]|2\d|3[01])\.|192\.168\.)/.test(hostname)) {
-            throw new Error('Private Netzwerkadressen sind nicht erlaubt.');
-        }
+/** Site info known from a stored account, until the site is asked again. */
+function storedSiteInfo(account: StoredAccount): SiteInfo {
+    return {
+        sitename: account.sitename,
+        username: account.username,
+        firstname: account.fullname.split(' ')[0] ?? '',
+        lastname: account.fullname.split(' ').slice(1).join(' ') ?? '',
+        fullname: account.fullname,
+        lang: 'de',
+        userid: account.userid,
+        siteurl: account.siteUrl,
+        userpictureurl: account.userpictureurl,
+        functions: [],
+        downloadfiles: null,
+        uploadfiles: null,
+        release: null,
+        version: null,
+        mobilecssurl: null,
+        advancedfeatures: [],
+        usercanmanageownfiles: null,
+        userquota: null,
+        usermaxuploadfilesize: null,
+        userhomepage: null,
+        userprivateaccesskey: null,
+        siteid: null,
+        sitecalendartype: null,
+        usercalendartype: null,
+        userissiteadmin: null,
+        theme: null,
+        limitconcurrentlogins: null,
+        policyagreed: null,
+    };
+}
 
-        // Return origin + pathname only (strip query/hash/credentials)
-        return `${parsed.origin}${parsed.pathname}`.replace(/\/+$/, '');
+/** Maps a backend login error (`{ kind, ... }`) to a user-facing message. */
+function loginError(err: unknown): Error {
+    const kind = typeof err === 'object' && err !== null
+        ? (err as Record<string, unknown>)['kind']
+        : undefined;
+    switch (kind) {
+        case 'invalidCredentials':
+            return new Error('Benutzername oder Passwort ist falsch.');
+        case 'mobileServiceDisabled':
+            return new Error('Die Moodle-App-Schnittstelle ist auf dieser Seite nicht aktiviert.');
+        case 'siteMaintenance':
+            return new Error('Die Seite befindet sich im Wartungsmodus.');
+        case 'notMoodle':
+            return new Error('Unter dieser Adresse wurde keine Moodle-Seite gefunden.');
+        case 'site':
+            return new Error('Ungültige oder nicht erlaubte Adresse. Bitte eine gültige Moodle-Adresse eingeben.');
+        default:
+            return toError(err);
     }
 }
@@ -1,7 +1,7 @@
 import { Injectable, signal } from '@angular/core';
 
 import { MoodleApiService } from './moodle-api.service';
-import type { CalendarEvent, CalendarEventType } from '../models/calendar-event.model';
+import type { CalendarEvent, CalendarEvents } from '../models/calendar-event.model';
 
 /**
  * Service for Moodle calendar events.
@@ -22,12 +22,12 @@ export class CalendarService {
         this.loading.set(true);
         try {
             const now = Math.floor(Date.now() / 1000);
-            const result = await this.api.call<RawActionEventsResponse>(
+            const result = await this.api.call<CalendarEvents>(
                 'core_calendar_get_action_events_by_timesort',
                 { timesortfrom: now, limitnum: limit },
             );
 
-            this.events.set((result.events ?? []).map((e) => this.mapEvent(e)));
+            this.events.set(result.events ?? []);
         } finally {
             this.loading.set(false);
         }
@@ -35,7 +35,7 @@ export class CalendarService {
 
     /** Loads events for a specific month. */
     async loadMonthEvents(year: number, month: number): Promise<CalendarEvent[]> {
-        const result = await this.api.call<RawMonthViewResponse>(
+        const result = await this.api.call<MonthView>(
             'core_calendar_get_calendar_monthly_view',
             { year, month },
         );
@@ -43,51 +43,14 @@ export class CalendarService {
         const events: CalendarEvent[] = [];
         for (const week of result.weeks ?? []) {
             for (const day of week.days ?? []) {
-                for (const event of day.events ?? []) {
-                    events.push(this.mapEvent(event));
-                }
+                events.push(...(day.events ?? []));
             }
         }
         return events;
     }
-
-    private mapEvent(e: RawEvent): CalendarEvent {
-        return {
-            id: e.id,
-            name: e.name,
-            description: e.description ?? '',
-            eventType: (e.eventtype ?? 'course') as CalendarEventType,
-            courseId: e.course?.id ?? 0,
-            courseName: e.course?.fullname ?? '',
-            timeStart: e.timestart,
-            timeDuration: e.timeduration ?? 0,
-            moduleName: e.modulename ?? '',
-            url: e.url ?? '',
-            isActionEvent: !!e.action,
-            actionUrl: e.action?.url ?? '',
-            actionName: e.action?.name ?? '',
-        };
-    }
 }
 
-// Raw Moodle response types
-type RawActionEventsResponse = {
-    events: RawEvent[];
-};
-
-type RawMonthViewResponse = {
-    weeks: { days: { events: RawEvent[] }[] }[];
-};
-
-type RawEvent = {
-    id: number;
-    name: string;
-    description?: string;
-    eventtype?: string;
-    course?: { id: number; fullname: string };
-    timestart: number;
-    timeduration?: number;
-    modulename?: string;
-    url?: string;
-    action?: { url: string; name: string };
+/** The part of core_calendar_get_calendar_monthly_view the calendar reads. */
+type MonthView = {
+    weeks: { days: { events: CalendarEvent[] }[] }[];
 };
@@ -1,7 +1,8 @@
 import { Injectable, signal } from '@angular/core';
 
 import { MoodleApiService } from './moodle-api.service';
-import type { Course, CourseSection } from '../models/course.model';
+import type { Course, CourseSection, MoodleFile } from '../models/course.model';
+import type { CourseSection as MoodleCourseSection } from '../models/moodle.generated';
 
 /**
  * Service for fetching course data from Moodle.
@@ -20,31 +21,16 @@ export class CourseService {
     async loadCourses(userId: number): Promise<void> {
         this.loading.set(true);
         try {
-            const raw = await this.api.call<RawCourse[]>(
+            const raw = await this.api.call<Course[]>(
                 'core_enrol_get_users_courses',
                 { userid: userId },
             );
 
-            const mapped: Course[] = raw.map((c) => ({
-                id: c.id,
-                shortname: c.shortname,
-                fullname: c.fullname,
-                displayname: c.displayname ?? c.fullname,
-                summary: c.summary,
-                summaryformat: c.summaryformat,
-                category: c.category,
-                progress: c.progress ?? null,
-                completed: c.completed ?? false,
-                startdate: c.startdate,
-                enddate: c.enddate,
-                visible: c.visible !== false,
-                courseImage: c.courseimage ?? this.extractImageFromOverviewFiles(c.overviewfiles),
-                isfavourite: c.isfavourite ?? false,
-                enrolledusercount: c.enrolledusercount ?? 0,
-                overviewfiles: (c.overviewfiles ?? []).map((f) => ({
-                    ...f,
-                    mimetype: f.mimetype ?? '',
-                })),
+            // Older sites send no courseimage; fall back to the overview files.
+            const mapped = raw.map((c) => ({
+                ...c,
+                courseimage: c.courseimage
+                    ?? this.extractImageFromOverviewFiles(c.overviewfiles ?? []),
             }));
 
             this.courses.set(mapped);
@@ -55,7 +41,7 @@ export class CourseService {
 
     /** Fetches the sections and modules of a single course. */
     async getCourseContents(courseId: number): Promise<CourseSection[]> {
-        const raw = await this.api.call<RawSection[]>(
+        const raw = await this.api.call<MoodleCourseSection[]>(
             'core_course_get_contents',
             { courseid: courseId },
         );
@@ -68,7 +54,7 @@ export class CourseService {
             modules: (s.modules ?? []).map((m) => ({
                 id: m.id,
                 name: m.name,
-                instance: m.instance,
+                instance: m.instance ?? 0,
                 modname: m.modname,
                 modicon: m.modicon,
                 description: m.description ?? '',
@@ -77,11 +63,11 @@ export class CourseService {
                 contents: (m.contents ?? []).map((ct) => ({
                     type: ct.type,
                     filename: ct.filename,
-                    filepath: ct.filepath,
+                    filepath: ct.filepath ?? '',
                     filesize: ct.filesize,
-                    fileurl: ct.fileurl,
-                    timecreated: ct.timecreated,
-                    timemodified: ct.timemodified,
+                    fileurl: ct.fileurl ?? '',
+                    timecreated: ct.timecreated ?? 0,
+                    timemodified: ct.timemodified ?? 0,
                     mimetype: ct.mimetype ?? '',
                 })),
             })),
@@ -89,12 +75,9 @@ export class CourseService {
     }
 
     /** Extracts a course image from overviewfiles if available. */
-    private extractImageFromOverviewFiles(files?: RawOverviewFile[]): string {
-        if (!files?.length) {
-            return '';
-        }
+    private extractImageFromOverviewFiles(files: MoodleFile[]): string | null {
         const img = files.find((f) => f.mimetype?.startsWith('image/'));
-        return img?.fileurl ?? '';
+        return img?.fileurl ?? null;
     }
 
     // ---- Course Search & Enrollment ----
@@ -273,65 +256,6 @@ export type CourseCategory = {
     path: string;
 };
 
-// Raw Moodle API response types
-type RawCourse = {
-    id: number;
-    shortname: string;
-    fullname: string;
-    displayname?: string;
-    summary: string;
-    summaryformat: number;
-    category: number;
-    progress?: number;
-    completed?: boolean;
-    startdate: number;
-    enddate: number;
-    visible?: boolean;
-    courseimage?: string;
-    isfavourite?: boolean;
-    enrolledusercount?: number;
-    overviewfiles?: RawOverviewFile[];
-};
-
-type RawOverviewFile = {
-    filename: string;
-    filepath: string;
-    filesize: number;
-    fileurl: string;
-    mimetype?: string;
-};
-
-type RawSection = {
-    id: number;
-    name: string;
-    summary: string;
-    visible: number;
-    modules?: RawModule[];
-};
-
-type RawModule = {
-    id: number;
-    name: string;
-    instance: number;
-    modname: string;
-    modicon: string;
-    description?: string;
-    url?: string;
-    visible: number;
-    contents?: RawContent[];
-};
-
-type RawContent = {
-    type: string;
-    filename: string;
-    filepath: string;
-    filesize: number;
-    fileurl: string;
-    timecreated: number;
-    timemodified: number;
-    mimetype?: string;
-};
-
 // --- Course Search & Enrolment raw types ---
 
 type RawCourseSearchResult = {
@@ -84,10 +84,8 @@ export class DownloadedFilesService {
         const record = await this.getRecord(fileUrl);
         if (!record) return false;
 
-        // Only allow opening files in the Downloads directory
-        if (!this.isInDownloadsDir(record.filePath)) return false;
-
         try {
+            // The backend only opens files inside a configured download root
             const { invoke } = await import('@tauri-apps/api/core' as string);
             await invoke('open_file', { path: record.filePath });
             return true;
@@ -102,14 +100,10 @@ export class DownloadedFilesService {
         const record = await this.getRecord(fileUrl);
         if (!record) return false;
 
-        // Only allow opening folders in the Downloads directory
-        if (!this.isInDownloadsDir(record.filePath)) return false;
-
         try {
-            const { open } = await import('@tauri-apps/plugin-shell' as string);
-            const sep = record.filePath.includes('\\') ? '\\' : '/';
-            const folder = record.filePath.substring(0, record.filePath.lastIndexOf(sep));
-            await open(folder);
+            // The backend only reveals files inside a configured download root
+            const { invoke } = await import('@tauri-apps/api/core' as string);
+            await invoke('reveal_file', { path: record.filePath });
             return true;
         } catch {
             return false;
@@ -132,18 +126,4 @@ export class DownloadedFilesService {
             return false;
         }
     }
-
-    /**
-     * Validates that a file path is inside the user's Downloads directory.
-     * Prevents path traversal attacks via manipulated storage records.
-     */
-    private isInDownloadsDir(filePath: string): boolean {
-        const normalised = filePath.replace(/\\/g, '/').toLowerCase();
-
-        // Reject path traversal sequences
-        if (normalised.includes('..')) return false;
-
-        // Must be in a Downloads-like directory
-        return normalised.includes('/downloads/') || normalised.includes('/download/');
-    }
 }
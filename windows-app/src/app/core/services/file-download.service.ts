@@ -1,13 +1,24 @@
 import { inject, Injectable, signal } from '@angular/core';
 
-import { MoodleApiService } from './moodle-api.service';
+import { MoodleApiService, toError } from './moodle-api.service';
 import { DownloadedFilesService } from './downloaded-files.service';
 
+/** Payload of the backend's `download://progress` event. */
+type BackendProgress = {
+    id: string;
+    status: 'queued' | 'downloading' | 'paused' | 'completed' | 'failed' | 'cancelled';
+    bytesDone: number;
+    totalBytes: number | null;
+    path: string | null;
+    error: string | null;
+};
+
 /**
  * Service for downloading files from Moodle.
  *
- * Uses Tauri's file system plugin for saving files natively,
- * with a browser fallback for development.
+ * Downloads run in the backend download manager, which attaches the token
+ * and saves into the account's or course's download root. Progress arrives
+ * as `download://progress` events.
  * Records successful downloads for later re-opening.
  */
 @Injectable({ providedIn: 'root' })
@@ -18,109 +29,100 @@ export class FileDownloadService {
 
     readonly activeDownloads = signal<DownloadProgress[]>([]);
 
+    /** Downloads waiting for their final event, by backend id. */
+    private readonly pending = new Map<string, { resolve: () => void; reject: (err: Error) => void }>();
+    private listening: Promise<void> | null = null;
+
     /**
-     * Downloads a file from Moodle and saves it to the user's Downloads folder.
+     * Downloads a file from Moodle into the download root and resolves once
+     * it is saved.
      *
      * @param fileUrl    Raw file URL from Moodle
      * @param filename   Desired filename
      * @param metadata   Optional course/module info for tracking
      */
     async downloadFile(fileUrl: string, filename: string, metadata?: DownloadMetadata): Promise<void> {
-        const url = this.api.getFileUrl(fileUrl);
-        const downloadId = crypto.randomUUID();
+        await this.listen();
+        const { invoke } = await import('@tauri-apps/api/core' as string);
+
+        let item: { id: string };
+        try {
+            item = await invoke('download_enqueue', {
+                session: this.api.handle,
+                fileUrl,
+                filename,
+                courseId: metadata?.courseId,
+                moduleId: metadata?.moduleId,
+            }) as { id: string };
+        } catch (err) {
+            throw toError(err);
+        }
 
         this.activeDownloads.update((list) => [
             ...list,
-            { id: downloadId, fileUrl, filename, progress: 0, status: 'downloading' },
+            {
+                id: item.id, fileUrl, filename, progress: 0, status: 'downloading',
+                moduleId: metadata?.moduleId, courseId: metadata?.courseId,
+            },
         ]);
 
-        try {
-            // Try Tauri native download first
-            if (await this.tryTauriDownload(url, filename, downloadId, fileUrl, metadata)) {
-                return;
-            }
-
-            // Browser fallback: trigger download via anchor element
-            await this.browserDownload(url, filename, downloadId);
-        } catch (error) {
-            this.activeDownloads.update((list) =>
-                list.map((d) =>
-                    d.id === downloadId ? { ...d, status: 'error' as const } : d,
-                ),
-            );
-            throw error;
-        }
+        await new Promise<void>((resolve, reject) => {
+            this.pending.set(item.id, { resolve, reject });
+        });
     }
 
-    /** Attempts to download using Tauri's FS plugin. */
-    private async tryTauriDownload(
-        url: string, filename: string, downloadId: string,
-        originalFileUrl: string, metadata?: DownloadMetadata,
-    ): Promise<boolean> {
-        try {
-            const { download } = await import('@tauri-apps/plugin-upload' as string);
-            const { downloadDir, join } = await import('@tauri-apps/api/path' as string);
-
-            const downloadPath = await downloadDir();
-            const filePath = await join(downloadPath, filename);
-
-            await download(url, filePath, (progress: number, total: number) => {
-                const pct = total > 0
-                    ? Math.round((progress / total) * 100)
-                    : 0;
-                this.activeDownloads.update((list) =>
-                    list.map((d) =>
-                        d.id === downloadId ? { ...d, progress: pct } : d,
-                    ),
-                );
+    /** Subscribes to backend progress events once. */
+    private listen(): Promise<void> {
+        this.listening ??= (async () => {
+            const { listen } = await import('@tauri-apps/api/event' as string);
+            await listen('download://progress', (event: { payload: BackendProgress }) => {
+                this.onProgress(event.payload);
             });
+        })();
+        return this.listening;
+    }
 
-            this.activeDownloads.update((list) =>
-                list.map((d) =>
-                    d.id === downloadId ? { ...d, progress: 100, status: 'complete' as const } : d,
-                ),
-            );
+    private onProgress(event: BackendProgress): void {
+        const download = this.activeDownloads().find((d) => d.id === event.id);
+        if (!download) return;
+
+        const pct = event.totalBytes
+            ? Math.round((event.bytesDone / event.totalBytes) * 100)
+            : 0;
+        const status = event.status === 'completed'
+            ? 'complete' as const
+            : event.status === 'failed' || event.status === 'cancelled'
+                ? 'error' as const
+                : 'downloading' as const;
+        this.activeDownloads.update((list) =>
+            list.map((d) =>
+                d.id === event.id
+                    ? { ...d, progress: status === 'complete' ? 100 : pct, status }
+                    : d,
+            ),
+        );
+        if (status === 'downloading') return;
 
-            this.scheduleRemove(downloadId);
+        this.scheduleRemove(event.id);
+        const waiter = this.pending.get(event.id);
+        this.pending.delete(event.id);
 
+        if (status === 'complete' && event.path) {
             // Record the download for later re-opening
             void this.downloadedFiles.recordDownload({
-                fileUrl: originalFileUrl,
-                filePath,
-                filename,
-                moduleId: metadata?.moduleId,
-                courseId: metadata?.courseId,
+                fileUrl: download.fileUrl,
+                filePath: event.path,
+                filename: download.filename,
+                moduleId: download.moduleId,
+                courseId: download.courseId,
                 downloadedAt: Date.now(),
             });
-
-            return true;
-        } catch {
-            return false;
+            waiter?.resolve();
+        } else {
+            waiter?.reject(new Error(event.error ?? 'Download abgebrochen.'));
         }
     }
 
-    /** Browser fallback download via hidden anchor. */
-    private async browserDownload(url: string, filename: string, downloadId: string): Promise<void> {
-        const response = await fetch(url);
-        const blob = await response.blob();
-        const objectUrl = URL.createObjectURL(blob);
-
-        const anchor = document.createElement('a');
-        anchor.href = objectUrl;
-        anchor.download = filename;
-        anchor.click();
-
-        URL.revokeObjectURL(objectUrl);
-
-        this.activeDownloads.update((list) =>
-            list.map((d) =>
-                d.id === downloadId ? { ...d, progress: 100, status: 'complete' as const } : d,
-            ),
-        );
-
-        this.scheduleRemove(downloadId);
-    }
-
     /** Removes a completed/errored download from the active list after a delay. */
     private scheduleRemove(downloadId: string): void {
         setTimeout(() => {
@@ -135,6 +137,8 @@ export type DownloadProgress = {
     filename: string;
     progress: number;
     status: 'downloading' | 'complete' | 'error';
+    moduleId?: number;
+    courseId?: number;
 };
 
 export type DownloadMetadata = {
@@ -1,6 +1,6 @@
 import { Injectable } from '@angular/core';
 
-import { MoodleApiService } from './moodle-api.service';
+import { MoodleApiService, toError } from './moodle-api.service';
 
 /** Metadata returned after uploading a file. */
 export type UploadedFile = {
@@ -31,37 +31,28 @@ export class FileUploadService {
     constructor(private readonly api: MoodleApiService) {}
 
     /**
-     * Uploads a file to the user's draft area using XMLHttpRequest
-     * so we can send actual FormData (fetch doesn't give us upload progress).
+     * Uploads a file to the user's draft area through the backend, which
+     * attaches the token. The file travels as the raw IPC body.
      *
      * @param file The File object from an <input> or drag-and-drop.
      * @param itemId Optional existing draft item ID to append to.
      * @returns Uploaded file info including the item ID.
      */
     async uploadToDraftArea(file: File, itemId = 0): Promise<UploadedFile[]> {
-        const session = this.api.getSession();
-        if (!session) throw new Error('Not authenticated');
+        const { invoke } = await import('@tauri-apps/api/core' as string);
+        const body = new Uint8Array(await file.arrayBuffer());
 
-        const url = `${session.siteUrl}/webservice/upload.php`;
-
-        const formData = new FormData();
-        formData.append('token', session.token);
-        formData.append('file_1', file, file.name);
-        formData.append('itemid', String(itemId));
-        formData.append('filearea', 'draft');
-
-        const response = await fetch(url, {
-            method: 'POST',
-            body: formData,
-        });
-
-        const result = await response.json() as UploadedFile[] | { error: string; errorcode: string };
-
-        if (!Array.isArray(result)) {
-            throw new Error((result as { error: string }).error ?? 'Upload failed');
+        try {
+            return await invoke('upload_draft_file', body, {
+                headers: {
+                    'x-session': this.api.handle,
+                    'x-filename': encodeURIComponent(file.name),
+                    'x-itemid': String(itemId),
+                },
+            }) as UploadedFile[];
+        } catch (err) {
+            throw toError(err);
         }
-
-        return result;
     }
 
     /**
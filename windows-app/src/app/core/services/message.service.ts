@@ -1,8 +1,9 @@
 import { Injectable, signal } from '@angular/core';
 
 import { MoodleApiService } from './moodle-api.service';
-import type { Conversation, Message, ConversationMember } from '../models/message.model';
-import { ConversationType } from '../models/message.model';
+import type {
+    Conversation, ConversationMember, Conversations, Message,
+} from '../models/message.model';
 
 /**
  * Service for Moodle messaging.
@@ -22,7 +23,7 @@ export class MessageService {
     async loadConversations(userId: number): Promise<void> {
         this.loading.set(true);
         try {
-            const result = await this.api.call<RawConversationsResponse>(
+            const result = await this.api.call<Conversations>(
                 'core_message_get_conversations',
                 {
                     userid: userId,
@@ -32,31 +33,12 @@ export class MessageService {
             );
 
             const mapped: Conversation[] = (result.conversations ?? []).map((c) => ({
-                id: c.id,
-                name: c.name ?? this.buildConversationName(c.members ?? []),
-                type: c.type as ConversationType,
-                memberCount: c.membercount,
-                unreadCount: c.unreadcount ?? 0,
-                imageUrl: c.imageurl ?? null,
-                isMuted: c.ismuted ?? false,
-                isFavourite: c.isfavourite ?? false,
-                members: (c.members ?? []).map((m) => ({
-                    id: m.id,
-                    fullname: m.fullname,
-                    profileImageUrl: m.profileimageurl ?? '',
-                    isOnline: m.isonline ?? false,
-                })),
-                messages: (c.messages ?? []).map((msg) => ({
-                    id: msg.id,
-                    userId: msg.useridfrom,
-                    text: msg.text,
-                    timeCreated: msg.timecreated,
-                    isRead: msg.isread,
-                })),
+                ...c,
+                name: c.name || this.buildConversationName(c.members ?? []),
             }));
 
             this.conversations.set(mapped);
-            this.totalUnread.set(mapped.reduce((sum, c) => sum + c.unreadCount, 0));
+            this.totalUnread.set(mapped.reduce((sum, c) => sum + (c.unreadcount ?? 0), 0));
         } finally {
             this.loading.set(false);
         }
@@ -72,7 +54,7 @@ export class MessageService {
 
     /** Fetches messages for a specific conversation. */
     async getMessages(conversationId: number, userId: number): Promise<Message[]> {
-        const result = await this.api.call<RawMessagesResponse>(
+        const result = await this.api.call<{ messages: Message[] }>(
             'core_message_get_conversation_messages',
             {
                 currentuserid: userId,
@@ -82,13 +64,7 @@ export class MessageService {
             },
         );
 
-        return (result.messages ?? []).map((msg) => ({
-            id: msg.id,
-            userId: msg.useridfrom,
-            text: msg.text,
-            timeCreated: msg.timecreated,
-            isRead: msg.isread,
-        }));
+        return result.messages ?? [];
     }
 
     /**
@@ -162,48 +138,11 @@ export class MessageService {
     }
 
     /** Builds a conversation name from member names (for individual chats). */
-    private buildConversationName(members: RawMember[]): string {
+    private buildConversationName(members: ConversationMember[]): string {
         return members.map((m) => m.fullname).join(', ') || 'Conversation';
     }
 }
 
-// Raw Moodle response types
-type RawConversationsResponse = {
-    conversations: RawConversation[];
-};
-
-type RawConversation = {
-    id: number;
-    name?: string;
-    type: number;
-    membercount: number;
-    unreadcount?: number;
-    imageurl?: string;
-    ismuted?: boolean;
-    isfavourite?: boolean;
-    members?: RawMember[];
-    messages?: RawMessage[];
-};
-
-type RawMember = {
-    id: number;
-    fullname: string;
-    profileimageurl?: string;
-    isonline?: boolean;
-};
-
-type RawMessage = {
-    id: number;
-    useridfrom: number;
-    text: string;
-    timecreated: number;
-    isread: boolean;
-};
-
-type RawMessagesResponse = {
-    messages: RawMessage[];
-};
-
 /** User search result (exposed to components). */
 export type UserSearchResult = {
     id: number;
@@ -211,6 +150,7 @@ export type UserSearchResult = {
     profileImageUrl: string;
 };
 
+// Raw Moodle response types
 type RawUserSearchResponse = RawSearchUser[];
 
 type RawSearchUser = {
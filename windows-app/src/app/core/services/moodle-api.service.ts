@@ -2,11 +2,27 @@ import { inject, Injectable } from '@angular/core';
 
 import { OfflineCacheService } from './offline-cache.service';
 
+/** Base URL of the `moodle-file` protocol, see `pluginfile::scheme_base`. */
+const FILE_SCHEME_BASE = navigator.userAgent.includes('Windows')
+    ? 'http://moodle-file.localhost'
+    : 'moodle-file://localhost';
+
+/** Site-relative paths the `moodle-file` protocol serves. */
+const PLUGINFILE_PATH = /^\/(?:webservice\/)?pluginfile\.php\//;
+
+/** The backend session the API runs on. */
+export type ApiSession = {
+    /** Opaque handle; the token itself never leaves the backend. */
+    handle: string;
+    siteUrl: string;
+};
+
 /**
  * Low-level service for calling Moodle Web Service functions.
  *
- * The service is configured with a site URL and token by AuthService
- * after login. All other services use this to communicate with Moodle.
+ * The service is configured with a backend session handle by AuthService
+ * after login. All other services use this to communicate with Moodle;
+ * requests go through the native client, which attaches the token.
  *
  * Integrates with OfflineCacheService for transparent offline support:
  * - Online: calls API, caches the result.
@@ -17,13 +33,19 @@ export class MoodleApiService {
 
     private readonly offlineCache = inject(OfflineCacheService);
 
-    private siteUrl = '';
-    private token = '';
+    private session: ApiSession | null = null;
 
-    /** Configures the API endpoint. Called by AuthService. */
-    configure(siteUrl: string, token: string): void {
-        this.siteUrl = siteUrl;
-        this.token = token;
+    /** Configures the backend session to use. Called by AuthService. */
+    configure(session: ApiSession | null): void {
+        this.session = session;
+    }
+
+    /** Handle of the current backend session, for commands taking one. */
+    get handle(): string {
+        if (!this.session) {
+            throw new Error('API not configured. Call AuthService.login() first.');
+        }
+        return this.session.handle;
     }
 
     /**
@@ -45,9 +67,7 @@ export class MoodleApiService {
         params: Record<string, unknown> = {},
         options?: { skipCache?: boolean },
     ): Promise<T> {
-        if (!this.siteUrl || !this.token) {
-            throw new Error('API not configured. Call AuthService.login() first.');
-        }
+        const handle = this.handle;
 
         // If offline, try cache immediately
         if (!navigator.onLine) {
@@ -63,7 +83,7 @@ export class MoodleApiService {
         }
 
         try {
-            const data = await this.fetchFromNetwork<T>(wsFunction, params);
+            const data = await this.fetchFromNetwork<T>(handle, wsFunction, params);
 
             // Cache the successful response (fire-and-forget)
             void this.offlineCache.put(wsFunction, params, data);
@@ -77,138 +97,111 @@ export class MoodleApiService {
         }
     }
 
-    /** Performs the actual network request to Moodle WS. */
-    private async fetchFromNetwork<T>(wsFunction: string, params: Record<string, unknown>): Promise<T> {
-
-        const url = `${this.siteUrl}/webservice/rest/server.php`;
-        const body = new URLSearchParams({
-            wstoken: this.token,
-            wsfunction: wsFunction,
-            moodlewsrestformat: 'json',
-        });
-
-        // Flatten nested params for Moodle WS format
-        this.flattenParams(params, body);
-
-        const response = await fetch(url, {
-            method: 'POST',
-            headers: { 'Content-Type': 'application/x-www-form-urlencoded' },
-            body: body.toString(),
-        });
-
-        if (!response.ok) {
-            throw new Error(`HTTP ${response.status}: ${response.statusText}`);
-        }
-
-        const data = await response.json() as Record<string, unknown>;
-
-        // Moodle WS error format
-        if (data['exception'] || data['errorcode']) {
-            const msg = (data['message'] as string) ?? (data['error'] as string) ?? 'Unknown Moodle error';
-            throw new Error(`Moodle WS Error [${data['errorcode']}]: ${msg}`);
+    /** Performs the actual request through the backend REST client. */
+    private async fetchFromNetwork<T>(
+        handle: string,
+        wsFunction: string,
+        params: Record<string, unknown>,
+    ): Promise<T> {
+        const { invoke } = await import('@tauri-apps/api/core' as string);
+        try {
+            return await invoke('moodle_call', { session: handle, wsfunction: wsFunction, params }) as T;
+        } catch (err) {
+            throw toError(err);
         }
-
-        return data as T;
     }
 
     /**
-     * Builds a URL for downloading a file from Moodle with the current token appended.
+     * Builds a URL the webview can load a Moodle file from.
      *
-     * Only appends the token if the file URL belongs to the configured site
-     * to prevent token leakage to third-party domains.
+     * Pluginfile URLs of the current site are mapped to the `moodle-file`
+     * protocol, which attaches the token in the backend. Other URLs are
+     * returned unchanged.
      *
      * @param fileUrl  The raw file URL from a Moodle WS response
-     * @returns URL with token query parameter
+     * @returns Protocol URL, or the input for external files
      */
     getFileUrl(fileUrl: string): string {
-        if (!fileUrl) {
-            return '';
-        }
-
-        // Only attach token to URLs on the configured Moodle site
-        try {
-            const fileOrigin = new URL(fileUrl).origin;
-            const siteOrigin = new URL(this.siteUrl).origin;
-            if (fileOrigin !== siteOrigin) {
-                return fileUrl; // External URL – do not leak token
-            }
-        } catch {
-            return fileUrl;
+        if (!fileUrl || !this.session) {
+            return fileUrl ?? '';
         }
-
-        const separator = fileUrl.includes('?') ? '&' : '?';
-        return `${fileUrl}${separator}token=${this.token}`;
+        return this.toProtocolUrl(fileUrl) ?? fileUrl;
     }
 
     /**
-     * Rewrites pluginfile URLs in HTML content to include the WS token.
+     * Rewrites pluginfile URLs in HTML content to `moodle-file` protocol URLs.
      *
      * Moodle's `external_format_text()` generates `webservice/pluginfile.php` URLs
-     * in HTML content, but those URLs require the WS token as a query parameter
-     * for authentication. This method appends the token to all such URLs while
-     * leaving `tokenpluginfile.php` URLs untouched (they already carry embedded auth).
+     * in HTML content, which need the WS token. The protocol URLs carry the
+     * session handle instead; the backend attaches the token. Existing
+     * `token=` parameters are dropped, and `tokenpluginfile.php` URLs are left
+     * untouched (they already carry embedded auth). Mirrors
+     * `pluginfile::rewrite_html`.
      *
      * Also replaces any remaining `@@PLUGINFILE@@` placeholders as a safety net
      * for older Moodle versions.
      *
      * @param html  Raw HTML string from the Moodle WS response
-     * @returns HTML with authenticated pluginfile URLs
+     * @returns HTML with protocol URLs
      */
     rewritePluginfileUrls(html: string): string {
-        if (!html || !this.siteUrl || !this.token) return html;
+        if (!html || !this.session) return html;
+        const siteUrl = this.session.siteUrl;
 
         // Safety net: replace @@PLUGINFILE@@ placeholders (older Moodle)
         if (html.includes('@@PLUGINFILE@@')) {
             html = html.replace(
                 /@@PLUGINFILE@@/g,
-                `${this.siteUrl}/webservice/pluginfile.php`,
+                `${siteUrl}/webservice/pluginfile.php`,
             );
         }
 
-        // Append token to pluginfile.php URLs from this Moodle site.
         // Matches /pluginfile.php/ and /webservice/pluginfile.php/ but
         // NOT /tokenpluginfile.php/ (which carries auth in the URL path).
-        const escapedSite = this.siteUrl.replace(/[.*+?^${}()|[\]\\]/g, '\\$&');
+        const escapedSite = siteUrl.replace(/[.*+?^${}()|[\]\\]/g, '\\$&');
         const re = new RegExp(
-            `(${escapedSite}/(?:webservice/)?pluginfile\\.php/[^"'\\s<>]*)`,
+            `${escapedSite}/(?:webservice/)?pluginfile\\.php/[^"'\\s<>]*`,
             'gi',
         );
 
-        return html.replace(re, (url) => {
-            if (url.includes('token=')) return url;
-            const sep = url.includes('?') ? '&' : '?';
-            return `${url}${sep}token=${this.token}`;
-        });
+        return html.replace(re, (url) => this.toProtocolUrl(url, '&amp;') ?? url);
     }
 
-    /** Returns the current session info for direct upload endpoints. */
-    getSession(): { siteUrl: string; token: string } | null {
-        if (!this.siteUrl || !this.token) return null;
-        return { siteUrl: this.siteUrl, token: this.token };
+    /**
+     * Maps a pluginfile URL of the current site to the `moodle-file`
+     * protocol, without any `token` parameter. `null` for other URLs.
+     */
+    private toProtocolUrl(url: string, separator = '&'): string | null {
+        const session = this.session;
+        if (!session || !url.toLowerCase().startsWith(`${session.siteUrl.toLowerCase()}/`)) {
+            return null;
+        }
+        const relative = url.slice(session.siteUrl.length);
+        const [path, query = ''] = relative.split(/\?(.*)/s);
+        if (!PLUGINFILE_PATH.test(path) || path.includes('..')) {
+            return null;
+        }
+        const pairs = query
+            .split('&')
+            .map((pair) => pair.replace(/^amp;/, ''))
+            .filter((pair) => pair && !pair.startsWith('token='));
+        const rest = pairs.length ? `?${pairs.join(separator)}` : '';
+        return `${FILE_SCHEME_BASE}/${session.handle}${path}${rest}`;
     }
+}
 
-    /** Recursively flattens nested objects/arrays into Moodle WS parameter format. */
-    private flattenParams(
-        params: Record<string, unknown>,
-        target: URLSearchParams,
-        prefix = '',
-    ): void {
-        for (const [key, value] of Object.entries(params)) {
-            const fullKey = prefix ? `${prefix}[${key}]` : key;
-
-            if (Array.isArray(value)) {
-                value.forEach((item, index) => {
-                    if (typeof item === 'object' && item !== null) {
-                        this.flattenParams(item as Record<string, unknown>, target, `${fullKey}[${index}]`);
-                    } else {
-                        target.append(`${fullKey}[${index}]`, String(item));
-                    }
-                });
-            } else if (typeof value === 'object' && value !== null) {
-                this.flattenParams(value as Record<string, unknown>, target, fullKey);
-            } else {
-                target.append(fullKey, String(value));
-            }
+/** Turns a backend error (`{ kind, ... }`) into an `Error`. */
+export function toError(err: unknown): Error {
+    if (err instanceof Error) return err;
+    if (typeof err === 'object' && err !== null) {
+        const data = err as Record<string, unknown>;
+        if (data['kind'] === 'moodle') {
+            return new Error(`Moodle WS Error [${data['errorcode']}]: ${data['message']}`);
+        }
+        if (data['kind'] === 'http') {
+            return new Error(`HTTP ${data['status']}`);
         }
+        return new Error((data['message'] as string) ?? String(data['kind'] ?? 'Unknown error'));
     }
+    return new Error(String(err));
 }
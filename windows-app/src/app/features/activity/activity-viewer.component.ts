@@ -662,7 +662,7 @@ export class ActivityViewerComponent implements OnInit, OnDestroy {
         this.folderFiles.set(
             mod.contents.map((c) => ({
                 filename: c.filename,
-                fileurl: this.api.getFileUrl(c.fileurl),
+                fileurl: c.fileurl,
                 filesize: c.filesize ?? 0,
             })),
         );
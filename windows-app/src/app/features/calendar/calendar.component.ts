@@ -50,7 +50,7 @@ export class CalendarComponent implements OnInit {
         for (let d = 1; d <= lastDay.getDate(); d++) {
             const date = new Date(year, month, d);
             const dayEvents = this.monthEvents().filter((e) => {
-                const eDate = new Date(e.timeStart * 1000);
+                const eDate = new Date(e.timestart * 1000);
                 return eDate.getDate() === d && eDate.getMonth() === month && eDate.getFullYear() === year;
             });
             days.push({ date, isCurrentMonth: true, events: dayEvents });
@@ -93,14 +93,14 @@ export class CalendarComponent implements OnInit {
     }
 
     formatEventTime(event: CalendarEvent): string {
-        return new Date(event.timeStart * 1000).toLocaleTimeString('de-DE', {
+        return new Date(event.timestart * 1000).toLocaleTimeString('de-DE', {
             hour: '2-digit',
             minute: '2-digit',
         });
     }
 
     formatEventDateTime(event: CalendarEvent): string {
-        return new Date(event.timeStart * 1000).toLocaleDateString('de-DE', {
+        return new Date(event.timestart * 1000).toLocaleDateString('de-DE', {
             weekday: 'short',
             day: 'numeric',
             month: 'short',
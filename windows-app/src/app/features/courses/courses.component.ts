@@ -53,7 +53,7 @@ export class CoursesComponent implements OnInit {
 
     hasCourseImage(course: Course): boolean {
         if (this.brokenImages().has(course.id)) return false;
-        const img = course.courseImage;
+        const img = course.courseimage;
         if (!img) return false;
         // Moodle default course images are not custom uploads
         if (img.includes('/course/generated/') || img.includes('course_defaultimage')) return false;
@@ -61,7 +61,7 @@ export class DashboardComponent implements OnInit {
 
     hasCourseImage(course: Course): boolean {
         if (this.brokenImages().has(course.id)) return false;
-        const img = course.courseImage;
+        const img = course.courseimage;
         if (!img) return false;
         if (img.includes('/course/generated/') || img.includes('course_defaultimage')) return false;
         return true;
@@ -106,7 +106,7 @@ export class DashboardComponent implements OnInit {
     }
 
     formatEventDate(event: CalendarEvent): string {
-        const date = new Date(event.timeStart * 1000);
+        const date = new Date(event.timestart * 1000);
         return date.toLocaleDateString('de-DE', {
             weekday: 'short',
             day: 'numeric',
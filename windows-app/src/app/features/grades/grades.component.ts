@@ -1,5 +1,4 @@
 import { Component, inject, signal, computed, type OnInit } from '@angular/core';
-import { DecimalPipe } from '@angular/common';
 
 import { AuthService } from '../../core/services/auth.service';
 import { GradeService } from '../../core/services/grade.service';
@@ -13,7 +12,6 @@ import type { GradeItem, CourseGrade } from '../../core/models/grade.model';
 @Component({
     selector: 'app-grades',
     standalone: true,
-    imports: [DecimalPipe],
     templateUrl: './grades.component.html',
     styleUrl: './grades.component.scss',
 })
@@ -31,6 +29,11 @@ export class GradesComponent implements OnInit {
 
     readonly userId = computed(() => this.auth.session()?.siteInfo.userid ?? 0);
 
+    /** Enrolled courses by id; the grade overview only carries course ids. */
+    private readonly coursesById = computed(
+        () => new Map(this.courseService.courses().map((c) => [c.id, c])),
+    );
+
     async ngOnInit(): Promise<void> {
         const uid = this.userId();
         if (!uid) return;
@@ -41,32 +44,14 @@ export class GradesComponent implements OnInit {
         }
 
         await this.gradeService.loadOverviewGrades(uid);
+    }
 
-        // Cross-reference course names from CourseService when API didn't return them
-        this.enrichCourseNames();
+    courseFullname(grade: CourseGrade): string {
+        return this.coursesById().get(grade.courseid)?.fullname ?? `Kurs ${grade.courseid}`;
     }
 
-    /**
-     * Fills in missing courseFullname / courseShortname from the loaded courses list.
-     * The Moodle API often omits these fields.
-     */
-    private enrichCourseNames(): void {
-        const courses = this.courseService.courses();
-        if (!courses.length) return;
-
-        const courseMap = new Map(courses.map((c) => [c.id, c]));
-        const enriched = this.courseGrades().map((g) => {
-            if (g.courseFullname) return g;
-            const course = courseMap.get(g.courseid);
-            if (!course) return g;
-            return {
-                ...g,
-                courseFullname: course.fullname,
-                courseShortname: course.shortname,
-            };
-        });
-
-        this.gradeService.courseGrades.set(enriched);
+    courseShortname(grade: CourseGrade): string {
+        return this.coursesById().get(grade.courseid)?.shortname ?? '';
     }
 
     async toggleCourseGrades(courseId: number): Promise<void> {
@@ -91,16 +76,17 @@ export class GradesComponent implements OnInit {
     }
 
     getGradeColor(grade: CourseGrade): string {
-        if (grade.rawgrade == null) return 'var(--fg-3)';
-        const pct = grade.rawgrade;
+        const pct = grade.rawgrade == null ? NaN : Number(grade.rawgrade);
+        if (isNaN(pct)) return 'var(--fg-3)';
         if (pct >= 80) return '#43A047';
         if (pct >= 60) return '#FB8C00';
         if (pct >= 40) return '#F4511E';
         return '#E53935';
     }
 
+    /** Activities and manual items are indented below their category. */
     getItemIndent(item: GradeItem): string {
-        return `${item.depth * 16}px`;
+        return this.isCategory(item) || this.isCourseTotal(item) ? '0px' : '16px';
     }
 
     isCategory(item: GradeItem): boolean {
@@ -46,7 +46,7 @@ export class MessagesComponent implements OnInit, AfterViewChecked {
         const query = this.searchQuery().toLowerCase().trim();
         if (!query) return this.conversations();
         return this.conversations().filter((c) =>
-            c.name.toLowerCase().includes(query),
+            (c.name ?? '').toLowerCase().includes(query),
         );
     });
 
@@ -200,7 +200,7 @@ export class MessagesComponent implements OnInit, AfterViewChecked {
     // ========== Helpers ==========
 
     getConversationAvatar(conv: Conversation): string {
-        return conv.imageUrl ?? conv.members[0]?.profileImageUrl ?? '';
+        return conv.imageurl ?? conv.members[0]?.profileimageurl ?? '';
     }
 
     getConversationInitials(conv: Conversation): string {
@@ -64,8 +64,8 @@ export class SettingsComponent implements OnInit {
         return `${info.firstname} ${info.lastname}`;
     }
 
-    get userEmail(): string {
-        return this.session()?.siteInfo.email ?? '-';
+    get userLogin(): string {
+        return this.session()?.siteInfo.username ?? '-';
     }
 
     async checkForUpdate(): Promise<void> {
@@ -5,7 +5,7 @@ import { MoodleApiService } from '../../core/services/moodle-api.service';
 /**
  * Pipe that prepares Moodle HTML for safe rendering in `[innerHTML]`.
  *
- * 1. Rewrites `pluginfile.php` URLs to include the WS token.
+ * 1. Rewrites `pluginfile.php` URLs to the authenticated `moodle-file` protocol.
  * 2. Bypasses Angular's built-in HTML sanitiser so that `<iframe>`,
  *    `<video>`, `<audio>`, inline `style` attributes etc. are preserved.
  *